@@ -1,10 +1,8 @@
-/// Supabase用スキーマ定義（SQLマイグレーション）
-///
-/// このファイルはSupabaseダッシュボードまたはマイグレーションで実行するSQLを定義します。
-/// RLS（Row Level Security）を有効にし、anon keyでのアクセスを制御します。
+-- 初期スキーマ（旧 src/db/schema.rs の CREATE_TABLES_SQL / CREATE_INDEXES_SQL / CREATE_REVIEW_STATS_FUNCTION）
+-- RLS（Row Level Security）を有効にし、anon keyでのアクセスを制御する
+-- 既存環境（ダッシュボードで適用済み）では `spirom-api migrate baseline 8` で適用済みとして記録する
 
-/// テーブル作成SQL
-pub const CREATE_TABLES_SQL: &str = r#"
+-- ========== テーブル ==========
 -- ========== ユーザー関連 ==========
 
 CREATE TABLE IF NOT EXISTS users (
@@ -189,10 +187,11 @@ CREATE POLICY "Users can access own cart items" ON cart_items
 
 -- ========== 注文関連 ==========
 
+-- orders / order_items は created_at（明細は order_created_at）で範囲パーティション化している
 CREATE TABLE IF NOT EXISTS orders (
-    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    id UUID NOT NULL DEFAULT gen_random_uuid(),
     user_id UUID NOT NULL REFERENCES users(id),
-    order_number TEXT UNIQUE NOT NULL,
+    order_number TEXT NOT NULL,
     status TEXT DEFAULT 'processing',
     subtotal BIGINT NOT NULL,
     shipping_fee BIGINT DEFAULT 0,
@@ -205,14 +204,37 @@ CREATE TABLE IF NOT EXISTS orders (
     payment_status TEXT DEFAULT 'pending',
     payment_id TEXT,
     notes TEXT,
-    created_at TIMESTAMPTZ DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     updated_at TIMESTAMPTZ DEFAULT NOW(),
     shipped_at TIMESTAMPTZ,
-    delivered_at TIMESTAMPTZ
-);
+    delivered_at TIMESTAMPTZ,
+    PRIMARY KEY (id, created_at)
+) PARTITION BY RANGE (created_at);
 
 ALTER TABLE orders ENABLE ROW LEVEL SECURITY;
 
+-- 注文番号の一意性
+-- パーティションテーブルには order_number 単独の一意制約を張れないため、登録表で保証する
+CREATE TABLE IF NOT EXISTS order_numbers (
+    order_number TEXT PRIMARY KEY,
+    order_id UUID NOT NULL
+);
+
+ALTER TABLE order_numbers ENABLE ROW LEVEL SECURITY;
+
+CREATE OR REPLACE FUNCTION register_order_number()
+RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO order_numbers (order_number, order_id) VALUES (NEW.order_number, NEW.id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER SET search_path = public;
+
+DROP TRIGGER IF EXISTS trg_orders_register_number ON orders;
+CREATE TRIGGER trg_orders_register_number
+    BEFORE INSERT ON orders
+    FOR EACH ROW EXECUTE FUNCTION register_order_number();
+
 -- ユーザーは自分の注文のみ参照可能
 CREATE POLICY "Users can view own orders" ON orders
     FOR SELECT USING (auth.uid()::text = user_id::text);
@@ -228,16 +250,62 @@ CREATE POLICY "Admins can manage all orders" ON orders
     );
 
 CREATE TABLE IF NOT EXISTS order_items (
-    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
-    order_id UUID NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    id UUID NOT NULL DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL,
+    order_created_at TIMESTAMPTZ NOT NULL,
     product_id UUID REFERENCES products(id),
     product_name TEXT NOT NULL,
     product_sku TEXT,
     price BIGINT NOT NULL,
     quantity INT NOT NULL,
     subtotal BIGINT NOT NULL,
-    image_url TEXT
-);
+    image_url TEXT,
+    PRIMARY KEY (id, order_created_at),
+    FOREIGN KEY (order_id, order_created_at)
+        REFERENCES orders(id, created_at) ON DELETE CASCADE
+) PARTITION BY RANGE (order_created_at);
+
+-- パーティション（006 で各パーティションにゲスト用ポリシーを追加する）
+DO $$
+DECLARE
+    p RECORD;
+BEGIN
+    FOR p IN
+        SELECT * FROM (VALUES
+            ('2024_q4', '2024-10-01', '2025-01-01'),
+            ('2025_01', '2025-01-01', '2025-02-01'),
+            ('2025_02', '2025-02-01', '2025-03-01'),
+            ('2025_03', '2025-03-01', '2025-04-01'),
+            ('2025_04', '2025-04-01', '2025-05-01'),
+            ('2025_05', '2025-05-01', '2025-06-01'),
+            ('2025_06', '2025-06-01', '2025-07-01'),
+            ('2025_07', '2025-07-01', '2025-08-01'),
+            ('2025_08', '2025-08-01', '2025-09-01'),
+            ('2025_09', '2025-09-01', '2025-10-01'),
+            ('2025_10', '2025-10-01', '2025-11-01'),
+            ('2025_11', '2025-11-01', '2025-12-01'),
+            ('2025_12', '2025-12-01', '2026-01-01'),
+            ('2026_q1', '2026-01-01', '2026-04-01'),
+            ('2026_q2', '2026-04-01', '2026-07-01'),
+            ('2026_q3', '2026-07-01', '2026-10-01'),
+            ('2026_q4', '2026-10-01', '2027-01-01'),
+            ('2027', '2027-01-01', '2028-01-01'),
+            ('2028', '2028-01-01', '2029-01-01')
+        ) AS t(suffix, range_from, range_to)
+    LOOP
+        EXECUTE format(
+            'CREATE TABLE IF NOT EXISTS %I PARTITION OF orders FOR VALUES FROM (%L) TO (%L)',
+            'orders_' || p.suffix, p.range_from, p.range_to
+        );
+        EXECUTE format(
+            'CREATE TABLE IF NOT EXISTS %I PARTITION OF order_items FOR VALUES FROM (%L) TO (%L)',
+            'order_items_' || p.suffix, p.range_from, p.range_to
+        );
+    END LOOP;
+END $$;
+
+CREATE TABLE IF NOT EXISTS orders_default PARTITION OF orders DEFAULT;
+CREATE TABLE IF NOT EXISTS order_items_default PARTITION OF order_items DEFAULT;
 
 ALTER TABLE order_items ENABLE ROW LEVEL SECURITY;
 
@@ -267,7 +335,7 @@ CREATE TABLE IF NOT EXISTS stripe_events (
     event_id TEXT PRIMARY KEY,
     event_type TEXT NOT NULL,
     payment_intent_id TEXT,
-    order_id UUID REFERENCES orders(id),
+    order_id UUID, -- パーティションテーブルのため外部キーなし
     payload JSONB NOT NULL,
     created_at TIMESTAMPTZ DEFAULT NOW()
 );
@@ -449,10 +517,8 @@ $$ LANGUAGE plpgsql SECURITY DEFINER;
 
 REVOKE ALL ON FUNCTION release_stock_bulk(JSONB) FROM PUBLIC;
 GRANT EXECUTE ON FUNCTION release_stock_bulk(JSONB) TO service_role;
-"#;
 
-/// インデックス作成SQL
-pub const CREATE_INDEXES_SQL: &str = r#"
+-- ========== インデックス ==========
 -- ユーザー関連
 CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
 CREATE INDEX IF NOT EXISTS idx_addresses_user_id ON addresses(user_id);
@@ -478,10 +544,8 @@ CREATE INDEX IF NOT EXISTS idx_reviews_approved ON reviews(is_approved) WHERE is
 
 -- カート関連
 CREATE INDEX IF NOT EXISTS idx_cart_items_session ON cart_items(session_id);
-"#;
 
-/// レビュー統計用RPC関数
-pub const CREATE_REVIEW_STATS_FUNCTION: &str = r#"
+-- ========== レビュー統計用RPC関数 ==========
 CREATE OR REPLACE FUNCTION get_review_stats(p_product_id UUID)
 RETURNS TABLE (
     average_rating NUMERIC,
@@ -506,4 +570,3 @@ BEGIN
     WHERE product_id = p_product_id AND is_approved = true;
 END;
 $$ LANGUAGE plpgsql SECURITY DEFINER;
-"#;
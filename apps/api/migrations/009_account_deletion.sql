@@ -0,0 +1,80 @@
+-- アカウント削除（退会）対応マイグレーション
+-- 会計上保持が必要な注文は匿名化して残し、それ以外の個人データを削除する
+
+-- 1. usersテーブルに退会日時を追加
+ALTER TABLE users
+ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ DEFAULT NULL;
+
+-- 2. アカウント匿名化RPC（単一トランザクションで実行）
+-- users行自体は削除しない（orders/reviews/token_blacklistの外部キーを維持するため）
+CREATE OR REPLACE FUNCTION anonymize_user_account(
+    p_user_id UUID
+) RETURNS TABLE(
+    anonymized_orders INTEGER,
+    deleted_addresses INTEGER,
+    deleted_carts INTEGER
+) AS $$
+DECLARE
+    v_orders INTEGER := 0;
+    v_addresses INTEGER := 0;
+    v_carts INTEGER := 0;
+BEGIN
+    -- 注文: 配送先は都道府県・国のみ残し、氏名・住所・電話番号を消去
+    UPDATE orders
+    SET shipping_address = jsonb_build_object(
+            'name', '退会済みユーザー',
+            'prefecture', shipping_address->>'prefecture',
+            'country', shipping_address->>'country'
+        ),
+        billing_address = NULL,
+        notes = NULL,
+        guest_email = NULL,
+        guest_name = NULL,
+        guest_phone = NULL,
+        guest_access_token_hash = NULL,
+        guest_token_expires_at = NULL,
+        updated_at = NOW()
+    WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_orders = ROW_COUNT;
+
+    -- 住所
+    DELETE FROM addresses WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_addresses = ROW_COUNT;
+
+    -- カート（cart_itemsはON DELETE CASCADE）
+    DELETE FROM cart_metadata WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_carts = ROW_COUNT;
+
+    -- レビュー: 表示名のみ匿名化（商品評価は残す）
+    UPDATE reviews
+    SET user_name = '退会済みユーザー'
+    WHERE user_id = p_user_id;
+
+    -- ユーザー: 個人情報を消去し無効化
+    UPDATE users
+    SET email = 'deleted+' || p_user_id::text || '@deleted.invalid',
+        password_hash = '',
+        name = '退会済みユーザー',
+        phone = NULL,
+        is_active = FALSE,
+        deleted_at = NOW(),
+        updated_at = NOW()
+    WHERE id = p_user_id;
+
+    RETURN QUERY SELECT v_orders, v_addresses, v_carts;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 3. service_roleのみ実行可能
+REVOKE ALL ON FUNCTION anonymize_user_account(UUID) FROM PUBLIC;
+REVOKE ALL ON FUNCTION anonymize_user_account(UUID) FROM anon, authenticated;
+GRANT EXECUTE ON FUNCTION anonymize_user_account(UUID) TO service_role;
+
+-- 4. 本人によるお問い合わせ履歴の参照（データエクスポート用）
+DROP POLICY IF EXISTS "Users can view own contact submissions" ON contact_submissions;
+CREATE POLICY "Users can view own contact submissions" ON contact_submissions
+    FOR SELECT
+    USING (auth.uid() = user_id);
+
+COMMENT ON COLUMN users.deleted_at IS '退会日時（NULL=有効なアカウント）';
+COMMENT ON FUNCTION anonymize_user_account(UUID) IS '退会時の個人データ匿名化（注文は会計用に保持）';
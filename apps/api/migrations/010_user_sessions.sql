@@ -0,0 +1,109 @@
+-- ログインセッション管理マイグレーション
+-- ログイン中の端末一覧表示と、端末単位のリモートログアウトを可能にする
+
+-- 1. セッションテーブル（idはSupabase AuthのJWTに含まれるsession_id）
+CREATE TABLE IF NOT EXISTS user_sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device VARCHAR(100),
+    user_agent TEXT,
+    ip_address VARCHAR(45),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ DEFAULT NULL
+);
+
+-- 2. ユーザーごとの有効セッション検索用インデックス
+CREATE INDEX IF NOT EXISTS idx_user_sessions_user_active
+ON user_sessions (user_id, last_seen_at DESC)
+WHERE revoked_at IS NULL;
+
+-- 3. RLSポリシー（本人は参照のみ、書き込みはservice_role）
+ALTER TABLE user_sessions ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Users can view own sessions" ON user_sessions
+    FOR SELECT
+    USING (auth.uid() = user_id);
+
+CREATE POLICY "Service role can manage user_sessions"
+ON user_sessions
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+-- 4. トークン失効チェックRPC（認証ミドルウェアから1往復で判定）
+CREATE OR REPLACE FUNCTION check_token_revocation(
+    p_user_id UUID,
+    p_jti TEXT DEFAULT NULL,
+    p_session_id UUID DEFAULT NULL
+) RETURNS TABLE(
+    token_revoked BOOLEAN,
+    user_revoked BOOLEAN,
+    session_revoked BOOLEAN
+) AS $$
+BEGIN
+    RETURN QUERY SELECT
+        (p_jti IS NOT NULL AND EXISTS (
+            SELECT 1 FROM token_blacklist WHERE jti = p_jti
+        )),
+        EXISTS (
+            SELECT 1 FROM token_blacklist
+            WHERE jti = 'all_tokens_' || p_user_id::text
+            AND expires_at > NOW()
+        ),
+        (p_session_id IS NOT NULL AND EXISTS (
+            SELECT 1 FROM user_sessions
+            WHERE id = p_session_id AND revoked_at IS NOT NULL
+        ));
+END;
+$$ LANGUAGE plpgsql STABLE SECURITY DEFINER;
+
+-- 5. セッション失効RPC
+-- auth.sessionsを削除するとリフレッシュトークンも失効し、再発行できなくなる
+CREATE OR REPLACE FUNCTION revoke_user_session(
+    p_user_id UUID,
+    p_session_id UUID
+) RETURNS BOOLEAN AS $$
+DECLARE
+    v_found BOOLEAN;
+BEGIN
+    UPDATE user_sessions
+    SET revoked_at = NOW()
+    WHERE id = p_session_id AND user_id = p_user_id AND revoked_at IS NULL;
+    v_found := FOUND;
+
+    DELETE FROM auth.sessions WHERE id = p_session_id AND user_id = p_user_id;
+
+    RETURN v_found;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 6. 現在のセッション以外を全て失効
+CREATE OR REPLACE FUNCTION revoke_other_user_sessions(
+    p_user_id UUID,
+    p_current_session_id UUID
+) RETURNS INTEGER AS $$
+DECLARE
+    v_count INTEGER;
+BEGIN
+    UPDATE user_sessions
+    SET revoked_at = NOW()
+    WHERE user_id = p_user_id AND id <> p_current_session_id AND revoked_at IS NULL;
+    GET DIAGNOSTICS v_count = ROW_COUNT;
+
+    DELETE FROM auth.sessions WHERE user_id = p_user_id AND id <> p_current_session_id;
+
+    RETURN v_count;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 7. service_roleのみ実行可能
+REVOKE ALL ON FUNCTION check_token_revocation(UUID, TEXT, UUID) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION revoke_user_session(UUID, UUID) FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION revoke_other_user_sessions(UUID, UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION check_token_revocation(UUID, TEXT, UUID) TO service_role;
+GRANT EXECUTE ON FUNCTION revoke_user_session(UUID, UUID) TO service_role;
+GRANT EXECUTE ON FUNCTION revoke_other_user_sessions(UUID, UUID) TO service_role;
+
+COMMENT ON TABLE user_sessions IS 'ログインセッション（端末）一覧。idはSupabase Authのsession_id';
@@ -0,0 +1,82 @@
+-- パスワードレスログイン（パスキー）マイグレーション
+-- マジックリンク（OTP）はSupabase Auth標準機能のためテーブル追加なし
+
+-- 1. パスキー（WebAuthnクレデンシャル）テーブル
+CREATE TABLE IF NOT EXISTS passkey_credentials (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL UNIQUE,     -- base64url
+    public_key TEXT NOT NULL,               -- SEC1非圧縮形式（base64url）
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    name VARCHAR(100),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ DEFAULT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_passkey_credentials_user_id
+ON passkey_credentials (user_id);
+
+-- 2. WebAuthnチャレンジ（ワンタイム、短命）
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,  -- 登録時のみ
+    challenge TEXT NOT NULL,
+    ceremony VARCHAR(20) NOT NULL CHECK (ceremony IN ('registration', 'authentication')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_challenges_expires
+ON webauthn_challenges (expires_at);
+
+-- 3. RLSポリシー
+ALTER TABLE passkey_credentials ENABLE ROW LEVEL SECURITY;
+ALTER TABLE webauthn_challenges ENABLE ROW LEVEL SECURITY;
+
+-- 本人は自分のパスキーを参照・削除可能（登録・カウンタ更新はservice_role）
+CREATE POLICY "Users can view own passkeys" ON passkey_credentials
+    FOR SELECT
+    USING (auth.uid() = user_id);
+
+CREATE POLICY "Users can delete own passkeys" ON passkey_credentials
+    FOR DELETE
+    USING (auth.uid() = user_id);
+
+CREATE POLICY "Service role can manage passkey_credentials"
+ON passkey_credentials
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+CREATE POLICY "Service role can manage webauthn_challenges"
+ON webauthn_challenges
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+-- 4. チャレンジ消費RPC（取得と削除を原子的に行い、再利用を防止）
+CREATE OR REPLACE FUNCTION consume_webauthn_challenge(
+    p_id UUID,
+    p_ceremony VARCHAR(20)
+) RETURNS TABLE(
+    challenge TEXT,
+    user_id UUID
+) AS $$
+BEGIN
+    -- 期限切れチャレンジの掃除
+    DELETE FROM webauthn_challenges WHERE expires_at < NOW() - INTERVAL '1 hour';
+
+    RETURN QUERY
+    DELETE FROM webauthn_challenges c
+    WHERE c.id = p_id AND c.ceremony = p_ceremony AND c.expires_at > NOW()
+    RETURNING c.challenge, c.user_id;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION consume_webauthn_challenge(UUID, VARCHAR) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION consume_webauthn_challenge(UUID, VARCHAR) TO service_role;
+
+COMMENT ON TABLE passkey_credentials IS 'WebAuthnパスキー（ES256公開鍵）';
+COMMENT ON TABLE webauthn_challenges IS 'WebAuthn登録・認証用のワンタイムチャレンジ';
@@ -0,0 +1,51 @@
+-- ソーシャルログイン（OAuth/OIDC PKCE）マイグレーション
+-- LINE / Google / Apple はSupabase Authのプロバイダ経由で認証する
+
+-- 1. PKCE状態テーブル（code_verifierをサーバー側で保持）
+CREATE TABLE IF NOT EXISTS oauth_states (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    provider VARCHAR(20) NOT NULL,
+    code_verifier TEXT NOT NULL,
+    redirect_to TEXT NOT NULL,
+    -- アカウント連携時のみ（ログイン時はNULL）
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_oauth_states_expires
+ON oauth_states (expires_at);
+
+-- 2. RLSポリシー（service_roleのみ）
+ALTER TABLE oauth_states ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Service role can manage oauth_states"
+ON oauth_states
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+-- 3. 状態消費RPC（取得と削除を原子的に行い、再利用を防止）
+CREATE OR REPLACE FUNCTION consume_oauth_state(
+    p_id UUID
+) RETURNS TABLE(
+    provider VARCHAR(20),
+    code_verifier TEXT,
+    redirect_to TEXT,
+    user_id UUID
+) AS $$
+BEGIN
+    DELETE FROM oauth_states WHERE expires_at < NOW() - INTERVAL '1 hour';
+
+    RETURN QUERY
+    DELETE FROM oauth_states s
+    WHERE s.id = p_id AND s.expires_at > NOW()
+    RETURNING s.provider, s.code_verifier, s.redirect_to, s.user_id;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION consume_oauth_state(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION consume_oauth_state(UUID) TO service_role;
+
+COMMENT ON TABLE oauth_states IS 'ソーシャルログインのPKCE状態（code_verifier）';
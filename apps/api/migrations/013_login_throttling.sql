@@ -0,0 +1,117 @@
+-- ログイン保護の強化マイグレーション
+-- メール単位の固定ロックは第三者が任意のアカウントをロックできてしまうため、
+-- IP+メール単位の段階的遅延・ロックと、メール単位のCAPTCHA要求に切り替える
+
+-- 1. account_locks をIP+メール単位に変更
+ALTER TABLE account_locks
+ADD COLUMN IF NOT EXISTS ip_address VARCHAR(45) NOT NULL DEFAULT '';
+
+ALTER TABLE account_locks
+DROP CONSTRAINT IF EXISTS account_locks_email_key;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_account_locks_email_ip
+ON account_locks (email, ip_address);
+
+-- IP+メール単位の失敗集計用
+CREATE INDEX IF NOT EXISTS idx_login_attempts_email_ip_time
+ON login_attempts (email, ip_address, attempted_at DESC);
+
+-- 2. ログイン可否の判定材料を取得
+-- pair_failures: このIP+メールの直近成功以降の失敗回数（ウィンドウ内）
+-- email_failures: メール単位の失敗回数（全IP、ウィンドウ内）
+CREATE OR REPLACE FUNCTION get_login_throttle(
+    p_email VARCHAR(255),
+    p_ip_address VARCHAR(45),
+    p_window_minutes INTEGER
+) RETURNS TABLE(
+    pair_failures INTEGER,
+    email_failures INTEGER,
+    last_failed_at TIMESTAMPTZ,
+    locked_until TIMESTAMPTZ
+) AS $$
+DECLARE
+    v_window_start TIMESTAMPTZ := NOW() - make_interval(mins => p_window_minutes);
+    v_last_success TIMESTAMPTZ;
+BEGIN
+    SELECT MAX(a.attempted_at) INTO v_last_success
+    FROM login_attempts a
+    WHERE a.email = p_email AND a.ip_address = p_ip_address AND a.success
+      AND a.attempted_at >= v_window_start;
+
+    RETURN QUERY
+    SELECT
+        (SELECT COUNT(*)::INTEGER FROM login_attempts a
+         WHERE a.email = p_email AND a.ip_address = p_ip_address AND NOT a.success
+           AND a.attempted_at >= GREATEST(v_window_start, COALESCE(v_last_success, v_window_start))),
+        (SELECT COUNT(*)::INTEGER FROM login_attempts a
+         WHERE a.email = p_email AND NOT a.success AND a.attempted_at >= v_window_start),
+        (SELECT MAX(a.attempted_at) FROM login_attempts a
+         WHERE a.email = p_email AND a.ip_address = p_ip_address AND NOT a.success
+           AND a.attempted_at >= v_window_start),
+        (SELECT MAX(l.locked_until) FROM account_locks l
+         WHERE l.email = p_email AND l.ip_address = p_ip_address AND l.locked_until > NOW());
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 3. ログイン失敗を記録し、閾値超過でIP+メールをロック
+-- notify_owner: アカウント所有者へロック通知を送るべきか
+-- 複数インスタンスからの同時失敗でも集計がずれないよう、メール単位のアドバイザリロックで直列化する
+CREATE OR REPLACE FUNCTION record_failed_login(
+    p_email VARCHAR(255),
+    p_ip_address VARCHAR(45),
+    p_user_agent TEXT,
+    p_window_minutes INTEGER,
+    p_lock_threshold INTEGER,
+    p_lock_minutes INTEGER
+) RETURNS TABLE(
+    pair_failures INTEGER,
+    email_failures INTEGER,
+    locked_until TIMESTAMPTZ,
+    newly_locked BOOLEAN,
+    notify_owner BOOLEAN
+) AS $$
+DECLARE
+    v_pair_failures INTEGER;
+    v_email_failures INTEGER;
+    v_locked_until TIMESTAMPTZ;
+    v_newly_locked BOOLEAN := FALSE;
+    v_notify_owner BOOLEAN := FALSE;
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('login:' || p_email));
+
+    INSERT INTO login_attempts (email, ip_address, success, user_agent)
+    VALUES (p_email, p_ip_address, FALSE, p_user_agent);
+
+    SELECT t.pair_failures, t.email_failures, t.locked_until
+    INTO v_pair_failures, v_email_failures, v_locked_until
+    FROM get_login_throttle(p_email, p_ip_address, p_window_minutes) t;
+
+    IF v_locked_until IS NULL AND v_pair_failures >= p_lock_threshold THEN
+        -- 複数IPから攻撃された場合に通知が大量送信されないよう、他IPのロックが有効な間は通知しない
+        v_notify_owner := NOT EXISTS (
+            SELECT 1 FROM account_locks l
+            WHERE l.email = p_email AND l.ip_address <> p_ip_address AND l.locked_until > NOW()
+        );
+        v_locked_until := NOW() + make_interval(mins => p_lock_minutes);
+        v_newly_locked := TRUE;
+
+        INSERT INTO account_locks (email, ip_address, locked_at, locked_until, reason, failed_attempts)
+        VALUES (p_email, p_ip_address, NOW(), v_locked_until, 'too_many_failed_attempts', v_pair_failures)
+        ON CONFLICT (email, ip_address) DO UPDATE
+        SET locked_at = EXCLUDED.locked_at,
+            locked_until = EXCLUDED.locked_until,
+            reason = EXCLUDED.reason,
+            failed_attempts = EXCLUDED.failed_attempts,
+            updated_at = NOW();
+    END IF;
+
+    RETURN QUERY SELECT v_pair_failures, v_email_failures, v_locked_until, v_newly_locked, v_notify_owner;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION get_login_throttle(VARCHAR, VARCHAR, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION get_login_throttle(VARCHAR, VARCHAR, INTEGER) TO service_role;
+REVOKE ALL ON FUNCTION record_failed_login(VARCHAR, VARCHAR, TEXT, INTEGER, INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION record_failed_login(VARCHAR, VARCHAR, TEXT, INTEGER, INTEGER, INTEGER) TO service_role;
+
+COMMENT ON COLUMN account_locks.ip_address IS 'ロック対象のIPアドレス（IP+メール単位でロック）';
@@ -0,0 +1,70 @@
+-- 分散レート制限マイグレーション
+-- 複数インスタンス（Fly.io等）でカウンターを共有するためのGCRAバケット
+-- RATE_LIMIT_BACKEND=postgres（またはグループ単位の *_RATE_LIMIT_BACKEND）で有効化
+
+-- 1. バケットテーブル（揮発データのためUNLOGGED）
+CREATE UNLOGGED TABLE IF NOT EXISTS rate_limit_buckets (
+    key TEXT PRIMARY KEY,
+    -- 理論到着時刻（Theoretical Arrival Time）
+    tat TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limit_buckets_tat
+ON rate_limit_buckets (tat);
+
+-- 2. RLSポリシー（service_roleのみ）
+ALTER TABLE rate_limit_buckets ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Service role can manage rate_limit_buckets"
+ON rate_limit_buckets
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+-- 3. レート制限判定RPC（GCRA）
+-- p_window_ms 内に p_max_requests 回まで許可し、均等間隔で回復する
+CREATE OR REPLACE FUNCTION rate_limit_check(
+    p_key TEXT,
+    p_window_ms BIGINT,
+    p_max_requests INTEGER
+) RETURNS TABLE(
+    allowed BOOLEAN,
+    remaining INTEGER,
+    retry_after_ms BIGINT
+) AS $$
+DECLARE
+    v_now TIMESTAMPTZ := clock_timestamp();
+    v_interval_ms BIGINT := GREATEST(p_window_ms / GREATEST(p_max_requests, 1), 1);
+    v_tat TIMESTAMPTZ;
+    v_new_tat TIMESTAMPTZ;
+    v_elapsed_ms BIGINT;
+BEGIN
+    INSERT INTO rate_limit_buckets (key, tat)
+    VALUES (p_key, v_now)
+    ON CONFLICT (key) DO NOTHING;
+
+    -- 同一キーへの同時リクエストは行ロックで直列化
+    SELECT GREATEST(b.tat, v_now) INTO v_tat
+    FROM rate_limit_buckets b
+    WHERE b.key = p_key
+    FOR UPDATE;
+
+    v_new_tat := v_tat + make_interval(secs => v_interval_ms / 1000.0);
+    v_elapsed_ms := (EXTRACT(EPOCH FROM (v_new_tat - v_now)) * 1000)::BIGINT;
+
+    IF v_elapsed_ms > p_window_ms THEN
+        RETURN QUERY SELECT FALSE, 0, GREATEST(v_elapsed_ms - p_window_ms, 0);
+        RETURN;
+    END IF;
+
+    UPDATE rate_limit_buckets SET tat = v_new_tat WHERE rate_limit_buckets.key = p_key;
+
+    RETURN QUERY SELECT TRUE, ((p_window_ms - v_elapsed_ms) / v_interval_ms)::INTEGER, 0::BIGINT;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION rate_limit_check(TEXT, BIGINT, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION rate_limit_check(TEXT, BIGINT, INTEGER) TO service_role;
+
+COMMENT ON TABLE rate_limit_buckets IS 'APIレート制限のGCRAバケット（全インスタンス共有）';
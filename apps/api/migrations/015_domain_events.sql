@@ -0,0 +1,180 @@
+-- ドメインイベント（トランザクショナル・アウトボックス）
+-- 注文・在庫の状態変更と同じトランザクション内でトリガーがイベントを書き込み、
+-- APIのディスパッチャーが購読者（在庫解放・通知等）へ配信する（リトライ・デッドレター付き）
+
+-- 1. アウトボックステーブル
+CREATE TABLE IF NOT EXISTS domain_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_type TEXT NOT NULL,
+    aggregate_type TEXT NOT NULL,
+    aggregate_id UUID NOT NULL,
+    payload JSONB NOT NULL DEFAULT '{}'::jsonb,
+    -- pending / processing / delivered / dead
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMPTZ,
+    -- 処理済みの購読者（リトライ時に再実行しない）
+    completed_subscribers TEXT[] NOT NULL DEFAULT '{}',
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_domain_events_dispatch
+ON domain_events (next_attempt_at)
+WHERE status IN ('pending', 'processing');
+
+CREATE INDEX IF NOT EXISTS idx_domain_events_status_created
+ON domain_events (status, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_domain_events_aggregate
+ON domain_events (aggregate_id, created_at);
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE domain_events ENABLE ROW LEVEL SECURITY;
+
+-- 2. 注文イベント
+-- payment_status はJSON文字列（例: "paid"）で保存されている場合があるため引用符を除いて比較する
+CREATE OR REPLACE FUNCTION enqueue_order_events() RETURNS TRIGGER AS $$
+DECLARE
+    v_payload JSONB;
+    v_new_payment TEXT := trim(both '"' from COALESCE(NEW.payment_status, ''));
+    v_old_payment TEXT := '';
+BEGIN
+    v_payload := jsonb_build_object(
+        'order_id', NEW.id,
+        'order_number', NEW.order_number,
+        'user_id', NEW.user_id,
+        'is_guest_order', COALESCE(NEW.is_guest_order, FALSE),
+        'status', NEW.status,
+        'payment_status', v_new_payment,
+        'total', NEW.total,
+        'currency', NEW.currency
+    );
+
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('OrderCreated', 'order', NEW.id, v_payload);
+        IF NEW.status = 'paid' THEN
+            INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+            VALUES ('OrderPaid', 'order', NEW.id, v_payload);
+        END IF;
+        RETURN NEW;
+    END IF;
+
+    v_old_payment := trim(both '"' from COALESCE(OLD.payment_status, ''));
+    v_payload := v_payload || jsonb_build_object('previous_status', OLD.status);
+
+    IF NEW.status IS DISTINCT FROM OLD.status THEN
+        IF NEW.status = 'paid' THEN
+            INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+            VALUES ('OrderPaid', 'order', NEW.id, v_payload);
+        ELSIF NEW.status = 'cancelled' THEN
+            INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+            VALUES ('OrderCancelled', 'order', NEW.id, v_payload);
+        ELSIF NEW.status = 'shipped' THEN
+            INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+            VALUES ('OrderShipped', 'order', NEW.id, v_payload);
+        END IF;
+    END IF;
+
+    IF v_new_payment IN ('refunding', 'refunded', 'partially_refunded')
+       AND v_old_payment NOT IN ('refunding', 'refunded', 'partially_refunded') THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('RefundIssued', 'order', NEW.id, v_payload);
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_orders_domain_events ON orders;
+CREATE TRIGGER trg_orders_domain_events
+AFTER INSERT OR UPDATE OF status, payment_status ON orders
+FOR EACH ROW EXECUTE FUNCTION enqueue_order_events();
+
+-- 3. 在庫僅少イベント（閾値を下回った時点で1回）
+CREATE OR REPLACE FUNCTION low_stock_threshold() RETURNS INTEGER AS $$
+    SELECT 5;
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION enqueue_stock_low_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.stock <= low_stock_threshold() AND OLD.stock > low_stock_threshold() THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('StockLow', 'product', NEW.id, jsonb_build_object(
+            'product_id', NEW.id,
+            'sku', NEW.sku,
+            'name', NEW.name,
+            'stock', NEW.stock,
+            'threshold', low_stock_threshold()
+        ));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_products_stock_low ON products;
+CREATE TRIGGER trg_products_stock_low
+AFTER UPDATE OF stock ON products
+FOR EACH ROW EXECUTE FUNCTION enqueue_stock_low_event();
+
+-- 4. 注文の在庫解放（冪等: stock_released_at で1回だけ実行）
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS stock_released_at TIMESTAMPTZ;
+
+CREATE OR REPLACE FUNCTION release_order_stock(p_order_id UUID) RETURNS BOOLEAN AS $$
+DECLARE
+    v_items JSONB;
+BEGIN
+    UPDATE orders SET stock_released_at = NOW()
+    WHERE id = p_order_id AND stock_released_at IS NULL;
+    IF NOT FOUND THEN
+        RETURN FALSE;
+    END IF;
+
+    SELECT COALESCE(jsonb_agg(jsonb_build_object('product_id', oi.product_id, 'quantity', oi.quantity)), '[]'::jsonb)
+    INTO v_items
+    FROM order_items oi
+    WHERE oi.order_id = p_order_id AND oi.product_id IS NOT NULL;
+
+    IF jsonb_array_length(v_items) > 0 THEN
+        PERFORM release_stock_bulk(v_items);
+    END IF;
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 5. 配信対象の取得（複数インスタンスで重複しないよう SKIP LOCKED でロックし processing にする）
+-- ロック期限切れの processing（処理中にプロセスが落ちた等）も再取得する
+CREATE OR REPLACE FUNCTION claim_domain_events(
+    p_limit INTEGER,
+    p_lock_seconds INTEGER
+) RETURNS SETOF domain_events AS $$
+BEGIN
+    RETURN QUERY
+    UPDATE domain_events e
+    SET status = 'processing',
+        attempts = e.attempts + 1,
+        locked_until = NOW() + make_interval(secs => p_lock_seconds)
+    WHERE e.id IN (
+        SELECT d.id FROM domain_events d
+        WHERE (d.status = 'pending' AND d.next_attempt_at <= NOW())
+           OR (d.status = 'processing' AND d.locked_until < NOW())
+        ORDER BY d.created_at
+        LIMIT p_limit
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING e.*;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION enqueue_order_events() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION enqueue_stock_low_event() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION release_order_stock(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION release_order_stock(UUID) TO service_role;
+REVOKE ALL ON FUNCTION claim_domain_events(INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION claim_domain_events(INTEGER, INTEGER) TO service_role;
+
+COMMENT ON TABLE domain_events IS 'ドメインイベントのアウトボックス（トリガーで書き込み、APIのディスパッチャーが配信）';
+COMMENT ON COLUMN orders.stock_released_at IS 'キャンセル時の在庫解放日時（二重解放防止）';
@@ -0,0 +1,128 @@
+-- 外部連携向けWebhook（フルフィルメント・会計ツール等）
+-- 管理者が登録したエンドポイントへ、ドメインイベントをHMAC署名付きで配信する（リトライ・配信ログ・再送付き）
+
+-- 1. 在庫変更イベント（products.stock が変化した時点で毎回）
+CREATE OR REPLACE FUNCTION enqueue_stock_changed_event() RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+    VALUES ('StockChanged', 'product', NEW.id, jsonb_build_object(
+        'product_id', NEW.id,
+        'sku', NEW.sku,
+        'name', NEW.name,
+        'previous_stock', OLD.stock,
+        'stock', NEW.stock
+    ));
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_products_stock_changed ON products;
+CREATE TRIGGER trg_products_stock_changed
+AFTER UPDATE OF stock ON products
+FOR EACH ROW
+WHEN (OLD.stock IS DISTINCT FROM NEW.stock)
+EXECUTE FUNCTION enqueue_stock_changed_event();
+
+-- 2. エンドポイント
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    url TEXT NOT NULL,
+    description TEXT,
+    -- 署名用シークレット（作成時のみAPIレスポンスで返す）
+    secret TEXT NOT NULL,
+    -- 購読するイベント（例: order.paid, order.shipped, order.refunded, product.stock_changed）
+    event_types TEXT[] NOT NULL DEFAULT '{}',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- 3. 配信ログ（1エンドポイント×1イベント=1行、再送は replay_of 付きの新しい行）
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    event_type TEXT NOT NULL,
+    -- 送信するリクエストボディ（再送時も同一内容）
+    body JSONB NOT NULL,
+    -- pending / processing / succeeded / dead
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMPTZ,
+    response_status INTEGER,
+    response_body TEXT,
+    last_error TEXT,
+    duration_ms INTEGER,
+    replay_of UUID REFERENCES webhook_deliveries(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+-- アウトボックスの再配信で同じイベントを二重登録しない（再送は除く）
+CREATE UNIQUE INDEX IF NOT EXISTS uq_webhook_deliveries_endpoint_event
+ON webhook_deliveries (endpoint_id, event_id)
+WHERE replay_of IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_dispatch
+ON webhook_deliveries (next_attempt_at)
+WHERE status IN ('pending', 'processing');
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_endpoint_created
+ON webhook_deliveries (endpoint_id, created_at DESC);
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE webhook_endpoints ENABLE ROW LEVEL SECURITY;
+ALTER TABLE webhook_deliveries ENABLE ROW LEVEL SECURITY;
+
+-- 4. イベントを購読中のエンドポイントへ配信登録（冪等、登録件数を返す）
+CREATE OR REPLACE FUNCTION enqueue_webhook_deliveries(
+    p_event_id UUID,
+    p_event_type TEXT,
+    p_body JSONB
+) RETURNS INTEGER AS $$
+DECLARE
+    v_count INTEGER;
+BEGIN
+    INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, body)
+    SELECT e.id, p_event_id, p_event_type, p_body
+    FROM webhook_endpoints e
+    WHERE e.is_active AND p_event_type = ANY(e.event_types)
+    ON CONFLICT (endpoint_id, event_id) WHERE replay_of IS NULL DO NOTHING;
+
+    GET DIAGNOSTICS v_count = ROW_COUNT;
+    RETURN v_count;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 5. 配信対象の取得（claim_domain_events と同様に SKIP LOCKED）
+CREATE OR REPLACE FUNCTION claim_webhook_deliveries(
+    p_limit INTEGER,
+    p_lock_seconds INTEGER
+) RETURNS SETOF webhook_deliveries AS $$
+BEGIN
+    RETURN QUERY
+    UPDATE webhook_deliveries w
+    SET status = 'processing',
+        attempts = w.attempts + 1,
+        locked_until = NOW() + make_interval(secs => p_lock_seconds)
+    WHERE w.id IN (
+        SELECT d.id FROM webhook_deliveries d
+        WHERE (d.status = 'pending' AND d.next_attempt_at <= NOW())
+           OR (d.status = 'processing' AND d.locked_until < NOW())
+        ORDER BY d.created_at
+        LIMIT p_limit
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING w.*;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION enqueue_stock_changed_event() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION enqueue_webhook_deliveries(UUID, TEXT, JSONB) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION enqueue_webhook_deliveries(UUID, TEXT, JSONB) TO service_role;
+REVOKE ALL ON FUNCTION claim_webhook_deliveries(INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION claim_webhook_deliveries(INTEGER, INTEGER) TO service_role;
+
+COMMENT ON TABLE webhook_endpoints IS '外部連携Webhookの配信先（管理者が登録）';
+COMMENT ON TABLE webhook_deliveries IS '外部連携Webhookの配信ログ（リトライ・再送）';
@@ -0,0 +1,48 @@
+-- ウィッシュリスト（お気に入り）
+-- ログインユーザーは user_id、ゲストは署名付きセッションID（X-Session-ID）で保持する
+-- 所有者の判定はAPI側で行うため、テーブルは service_role からのみアクセスする
+
+-- 1. アイテム
+CREATE TABLE IF NOT EXISTS wishlist_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    session_id TEXT,
+    -- 所有者キー（'user:<uuid>' またはセッションID）。upsert の競合判定に使う
+    owner_key TEXT GENERATED ALWAYS AS (COALESCE('user:' || user_id::text, session_id)) STORED,
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    variant_id UUID REFERENCES product_variants(id) ON DELETE CASCADE,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT wishlist_items_owner_check CHECK ((user_id IS NULL) <> (session_id IS NULL)),
+    CONSTRAINT wishlist_items_owner_product_key UNIQUE NULLS NOT DISTINCT (owner_key, product_id, variant_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_wishlist_items_user ON wishlist_items(user_id) WHERE user_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_wishlist_items_session ON wishlist_items(session_id) WHERE session_id IS NOT NULL;
+
+-- 2. 共有リンク（ログインユーザーのみ。トークンはハッシュで保存し、再発行で旧リンクは無効になる）
+CREATE TABLE IF NOT EXISTS wishlist_shares (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- 3. RLSポリシー（service_roleのみ）
+ALTER TABLE wishlist_items ENABLE ROW LEVEL SECURITY;
+ALTER TABLE wishlist_shares ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY "Service role can manage wishlist_items"
+ON wishlist_items
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+CREATE POLICY "Service role can manage wishlist_shares"
+ON wishlist_shares
+FOR ALL
+TO service_role
+USING (true)
+WITH CHECK (true);
+
+COMMENT ON TABLE wishlist_items IS 'ウィッシュリスト（ユーザーまたはゲストセッション単位）';
+COMMENT ON TABLE wishlist_shares IS 'ウィッシュリストの公開共有リンク';
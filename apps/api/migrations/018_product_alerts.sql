@@ -0,0 +1,152 @@
+-- 再入荷・値下げ通知
+-- 顧客が商品（またはバリアント）を購読し、在庫が0から補充された時・購読時の価格を下回った時に通知する
+-- 通知は同じ宛先へ一定間隔を空けてまとめて送り、送信後は購読を終了する
+
+-- 1. 再入荷イベント（在庫が0以下から1以上になった時点で1回。商品・バリアントとも）
+CREATE OR REPLACE FUNCTION enqueue_product_back_in_stock_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.stock > 0 AND COALESCE(OLD.stock, 0) <= 0 THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('BackInStock', 'product', NEW.id, jsonb_build_object(
+            'product_id', NEW.id,
+            'variant_id', NULL,
+            'stock', NEW.stock
+        ));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_products_back_in_stock ON products;
+CREATE TRIGGER trg_products_back_in_stock
+AFTER UPDATE OF stock ON products
+FOR EACH ROW EXECUTE FUNCTION enqueue_product_back_in_stock_event();
+
+CREATE OR REPLACE FUNCTION enqueue_variant_back_in_stock_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.stock > 0 AND COALESCE(OLD.stock, 0) <= 0 THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('BackInStock', 'product', NEW.product_id, jsonb_build_object(
+            'product_id', NEW.product_id,
+            'variant_id', NEW.id,
+            'size', NEW.size,
+            'stock', NEW.stock
+        ));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_product_variants_back_in_stock ON product_variants;
+CREATE TRIGGER trg_product_variants_back_in_stock
+AFTER UPDATE OF stock ON product_variants
+FOR EACH ROW EXECUTE FUNCTION enqueue_variant_back_in_stock_event();
+
+-- 2. 値下げイベント
+CREATE OR REPLACE FUNCTION enqueue_price_dropped_event() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.price < OLD.price THEN
+        INSERT INTO domain_events (event_type, aggregate_type, aggregate_id, payload)
+        VALUES ('PriceDropped', 'product', NEW.id, jsonb_build_object(
+            'product_id', NEW.id,
+            'previous_price', OLD.price,
+            'price', NEW.price
+        ));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+DROP TRIGGER IF EXISTS trg_products_price_dropped ON products;
+CREATE TRIGGER trg_products_price_dropped
+AFTER UPDATE OF price ON products
+FOR EACH ROW EXECUTE FUNCTION enqueue_price_dropped_event();
+
+-- 3. 購読
+CREATE TABLE IF NOT EXISTS product_alerts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- back_in_stock / price_drop
+    kind TEXT NOT NULL CHECK (kind IN ('back_in_stock', 'price_drop')),
+    product_id UUID NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    variant_id UUID REFERENCES product_variants(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    -- 値下げ通知の基準価格（購読時の価格。これを下回ったら通知）
+    target_price BIGINT,
+    -- active（待機中） / queued（通知待ち） / sending / sent（通知済み=購読終了） / cancelled / failed
+    status TEXT NOT NULL DEFAULT 'active',
+    -- ゲストの購読解除用（ハッシュで保存）
+    unsubscribe_token_hash TEXT UNIQUE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    queued_at TIMESTAMPTZ,
+    sent_at TIMESTAMPTZ
+);
+
+-- 同じ宛先・商品・バリアント・種別の有効な購読は1件まで
+CREATE UNIQUE INDEX IF NOT EXISTS uq_product_alerts_open
+ON product_alerts (email, kind, product_id, variant_id) NULLS NOT DISTINCT
+WHERE status IN ('active', 'queued', 'sending');
+
+CREATE INDEX IF NOT EXISTS idx_product_alerts_product_active
+ON product_alerts (product_id, kind)
+WHERE status = 'active';
+
+CREATE INDEX IF NOT EXISTS idx_product_alerts_dispatch
+ON product_alerts (queued_at)
+WHERE status IN ('queued', 'sending');
+
+CREATE INDEX IF NOT EXISTS idx_product_alerts_email_sent
+ON product_alerts (email, sent_at DESC)
+WHERE status = 'sent';
+
+CREATE INDEX IF NOT EXISTS idx_product_alerts_user
+ON product_alerts (user_id, created_at DESC)
+WHERE user_id IS NOT NULL;
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE product_alerts ENABLE ROW LEVEL SECURITY;
+
+-- 4. 送信対象の取得（宛先単位。直近 p_recipient_interval_seconds 以内に通知済みの宛先は後回し）
+CREATE OR REPLACE FUNCTION claim_product_alerts(
+    p_recipients INTEGER,
+    p_recipient_interval_seconds INTEGER,
+    p_lock_seconds INTEGER
+) RETURNS SETOF product_alerts AS $$
+BEGIN
+    RETURN QUERY
+    UPDATE product_alerts a
+    SET status = 'sending',
+        attempts = a.attempts + 1,
+        locked_until = NOW() + make_interval(secs => p_lock_seconds)
+    WHERE a.id IN (
+        SELECT q.id FROM product_alerts q
+        WHERE (q.status = 'queued' OR (q.status = 'sending' AND q.locked_until < NOW()))
+          AND q.email IN (
+            SELECT r.email FROM product_alerts r
+            WHERE (r.status = 'queued' OR (r.status = 'sending' AND r.locked_until < NOW()))
+              AND NOT EXISTS (
+                SELECT 1 FROM product_alerts s
+                WHERE s.email = r.email
+                  AND s.status = 'sent'
+                  AND s.sent_at > NOW() - make_interval(secs => p_recipient_interval_seconds)
+              )
+            GROUP BY r.email
+            ORDER BY MIN(r.queued_at)
+            LIMIT p_recipients
+          )
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING a.*;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION enqueue_product_back_in_stock_event() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION enqueue_variant_back_in_stock_event() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION enqueue_price_dropped_event() FROM PUBLIC, anon, authenticated;
+REVOKE ALL ON FUNCTION claim_product_alerts(INTEGER, INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION claim_product_alerts(INTEGER, INTEGER, INTEGER) TO service_role;
+
+COMMENT ON TABLE product_alerts IS '再入荷・値下げ通知の購読（送信後に終了）';
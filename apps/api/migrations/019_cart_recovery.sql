@@ -0,0 +1,105 @@
+-- カゴ落ち（放置カート）の回復
+-- ログインユーザーのカートが一定時間放置されたら、署名付きの回復リンクをメールで送る。
+-- リンクから新しいセッションにカートを復元し、そのセッションで注文されたらコンバージョンとして記録する
+
+-- 1. 回復リンクの送信記録（1つの放置状態につき1回: session_id + cart_updated_at）
+CREATE TABLE IF NOT EXISTS cart_recoveries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id TEXT NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    -- 放置されたカートの最終更新日時（同じ放置状態に二重送信しないためのキー）
+    cart_updated_at TIMESTAMPTZ NOT NULL,
+    -- 送信時点のカート内容（元のカートが変更・削除されても復元できるように保持）
+    items JSONB NOT NULL DEFAULT '[]'::jsonb,
+    item_count INTEGER NOT NULL DEFAULT 0,
+    subtotal BIGINT NOT NULL DEFAULT 0,
+    -- pending（送信中） / sent / recovered / converted / failed
+    status TEXT NOT NULL DEFAULT 'pending',
+    last_error TEXT,
+    expires_at TIMESTAMPTZ NOT NULL,
+    sent_at TIMESTAMPTZ,
+    recovered_at TIMESTAMPTZ,
+    -- 復元先のセッション（このセッションで作成された注文をコンバージョンとする）
+    recovered_session_id TEXT,
+    converted_at TIMESTAMPTZ,
+    converted_order_id UUID, -- パーティションテーブルのため外部キーなし
+    converted_total BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT cart_recoveries_session_updated_key UNIQUE (session_id, cart_updated_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_cart_recoveries_recovered_session
+ON cart_recoveries (recovered_session_id)
+WHERE status = 'recovered';
+
+CREATE INDEX IF NOT EXISTS idx_cart_recoveries_created
+ON cart_recoveries (created_at DESC);
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE cart_recoveries ENABLE ROW LEVEL SECURITY;
+
+CREATE INDEX IF NOT EXISTS idx_cart_metadata_user_updated
+ON cart_metadata (updated_at)
+WHERE user_id IS NOT NULL;
+
+-- 2. 放置カートの検索
+-- p_idle_seconds 以上更新がなく、p_max_age_seconds より新しい、アイテムのあるログインユーザーのカート
+CREATE OR REPLACE FUNCTION find_abandoned_carts(
+    p_idle_seconds INTEGER,
+    p_max_age_seconds INTEGER,
+    p_limit INTEGER
+) RETURNS TABLE(
+    session_id TEXT,
+    user_id UUID,
+    email TEXT,
+    cart_updated_at TIMESTAMPTZ
+) AS $$
+BEGIN
+    RETURN QUERY
+    SELECT m.session_id, m.user_id, u.email, m.updated_at
+    FROM cart_metadata m
+    JOIN users u ON u.id = m.user_id
+    WHERE m.user_id IS NOT NULL
+      AND m.updated_at < NOW() - make_interval(secs => p_idle_seconds)
+      AND m.updated_at > NOW() - make_interval(secs => p_max_age_seconds)
+      AND EXISTS (SELECT 1 FROM cart_items i WHERE i.session_id = m.session_id)
+      AND NOT EXISTS (
+        SELECT 1 FROM cart_recoveries r
+        WHERE r.session_id = m.session_id AND r.cart_updated_at = m.updated_at
+      )
+    ORDER BY m.updated_at
+    LIMIT p_limit;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 3. 管理者向け集計（p_since 以降に作成された回復リンク）
+CREATE OR REPLACE FUNCTION cart_recovery_stats(p_since TIMESTAMPTZ)
+RETURNS TABLE(
+    sent BIGINT,
+    recovered BIGINT,
+    converted BIGINT,
+    failed BIGINT,
+    abandoned_value BIGINT,
+    recovered_revenue BIGINT
+) AS $$
+BEGIN
+    RETURN QUERY
+    SELECT
+        COUNT(*) FILTER (WHERE r.status IN ('sent', 'recovered', 'converted')),
+        COUNT(*) FILTER (WHERE r.status IN ('recovered', 'converted')),
+        COUNT(*) FILTER (WHERE r.status = 'converted'),
+        COUNT(*) FILTER (WHERE r.status = 'failed'),
+        COALESCE(SUM(r.subtotal) FILTER (WHERE r.status IN ('sent', 'recovered', 'converted')), 0)::BIGINT,
+        COALESCE(SUM(r.converted_total) FILTER (WHERE r.status = 'converted'), 0)::BIGINT
+    FROM cart_recoveries r
+    WHERE r.created_at >= p_since;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION find_abandoned_carts(INTEGER, INTEGER, INTEGER) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION find_abandoned_carts(INTEGER, INTEGER, INTEGER) TO service_role;
+REVOKE ALL ON FUNCTION cart_recovery_stats(TIMESTAMPTZ) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION cart_recovery_stats(TIMESTAMPTZ) TO service_role;
+
+COMMENT ON TABLE cart_recoveries IS 'カゴ落ち回復リンクの送信・復元・コンバージョン記録';
@@ -0,0 +1,24 @@
+-- Idempotency-Key（注文作成・決済開始の二重実行防止）
+-- 同じキーの再送には保存したレスポンスを返し、別の内容での再利用は拒否する
+
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- キーの名前空間（user:{id} / session:{id} / guest）
+    scope TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    -- メソッド・パス・ボディのSHA-256（別リクエストでの再利用検出）
+    request_hash TEXT NOT NULL,
+    -- in_progress（処理中） / completed
+    status TEXT NOT NULL DEFAULT 'in_progress',
+    response_status INTEGER,
+    response_body TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    CONSTRAINT idempotency_keys_scope_key UNIQUE (scope, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires
+ON idempotency_keys (expires_at);
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE idempotency_keys ENABLE ROW LEVEL SECURITY;
@@ -0,0 +1,47 @@
+-- ゲスト注文の再アクセスリンク
+-- 注文番号 + メールアドレス/電話番号で照会したゲストに、1回限り有効なリンクをメールで送る。
+-- リンクを開くとゲストアクセストークンを再発行（旧トークンは無効化）する
+
+CREATE TABLE IF NOT EXISTS guest_order_access_links (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL, -- パーティションテーブルのため外部キーなし
+    -- リンクに含めるトークンのSHA-256（平文は保存しない）
+    token_hash TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- 使用済み（1回限り）
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT guest_order_access_links_token_hash_key UNIQUE (token_hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_guest_order_access_links_order
+ON guest_order_access_links (order_id);
+
+CREATE INDEX IF NOT EXISTS idx_guest_order_access_links_expires
+ON guest_order_access_links (expires_at);
+
+-- service_role のみアクセス（ポリシーなし）
+ALTER TABLE guest_order_access_links ENABLE ROW LEVEL SECURITY;
+
+-- ゲスト注文をアカウントに紐付け（確認済みメールアドレスと注文時のメールアドレスが一致するもの）
+-- 紐付け後はログインユーザーの注文として扱い、ゲストアクセストークンは無効化する
+CREATE OR REPLACE FUNCTION claim_guest_orders(p_user_id UUID, p_email TEXT)
+RETURNS TABLE (id UUID) AS $$
+BEGIN
+    RETURN QUERY
+    UPDATE orders o
+    SET user_id = p_user_id,
+        is_guest_order = FALSE,
+        guest_access_token_hash = NULL,
+        guest_token_expires_at = NULL,
+        updated_at = NOW()
+    WHERE o.is_guest_order = TRUE
+      AND o.user_id IS NULL
+      AND o.guest_email IS NOT NULL
+      AND LOWER(TRIM(o.guest_email)) = LOWER(TRIM(p_email))
+    RETURNING o.id;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION claim_guest_orders(UUID, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION claim_guest_orders(UUID, TEXT) TO service_role;
@@ -0,0 +1,156 @@
+-- 発送前の注文変更（購入者による配送先の変更・同一商品内のサイズ交換）
+-- 差額がある場合は追加決済（PaymentIntent）または一部返金で精算する。
+-- 変更の適用（在庫移動・明細/金額/配送先の更新）は apply_order_modification で1トランザクションにまとめる
+
+CREATE TABLE IF NOT EXISTS order_modifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL, -- パーティションテーブルのため外部キーなし
+    -- 変更を依頼したユーザー（ゲストはNULL）
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- 変更後の配送先（変更しない場合はNULL）
+    shipping_address JSONB,
+    -- サイズ交換 [{product_id, from_variant_id, to_variant_id, to_size, quantity, price}]
+    item_changes JSONB NOT NULL DEFAULT '[]'::jsonb,
+    -- 変更前の合計（適用時に注文が変わっていないかの確認用）
+    previous_total BIGINT NOT NULL,
+    subtotal BIGINT NOT NULL,
+    shipping_fee BIGINT NOT NULL,
+    tax BIGINT NOT NULL,
+    total BIGINT NOT NULL,
+    -- total - previous_total（正: 追加決済、負: 返金）
+    amount_difference BIGINT NOT NULL,
+    -- pending（適用待ち） / awaiting_payment（追加決済待ち） / applied / superseded / failed / expired
+    status TEXT NOT NULL DEFAULT 'pending',
+    -- 追加決済のPaymentIntent ID
+    payment_id TEXT,
+    refund_id TEXT,
+    -- not_required / pending / succeeded / failed
+    refund_status TEXT NOT NULL DEFAULT 'not_required',
+    failure_reason TEXT,
+    -- 追加決済の期限（過ぎたら決済リコンサイラがPaymentIntentを取り消して expired にする）
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    applied_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_order_modifications_order
+ON order_modifications (order_id, created_at DESC);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_order_modifications_payment
+ON order_modifications (payment_id)
+WHERE payment_id IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_order_modifications_awaiting_expiry
+ON order_modifications (expires_at)
+WHERE status = 'awaiting_payment';
+
+-- service_role のみアクセス（ポリシーなし。購入者はAPI経由で参照）
+ALTER TABLE order_modifications ENABLE ROW LEVEL SECURITY;
+
+-- 変更の適用
+-- 戻り値: applied / not_pending（適用済み・取り消し済み） / order_changed（注文の状態・金額が変わった） / out_of_stock
+CREATE OR REPLACE FUNCTION apply_order_modification(p_modification_id UUID)
+RETURNS TEXT AS $$
+DECLARE
+    v_mod order_modifications%ROWTYPE;
+    v_order orders%ROWTYPE;
+    v_change JSONB;
+    v_need RECORD;
+    v_stock INT;
+BEGIN
+    SELECT * INTO v_mod FROM order_modifications WHERE id = p_modification_id FOR UPDATE;
+    IF NOT FOUND OR v_mod.status NOT IN ('pending', 'awaiting_payment') THEN
+        RETURN 'not_pending';
+    END IF;
+
+    SELECT * INTO v_order FROM orders WHERE id = v_mod.order_id FOR UPDATE;
+    IF NOT FOUND
+       OR v_order.status NOT IN ('paid', 'processing')
+       OR v_order.total <> v_mod.previous_total THEN
+        UPDATE order_modifications
+        SET status = 'failed', failure_reason = 'order_changed', updated_at = NOW()
+        WHERE id = p_modification_id;
+        RETURN 'order_changed';
+    END IF;
+
+    -- 先に明細を確認（行ロック）
+    FOR v_change IN SELECT * FROM jsonb_array_elements(v_mod.item_changes)
+    LOOP
+        PERFORM 1 FROM order_items
+        WHERE order_id = v_mod.order_id
+          AND product_id = (v_change->>'product_id')::uuid
+          AND variant_id = (v_change->>'from_variant_id')::uuid
+          AND quantity = (v_change->>'quantity')::int
+        FOR UPDATE;
+        IF NOT FOUND THEN
+            UPDATE order_modifications
+            SET status = 'failed', failure_reason = 'order_changed', updated_at = NOW()
+            WHERE id = p_modification_id;
+            RETURN 'order_changed';
+        END IF;
+    END LOOP;
+
+    -- 交換先の在庫を確認（同じ交換先への交換は数量を合算する。行ロック）
+    FOR v_need IN
+        SELECT (c->>'product_id')::uuid AS product_id,
+               (c->>'to_variant_id')::uuid AS variant_id,
+               SUM((c->>'quantity')::int) AS quantity
+        FROM jsonb_array_elements(v_mod.item_changes) c
+        GROUP BY 1, 2
+    LOOP
+        SELECT stock INTO v_stock FROM product_variants
+        WHERE id = v_need.variant_id
+          AND product_id = v_need.product_id
+          AND is_active = TRUE
+        FOR UPDATE;
+        IF NOT FOUND OR v_stock < v_need.quantity THEN
+            UPDATE order_modifications
+            SET status = 'failed', failure_reason = 'out_of_stock', updated_at = NOW()
+            WHERE id = p_modification_id;
+            RETURN 'out_of_stock';
+        END IF;
+    END LOOP;
+
+    -- 在庫移動（交換元に戻し、交換先から確保）と明細の更新
+    FOR v_change IN SELECT * FROM jsonb_array_elements(v_mod.item_changes)
+    LOOP
+        UPDATE product_variants
+        SET stock = stock + (v_change->>'quantity')::int, updated_at = NOW()
+        WHERE id = (v_change->>'from_variant_id')::uuid;
+
+        UPDATE product_variants
+        SET stock = stock - (v_change->>'quantity')::int, updated_at = NOW()
+        WHERE id = (v_change->>'to_variant_id')::uuid;
+
+        UPDATE order_items
+        SET variant_id = (v_change->>'to_variant_id')::uuid,
+            size = v_change->>'to_size',
+            price = (v_change->>'price')::bigint,
+            subtotal = (v_change->>'price')::bigint * quantity
+        WHERE order_id = v_mod.order_id
+          AND product_id = (v_change->>'product_id')::uuid
+          AND variant_id = (v_change->>'from_variant_id')::uuid;
+    END LOOP;
+
+    UPDATE orders
+    SET shipping_address = COALESCE(v_mod.shipping_address, shipping_address),
+        subtotal = v_mod.subtotal,
+        shipping_fee = v_mod.shipping_fee,
+        tax = v_mod.tax,
+        total = v_mod.total,
+        updated_at = NOW()
+    WHERE id = v_mod.order_id;
+
+    UPDATE order_modifications
+    SET status = 'applied', applied_at = NOW(), updated_at = NOW()
+    WHERE id = p_modification_id;
+
+    RETURN 'applied';
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION apply_order_modification(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION apply_order_modification(UUID) TO service_role;
+
+COMMENT ON TABLE order_modifications IS '購入者による発送前の注文変更（配送先・サイズ交換）と差額の精算記録';
@@ -0,0 +1,220 @@
+-- 予約販売（在庫入荷前の受注）
+-- 在庫が足りない場合、予約販売の商品は受付上限（preorder_cap）まで予約明細として受注する。
+-- 予約分は在庫を減らさず preorder_allocated で数え、入荷後に release_product_preorders で在庫を引き当てる。
+-- 引当前の予約明細を含む注文は通常の出荷キューに出さない（分割発送を選んだ注文は在庫分のみ先に出荷）
+
+-- 1. 商品・バリアントの予約設定
+ALTER TABLE products ADD COLUMN IF NOT EXISTS preorder_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE products ADD COLUMN IF NOT EXISTS preorder_cap INT NOT NULL DEFAULT 0;
+ALTER TABLE products ADD COLUMN IF NOT EXISTS preorder_allocated INT NOT NULL DEFAULT 0;
+ALTER TABLE products ADD COLUMN IF NOT EXISTS expected_ship_date DATE;
+
+ALTER TABLE products DROP CONSTRAINT IF EXISTS products_preorder_cap_check;
+ALTER TABLE products ADD CONSTRAINT products_preorder_cap_check
+    CHECK (preorder_cap >= 0 AND preorder_allocated >= 0);
+
+-- バリアント別の上限（NULLは商品の上限のみ）
+ALTER TABLE product_variants ADD COLUMN IF NOT EXISTS preorder_cap INT;
+ALTER TABLE product_variants ADD COLUMN IF NOT EXISTS preorder_allocated INT NOT NULL DEFAULT 0;
+
+-- 2. 注文・明細
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS split_shipment BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE order_items ADD COLUMN IF NOT EXISTS is_preorder BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE order_items ADD COLUMN IF NOT EXISTS expected_ship_date DATE;
+ALTER TABLE order_items ADD COLUMN IF NOT EXISTS preorder_released_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_order_items_preorder_waiting
+ON order_items (product_id, order_created_at)
+WHERE is_preorder = TRUE AND preorder_released_at IS NULL;
+
+-- 3. 予約枠の確保/解放
+-- p_items: [{product_id, variant_id?, quantity}]
+CREATE OR REPLACE FUNCTION reserve_preorder_bulk(p_items JSONB)
+RETURNS BOOLEAN AS $$
+DECLARE
+    item JSONB;
+    pid UUID;
+    vid UUID;
+    qty INT;
+    v_product products%ROWTYPE;
+    v_variant product_variants%ROWTYPE;
+BEGIN
+    IF p_items IS NULL OR jsonb_typeof(p_items) <> 'array' THEN
+        RETURN FALSE;
+    END IF;
+
+    FOR item IN SELECT * FROM jsonb_array_elements(p_items)
+    LOOP
+        pid := (item->>'product_id')::uuid;
+        vid := NULLIF(item->>'variant_id', '')::uuid;
+        qty := (item->>'quantity')::int;
+        IF qty IS NULL OR qty <= 0 THEN
+            RETURN FALSE;
+        END IF;
+
+        SELECT * INTO v_product FROM products WHERE id = pid FOR UPDATE;
+        IF NOT FOUND
+           OR NOT v_product.preorder_enabled
+           OR v_product.preorder_allocated + qty > v_product.preorder_cap THEN
+            RETURN FALSE;
+        END IF;
+
+        IF vid IS NOT NULL THEN
+            SELECT * INTO v_variant FROM product_variants WHERE id = vid AND product_id = pid FOR UPDATE;
+            IF NOT FOUND
+               OR (v_variant.preorder_cap IS NOT NULL
+                   AND v_variant.preorder_allocated + qty > v_variant.preorder_cap) THEN
+                RETURN FALSE;
+            END IF;
+        END IF;
+    END LOOP;
+
+    FOR item IN SELECT * FROM jsonb_array_elements(p_items)
+    LOOP
+        pid := (item->>'product_id')::uuid;
+        vid := NULLIF(item->>'variant_id', '')::uuid;
+        qty := (item->>'quantity')::int;
+
+        UPDATE products
+        SET preorder_allocated = preorder_allocated + qty, updated_at = NOW()
+        WHERE id = pid;
+
+        IF vid IS NOT NULL THEN
+            UPDATE product_variants
+            SET preorder_allocated = preorder_allocated + qty, updated_at = NOW()
+            WHERE id = vid;
+        END IF;
+    END LOOP;
+
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+CREATE OR REPLACE FUNCTION release_preorder_bulk(p_items JSONB)
+RETURNS BOOLEAN AS $$
+DECLARE
+    item JSONB;
+    vid UUID;
+    qty INT;
+BEGIN
+    IF p_items IS NULL OR jsonb_typeof(p_items) <> 'array' THEN
+        RETURN FALSE;
+    END IF;
+
+    FOR item IN SELECT * FROM jsonb_array_elements(p_items)
+    LOOP
+        vid := NULLIF(item->>'variant_id', '')::uuid;
+        qty := (item->>'quantity')::int;
+        IF qty IS NULL OR qty <= 0 THEN
+            RETURN FALSE;
+        END IF;
+
+        UPDATE products
+        SET preorder_allocated = GREATEST(preorder_allocated - qty, 0), updated_at = NOW()
+        WHERE id = (item->>'product_id')::uuid;
+
+        IF vid IS NOT NULL THEN
+            UPDATE product_variants
+            SET preorder_allocated = GREATEST(preorder_allocated - qty, 0), updated_at = NOW()
+            WHERE id = vid;
+        END IF;
+    END LOOP;
+
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 4. 入荷した在庫を予約明細に引き当てる（注文の古い順。在庫が足りなくなった時点で止める）
+-- 戻り値: 引き当てた明細数
+CREATE OR REPLACE FUNCTION release_product_preorders(p_product_id UUID)
+RETURNS INTEGER AS $$
+DECLARE
+    v_stock INT;
+    v_line RECORD;
+    v_released INT := 0;
+BEGIN
+    SELECT stock INTO v_stock FROM products WHERE id = p_product_id FOR UPDATE;
+    IF NOT FOUND THEN
+        RETURN 0;
+    END IF;
+
+    FOR v_line IN
+        SELECT oi.id, oi.variant_id, oi.quantity
+        FROM order_items oi
+        JOIN orders o ON o.id = oi.order_id
+        WHERE oi.product_id = p_product_id
+          AND oi.is_preorder = TRUE
+          AND oi.preorder_released_at IS NULL
+          AND o.status NOT IN ('cancelled', 'refunded')
+        ORDER BY oi.order_created_at, oi.id
+        FOR UPDATE OF oi
+    LOOP
+        EXIT WHEN v_stock < v_line.quantity;
+
+        UPDATE order_items SET preorder_released_at = NOW() WHERE id = v_line.id;
+        PERFORM release_preorder_bulk(jsonb_build_array(jsonb_build_object(
+            'product_id', p_product_id,
+            'variant_id', v_line.variant_id,
+            'quantity', v_line.quantity
+        )));
+        v_stock := v_stock - v_line.quantity;
+        v_released := v_released + 1;
+    END LOOP;
+
+    IF v_released > 0 THEN
+        UPDATE products SET stock = v_stock, updated_at = NOW() WHERE id = p_product_id;
+    END IF;
+    RETURN v_released;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- 5. 注文の在庫解放（キャンセル時）: 引当前の予約明細は在庫ではなく予約枠を戻す
+CREATE OR REPLACE FUNCTION release_order_stock(p_order_id UUID) RETURNS BOOLEAN AS $$
+DECLARE
+    v_items JSONB;
+    v_preorders JSONB;
+BEGIN
+    UPDATE orders SET stock_released_at = NOW()
+    WHERE id = p_order_id AND stock_released_at IS NULL;
+    IF NOT FOUND THEN
+        RETURN FALSE;
+    END IF;
+
+    SELECT COALESCE(jsonb_agg(jsonb_build_object('product_id', oi.product_id, 'quantity', oi.quantity)), '[]'::jsonb)
+    INTO v_items
+    FROM order_items oi
+    WHERE oi.order_id = p_order_id AND oi.product_id IS NOT NULL
+      AND (oi.is_preorder = FALSE OR oi.preorder_released_at IS NOT NULL);
+
+    SELECT COALESCE(jsonb_agg(jsonb_build_object(
+        'product_id', oi.product_id, 'variant_id', oi.variant_id, 'quantity', oi.quantity
+    )), '[]'::jsonb)
+    INTO v_preorders
+    FROM order_items oi
+    WHERE oi.order_id = p_order_id AND oi.product_id IS NOT NULL
+      AND oi.is_preorder = TRUE AND oi.preorder_released_at IS NULL;
+
+    IF jsonb_array_length(v_items) > 0 THEN
+        PERFORM release_stock_bulk(v_items);
+    END IF;
+    IF jsonb_array_length(v_preorders) > 0 THEN
+        PERFORM release_preorder_bulk(v_preorders);
+    END IF;
+    RETURN TRUE;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION reserve_preorder_bulk(JSONB) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION reserve_preorder_bulk(JSONB) TO service_role;
+REVOKE ALL ON FUNCTION release_preorder_bulk(JSONB) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION release_preorder_bulk(JSONB) TO service_role;
+REVOKE ALL ON FUNCTION release_product_preorders(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION release_product_preorders(UUID) TO service_role;
+REVOKE ALL ON FUNCTION release_order_stock(UUID) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION release_order_stock(UUID) TO service_role;
+
+COMMENT ON COLUMN products.preorder_cap IS '予約の受付上限（数量）';
+COMMENT ON COLUMN products.preorder_allocated IS '受付済みで在庫未引当の予約数量';
+COMMENT ON COLUMN order_items.preorder_released_at IS '予約明細に在庫を引き当てた日時（NULLの間は出荷しない）';
+COMMENT ON COLUMN orders.split_shipment IS '予約明細と在庫明細を分けて発送する';
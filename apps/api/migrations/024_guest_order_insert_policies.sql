@@ -0,0 +1,33 @@
+-- ゲスト注文の作成を anon ロールで行うためのINSERTポリシー（親テーブル）
+-- 006 はパーティションにのみゲスト用ポリシーを追加したが、親テーブル経由のINSERTでは
+-- 親テーブルのポリシーのみが評価されるため、DATABASE_BACKEND=postgres の注文確定（anon）が通らなかった。
+-- 明細は決済待ちのゲスト注文にのみ追加できる（anon は orders を参照できないため SECURITY DEFINER で確認）
+
+CREATE OR REPLACE FUNCTION is_pending_guest_order(p_order_id UUID)
+RETURNS BOOLEAN AS $$
+    SELECT EXISTS (
+        SELECT 1 FROM orders
+        WHERE id = p_order_id
+          AND user_id IS NULL
+          AND is_guest_order = TRUE
+          AND status = 'pending_payment'
+    );
+$$ LANGUAGE sql STABLE SECURITY DEFINER SET search_path = public;
+
+REVOKE ALL ON FUNCTION is_pending_guest_order(UUID) FROM PUBLIC;
+GRANT EXECUTE ON FUNCTION is_pending_guest_order(UUID) TO anon, authenticated, service_role;
+
+DROP POLICY IF EXISTS guest_orders_insert_policy ON orders;
+CREATE POLICY guest_orders_insert_policy ON orders
+    FOR INSERT TO anon
+    WITH CHECK (
+        auth.uid() IS NULL
+        AND user_id IS NULL
+        AND is_guest_order = TRUE
+        AND status = 'pending_payment'
+    );
+
+DROP POLICY IF EXISTS guest_order_items_insert_policy ON order_items;
+CREATE POLICY guest_order_items_insert_policy ON order_items
+    FOR INSERT TO anon
+    WITH CHECK (auth.uid() IS NULL AND is_pending_guest_order(order_id));
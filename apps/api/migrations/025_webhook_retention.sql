@@ -0,0 +1,8 @@
+-- 外部連携Webhookの配信ログ・ドメインイベントの保持
+-- 配信先のレスポンスボディは機微情報を含み得るため保存しない（ステータスコードとエラーのみ記録する）
+ALTER TABLE webhook_deliveries DROP COLUMN IF EXISTS response_body;
+
+-- 保持期間を過ぎた完了済みの配信ログを削除するためのインデックス
+-- （domain_events は idx_domain_events_status_created を使う）
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_status_created
+ON webhook_deliveries (status, created_at);
@@ -0,0 +1,20 @@
+-- 再入荷・値下げ通知のダブルオプトイン
+-- ゲストの購読はメールの確認リンクを開くまで pending_confirmation のまま通知しない。
+-- 購読解除は全ての通知メールに載せる署名付きリンク（宛先単位）で行うため、購読ごとの解除トークンは廃止する
+
+ALTER TABLE product_alerts ADD COLUMN IF NOT EXISTS confirmed_at TIMESTAMPTZ;
+ALTER TABLE product_alerts DROP COLUMN IF EXISTS unsubscribe_token_hash;
+
+COMMENT ON COLUMN product_alerts.status IS
+    'pending_confirmation（確認待ち） / active（待機中） / queued（通知待ち） / sending / sent（通知済み=購読終了） / cancelled / failed';
+
+-- 確認待ちも重複登録の判定対象にする（確認メールの連続送信を防ぐ）
+DROP INDEX IF EXISTS uq_product_alerts_open;
+CREATE UNIQUE INDEX IF NOT EXISTS uq_product_alerts_open
+ON product_alerts (email, kind, product_id, variant_id) NULLS NOT DISTINCT
+WHERE status IN ('pending_confirmation', 'active', 'queued', 'sending');
+
+-- 期限切れの確認待ちの取り消し用
+CREATE INDEX IF NOT EXISTS idx_product_alerts_pending_confirmation
+ON product_alerts (created_at)
+WHERE status = 'pending_confirmation';
@@ -0,0 +1,92 @@
+-- 退会処理の再開対応
+-- トークン無効化後に匿名化やSupabase Authユーザー削除が失敗しても、
+-- 本人は再リクエストできないため、進捗を記録して定期ジョブで再試行する
+
+-- 1. 進捗の記録
+-- deletion_requested_at: 退会受付日時（トークン無効化前に記録）
+-- deleted_at: 個人データ匿名化の完了日時（009で追加済み）
+-- auth_user_deleted_at: Supabase Authユーザー削除の完了日時
+ALTER TABLE users
+ADD COLUMN IF NOT EXISTS deletion_requested_at TIMESTAMPTZ DEFAULT NULL,
+ADD COLUMN IF NOT EXISTS auth_user_deleted_at TIMESTAMPTZ DEFAULT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_users_pending_deletion
+ON users (deletion_requested_at)
+WHERE deletion_requested_at IS NOT NULL AND auth_user_deleted_at IS NULL;
+
+-- 2. 匿名化RPCにお問い合わせ履歴を追加
+-- 対応記録（種別・ステータス・管理メモ）は残し、送信者情報と本文を消去する
+CREATE OR REPLACE FUNCTION anonymize_user_account(
+    p_user_id UUID
+) RETURNS TABLE(
+    anonymized_orders INTEGER,
+    deleted_addresses INTEGER,
+    deleted_carts INTEGER
+) AS $$
+DECLARE
+    v_orders INTEGER := 0;
+    v_addresses INTEGER := 0;
+    v_carts INTEGER := 0;
+BEGIN
+    -- 注文: 配送先は都道府県・国のみ残し、氏名・住所・電話番号を消去
+    UPDATE orders
+    SET shipping_address = jsonb_build_object(
+            'name', '退会済みユーザー',
+            'prefecture', shipping_address->>'prefecture',
+            'country', shipping_address->>'country'
+        ),
+        billing_address = NULL,
+        notes = NULL,
+        guest_email = NULL,
+        guest_name = NULL,
+        guest_phone = NULL,
+        guest_access_token_hash = NULL,
+        guest_token_expires_at = NULL,
+        updated_at = NOW()
+    WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_orders = ROW_COUNT;
+
+    -- 住所
+    DELETE FROM addresses WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_addresses = ROW_COUNT;
+
+    -- カート（cart_itemsはON DELETE CASCADE）
+    DELETE FROM cart_metadata WHERE user_id = p_user_id;
+    GET DIAGNOSTICS v_carts = ROW_COUNT;
+
+    -- レビュー: 表示名のみ匿名化（商品評価は残す）
+    UPDATE reviews
+    SET user_name = '退会済みユーザー'
+    WHERE user_id = p_user_id;
+
+    -- お問い合わせ: 送信者情報・本文・接続元を消去
+    UPDATE contact_submissions
+    SET name = '退会済みユーザー',
+        email = 'deleted+' || p_user_id::text || '@deleted.invalid',
+        message = '',
+        ip_address = NULL,
+        user_agent = NULL
+    WHERE user_id = p_user_id;
+
+    -- ユーザー: 個人情報を消去し無効化（再実行時は匿名化日時を維持）
+    UPDATE users
+    SET email = 'deleted+' || p_user_id::text || '@deleted.invalid',
+        password_hash = '',
+        name = '退会済みユーザー',
+        phone = NULL,
+        is_active = FALSE,
+        deletion_requested_at = COALESCE(deletion_requested_at, NOW()),
+        deleted_at = COALESCE(deleted_at, NOW()),
+        updated_at = NOW()
+    WHERE id = p_user_id;
+
+    RETURN QUERY SELECT v_orders, v_addresses, v_carts;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION anonymize_user_account(UUID) FROM PUBLIC;
+REVOKE ALL ON FUNCTION anonymize_user_account(UUID) FROM anon, authenticated;
+GRANT EXECUTE ON FUNCTION anonymize_user_account(UUID) TO service_role;
+
+COMMENT ON COLUMN users.deletion_requested_at IS '退会受付日時（NULL=退会未申請）';
+COMMENT ON COLUMN users.auth_user_deleted_at IS 'Supabase Authユーザー削除の完了日時（NULL=未完了、定期ジョブで再試行）';
@@ -0,0 +1,40 @@
+-- ソーシャルログインの状態をブラウザに紐付ける（ログインCSRF対策）
+-- 認可開始時にHttpOnly Cookieへ発行した値のハッシュを保存し、
+-- コールバックでは同じCookieを持つブラウザからのみ状態を消費できるようにする
+
+-- 1. 紐付け値のハッシュ列
+-- 既存の状態は紐付けを持たないため破棄する（有効期限は10分のため影響は進行中のログインのみ）
+DELETE FROM oauth_states;
+
+ALTER TABLE oauth_states
+ADD COLUMN IF NOT EXISTS browser_binding_hash TEXT NOT NULL;
+
+-- 2. 状態消費RPC（紐付けが一致する場合のみ消費し、作成日時も返す）
+DROP FUNCTION IF EXISTS consume_oauth_state(UUID);
+
+CREATE OR REPLACE FUNCTION consume_oauth_state(
+    p_id UUID,
+    p_browser_binding_hash TEXT
+) RETURNS TABLE(
+    provider VARCHAR(20),
+    code_verifier TEXT,
+    redirect_to TEXT,
+    user_id UUID,
+    created_at TIMESTAMPTZ
+) AS $$
+BEGIN
+    DELETE FROM oauth_states WHERE expires_at < NOW() - INTERVAL '1 hour';
+
+    RETURN QUERY
+    DELETE FROM oauth_states s
+    WHERE s.id = p_id
+      AND s.browser_binding_hash = p_browser_binding_hash
+      AND s.expires_at > NOW()
+    RETURNING s.provider, s.code_verifier, s.redirect_to, s.user_id, s.created_at;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+REVOKE ALL ON FUNCTION consume_oauth_state(UUID, TEXT) FROM PUBLIC, anon, authenticated;
+GRANT EXECUTE ON FUNCTION consume_oauth_state(UUID, TEXT) TO service_role;
+
+COMMENT ON COLUMN oauth_states.browser_binding_hash IS '認可を開始したブラウザのCookie値のSHA-256（hex）';
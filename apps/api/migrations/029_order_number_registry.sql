@@ -0,0 +1,53 @@
+-- 注文番号の一意性（baseline 8 で 000 を適用済みとして記録した既存環境向け）
+-- orders はパーティションテーブルのため order_number 単独の一意制約を張れず、登録表（order_numbers）で保証する。
+-- 000 と同じ定義を作成し、既存の注文番号を登録する（新規環境では作成済みのため既存の登録を維持する）
+
+-- 1. 登録表
+CREATE TABLE IF NOT EXISTS order_numbers (
+    order_number TEXT PRIMARY KEY,
+    order_id UUID NOT NULL
+);
+
+ALTER TABLE order_numbers ENABLE ROW LEVEL SECURITY;
+
+-- 2. 注文作成時に登録するトリガー（重複時は order_numbers_pkey の一意制約違反になる）
+CREATE OR REPLACE FUNCTION register_order_number()
+RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO order_numbers (order_number, order_id) VALUES (NEW.order_number, NEW.id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER SET search_path = public;
+
+DROP TRIGGER IF EXISTS trg_orders_register_number ON orders;
+CREATE TRIGGER trg_orders_register_number
+    BEFORE INSERT ON orders
+    FOR EACH ROW EXECUTE FUNCTION register_order_number();
+
+-- 3. 既存の注文番号を登録
+-- 登録表がない間に重複した注文番号は自動で解消できないため、一覧を出して失敗させる
+DO $$
+DECLARE
+    v_duplicates TEXT;
+BEGIN
+    SELECT string_agg(order_number, ', ' ORDER BY order_number) INTO v_duplicates
+    FROM (
+        SELECT o.order_number
+        FROM orders o
+        LEFT JOIN order_numbers n ON n.order_number = o.order_number
+        GROUP BY o.order_number
+        HAVING COUNT(DISTINCT o.id) > 1
+            OR bool_or(n.order_id IS NOT NULL AND n.order_id <> o.id)
+    ) d;
+
+    IF v_duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Duplicate order numbers must be resolved before migration: %', v_duplicates;
+    END IF;
+END $$;
+
+INSERT INTO order_numbers (order_number, order_id)
+SELECT o.order_number, o.id
+FROM orders o
+WHERE NOT EXISTS (SELECT 1 FROM order_numbers n WHERE n.order_number = o.order_number);
+
+COMMENT ON TABLE order_numbers IS '注文番号の登録表（パーティションテーブル orders の order_number 一意性を保証）';
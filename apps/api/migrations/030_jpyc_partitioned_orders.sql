@@ -0,0 +1,16 @@
+-- JPYC決済をパーティション化された orders に合わせる
+-- 008_jpyc_payment は orders の一意インデックスと外部キーを前提にしているため、
+-- baseline 8 で記録した既存環境と新規環境（000 で作成）の定義をここで揃える
+
+-- 1. orders.crypto_tx_hash は検索用の通常インデックスにする
+-- （パーティションテーブルでは単独列の一意インデックスを張れない。二重使用は jpyc_payment_events.tx_hash の一意制約で防ぐ）
+DROP INDEX IF EXISTS idx_orders_crypto_tx_hash;
+CREATE INDEX IF NOT EXISTS idx_orders_crypto_tx_hash
+ON orders (crypto_tx_hash)
+WHERE crypto_tx_hash IS NOT NULL;
+
+-- 2. jpyc_payment_events.order_id の外部キーを外す（orders の主キーは (id, created_at)）
+ALTER TABLE jpyc_payment_events
+DROP CONSTRAINT IF EXISTS jpyc_payment_events_order_id_fkey;
+
+COMMENT ON COLUMN jpyc_payment_events.order_id IS '対象の注文ID（パーティションテーブルのため外部キーなし）';
@@ -1,121 +1,675 @@
-use serde::Deserialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use crate::db::repositories::CheckoutStore;
 use crate::db::SupabaseClient;
-use anyhow::{anyhow, bail, Context};
+use anyhow::bail;
+
+mod secret;
+mod sources;
+
+pub use secret::Secret;
 
 /// アプリケーション設定
-#[derive(Debug, Clone, Deserialize)]
+/// 起動時に環境変数（+ 任意のTOMLファイル）から読み込み、全体を検証してから使う
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
+    /// production / staging / development / local
+    pub environment: String,
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub jwt: JwtConfig,
     pub cors: CorsConfig,
+    pub session: SessionConfig,
+    pub rate_limit: RateLimitConfig,
+    pub stripe: StripeConfig,
+    pub jpyc: JpycPaymentConfig,
+    pub reconciler: ReconcilerConfig,
+    pub outbox: OutboxConfig,
+    pub webhooks: WebhookDispatchConfig,
+    pub product_alerts: ProductAlertConfig,
+    pub cart_recovery: CartRecoveryConfig,
+    pub guest_order: GuestOrderConfig,
+    pub email: EmailConfig,
+    pub captcha: CaptchaConfig,
+    pub passkey: PasskeyConfig,
+    pub oauth: OAuthConfig,
+    pub metrics: MetricsConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub port: u16,
     pub host: String,
+    /// リクエストボディの上限（メモリ枯渇対策）
+    pub request_body_limit_bytes: usize,
+    /// リクエストタイムアウト（SlowLoris対策）
+    pub request_timeout_seconds: u64,
+    /// エラーレスポンスに詳細を含める（API_DEBUG_ERRORS）
+    pub debug_errors: bool,
+    /// 認証まわりのデバッグログ（API_DEBUG_AUTH、トークン自体は出さない）
+    pub debug_auth: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 3001,
+            host: "0.0.0.0".to_string(),
+            request_body_limit_bytes: 1024 * 1024,
+            request_timeout_seconds: 30,
+            debug_errors: false,
+            debug_auth: false,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct DatabaseConfig {
     pub url: String,
     pub anon_key: String,
+    pub service_role_key: Secret,
+    /// トランザクションが必要な処理のバックエンド
+    pub backend: DatabaseBackend,
+    /// Postgres直接接続URL（backend=postgres のとき必須）
+    pub postgres_url: Option<Secret>,
+    pub postgres_max_connections: u32,
+    pub http_connect_timeout_seconds: u64,
+    pub http_timeout_seconds: u64,
+    pub max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    pub circuit_failure_threshold: u32,
+    pub circuit_open_seconds: u64,
+    /// Supabase呼び出しの認証モードをログ出力（SUPABASE_DEBUG_AUTH）
+    pub debug_auth: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            anon_key: String::new(),
+            service_role_key: Secret::default(),
+            backend: DatabaseBackend::Rest,
+            postgres_url: None,
+            postgres_max_connections: 10,
+            http_connect_timeout_seconds: 10,
+            http_timeout_seconds: 15,
+            max_retries: 2,
+            retry_base_delay_ms: 100,
+            circuit_failure_threshold: 5,
+            circuit_open_seconds: 30,
+            debug_auth: false,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// データベースバックエンド
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    /// Supabase REST（PostgREST）経由
+    #[default]
+    Rest,
+    /// Postgres直接接続（トランザクション使用）
+    Postgres,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct JwtConfig {
-    pub secret: String,
+    pub secret: Secret,
+    /// ローカル開発でのみ弱いJWT_SECRETを許可する
+    pub allow_weak_secret: bool,
+    /// SUPABASE_URL から導出
+    #[serde(skip_deserializing)]
     pub issuer: String,            // Supabase project URL
+    #[serde(skip_deserializing)]
     pub audience: String,          // "authenticated"
     pub access_token_expiry: i64,  // 秒
     pub refresh_token_expiry: i64, // 秒
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            secret: Secret::default(),
+            allow_weak_secret: false,
+            issuer: String::new(),
+            audience: "authenticated".to_string(),
+            access_token_expiry: 3600,
+            refresh_token_expiry: 2592000, // 30 days
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct CorsConfig {
     pub allowed_origins: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// カートセッションIDの署名鍵（未設定時はJWT_SECRETを使う）
+    pub secret: Option<Secret>,
+    /// BFF（Next.js）からのリクエストであることを示すトークン（本番必須）
+    pub bff_proxy_token: Secret,
+}
+
+/// レート制限（グループの既定値は middleware::rate_limiter 側）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// memory | postgres
+    pub backend: String,
+    /// X-Forwarded-For を信頼するプロキシ（未設定時はローカルのみ）
+    pub trusted_proxy_ips: Vec<String>,
+    pub global: RateLimitGroupConfig,
+    pub payment: RateLimitGroupConfig,
+    pub contact: RateLimitGroupConfig,
+    pub guest_order: RateLimitGroupConfig,
+    pub passkey_login: RateLimitGroupConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            backend: "memory".to_string(),
+            trusted_proxy_ips: Vec::new(),
+            global: RateLimitGroupConfig::default(),
+            payment: RateLimitGroupConfig::default(),
+            contact: RateLimitGroupConfig::default(),
+            guest_order: RateLimitGroupConfig::default(),
+            passkey_login: RateLimitGroupConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitGroupConfig {
+    pub window_seconds: Option<u64>,
+    pub max_requests: Option<u32>,
+    /// 未設定時は rate_limit.backend
+    pub backend: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StripeConfig {
+    pub secret_key: Option<Secret>,
+    /// 互換用（単一secret）
+    pub webhook_secret: Option<Secret>,
+    /// ローテーション用（複数指定）
+    pub webhook_secrets: Vec<Secret>,
+    /// 署名タイムスタンプの許容範囲（既定: 本番120秒、それ以外300秒。本番は120秒が上限）
+    pub webhook_tolerance_seconds: Option<i64>,
+}
+
+/// JPYC決済（チェーン設定の既定値は services::payment::jpyc 側）
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JpycPaymentConfig {
+    pub recipient_address: Option<String>,
+    pub test_mode: bool,
+    pub chain_id: Option<i32>,
+    pub contract_address: Option<String>,
+    pub required_confirmations: Option<u64>,
+    pub rpc_url: Option<String>,
+}
+
+/// 決済状態の回収タスク
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReconcilerConfig {
+    pub interval_seconds: i64,
+    pub min_age_seconds: i64,
+    pub batch_size: i32,
+    /// PaymentIntent未作成の注文を自動キャンセルするまでの時間
+    pub payment_intent_max_age_seconds: i64,
+}
+
+impl Default for ReconcilerConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 60,
+            min_age_seconds: 30,
+            batch_size: 50,
+            payment_intent_max_age_seconds: 1800,
+        }
+    }
+}
+
+/// ドメインイベントのディスパッチャー
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutboxConfig {
+    pub poll_interval_ms: u64,
+    pub batch_size: i32,
+    pub max_attempts: i32,
+    /// 在庫僅少アラートの送信先（未設定時は送らない）
+    pub stock_alert_email: Option<String>,
+    /// 配信済み・デッドレターのイベントを保持する日数
+    pub retention_days: i64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 2000,
+            batch_size: 50,
+            max_attempts: 8,
+            stock_alert_email: None,
+            retention_days: 30,
+        }
+    }
+}
+
+/// 外部連携Webhookの配信タスク
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebhookDispatchConfig {
+    pub poll_interval_ms: u64,
+    pub batch_size: i32,
+    pub max_attempts: i32,
+    pub timeout_seconds: u64,
+    /// 完了（成功・dead）した配信ログを保持する日数
+    pub retention_days: i64,
+}
+
+impl Default for WebhookDispatchConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 2000,
+            batch_size: 20,
+            max_attempts: 10,
+            timeout_seconds: 10,
+            retention_days: 30,
+        }
+    }
+}
+
+/// 再入荷・値下げ通知の送信タスク
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProductAlertConfig {
+    pub interval_seconds: u64,
+    /// 1回に処理する宛先数
+    pub batch_size: i32,
+    /// 同じ宛先へ続けて通知するまでの最小間隔（まとめて1通で送る）
+    pub recipient_interval_seconds: i32,
+    pub max_attempts: i32,
+    /// 1つの宛先が同時に購読できる上限
+    pub max_subscriptions_per_email: usize,
+    /// 登録確認ページのURL（`?token=...` を付けて送る。未設定時はゲストの購読を受け付けない）
+    pub confirm_link_base_url: String,
+    /// 購読解除ページのURL（`?token=...` を付けて全ての通知メールに載せる。未設定時は購読・送信しない）
+    pub unsubscribe_link_base_url: String,
+    /// 登録確認リンクの有効期限（過ぎた確認待ちは取り消す）
+    pub confirmation_ttl_seconds: i64,
+}
+
+impl Default for ProductAlertConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 60,
+            batch_size: 50,
+            recipient_interval_seconds: 3600,
+            max_attempts: 5,
+            max_subscriptions_per_email: 50,
+            confirm_link_base_url: String::new(),
+            unsubscribe_link_base_url: String::new(),
+            confirmation_ttl_seconds: 48 * 3600,
+        }
+    }
+}
+
+/// カゴ落ち（放置カート）の回復リンク送信
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CartRecoveryConfig {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+    /// 最終更新からこの時間が経ったカートを放置とみなす
+    pub idle_seconds: i32,
+    /// これより古い放置カートには送らない
+    pub max_age_seconds: i32,
+    pub batch_size: i32,
+    /// 回復リンクの有効期限
+    pub link_ttl_seconds: i64,
+    /// 回復ページのURL（`?token=...` を付けて送る。例: https://spirom.com/cart/recover）
+    pub link_base_url: String,
+}
+
+impl Default for CartRecoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: 600,
+            idle_seconds: 4 * 3600,
+            max_age_seconds: 7 * 24 * 3600,
+            batch_size: 50,
+            link_ttl_seconds: 7 * 24 * 3600,
+            link_base_url: String::new(),
+        }
+    }
+}
+
+/// ゲスト注文の照会（注文番号 + メールアドレス/電話番号で再アクセスリンクを送る）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GuestOrderConfig {
+    /// 再アクセスリンクの有効期限（1回限り）
+    pub access_link_ttl_seconds: i64,
+    /// 再アクセスページのURL（`?token=...` を付けて送る。未設定時は照会を受け付けてもメールを送らない）
+    pub access_link_base_url: String,
+}
+
+impl Default for GuestOrderConfig {
+    fn default() -> Self {
+        Self {
+            access_link_ttl_seconds: 1800,
+            access_link_base_url: String::new(),
+        }
+    }
+}
+
+/// トランザクションメール（Resend互換API。api_key 未設定時は送信しない）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EmailConfig {
+    pub api_key: Option<Secret>,
+    pub api_url: String,
+    /// 送信元（例: Spirom <noreply@spirom.com>）
+    pub from: String,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            api_url: "https://api.resend.com/emails".to_string(),
+            from: "Spirom <noreply@spirom.com>".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CaptchaConfig {
+    /// Cloudflare Turnstile（未設定時はCAPTCHA検証を行わない）
+    pub turnstile_secret_key: Option<Secret>,
+}
+
+/// パスキー（WebAuthn）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasskeyConfig {
+    /// 未設定時は cors.allowed_origins
+    pub origins: Vec<String>,
+    /// 未設定時は最初のオリジンのホスト名
+    pub rp_id: Option<String>,
+    pub rp_name: String,
+}
+
+impl Default for PasskeyConfig {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            rp_id: None,
+            rp_name: "Spirom".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OAuthConfig {
+    /// LINEはSupabase標準プロバイダにないため、カスタムOIDCプロバイダ名を指定する
+    pub line_provider: String,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            line_provider: "custom:line".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// 未設定時は /metrics を無効（404）にする
+    pub token: Option<Secret>,
+}
+
+/// 起動時に読み込んだ設定（AppState を持たないエラー変換から参照）
+static GLOBAL: OnceLock<Arc<Config>> = OnceLock::new();
+
+/// 設定を参照（init 前は None。既定値で代用しない）
+pub fn get() -> Option<&'static Config> {
+    GLOBAL.get().map(Arc::as_ref)
+}
+
 impl Config {
-    pub fn from_env() -> anyhow::Result<Self> {
-        let jwt_secret = std::env::var("JWT_SECRET")
-            .context("JWT_SECRET が設定されていません（例: `openssl rand -hex 32` で生成）")?;
-        validate_jwt_secret(&jwt_secret)?;
-
-        let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-        let is_dev = env == "development" || env == "local";
-        validate_stripe_env(&env)?;
-        validate_supabase_service_role_key()?;
-
-        Ok(Self {
-            server: ServerConfig {
-                port: std::env::var("PORT")
-                    .unwrap_or_else(|_| "3001".to_string())
-                    .parse()
-                    .map_err(|e| anyhow!("PORT must be a number: {}", e))?,
-                host: std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
-            },
-            database: DatabaseConfig {
-                url: std::env::var("SUPABASE_URL").context("SUPABASE_URL must be set")?,
-                anon_key: std::env::var("SUPABASE_ANON_KEY").context("SUPABASE_ANON_KEY must be set")?,
-            },
-            jwt: JwtConfig {
-                secret: jwt_secret,
-                // issuerはSupabaseプロジェクトURL（末尾の/auth/v1を付与）
-                issuer: format!(
-                    "{}/auth/v1",
-                    std::env::var("SUPABASE_URL")
-                        .context("SUPABASE_URL must be set")?
-                        .trim_end_matches('/')
-                ),
-                audience: "authenticated".to_string(),
-                access_token_expiry: std::env::var("JWT_ACCESS_EXPIRY")
-                    .unwrap_or_else(|_| "3600".to_string())
-                    .parse()
-                    .unwrap_or(3600),
-                refresh_token_expiry: std::env::var("JWT_REFRESH_EXPIRY")
-                    .unwrap_or_else(|_| "2592000".to_string())
-                    .parse()
-                    .unwrap_or(2592000), // 30 days
-            },
-            cors: CorsConfig {
-                allowed_origins: std::env::var("CORS_ORIGINS")
-                    .ok()
-                    .or_else(|| {
-                        if is_dev {
-                            Some("http://localhost:3000".to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .ok_or_else(|| anyhow!("CORS_ORIGINS must be set in production"))?
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect(),
-            },
-        })
-    }
-}
-
-fn validate_stripe_env(environment: &str) -> anyhow::Result<()> {
-    // Stripeは決済系エンドポイントで必須。productionではテストキー混入を防ぐ。
-    let is_prod = environment == "production";
+    /// TOMLファイル（省略時は CONFIG_FILE）と環境変数から読み込み、全体を検証する
+    pub fn load(file: Option<&Path>) -> anyhow::Result<Self> {
+        let env: std::collections::HashMap<String, String> = std::env::vars().collect();
+        let file = file.map(Path::to_path_buf).or_else(|| {
+            env.get("CONFIG_FILE")
+                .filter(|s| !s.trim().is_empty())
+                .map(std::path::PathBuf::from)
+        });
+
+        let mut config = sources::build(file.as_deref(), &env)?;
+        config.resolve();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 他の値から導出する項目を埋める
+    fn resolve(&mut self) {
+        if self.environment.trim().is_empty() {
+            self.environment = "production".to_string();
+        }
+        // issuerはSupabaseプロジェクトURL（末尾の/auth/v1を付与）
+        self.jwt.issuer = format!("{}/auth/v1", self.database.url.trim_end_matches('/'));
+        if self.jwt.audience.is_empty() {
+            self.jwt.audience = "authenticated".to_string();
+        }
+        if self.cors.allowed_origins.is_empty() && self.is_development() {
+            self.cors.allowed_origins = vec!["http://localhost:3000".to_string()];
+        }
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.environment == "production"
+    }
 
-    if let Ok(sk) = std::env::var("STRIPE_SECRET_KEY") {
-        if is_prod && sk.starts_with("sk_test_") {
+    /// development / local（テスト用エンドポイント等を許可する環境）
+    pub fn is_development(&self) -> bool {
+        self.environment == "development" || self.environment == "local"
+    }
+
+    /// カートセッションの署名鍵（SESSION_SECRET → JWT_SECRET）
+    pub fn session_secret(&self) -> &str {
+        self.session
+            .secret
+            .as_ref()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(&self.jwt.secret)
+            .expose()
+    }
+
+    /// 全体を検証し、問題をまとめて報告する
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors: Vec<String> = Vec::new();
+        let is_prod = self.is_production();
+
+        if self.database.url.trim().is_empty() {
+            errors.push("SUPABASE_URL (database.url) must be set".to_string());
+        }
+        if self.database.anon_key.trim().is_empty() {
+            errors.push("SUPABASE_ANON_KEY (database.anon_key) must be set".to_string());
+        }
+        if self.database.service_role_key.is_empty() {
+            errors.push(
+                "SUPABASE_SERVICE_ROLE_KEY が設定されていません。\
+                 Supabase Dashboard → Settings → API → service_role (secret) から取得してください。"
+                    .to_string(),
+            );
+        }
+        if self.database.backend == DatabaseBackend::Postgres
+            && self.database.postgres_url.as_ref().is_none_or(|u| u.is_empty())
+        {
+            errors.push("DATABASE_BACKEND=postgres の場合は DATABASE_URL が必須です。".to_string());
+        }
+
+        if let Err(e) = validate_jwt_secret(&self.jwt, self.is_development()) {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = validate_stripe(&self.stripe, is_prod) {
+            errors.push(e.to_string());
+        }
+
+        if self.cors.allowed_origins.is_empty() {
+            errors.push("CORS_ORIGINS must be set in production".to_string());
+        }
+        if let Some(origin) = self
+            .cors
+            .allowed_origins
+            .iter()
+            .find(|o| o.parse::<axum::http::HeaderValue>().is_err())
+        {
+            errors.push(format!("CORS_ORIGINS contains an invalid origin: {}", origin));
+        }
+        if self.server.host.parse::<std::net::IpAddr>().is_err() {
+            errors.push(format!("HOST must be an IP address (got `{}`)", self.server.host));
+        }
+        if self.server.request_body_limit_bytes == 0 {
+            errors.push("REQUEST_BODY_LIMIT_BYTES must be greater than 0".to_string());
+        }
+        if self.server.request_timeout_seconds == 0 {
+            errors.push("REQUEST_TIMEOUT_SECONDS must be greater than 0".to_string());
+        }
+        if !matches!(self.rate_limit.backend.as_str(), "memory" | "postgres") {
+            errors.push(format!(
+                "RATE_LIMIT_BACKEND must be `memory` or `postgres` (got `{}`)",
+                self.rate_limit.backend
+            ));
+        }
+        if is_prod && self.session.bff_proxy_token.is_empty() {
+            errors.push("本番環境ではBFF_PROXY_TOKENが必須です。".to_string());
+        }
+        if !(1..=200).contains(&self.reconciler.batch_size) {
+            errors.push("PAYMENT_RECONCILE_BATCH_SIZE must be between 1 and 200".to_string());
+        }
+        if !(1..=500).contains(&self.outbox.batch_size) {
+            errors.push("OUTBOX_BATCH_SIZE must be between 1 and 500".to_string());
+        }
+        if !(1..=200).contains(&self.webhooks.batch_size) {
+            errors.push("WEBHOOK_BATCH_SIZE must be between 1 and 200".to_string());
+        }
+        if !(1..=60).contains(&self.webhooks.timeout_seconds) {
+            errors.push("WEBHOOK_TIMEOUT_SECONDS must be between 1 and 60".to_string());
+        }
+        if self.outbox.retention_days < 1 {
+            errors.push("OUTBOX_RETENTION_DAYS must be at least 1".to_string());
+        }
+        if self.webhooks.retention_days < 1 {
+            errors.push("WEBHOOK_RETENTION_DAYS must be at least 1".to_string());
+        }
+        if !(1..=500).contains(&self.product_alerts.batch_size) {
+            errors.push("PRODUCT_ALERT_BATCH_SIZE must be between 1 and 500".to_string());
+        }
+        if self.product_alerts.recipient_interval_seconds < 0 {
+            errors.push("PRODUCT_ALERT_RECIPIENT_INTERVAL_SECONDS must not be negative".to_string());
+        }
+        for (name, url) in [
+            ("PRODUCT_ALERT_CONFIRM_LINK_BASE_URL", &self.product_alerts.confirm_link_base_url),
+            ("PRODUCT_ALERT_UNSUBSCRIBE_LINK_BASE_URL", &self.product_alerts.unsubscribe_link_base_url),
+        ] {
+            let allowed_scheme = url.starts_with("https://") || (!is_prod && url.starts_with("http://"));
+            if !url.is_empty() && !allowed_scheme {
+                errors.push(format!("{} must be an https URL", name));
+            }
+        }
+        if !(300..=7 * 86400).contains(&self.product_alerts.confirmation_ttl_seconds) {
+            errors.push("PRODUCT_ALERT_CONFIRMATION_TTL_SECONDS must be between 300 and 604800".to_string());
+        }
+        if self.cart_recovery.enabled {
+            let recovery = &self.cart_recovery;
+            let allowed_scheme = recovery.link_base_url.starts_with("https://")
+                || (!is_prod && recovery.link_base_url.starts_with("http://"));
+            if !allowed_scheme {
+                errors.push("CART_RECOVERY_LINK_BASE_URL must be an https URL when CART_RECOVERY_ENABLED=true".to_string());
+            }
+            if recovery.idle_seconds <= 0 || recovery.max_age_seconds <= recovery.idle_seconds {
+                errors.push("CART_RECOVERY_MAX_AGE_SECONDS must be greater than CART_RECOVERY_IDLE_SECONDS (> 0)".to_string());
+            }
+            if !(1..=500).contains(&recovery.batch_size) {
+                errors.push("CART_RECOVERY_BATCH_SIZE must be between 1 and 500".to_string());
+            }
+            if recovery.link_ttl_seconds <= 0 {
+                errors.push("CART_RECOVERY_LINK_TTL_SECONDS must be greater than 0".to_string());
+            }
+        }
+        if !self.guest_order.access_link_base_url.is_empty() {
+            let url = &self.guest_order.access_link_base_url;
+            let allowed_scheme = url.starts_with("https://") || (!is_prod && url.starts_with("http://"));
+            if !allowed_scheme {
+                errors.push("GUEST_ORDER_ACCESS_LINK_BASE_URL must be an https URL".to_string());
+            }
+        }
+        if !(60..=86400).contains(&self.guest_order.access_link_ttl_seconds) {
+            errors.push("GUEST_ORDER_ACCESS_LINK_TTL_SECONDS must be between 60 and 86400".to_string());
+        }
+        if self.metrics.token.as_ref().is_some_and(|t| t.expose().trim().len() < 16) {
+            errors.push("METRICS_TOKEN must be at least 16 characters".to_string());
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        bail!(
+            "設定に{}件の問題があります:\n{}",
+            errors.len(),
+            errors.iter().map(|e| format!("  - {}", e)).collect::<Vec<_>>().join("\n")
+        )
+    }
+
+    /// 秘密値を伏せたTOML（`--print-config`）
+    pub fn to_redacted_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// 起動時に一度だけ設定を登録する
+pub fn init(config: Arc<Config>) {
+    if GLOBAL.set(config).is_err() {
+        tracing::warn!("Configuration was already initialized");
+    }
+}
+
+fn validate_stripe(stripe: &StripeConfig, is_prod: bool) -> anyhow::Result<()> {
+    // Stripeは決済系エンドポイントで必須。productionではテストキー混入を防ぐ。
+    match stripe.secret_key.as_ref().filter(|k| !k.is_empty()) {
+        Some(sk) if is_prod && sk.expose().starts_with("sk_test_") => {
             bail!("本番環境でSTRIPE_SECRET_KEYにsk_test_が設定されています。sk_live_を設定してください。");
         }
-    } else if is_prod {
-        bail!("本番環境ではSTRIPE_SECRET_KEYが必須です。");
+        None if is_prod => bail!("本番環境ではSTRIPE_SECRET_KEYが必須です。"),
+        _ => {}
     }
 
-    // Webhook secretはローテーション対応（CSV）
-    let wh_single = std::env::var("STRIPE_WEBHOOK_SECRET").ok().unwrap_or_default();
-    let wh_multi = std::env::var("STRIPE_WEBHOOK_SECRETS").ok().unwrap_or_default();
-    let has_any = !wh_single.trim().is_empty() || !wh_multi.trim().is_empty();
+    // Webhook secretはローテーション対応（複数指定）
+    let has_any = stripe.webhook_secret.as_ref().is_some_and(|s| !s.is_empty())
+        || stripe.webhook_secrets.iter().any(|s| !s.is_empty());
     if is_prod && !has_any {
         bail!("本番環境ではSTRIPE_WEBHOOK_SECRET または STRIPE_WEBHOOK_SECRETS が必須です。");
     }
@@ -123,31 +677,17 @@ fn validate_stripe_env(environment: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn validate_supabase_service_role_key() -> anyhow::Result<()> {
-    let key = std::env::var("SUPABASE_SERVICE_ROLE_KEY").unwrap_or_default();
-    if key.trim().is_empty() {
-        bail!(
-            "SUPABASE_SERVICE_ROLE_KEY が設定されていません。\n\
-             Supabase Dashboard → Settings → API → service_role (secret) から取得してください。"
-        );
+fn validate_jwt_secret(jwt: &JwtConfig, is_dev: bool) -> anyhow::Result<()> {
+    let s = jwt.secret.expose().trim();
+    if s.is_empty() {
+        bail!("JWT_SECRET が設定されていません（例: `openssl rand -hex 32` で生成）");
     }
-    Ok(())
-}
 
-fn validate_jwt_secret(secret: &str) -> anyhow::Result<()> {
     // ローカル開発でどうしても弱い値を使う場合は明示的に許可する（デフォルトは拒否）
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-    let is_dev = env == "development" || env == "local";
-    let allow_weak = std::env::var("ALLOW_WEAK_JWT_SECRET")
-        .ok()
-        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-        .unwrap_or(false);
-
-    if is_dev && allow_weak {
+    if is_dev && jwt.allow_weak_secret {
         return Ok(());
     }
 
-    let s = secret.trim();
     let lower = s.to_ascii_lowercase();
 
     // よくあるダメ値・推測可能値・短すぎる値は拒否する
@@ -158,7 +698,7 @@ fn validate_jwt_secret(secret: &str) -> anyhow::Result<()> {
         || lower.contains("jwt-secret-spirom")
         || lower.contains("spirom-2024");
 
-    if s.is_empty() || too_short || looks_default {
+    if too_short || looks_default {
         bail!(
             "JWT_SECRET が弱すぎます。32文字以上のランダムな値に変更してください（例: `openssl rand -hex 32`）。"
         );
@@ -172,13 +712,87 @@ fn validate_jwt_secret(secret: &str) -> anyhow::Result<()> {
 pub struct AppState {
     pub config: Arc<Config>,
     pub db: Arc<SupabaseClient>,
+    /// 注文確定（在庫確保 → 注文作成 → カートクリア）
+    pub checkout: Arc<dyn CheckoutStore>,
 }
 
 impl AppState {
-    pub fn new(config: Config, db: SupabaseClient) -> Self {
-        Self {
-            config: Arc::new(config),
-            db: Arc::new(db),
+    pub fn new(config: Arc<Config>, db: Arc<SupabaseClient>, checkout: Arc<dyn CheckoutStore>) -> Self {
+        Self { config, db, checkout }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn valid_env() -> HashMap<String, String> {
+        env(&[
+            ("ENVIRONMENT", "production"),
+            ("SUPABASE_URL", "https://example.supabase.co/"),
+            ("SUPABASE_ANON_KEY", "anon"),
+            ("SUPABASE_SERVICE_ROLE_KEY", "service-role-secret"),
+            ("JWT_SECRET", "0123456789abcdef0123456789abcdef"),
+            ("CORS_ORIGINS", "https://spirom.com, https://www.spirom.com"),
+            ("BFF_PROXY_TOKEN", "bff-token"),
+            ("STRIPE_SECRET_KEY", "sk_live_abc"),
+            ("STRIPE_WEBHOOK_SECRETS", "whsec_a,whsec_b"),
+            ("PAYMENT_RECONCILE_BATCH_SIZE", "25"),
+        ])
+    }
+
+    fn load(env: &HashMap<String, String>) -> anyhow::Result<Config> {
+        let mut config = sources::build(None, env)?;
+        config.resolve();
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_load_from_env() {
+        let config = load(&valid_env()).unwrap();
+        assert_eq!(config.jwt.issuer, "https://example.supabase.co/auth/v1");
+        assert_eq!(config.cors.allowed_origins, vec!["https://spirom.com", "https://www.spirom.com"]);
+        assert_eq!(config.stripe.webhook_secrets.len(), 2);
+        assert_eq!(config.reconciler.batch_size, 25);
+        assert_eq!(config.server.port, 3001);
+        assert_eq!(config.session_secret(), "0123456789abcdef0123456789abcdef");
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let mut env = valid_env();
+        env.remove("JWT_SECRET");
+        env.remove("STRIPE_SECRET_KEY");
+        env.insert("WEBHOOK_BATCH_SIZE".to_string(), "0".to_string());
+
+        let message = load(&env).unwrap_err().to_string();
+        assert!(message.contains("3件"), "{}", message);
+        assert!(message.contains("JWT_SECRET"));
+        assert!(message.contains("STRIPE_SECRET_KEY"));
+        assert!(message.contains("WEBHOOK_BATCH_SIZE"));
+    }
+
+    #[test]
+    fn test_type_error_names_env_var() {
+        let mut env = valid_env();
+        env.insert("PORT".to_string(), "http".to_string());
+        let message = load(&env).unwrap_err().to_string();
+        assert!(message.contains("PORT"), "{}", message);
+    }
+
+    #[test]
+    fn test_redacted_output_hides_secrets() {
+        let output = load(&valid_env()).unwrap().to_redacted_toml().unwrap();
+        assert!(output.contains(secret::REDACTED));
+        for secret in ["service-role-secret", "0123456789abcdef", "bff-token", "sk_live_abc", "whsec_a"] {
+            assert!(!output.contains(secret), "{} leaked", secret);
         }
+        assert!(output.contains("https://example.supabase.co/"));
     }
 }
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize, Serializer};
+
+/// 秘密値（ログ・`--print-config` では伏せ字で出力する）
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+/// 伏せ字の表記
+pub const REDACTED: &str = "[REDACTED]";
+
+impl Secret {
+    /// 実際の値（外部APIへの送信・署名にのみ使う）
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if self.is_empty() { "" } else { REDACTED })
+    }
+}
@@ -0,0 +1,199 @@
+//! 設定の読み込み元
+//! 優先順位: 既定値 < TOMLファイル（`--config` / CONFIG_FILE） < 環境変数
+//! 環境変数名は従来どおり（下表で設定ツリーのキーに対応付ける）
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use config::{File, FileFormat};
+
+use super::Config;
+
+/// 環境変数の値の形式
+#[derive(Clone, Copy)]
+enum Kind {
+    Value,
+    /// カンマ区切りのリスト
+    List,
+}
+
+/// 環境変数 → 設定キー
+const ENV_KEYS: &[(&str, &str, Kind)] = &[
+    ("ENVIRONMENT", "environment", Kind::Value),
+    // server
+    ("HOST", "server.host", Kind::Value),
+    ("PORT", "server.port", Kind::Value),
+    ("REQUEST_BODY_LIMIT_BYTES", "server.request_body_limit_bytes", Kind::Value),
+    ("REQUEST_TIMEOUT_SECONDS", "server.request_timeout_seconds", Kind::Value),
+    ("API_DEBUG_ERRORS", "server.debug_errors", Kind::Value),
+    ("API_DEBUG_AUTH", "server.debug_auth", Kind::Value),
+    // database
+    ("SUPABASE_URL", "database.url", Kind::Value),
+    ("SUPABASE_ANON_KEY", "database.anon_key", Kind::Value),
+    ("SUPABASE_SERVICE_ROLE_KEY", "database.service_role_key", Kind::Value),
+    ("DATABASE_BACKEND", "database.backend", Kind::Value),
+    ("DATABASE_URL", "database.postgres_url", Kind::Value),
+    ("DATABASE_MAX_CONNECTIONS", "database.postgres_max_connections", Kind::Value),
+    ("SUPABASE_HTTP_CONNECT_TIMEOUT_SECONDS", "database.http_connect_timeout_seconds", Kind::Value),
+    ("SUPABASE_HTTP_TIMEOUT_SECONDS", "database.http_timeout_seconds", Kind::Value),
+    ("SUPABASE_MAX_RETRIES", "database.max_retries", Kind::Value),
+    ("SUPABASE_RETRY_BASE_DELAY_MS", "database.retry_base_delay_ms", Kind::Value),
+    ("SUPABASE_CIRCUIT_FAILURE_THRESHOLD", "database.circuit_failure_threshold", Kind::Value),
+    ("SUPABASE_CIRCUIT_OPEN_SECONDS", "database.circuit_open_seconds", Kind::Value),
+    ("SUPABASE_DEBUG_AUTH", "database.debug_auth", Kind::Value),
+    // jwt
+    ("JWT_SECRET", "jwt.secret", Kind::Value),
+    ("ALLOW_WEAK_JWT_SECRET", "jwt.allow_weak_secret", Kind::Value),
+    ("JWT_ACCESS_EXPIRY", "jwt.access_token_expiry", Kind::Value),
+    ("JWT_REFRESH_EXPIRY", "jwt.refresh_token_expiry", Kind::Value),
+    // cors
+    ("CORS_ORIGINS", "cors.allowed_origins", Kind::List),
+    // session
+    ("SESSION_SECRET", "session.secret", Kind::Value),
+    ("BFF_PROXY_TOKEN", "session.bff_proxy_token", Kind::Value),
+    // rate_limit
+    ("RATE_LIMIT_BACKEND", "rate_limit.backend", Kind::Value),
+    ("TRUSTED_PROXY_IPS", "rate_limit.trusted_proxy_ips", Kind::List),
+    ("RATE_LIMIT_WINDOW_SECONDS", "rate_limit.global.window_seconds", Kind::Value),
+    ("RATE_LIMIT_MAX_REQUESTS", "rate_limit.global.max_requests", Kind::Value),
+    ("PAYMENT_RATE_LIMIT_WINDOW_SECONDS", "rate_limit.payment.window_seconds", Kind::Value),
+    ("PAYMENT_RATE_LIMIT_MAX_REQUESTS", "rate_limit.payment.max_requests", Kind::Value),
+    ("PAYMENT_RATE_LIMIT_BACKEND", "rate_limit.payment.backend", Kind::Value),
+    ("CONTACT_RATE_LIMIT_WINDOW_SECONDS", "rate_limit.contact.window_seconds", Kind::Value),
+    ("CONTACT_RATE_LIMIT_MAX_REQUESTS", "rate_limit.contact.max_requests", Kind::Value),
+    ("CONTACT_RATE_LIMIT_BACKEND", "rate_limit.contact.backend", Kind::Value),
+    ("GUEST_ORDER_RATE_LIMIT_WINDOW_SECONDS", "rate_limit.guest_order.window_seconds", Kind::Value),
+    ("GUEST_ORDER_RATE_LIMIT_MAX_REQUESTS", "rate_limit.guest_order.max_requests", Kind::Value),
+    ("GUEST_ORDER_RATE_LIMIT_BACKEND", "rate_limit.guest_order.backend", Kind::Value),
+    ("PASSKEY_LOGIN_RATE_LIMIT_WINDOW_SECONDS", "rate_limit.passkey_login.window_seconds", Kind::Value),
+    ("PASSKEY_LOGIN_RATE_LIMIT_MAX_REQUESTS", "rate_limit.passkey_login.max_requests", Kind::Value),
+    ("PASSKEY_LOGIN_RATE_LIMIT_BACKEND", "rate_limit.passkey_login.backend", Kind::Value),
+    // stripe
+    ("STRIPE_SECRET_KEY", "stripe.secret_key", Kind::Value),
+    ("STRIPE_WEBHOOK_SECRET", "stripe.webhook_secret", Kind::Value),
+    ("STRIPE_WEBHOOK_SECRETS", "stripe.webhook_secrets", Kind::List),
+    ("STRIPE_WEBHOOK_TOLERANCE_SECONDS", "stripe.webhook_tolerance_seconds", Kind::Value),
+    // jpyc
+    ("JPYC_RECIPIENT_ADDRESS", "jpyc.recipient_address", Kind::Value),
+    ("JPYC_TEST_MODE", "jpyc.test_mode", Kind::Value),
+    ("JPYC_CHAIN_ID", "jpyc.chain_id", Kind::Value),
+    ("JPYC_CONTRACT_ADDRESS", "jpyc.contract_address", Kind::Value),
+    ("JPYC_REQUIRED_CONFIRMATIONS", "jpyc.required_confirmations", Kind::Value),
+    ("POLYGON_RPC_URL", "jpyc.rpc_url", Kind::Value),
+    // reconciler
+    ("PAYMENT_RECONCILE_INTERVAL_SECONDS", "reconciler.interval_seconds", Kind::Value),
+    ("PAYMENT_RECONCILE_MIN_AGE_SECONDS", "reconciler.min_age_seconds", Kind::Value),
+    ("PAYMENT_RECONCILE_BATCH_SIZE", "reconciler.batch_size", Kind::Value),
+    ("PAYMENT_INTENT_MAX_AGE_SECONDS", "reconciler.payment_intent_max_age_seconds", Kind::Value),
+    // outbox
+    ("OUTBOX_POLL_INTERVAL_MS", "outbox.poll_interval_ms", Kind::Value),
+    ("OUTBOX_BATCH_SIZE", "outbox.batch_size", Kind::Value),
+    ("OUTBOX_MAX_ATTEMPTS", "outbox.max_attempts", Kind::Value),
+    ("STOCK_ALERT_EMAIL", "outbox.stock_alert_email", Kind::Value),
+    ("OUTBOX_RETENTION_DAYS", "outbox.retention_days", Kind::Value),
+    // webhooks
+    ("WEBHOOK_POLL_INTERVAL_MS", "webhooks.poll_interval_ms", Kind::Value),
+    ("WEBHOOK_BATCH_SIZE", "webhooks.batch_size", Kind::Value),
+    ("WEBHOOK_MAX_ATTEMPTS", "webhooks.max_attempts", Kind::Value),
+    ("WEBHOOK_TIMEOUT_SECONDS", "webhooks.timeout_seconds", Kind::Value),
+    ("WEBHOOK_RETENTION_DAYS", "webhooks.retention_days", Kind::Value),
+    // product_alerts
+    ("PRODUCT_ALERT_INTERVAL_SECONDS", "product_alerts.interval_seconds", Kind::Value),
+    ("PRODUCT_ALERT_BATCH_SIZE", "product_alerts.batch_size", Kind::Value),
+    ("PRODUCT_ALERT_RECIPIENT_INTERVAL_SECONDS", "product_alerts.recipient_interval_seconds", Kind::Value),
+    ("PRODUCT_ALERT_MAX_ATTEMPTS", "product_alerts.max_attempts", Kind::Value),
+    ("PRODUCT_ALERT_MAX_SUBSCRIPTIONS_PER_EMAIL", "product_alerts.max_subscriptions_per_email", Kind::Value),
+    ("PRODUCT_ALERT_CONFIRM_LINK_BASE_URL", "product_alerts.confirm_link_base_url", Kind::Value),
+    ("PRODUCT_ALERT_UNSUBSCRIBE_LINK_BASE_URL", "product_alerts.unsubscribe_link_base_url", Kind::Value),
+    ("PRODUCT_ALERT_CONFIRMATION_TTL_SECONDS", "product_alerts.confirmation_ttl_seconds", Kind::Value),
+    // cart_recovery
+    ("CART_RECOVERY_ENABLED", "cart_recovery.enabled", Kind::Value),
+    ("CART_RECOVERY_INTERVAL_SECONDS", "cart_recovery.interval_seconds", Kind::Value),
+    ("CART_RECOVERY_IDLE_SECONDS", "cart_recovery.idle_seconds", Kind::Value),
+    ("CART_RECOVERY_MAX_AGE_SECONDS", "cart_recovery.max_age_seconds", Kind::Value),
+    ("CART_RECOVERY_BATCH_SIZE", "cart_recovery.batch_size", Kind::Value),
+    ("CART_RECOVERY_LINK_TTL_SECONDS", "cart_recovery.link_ttl_seconds", Kind::Value),
+    ("CART_RECOVERY_LINK_BASE_URL", "cart_recovery.link_base_url", Kind::Value),
+    // guest_order
+    ("GUEST_ORDER_ACCESS_LINK_TTL_SECONDS", "guest_order.access_link_ttl_seconds", Kind::Value),
+    ("GUEST_ORDER_ACCESS_LINK_BASE_URL", "guest_order.access_link_base_url", Kind::Value),
+    // email
+    ("EMAIL_API_KEY", "email.api_key", Kind::Value),
+    ("EMAIL_API_URL", "email.api_url", Kind::Value),
+    ("EMAIL_FROM", "email.from", Kind::Value),
+    // captcha / passkey / oauth / metrics
+    ("TURNSTILE_SECRET_KEY", "captcha.turnstile_secret_key", Kind::Value),
+    ("WEBAUTHN_ORIGINS", "passkey.origins", Kind::List),
+    ("WEBAUTHN_RP_ID", "passkey.rp_id", Kind::Value),
+    ("WEBAUTHN_RP_NAME", "passkey.rp_name", Kind::Value),
+    ("OAUTH_LINE_PROVIDER", "oauth.line_provider", Kind::Value),
+    ("METRICS_TOKEN", "metrics.token", Kind::Value),
+];
+
+/// 既定値・TOMLファイル・環境変数を合成して設定ツリーを作る（検証は呼び出し側）
+pub(super) fn build(file: Option<&Path>, env: &HashMap<String, String>) -> anyhow::Result<Config> {
+    let mut builder = config::Config::builder();
+    if let Some(path) = file {
+        builder = builder.add_source(File::from(path).format(FileFormat::Toml).required(true));
+    }
+
+    for (name, key, kind) in ENV_KEYS {
+        // 空文字は未設定として扱う
+        let Some(value) = env.get(*name).map(|v| v.trim()).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        builder = match kind {
+            Kind::Value => builder.set_override(*key, value),
+            Kind::List => builder.set_override(
+                *key,
+                value
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>(),
+            ),
+        }
+        .with_context(|| format!("{} を設定に反映できません", name))?;
+    }
+
+    let source = match file {
+        Some(path) => format!("{} と環境変数", path.display()),
+        None => "環境変数".to_string(),
+    };
+    builder
+        .build()
+        .and_then(|c| c.try_deserialize::<Config>())
+        .map_err(|e| anyhow!("{}の読み込みに失敗しました: {}", source, describe_error(&e)))
+}
+
+/// 型エラー等のメッセージに対応する環境変数名を添える
+fn describe_error(error: &config::ConfigError) -> String {
+    let message = error.to_string();
+    match ENV_KEYS
+        .iter()
+        .filter(|(_, key, _)| message.contains(&format!("`{}`", key)))
+        .map(|(name, _, _)| *name)
+        .next()
+    {
+        Some(name) => format!("{}（環境変数 {}）", message, name),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_keys_are_unique() {
+        let mut names: Vec<_> = ENV_KEYS.iter().map(|(name, _, _)| *name).collect();
+        let mut keys: Vec<_> = ENV_KEYS.iter().map(|(_, key, _)| *key).collect();
+        names.sort();
+        names.dedup();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(names.len(), ENV_KEYS.len());
+        assert_eq!(keys.len(), ENV_KEYS.len());
+    }
+}
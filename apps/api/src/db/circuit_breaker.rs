@@ -0,0 +1,207 @@
+//! Supabase REST 呼び出し用サーキットブレーカー
+//! 連続失敗が閾値に達すると一定時間リクエストを即時失敗させ、復旧確認（half-open）は1リクエストのみ通す
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// ブレーカー状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// half-open中の試行リクエストが実行中か
+    probe_in_flight: bool,
+    /// 試行リクエストの通し番号（古い試行の許可証が新しい試行を打ち切らないように）
+    probe_id: u64,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            inner: Mutex::new(Inner {
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_in_flight: false,
+                probe_id: 0,
+            }),
+        }
+    }
+
+    fn state_at(&self, inner: &Inner, now: Instant) -> CircuitState {
+        match inner.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if now.duration_since(opened_at) < self.open_duration => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// 現在の状態
+    pub fn state(&self) -> CircuitState {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        self.state_at(&inner, Instant::now())
+    }
+
+    /// リクエストを通してよいか（half-open時は試行を1件だけ許可）
+    /// 許可証はリクエストが終わるまで保持する。試行が結果を記録せずに破棄された場合
+    /// （クライアント切断・タイムアウトによるキャンセル等）は失敗として扱い、再度オープンにする
+    pub fn allow_request(&self) -> Option<CallPermit<'_>> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match self.state_at(&inner, Instant::now()) {
+            CircuitState::Closed => Some(CallPermit { breaker: self, probe_id: None }),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen => {
+                if inner.probe_in_flight {
+                    None
+                } else {
+                    inner.probe_in_flight = true;
+                    inner.probe_id = inner.probe_id.wrapping_add(1);
+                    Some(CallPermit {
+                        breaker: self,
+                        probe_id: Some(inner.probe_id),
+                    })
+                }
+            }
+        }
+    }
+
+    /// 結果を記録しないまま終わった試行を失敗として扱う
+    fn abandon_probe(&self, probe_id: u64) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.probe_in_flight && inner.probe_id == probe_id {
+            tracing::warn!("Supabase circuit breaker probe was cancelled; reopening");
+            inner.opened_at = Some(Instant::now());
+            inner.probe_in_flight = false;
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.opened_at.is_some() {
+            tracing::info!("Supabase circuit breaker closed");
+        }
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.probe_in_flight = false;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        let reopen = match self.state_at(&inner, now) {
+            CircuitState::Closed => inner.consecutive_failures >= self.failure_threshold,
+            // 試行が失敗したら再度オープン
+            CircuitState::HalfOpen => inner.probe_in_flight,
+            CircuitState::Open => false,
+        };
+        if reopen {
+            tracing::warn!(
+                "Supabase circuit breaker opened: consecutive_failures={}",
+                inner.consecutive_failures
+            );
+            inner.opened_at = Some(now);
+            inner.probe_in_flight = false;
+        }
+    }
+}
+
+/// リクエストの実行許可（half-open時の試行はドロップ時に結果が未記録なら失敗扱い）
+#[derive(Debug)]
+pub struct CallPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe_id: Option<u64>,
+}
+
+impl Drop for CallPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(probe_id) = self.probe_id {
+            self.breaker.abandon_probe(probe_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker_transitions() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        assert!(breaker.allow_request().is_some());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.allow_request().is_none());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        let probe = breaker.allow_request();
+        assert!(probe.is_some());
+        // 試行中は他のリクエストを通さない
+        assert!(breaker.allow_request().is_none());
+
+        breaker.record_failure();
+        drop(probe);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        std::thread::sleep(Duration::from_millis(30));
+        let probe = breaker.allow_request();
+        assert!(probe.is_some());
+        breaker.record_success();
+        drop(probe);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow_request().is_some());
+    }
+
+    #[test]
+    fn test_dropped_probe_reopens_breaker() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+
+        // 結果を記録せずに試行がキャンセルされても、ブレーカーが塞がったままにならない
+        let probe = breaker.allow_request();
+        assert!(probe.is_some());
+        drop(probe);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        std::thread::sleep(Duration::from_millis(30));
+        let probe = breaker.allow_request();
+        assert!(probe.is_some());
+        // 古い試行の許可証は新しい試行に影響しない
+        let stale = CallPermit { breaker: &breaker, probe_id: Some(0) };
+        drop(stale);
+        assert!(breaker.allow_request().is_none());
+        breaker.record_success();
+        drop(probe);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
@@ -1,6 +1,11 @@
+pub mod circuit_breaker;
+pub mod migrations;
+pub mod postgres;
+pub mod query;
 pub mod supabase;
-pub mod schema;
 pub mod repositories;
 
+pub use circuit_breaker::CircuitState;
+pub use postgres::PostgresClient;
+pub use query::Query;
 pub use supabase::*;
-pub use schema::*;
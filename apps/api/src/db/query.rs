@@ -0,0 +1,173 @@
+//! PostgRESTクエリビルダー
+//! フィルタ値はすべてエスケープ・URLエンコードされるため、値に `,` `)` `&` 等を含んでも安全
+
+use std::fmt::Display;
+
+use crate::models::SortOrder;
+
+/// PostgRESTクエリ（フィルタ・select・order・limit/offset・Range）
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// エンコード前の (キー, 値)
+    params: Vec<(String, String)>,
+    orders: Vec<String>,
+    range: Option<(u64, u64)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 取得カラム（埋め込みリソース可: `*,categories(id,slug,name)`）
+    pub fn select(mut self, columns: &str) -> Self {
+        self.params.push(("select".to_string(), columns.to_string()));
+        self
+    }
+
+    fn filter(mut self, column: &str, operator: &str, value: impl Display) -> Self {
+        self.params
+            .push((column.to_string(), format!("{}.{}", operator, value)));
+        self
+    }
+
+    /// column = value
+    pub fn eq(self, column: &str, value: impl Display) -> Self {
+        self.filter(column, "eq", value)
+    }
+
+    /// column <> value
+    pub fn neq(self, column: &str, value: impl Display) -> Self {
+        self.filter(column, "neq", value)
+    }
+
+    /// column > value
+    pub fn gt(self, column: &str, value: impl Display) -> Self {
+        self.filter(column, "gt", value)
+    }
+
+    /// column >= value
+    pub fn gte(self, column: &str, value: impl Display) -> Self {
+        self.filter(column, "gte", value)
+    }
+
+    /// column < value
+    pub fn lt(self, column: &str, value: impl Display) -> Self {
+        self.filter(column, "lt", value)
+    }
+
+    /// column IS NULL
+    pub fn where_null(self, column: &str) -> Self {
+        self.filter(column, "is", "null")
+    }
+
+    /// column IN (values)
+    /// 各値はダブルクォートで囲み、`"` と `\` をエスケープする
+    pub fn in_list<I, V>(self, column: &str, values: I) -> Self
+    where
+        I: IntoIterator<Item = V>,
+        V: Display,
+    {
+        let list = values
+            .into_iter()
+            .map(|v| quote_list_value(&v.to_string()))
+            .collect::<Vec<_>>()
+            .join(",");
+        self.filter(column, "in", format!("({})", list))
+    }
+
+    /// 並び順（複数回呼ぶと第2キー以降になる）
+    pub fn order(mut self, column: &str, order: SortOrder) -> Self {
+        let direction = match order {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        self.orders.push(format!("{}.{}", column, direction));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.params.push(("limit".to_string(), limit.to_string()));
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.params.push(("offset".to_string(), offset.to_string()));
+        self
+    }
+
+    /// 取得範囲（Rangeヘッダー、両端を含む）
+    pub fn range(mut self, from: u64, to: u64) -> Self {
+        self.range = Some((from, to.max(from)));
+        self
+    }
+
+    /// UPSERT時の競合カラム
+    pub fn on_conflict(mut self, columns: &str) -> Self {
+        self.params
+            .push(("on_conflict".to_string(), columns.to_string()));
+        self
+    }
+
+    /// Rangeヘッダー値（例: `0-19`）
+    pub(crate) fn range_header(&self) -> Option<String> {
+        self.range.map(|(from, to)| format!("{}-{}", from, to))
+    }
+
+    /// URLクエリ文字列（エンコード済み）
+    pub fn to_query_string(&self) -> String {
+        let mut parts: Vec<String> = self
+            .params
+            .iter()
+            .map(|(key, value)| {
+                format!("{}={}", urlencoding::encode(key), urlencoding::encode(value))
+            })
+            .collect();
+        if !self.orders.is_empty() {
+            parts.push(format!("order={}", urlencoding::encode(&self.orders.join(","))));
+        }
+        parts.join("&")
+    }
+}
+
+/// in.(...) リスト内の値をクォート
+fn quote_list_value(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Content-Range ヘッダー（例: `0-19/125`, `*/0`）から総件数を取得
+pub(crate) fn parse_content_range_total(value: &str) -> Option<i64> {
+    value.rsplit('/').next().and_then(|total| total.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_string_escaping() {
+        let query = Query::new()
+            .eq("email", "a+b@example.com")
+            .in_list("id", ["a,b", "c)\"d"])
+            .select("*,categories(id,slug,name)")
+            .order("is_default", SortOrder::Desc)
+            .order("created_at", SortOrder::Desc)
+            .limit(10);
+
+        assert_eq!(
+            query.to_query_string(),
+            "email=eq.a%2Bb%40example.com\
+             &id=in.%28%22a%2Cb%22%2C%22c%29%5C%22d%22%29\
+             &select=%2A%2Ccategories%28id%2Cslug%2Cname%29\
+             &limit=10\
+             &order=is_default.desc%2Ccreated_at.desc"
+        );
+    }
+
+    #[test]
+    fn test_parse_content_range_total() {
+        assert_eq!(parse_content_range_total("0-19/125"), Some(125));
+        assert_eq!(parse_content_range_total("*/0"), Some(0));
+        assert_eq!(parse_content_range_total("0-19/*"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::{AppError, Result};
+use crate::models::{AbandonedCart, CartItem, CartRecovery, CartRecoveryCounts, CartRecoveryStatus};
+
+/// カゴ落ち回復リポジトリ（service_role必須）
+pub struct CartRecoveryRepository {
+    client: AuthenticatedClient,
+}
+
+/// 回復リンク作成の入力
+#[derive(Debug, Serialize)]
+pub struct NewCartRecovery {
+    pub session_id: String,
+    pub user_id: Uuid,
+    pub email: String,
+    pub cart_updated_at: DateTime<Utc>,
+    pub items: Vec<CartItem>,
+    pub item_count: i32,
+    pub subtotal: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct RecoveryUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<CartRecoveryStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sent_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovered_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovered_session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    converted_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    converted_order_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    converted_total: Option<i64>,
+}
+
+impl CartRecoveryRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// 回復リンク未送信の放置カート
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::find_abandoned")]
+    pub async fn find_abandoned(
+        &self,
+        idle_seconds: i32,
+        max_age_seconds: i32,
+        limit: i32,
+    ) -> Result<Vec<AbandonedCart>> {
+        #[derive(Serialize)]
+        struct Params {
+            p_idle_seconds: i32,
+            p_max_age_seconds: i32,
+            p_limit: i32,
+        }
+
+        self.client
+            .rpc(
+                "find_abandoned_carts",
+                &Params {
+                    p_idle_seconds: idle_seconds,
+                    p_max_age_seconds: max_age_seconds,
+                    p_limit: limit,
+                },
+            )
+            .await
+    }
+
+    /// 回復リンク作成（同じ放置状態で作成済みならNone。複数インスタンスでの二重送信防止を兼ねる）
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::create")]
+    pub async fn create(&self, input: &NewCartRecovery) -> Result<Option<CartRecovery>> {
+        match self.client.insert("cart_recoveries", input).await {
+            Ok(recovery) => Ok(Some(recovery)),
+            Err(AppError::UniqueViolation(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::find_by_id")]
+    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<CartRecovery>> {
+        let query = Query::new().eq("id", id);
+        self.client.select_single("cart_recoveries", &query).await
+    }
+
+    /// 送信済みにする
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::mark_sent")]
+    pub async fn mark_sent(&self, id: Uuid) -> Result<()> {
+        let update = RecoveryUpdate {
+            status: Some(CartRecoveryStatus::Sent),
+            sent_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        self.update(Query::new().eq("id", id), &update).await.map(|_| ())
+    }
+
+    /// 送信失敗を記録（再送はしない）
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::mark_failed")]
+    pub async fn mark_failed(&self, id: Uuid, error: &str) -> Result<()> {
+        let update = RecoveryUpdate {
+            status: Some(CartRecoveryStatus::Failed),
+            last_error: Some(error.to_string()),
+            ..Default::default()
+        };
+        self.update(Query::new().eq("id", id), &update).await.map(|_| ())
+    }
+
+    /// 復元済みにする（複数回復元した場合は最新のセッションを記録）
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::mark_recovered")]
+    pub async fn mark_recovered(&self, id: Uuid, session_id: &str) -> Result<()> {
+        let query = Query::new()
+            .eq("id", id)
+            .in_list("status", [CartRecoveryStatus::Sent, CartRecoveryStatus::Recovered]);
+        let update = RecoveryUpdate {
+            status: Some(CartRecoveryStatus::Recovered),
+            recovered_at: Some(Utc::now()),
+            recovered_session_id: Some(session_id.to_string()),
+            ..Default::default()
+        };
+        self.update(query, &update).await.map(|_| ())
+    }
+
+    /// 復元したセッションで注文が作成されたらコンバージョンとして記録（記録した件数）
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::mark_converted")]
+    pub async fn mark_converted(&self, session_id: &str, order_id: Uuid, total: i64) -> Result<usize> {
+        let query = Query::new()
+            .eq("recovered_session_id", session_id)
+            .eq("status", CartRecoveryStatus::Recovered);
+        let update = RecoveryUpdate {
+            status: Some(CartRecoveryStatus::Converted),
+            converted_at: Some(Utc::now()),
+            converted_order_id: Some(order_id),
+            converted_total: Some(total),
+            ..Default::default()
+        };
+        self.update(query, &update).await
+    }
+
+    /// 期間内の集計
+    #[tracing::instrument(skip_all, name = "CartRecoveryRepository::stats")]
+    pub async fn stats(&self, since: DateTime<Utc>) -> Result<CartRecoveryCounts> {
+        #[derive(Serialize)]
+        struct Params {
+            p_since: DateTime<Utc>,
+        }
+
+        let rows: Vec<CartRecoveryCounts> = self
+            .client
+            .rpc("cart_recovery_stats", &Params { p_since: since })
+            .await?;
+        Ok(rows.into_iter().next().unwrap_or_default())
+    }
+
+    async fn update(&self, query: Query, update: &RecoveryUpdate) -> Result<usize> {
+        let rows: Vec<serde_json::Value> = self
+            .client
+            .update("cart_recoveries", &query.select("id"), update)
+            .await?;
+        Ok(rows.len())
+    }
+}
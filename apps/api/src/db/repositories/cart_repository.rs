@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::Result;
 use crate::models::{Cart, CartItem};
 
@@ -16,18 +16,19 @@ impl CartRepository {
     }
 
     /// カート取得
+    #[tracing::instrument(skip_all, name = "CartRepository::find_by_session")]
     pub async fn find_by_session(&self, session_id: &str) -> Result<Cart> {
         let mut cart = Cart::new(session_id.to_string());
 
         // カートアイテム取得
-        let query = format!("session_id=eq.{}", urlencoding::encode(session_id));
+        let query = Query::new().eq("session_id", session_id);
         let items: Vec<CartItemRow> = self.client.select("cart_items", &query).await?;
 
         cart.items = items.into_iter().map(|r| r.into_cart_item()).collect();
         cart.calculate_totals();
 
         // メタデータ取得
-        let meta_query = format!("session_id=eq.{}", urlencoding::encode(session_id));
+        let meta_query = Query::new().eq("session_id", session_id);
         let meta: Option<CartMetaRow> = self.client.select_single("cart_metadata", &meta_query).await?;
 
         if let Some(meta) = meta {
@@ -40,6 +41,7 @@ impl CartRepository {
     }
 
     /// カートにアイテム追加/更新
+    #[tracing::instrument(skip_all, name = "CartRepository::add_item")]
     pub async fn add_item(&self, session_id: &str, item: &CartItem) -> Result<()> {
         // メタデータが存在しない場合は作成
         self.ensure_metadata(session_id, None).await?;
@@ -67,12 +69,11 @@ impl CartRepository {
     }
 
     /// アイテム数量更新
+    #[tracing::instrument(skip_all, name = "CartRepository::update_quantity")]
     pub async fn update_quantity(&self, session_id: &str, product_id: Uuid, quantity: i32) -> Result<()> {
-        let query = format!(
-            "session_id=eq.{}&product_id=eq.{}",
-            urlencoding::encode(session_id),
-            product_id
-        );
+        let query = Query::new()
+            .eq("session_id", session_id)
+            .eq("product_id", product_id);
         let update = QuantityUpdate { quantity };
 
         let _: Vec<CartItemRow> = self.client.update("cart_items", &query, &update).await?;
@@ -82,21 +83,57 @@ impl CartRepository {
     }
 
     /// アイテム削除
+    #[tracing::instrument(skip_all, name = "CartRepository::remove_item")]
     pub async fn remove_item(&self, session_id: &str, product_id: Uuid) -> Result<()> {
-        let query = format!(
-            "session_id=eq.{}&product_id=eq.{}",
-            urlencoding::encode(session_id),
-            product_id
-        );
+        let query = Query::new()
+            .eq("session_id", session_id)
+            .eq("product_id", product_id);
         self.client.delete("cart_items", &query).await?;
         self.update_metadata_timestamp(session_id).await?;
 
         Ok(())
     }
 
+    /// 再検証結果の反映（価格・商品情報・数量を上書き。ユーザー操作ではないため更新日時は変えない）
+    #[tracing::instrument(skip_all, name = "CartRepository::update_item_snapshot")]
+    pub async fn update_item_snapshot(&self, session_id: &str, item: &CartItem) -> Result<()> {
+        let query = Self::line_query(session_id, item.product_id, item.variant_id);
+        let update = SnapshotUpdate {
+            product_name: item.product_name.clone(),
+            product_slug: item.product_slug.clone(),
+            price: item.price,
+            quantity: item.quantity,
+            image_url: item.image_url.clone(),
+        };
+
+        let _: Vec<CartItemRow> = self.client.update("cart_items", &query, &update).await?;
+
+        Ok(())
+    }
+
+    /// 再検証で販売不可となったアイテムを削除（バリアント単位）
+    #[tracing::instrument(skip_all, name = "CartRepository::remove_line")]
+    pub async fn remove_line(&self, session_id: &str, product_id: Uuid, variant_id: Option<Uuid>) -> Result<()> {
+        let query = Self::line_query(session_id, product_id, variant_id);
+        self.client.delete("cart_items", &query).await?;
+
+        Ok(())
+    }
+
+    fn line_query(session_id: &str, product_id: Uuid, variant_id: Option<Uuid>) -> Query {
+        let query = Query::new()
+            .eq("session_id", session_id)
+            .eq("product_id", product_id);
+        match variant_id {
+            Some(variant_id) => query.eq("variant_id", variant_id),
+            None => query.where_null("variant_id"),
+        }
+    }
+
     /// カートクリア
+    #[tracing::instrument(skip_all, name = "CartRepository::clear")]
     pub async fn clear(&self, session_id: &str) -> Result<()> {
-        let query = format!("session_id=eq.{}", urlencoding::encode(session_id));
+        let query = Query::new().eq("session_id", session_id);
         self.client.delete("cart_items", &query).await?;
         self.client.delete("cart_metadata", &query).await?;
 
@@ -104,6 +141,7 @@ impl CartRepository {
     }
 
     /// カート統合（ゲスト → ログインユーザー）
+    #[tracing::instrument(skip_all, name = "CartRepository::merge")]
     pub async fn merge(&self, guest_session_id: &str, user_session_id: &str, user_id: Uuid) -> Result<()> {
         // ゲストのカートを取得
         let guest_cart = self.find_by_session(guest_session_id).await?;
@@ -123,6 +161,7 @@ impl CartRepository {
     }
 
     /// メタデータが存在しない場合は作成
+    #[tracing::instrument(skip_all, name = "CartRepository::ensure_metadata")]
     async fn ensure_metadata(&self, session_id: &str, user_id: Option<Uuid>) -> Result<()> {
         let now = Utc::now();
 
@@ -140,8 +179,9 @@ impl CartRepository {
     }
 
     /// メタデータのタイムスタンプ更新
+    #[tracing::instrument(skip_all, name = "CartRepository::update_metadata_timestamp")]
     async fn update_metadata_timestamp(&self, session_id: &str) -> Result<()> {
-        let query = format!("session_id=eq.{}", urlencoding::encode(session_id));
+        let query = Query::new().eq("session_id", session_id);
         let update = TimestampUpdate {
             updated_at: Utc::now(),
         };
@@ -152,8 +192,9 @@ impl CartRepository {
     }
 
     /// ユーザーID更新
+    #[tracing::instrument(skip_all, name = "CartRepository::update_user_id")]
     async fn update_user_id(&self, session_id: &str, user_id: Uuid) -> Result<()> {
-        let query = format!("session_id=eq.{}", urlencoding::encode(session_id));
+        let query = Query::new().eq("session_id", session_id);
         let update = UserIdUpdate {
             user_id: Some(user_id),
         };
@@ -187,6 +228,15 @@ struct QuantityUpdate {
     quantity: i32,
 }
 
+#[derive(Debug, Serialize)]
+struct SnapshotUpdate {
+    product_name: String,
+    product_slug: String,
+    price: i64,
+    quantity: i32,
+    image_url: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 struct CartMetaInput {
     session_id: String,
@@ -2,9 +2,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::Result;
-use crate::models::Category;
+use crate::models::{Category, SortOrder};
 
 pub struct CategoryRepository {
     client: AuthenticatedClient,
@@ -16,6 +16,7 @@ impl CategoryRepository {
     }
 
     /// カテゴリ作成
+    #[tracing::instrument(skip_all, name = "CategoryRepository::create")]
     pub async fn create(&self, category: &Category) -> Result<Category> {
         let input = CategoryInput {
             id: category.id,
@@ -36,36 +37,41 @@ impl CategoryRepository {
     }
 
     /// IDでカテゴリ取得
+    #[tracing::instrument(skip_all, name = "CategoryRepository::find_by_id")]
     pub async fn find_by_id(&self, id: Uuid) -> Result<Option<Category>> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let result: Option<CategoryRow> = self.client.select_single("categories", &query).await?;
         Ok(result.map(|r| r.into_category()))
     }
 
     /// スラッグでカテゴリ取得
+    #[tracing::instrument(skip_all, name = "CategoryRepository::find_by_slug")]
     pub async fn find_by_slug(&self, slug: &str) -> Result<Option<Category>> {
-        let query = format!("slug=eq.{}", urlencoding::encode(slug));
+        let query = Query::new().eq("slug", slug);
         let result: Option<CategoryRow> = self.client.select_single("categories", &query).await?;
         Ok(result.map(|r| r.into_category()))
     }
 
     /// 全カテゴリ取得
+    #[tracing::instrument(skip_all, name = "CategoryRepository::find_all")]
     pub async fn find_all(&self) -> Result<Vec<Category>> {
-        let query = "order=sort_order.asc";
-        let results: Vec<CategoryRow> = self.client.select("categories", query).await?;
+        let query = Query::new().order("sort_order", SortOrder::Asc);
+        let results: Vec<CategoryRow> = self.client.select("categories", &query).await?;
         Ok(results.into_iter().map(|r| r.into_category()).collect())
     }
 
     /// アクティブなカテゴリのみ取得
+    #[tracing::instrument(skip_all, name = "CategoryRepository::find_active")]
     pub async fn find_active(&self) -> Result<Vec<Category>> {
-        let query = "is_active=eq.true&order=sort_order.asc";
-        let results: Vec<CategoryRow> = self.client.select("categories", query).await?;
+        let query = Query::new().eq("is_active", true).order("sort_order", SortOrder::Asc);
+        let results: Vec<CategoryRow> = self.client.select("categories", &query).await?;
         Ok(results.into_iter().map(|r| r.into_category()).collect())
     }
 
     /// 商品数を更新
+    #[tracing::instrument(skip_all, name = "CategoryRepository::update_product_count")]
     pub async fn update_product_count(&self, id: Uuid, count: i32) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = ProductCountUpdate {
             product_count: count,
             updated_at: Utc::now(),
@@ -76,8 +82,9 @@ impl CategoryRepository {
     }
 
     /// カテゴリ削除
+    #[tracing::instrument(skip_all, name = "CategoryRepository::delete")]
     pub async fn delete(&self, id: Uuid) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         self.client.delete("categories", &query).await
     }
 }
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::postgres::{db_error, insert_json, set_role, DbRole, PostgresClient};
+use crate::db::repositories::order_repository::{order_input, order_item_inputs, with_unique_order_number};
+use crate::db::repositories::{CartRepository, OrderRepository, ProductRepository};
+use crate::db::SupabaseClient;
+use crate::error::Result;
+use crate::models::{split_reservations, Order};
+
+/// 注文確定の結果
+#[derive(Debug, Clone)]
+pub enum PlaceOrderResult {
+    /// 作成した注文（注文番号が重複した場合は振り直した番号になっている）
+    Created(Box<Order>),
+    /// 在庫不足または予約の受付上限（何も変更していない）
+    OutOfStock,
+}
+
+/// 注文を作成する呼び出し元（在庫の確保は service_role、注文の作成はこのロールで行いRLSを適用する）
+#[derive(Debug, Clone, Copy)]
+pub enum OrderActor<'a> {
+    /// ログインユーザー（アクセストークン）
+    User { token: &'a str, user_id: Uuid },
+    /// ゲスト（anon）
+    Guest,
+    /// サーバー内部の処理（Webhookでの注文確定）
+    Service,
+}
+
+impl OrderActor<'_> {
+    /// Postgres直接接続で使うロール
+    pub fn db_role(&self) -> DbRole {
+        match self {
+            OrderActor::User { user_id, .. } => DbRole::Authenticated(*user_id),
+            OrderActor::Guest => DbRole::Anon,
+            OrderActor::Service => DbRole::ServiceRole,
+        }
+    }
+}
+
+/// 注文確定（在庫・予約枠の確保 → 注文作成 → カートクリア）
+/// 予約明細（is_preorder）は在庫ではなく予約枠（reserve_preorder_bulk）を確保する
+/// DATABASE_BACKEND により REST（補償処理）か Postgres直接接続（トランザクション）を選択する
+#[async_trait]
+pub trait CheckoutStore: Send + Sync {
+    async fn place_order(
+        &self,
+        order: &Order,
+        actor: OrderActor<'_>,
+        cart_session_id: Option<&str>,
+    ) -> Result<PlaceOrderResult>;
+
+    /// バックエンド名（ログ用）
+    fn backend(&self) -> &'static str;
+}
+
+#[derive(Debug, Serialize)]
+struct StockItem {
+    product_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant_id: Option<Uuid>,
+    quantity: i32,
+}
+
+/// Supabase REST 実装
+/// 各ステップは別リクエストのため、注文作成に失敗した場合は在庫を解放して戻す
+pub struct RestCheckoutStore {
+    db: Arc<SupabaseClient>,
+}
+
+impl RestCheckoutStore {
+    pub fn new(db: Arc<SupabaseClient>) -> Self {
+        Self { db }
+    }
+
+    /// 呼び出し元のクライアントで注文を作成
+    /// ゲスト注文はRPC関数（create_guest_order_with_items）で作成するが、予約の列を扱わないため
+    /// 予約明細を含むゲスト注文は service_role で作成する
+    async fn create_order(&self, order: &Order, actor: OrderActor<'_>) -> Result<Order> {
+        match actor {
+            OrderActor::User { token, .. } => OrderRepository::new(self.db.with_auth(token)).create(order).await,
+            OrderActor::Guest if !order.items.iter().any(|i| i.is_preorder) => {
+                OrderRepository::new(self.db.anonymous()).create_guest_order_rpc(order).await
+            }
+            OrderActor::Guest | OrderActor::Service => OrderRepository::new(self.db.service()).create(order).await,
+        }
+    }
+}
+
+#[async_trait]
+impl CheckoutStore for RestCheckoutStore {
+    #[tracing::instrument(skip_all, name = "RestCheckoutStore::place_order")]
+    async fn place_order(
+        &self,
+        order: &Order,
+        actor: OrderActor<'_>,
+        cart_session_id: Option<&str>,
+    ) -> Result<PlaceOrderResult> {
+        let product_repo = ProductRepository::new(self.db.service());
+        let (stock_items, preorder_items) = split_reservations(&order.items);
+
+        if !stock_items.is_empty() && !product_repo.reserve_stock_bulk(&stock_items).await? {
+            return Ok(PlaceOrderResult::OutOfStock);
+        }
+        let release = || async {
+            if !stock_items.is_empty() {
+                let _ = product_repo.release_stock_bulk(&stock_items).await;
+            }
+        };
+
+        if !preorder_items.is_empty() {
+            match product_repo.reserve_preorder_bulk(&preorder_items).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    release().await;
+                    return Ok(PlaceOrderResult::OutOfStock);
+                }
+                Err(e) => {
+                    release().await;
+                    return Err(e);
+                }
+            }
+        }
+
+        let created = match self.create_order(order, actor).await {
+            Ok(created) => created,
+            Err(e) => {
+                release().await;
+                if !preorder_items.is_empty() {
+                    let _ = product_repo.release_preorder_bulk(&preorder_items).await;
+                }
+                return Err(e);
+            }
+        };
+
+        if let Some(session_id) = cart_session_id {
+            if let Err(e) = CartRepository::new(self.db.service()).clear(session_id).await {
+                tracing::warn!("Failed to clear cart after order {}: {}", order.id, e);
+            }
+        }
+
+        Ok(PlaceOrderResult::Created(Box::new(created)))
+    }
+
+    fn backend(&self) -> &'static str {
+        "rest"
+    }
+}
+
+/// Postgres直接接続の実装（1トランザクションで実行）
+pub struct PgCheckoutStore {
+    pg: PostgresClient,
+}
+
+impl PgCheckoutStore {
+    pub fn new(pg: PostgresClient) -> Self {
+        Self { pg }
+    }
+
+    async fn place_order_once(
+        &self,
+        order: &Order,
+        actor: OrderActor<'_>,
+        cart_session_id: Option<&str>,
+    ) -> Result<PlaceOrderResult> {
+        // 在庫・予約枠の確保とカートのクリアは service_role（RPC関数は service_role のみ実行可）
+        let mut tx = self.pg.begin_as(DbRole::ServiceRole).await?;
+
+        let (stock_items, preorder_items) = split_reservations(&order.items);
+        let stock_payload = stock_items
+            .into_iter()
+            .map(|(product_id, quantity)| StockItem { product_id, variant_id: None, quantity })
+            .collect::<Vec<_>>();
+        let preorder_payload = preorder_items
+            .into_iter()
+            .map(|(product_id, variant_id, quantity)| StockItem { product_id, variant_id, quantity })
+            .collect::<Vec<_>>();
+
+        // 在庫・予約枠の確保（REST版と同じRPC関数を使用、対象行は FOR UPDATE でロックされる）
+        for (function, items) in [("reserve_stock_bulk", stock_payload), ("reserve_preorder_bulk", preorder_payload)] {
+            if items.is_empty() {
+                continue;
+            }
+            let payload = serde_json::to_value(items).unwrap_or_else(|_| serde_json::json!([]));
+            let (reserved,): (bool,) = sqlx::query_as(&format!("SELECT {}($1)", function))
+                .bind(payload)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| db_error("Reserve stock", e))?;
+            if !reserved {
+                tx.rollback().await.map_err(|e| db_error("Rollback", e))?;
+                return Ok(PlaceOrderResult::OutOfStock);
+            }
+        }
+
+        if let Some(session_id) = cart_session_id {
+            for table in ["cart_items", "cart_metadata"] {
+                sqlx::query(&format!("DELETE FROM {} WHERE session_id = $1", table))
+                    .bind(session_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| db_error("Clear cart", e))?;
+            }
+        }
+
+        // 注文は呼び出し元のロールで作成する（REST版と同じRLSを通す）
+        set_role(&mut tx, actor.db_role()).await?;
+        insert_json(&mut tx, "orders", &order_input(order)).await?;
+        for item in order_item_inputs(order) {
+            insert_json(&mut tx, "order_items", &item).await?;
+        }
+
+        tx.commit().await.map_err(|e| db_error("Commit", e))?;
+        Ok(PlaceOrderResult::Created(Box::new(order.clone())))
+    }
+}
+
+#[async_trait]
+impl CheckoutStore for PgCheckoutStore {
+    /// 注文番号が重複した場合はトランザクションごとやり直す
+    #[tracing::instrument(skip_all, name = "PgCheckoutStore::place_order")]
+    async fn place_order(
+        &self,
+        order: &Order,
+        actor: OrderActor<'_>,
+        cart_session_id: Option<&str>,
+    ) -> Result<PlaceOrderResult> {
+        with_unique_order_number(order, |order| async move {
+            self.place_order_once(&order, actor, cart_session_id).await
+        })
+        .await
+    }
+
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_actor_db_role() {
+        let user_id = Uuid::new_v4();
+        let actor = OrderActor::User { token: "jwt", user_id };
+        assert!(matches!(actor.db_role(), DbRole::Authenticated(id) if id == user_id));
+        assert!(matches!(OrderActor::Guest.db_role(), DbRole::Anon));
+        assert!(matches!(OrderActor::Service.db_role(), DbRole::ServiceRole));
+    }
+}
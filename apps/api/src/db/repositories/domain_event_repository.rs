@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+use crate::models::{DomainEvent, DomainEventStatus, SortOrder};
+
+/// ドメインイベント（アウトボックス）リポジトリ
+/// イベント自体はDBトリガーが書き込むため、ここでは配信状態のみ扱う（service_role必須）
+pub struct DomainEventRepository {
+    client: AuthenticatedClient,
+}
+
+/// 配信結果の記録先（ディスパッチャーのテストではメモリ上の実装に差し替える）
+#[async_trait]
+pub trait DomainEventStore: Send + Sync {
+    /// 配信完了
+    async fn mark_delivered(&self, id: Uuid, completed_subscribers: &[String]) -> Result<()>;
+
+    /// 配信失敗（next_attempt_at=None の場合はデッドレター）
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        completed_subscribers: &[String],
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()>;
+}
+
+impl DomainEventRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// 配信対象を取得してロック（status=processing, attempts+1）
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::claim_batch")]
+    pub async fn claim_batch(&self, limit: i32, lock_seconds: i32) -> Result<Vec<DomainEvent>> {
+        #[derive(Serialize)]
+        struct Params {
+            p_limit: i32,
+            p_lock_seconds: i32,
+        }
+
+        self.client
+            .rpc(
+                "claim_domain_events",
+                &Params {
+                    p_limit: limit,
+                    p_lock_seconds: lock_seconds,
+                },
+            )
+            .await
+    }
+
+    /// 一覧（管理者用、新しい順）
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::find_all")]
+    pub async fn find_all(&self, status: Option<DomainEventStatus>, limit: usize) -> Result<Vec<DomainEvent>> {
+        let mut query = Query::new()
+            .order("created_at", SortOrder::Desc)
+            .limit(limit);
+        if let Some(status) = status {
+            query = query.eq("status", status);
+        }
+        self.client.select("domain_events", &query).await
+    }
+
+    /// 保持期間を過ぎた配信済み・デッドレターのイベントを削除
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::purge_finished")]
+    pub async fn purge_finished(&self, before: DateTime<Utc>) -> Result<()> {
+        let query = Query::new()
+            .in_list("status", [DomainEventStatus::Delivered, DomainEventStatus::Dead])
+            .lt("created_at", before.to_rfc3339());
+        self.client.delete("domain_events", &query).await
+    }
+
+    /// デッドレター・失敗待ちのイベントを即時再配信キューに戻す（試行回数はリセット）
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::requeue")]
+    pub async fn requeue(&self, id: Uuid) -> Result<Option<DomainEvent>> {
+        #[derive(Serialize)]
+        struct Update {
+            status: DomainEventStatus,
+            attempts: i32,
+            next_attempt_at: DateTime<Utc>,
+        }
+
+        let rows: Vec<DomainEvent> = self
+            .client
+            .update(
+                "domain_events",
+                &Query::new()
+                    .eq("id", id)
+                    .in_list("status", [DomainEventStatus::Dead, DomainEventStatus::Pending]),
+                &Update {
+                    status: DomainEventStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: Utc::now(),
+                },
+            )
+            .await?;
+        Ok(rows.into_iter().next())
+    }
+}
+
+#[async_trait]
+impl DomainEventStore for DomainEventRepository {
+    /// 配信完了
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::mark_delivered")]
+    async fn mark_delivered(&self, id: Uuid, completed_subscribers: &[String]) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            status: DomainEventStatus,
+            completed_subscribers: &'a [String],
+            last_error: Option<String>,
+            locked_until: Option<DateTime<Utc>>,
+            delivered_at: DateTime<Utc>,
+        }
+
+        let _: Vec<DomainEvent> = self
+            .client
+            .update(
+                "domain_events",
+                &Query::new().eq("id", id),
+                &Update {
+                    status: DomainEventStatus::Delivered,
+                    completed_subscribers,
+                    last_error: None,
+                    locked_until: None,
+                    delivered_at: Utc::now(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// 配信失敗（next_attempt_at=None の場合はデッドレター）
+    #[tracing::instrument(skip_all, name = "DomainEventRepository::mark_failed")]
+    async fn mark_failed(
+        &self,
+        id: Uuid,
+        completed_subscribers: &[String],
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            status: DomainEventStatus,
+            completed_subscribers: &'a [String],
+            last_error: &'a str,
+            locked_until: Option<DateTime<Utc>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            next_attempt_at: Option<DateTime<Utc>>,
+        }
+
+        let status = if next_attempt_at.is_some() {
+            DomainEventStatus::Pending
+        } else {
+            DomainEventStatus::Dead
+        };
+
+        let _: Vec<DomainEvent> = self
+            .client
+            .update(
+                "domain_events",
+                &Query::new().eq("id", id),
+                &Update {
+                    status,
+                    completed_subscribers,
+                    last_error: error,
+                    locked_until: None,
+                    next_attempt_at,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+
+/// ゲスト注文の再アクセスリンクリポジトリ（service_role必須）
+pub struct GuestOrderAccessRepository {
+    client: AuthenticatedClient,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessLinkRow {
+    order_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct NewAccessLink<'a> {
+    order_id: Uuid,
+    token_hash: &'a str,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct UsedUpdate {
+    used_at: DateTime<Utc>,
+}
+
+impl GuestOrderAccessRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// リンクを発行（同じ注文の未使用リンクは無効化し、最新の1件だけ有効にする）
+    #[tracing::instrument(skip_all, name = "GuestOrderAccessRepository::create")]
+    pub async fn create(&self, order_id: Uuid, token_hash: &str, expires_at: DateTime<Utc>) -> Result<()> {
+        let query = Query::new().eq("order_id", order_id).where_null("used_at");
+        self.client.delete("guest_order_access_links", &query).await?;
+
+        let input = NewAccessLink {
+            order_id,
+            token_hash,
+            expires_at,
+        };
+        let _: AccessLinkRow = self.client.insert("guest_order_access_links", &input).await?;
+        Ok(())
+    }
+
+    /// リンクを使用済みにして注文IDを返す（未使用・期限内のみ。同時に開いても1回だけ成功する）
+    #[tracing::instrument(skip_all, name = "GuestOrderAccessRepository::consume")]
+    pub async fn consume(&self, token_hash: &str) -> Result<Option<Uuid>> {
+        let now = Utc::now();
+        let query = Query::new()
+            .eq("token_hash", token_hash)
+            .where_null("used_at")
+            .gt("expires_at", now.to_rfc3339())
+            .select("order_id");
+        let rows: Vec<AccessLinkRow> = self
+            .client
+            .update("guest_order_access_links", &query, &UsedUpdate { used_at: now })
+            .await?;
+        Ok(rows.into_iter().next().map(|row| row.order_id))
+    }
+
+    /// 期限切れのリンクを削除
+    #[tracing::instrument(skip_all, name = "GuestOrderAccessRepository::purge_expired")]
+    pub async fn purge_expired(&self) -> Result<()> {
+        let query = Query::new().lt("expires_at", Utc::now().to_rfc3339());
+        self.client.delete("guest_order_access_links", &query).await
+    }
+}
@@ -0,0 +1,142 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::{AppError, Result};
+
+/// Idempotency-Key リポジトリ（service_role必須）
+pub struct IdempotencyRepository {
+    client: AuthenticatedClient,
+}
+
+/// 保存済みのキー
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdempotencyRecord {
+    pub id: Uuid,
+    pub request_hash: String,
+    pub status: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    pub fn is_completed(&self) -> bool {
+        self.status == "completed"
+    }
+}
+
+/// キー確保の結果
+#[derive(Debug)]
+pub enum IdempotencyClaim {
+    /// 新しく確保した（このリクエストで処理する）
+    Acquired(Uuid),
+    /// 既に使われている
+    Existing(IdempotencyRecord),
+}
+
+#[derive(Debug, Serialize)]
+struct NewIdempotencyKey<'a> {
+    scope: &'a str,
+    idempotency_key: &'a str,
+    request_hash: &'a str,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct CompleteUpdate<'a> {
+    status: &'static str,
+    response_status: i32,
+    response_body: &'a str,
+}
+
+/// キーの保存先（ミドルウェアのテストではメモリ上の実装に差し替える）
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// キーを確保（同じスコープ・キーは1リクエストだけが確保できる）
+    async fn claim(
+        &self,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<IdempotencyClaim>;
+
+    /// レスポンスを保存
+    async fn complete(&self, id: Uuid, response_status: u16, response_body: &str) -> Result<()>;
+
+    /// キーを解放（再試行できるようにする）
+    async fn release(&self, id: Uuid) -> Result<()>;
+}
+
+impl IdempotencyRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// 期限切れのキーを削除
+    #[tracing::instrument(skip_all, name = "IdempotencyRepository::purge_expired")]
+    pub async fn purge_expired(&self) -> Result<()> {
+        let query = Query::new().lt("expires_at", Utc::now().to_rfc3339());
+        self.client.delete("idempotency_keys", &query).await
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for IdempotencyRepository {
+    /// UNIQUE制約で同時実行を1つに絞る
+    #[tracing::instrument(skip_all, name = "IdempotencyRepository::claim")]
+    async fn claim(
+        &self,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<IdempotencyClaim> {
+        let input = NewIdempotencyKey {
+            scope,
+            idempotency_key: key,
+            request_hash,
+            expires_at,
+        };
+
+        match self.client.insert::<_, IdempotencyRecord>("idempotency_keys", &input).await {
+            Ok(record) => Ok(IdempotencyClaim::Acquired(record.id)),
+            Err(AppError::UniqueViolation(_)) => {
+                let query = Query::new().eq("scope", scope).eq("idempotency_key", key);
+                let record: Option<IdempotencyRecord> =
+                    self.client.select_single("idempotency_keys", &query).await?;
+                match record {
+                    Some(record) => Ok(IdempotencyClaim::Existing(record)),
+                    // 確認までの間に削除された
+                    None => Err(AppError::Conflict("Idempotency-Key を確保できませんでした".to_string())),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    #[tracing::instrument(skip_all, name = "IdempotencyRepository::complete")]
+    async fn complete(&self, id: Uuid, response_status: u16, response_body: &str) -> Result<()> {
+        let query = Query::new().eq("id", id);
+        let update = CompleteUpdate {
+            status: "completed",
+            response_status: response_status as i32,
+            response_body,
+        };
+        let _: Vec<serde_json::Value> = self
+            .client
+            .update("idempotency_keys", &query.select("id"), &update)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, name = "IdempotencyRepository::release")]
+    async fn release(&self, id: Uuid) -> Result<()> {
+        let query = Query::new().eq("id", id);
+        self.client.delete("idempotency_keys", &query).await
+    }
+}
@@ -2,18 +2,22 @@ use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
-use crate::error::Result;
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::{AppError, Result};
 
 /// ログイン試行追跡 + アカウントロック管理
 pub struct LoginAttemptsRepository {
     client: AuthenticatedClient,
 }
 
-/// アカウントロックの設定
-const MAX_FAILED_ATTEMPTS: i32 = 10; // 最大失敗回数
+/// ログイン保護の設定
+/// ロック・遅延はIP+メール単位（第三者による任意アカウントのロックを防ぐ）
+const MAX_FAILED_ATTEMPTS: i32 = 10; // IP+メール単位のロックまでの失敗回数
 const LOCKOUT_DURATION_MINUTES: i64 = 30; // ロック時間（分）
 const ATTEMPT_WINDOW_MINUTES: i64 = 15; // 失敗カウント対象時間（分）
+const DELAY_FREE_ATTEMPTS: i32 = 3; // 遅延なしで許容する失敗回数
+const MAX_DELAY_SECONDS: i64 = 300; // 段階的遅延の上限（秒）
+const CAPTCHA_AFTER_FAILURES: i32 = 5; // メール単位（全IP）でCAPTCHAを要求する失敗回数
 
 impl LoginAttemptsRepository {
     pub fn new(client: AuthenticatedClient) -> Self {
@@ -21,6 +25,7 @@ impl LoginAttemptsRepository {
     }
 
     /// ログイン試行を記録
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::record_attempt")]
     pub async fn record_attempt(
         &self,
         email: &str,
@@ -42,128 +47,119 @@ impl LoginAttemptsRepository {
         Ok(())
     }
 
-    /// 指定時間内の失敗試行回数を取得
-    pub async fn get_failed_attempts_count(&self, email: &str) -> Result<i32> {
-        let window_start = (Utc::now() - Duration::minutes(ATTEMPT_WINDOW_MINUTES)).to_rfc3339();
-        let query = format!(
-            "email=eq.{}&success=eq.false&attempted_at=gte.{}&select=id",
-            urlencoding::encode(email),
-            urlencoding::encode(&window_start)
-        );
-
-        // カウントを取得
-        let result: Vec<CountRow> = self.client.select("login_attempts", &query).await?;
-        Ok(result.len() as i32)
-    }
-
-    /// アカウントがロックされているか確認
-    pub async fn is_account_locked(&self, email: &str) -> Result<Option<AccountLock>> {
-        let now = Utc::now().to_rfc3339();
-        let query = format!(
-            "email=eq.{}&locked_until=gt.{}",
-            urlencoding::encode(email),
-            urlencoding::encode(&now)
-        );
-
-        let result: Option<AccountLockRow> = self.client.select_single("account_locks", &query).await?;
-        Ok(result.map(|r| AccountLock {
-            email: r.email,
-            locked_at: r.locked_at,
-            locked_until: r.locked_until,
-            failed_attempts: r.failed_attempts,
-        }))
-    }
-
-    /// アカウントをロック
-    pub async fn lock_account(&self, email: &str, failed_attempts: i32) -> Result<AccountLock> {
-        let now = Utc::now();
-        let locked_until = now + Duration::minutes(LOCKOUT_DURATION_MINUTES);
-
-        // 既存のロックを削除してから新規作成
-        let delete_query = format!("email=eq.{}", urlencoding::encode(email));
-        let _ = self.client.delete("account_locks", &delete_query).await;
-
-        let input = AccountLockInput {
-            id: Uuid::new_v4(),
-            email: email.to_string(),
-            locked_at: now,
-            locked_until,
-            reason: "too_many_failed_attempts".to_string(),
-            failed_attempts,
-            created_at: now,
-            updated_at: now,
-        };
-
-        let row: AccountLockRow = self.client.insert("account_locks", &input).await?;
+    /// IP+メールのログイン制限状態を取得
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::get_throttle")]
+    pub async fn get_throttle(&self, email: &str, ip_address: &str) -> Result<LoginThrottle> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_email: &'a str,
+            p_ip_address: &'a str,
+            p_window_minutes: i64,
+        }
 
-        Ok(AccountLock {
-            email: row.email,
-            locked_at: row.locked_at,
-            locked_until: row.locked_until,
-            failed_attempts: row.failed_attempts,
-        })
+        let rows: Vec<LoginThrottle> = self
+            .client
+            .rpc(
+                "get_login_throttle",
+                &Params {
+                    p_email: email,
+                    p_ip_address: ip_address,
+                    p_window_minutes: ATTEMPT_WINDOW_MINUTES,
+                },
+            )
+            .await?;
+
+        Ok(rows.into_iter().next().unwrap_or_default())
     }
 
-    /// アカウントロックを解除
+    /// アカウントロックを解除（管理者用。全IPのロックと失敗履歴をリセット）
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::unlock_account")]
     pub async fn unlock_account(&self, email: &str) -> Result<()> {
-        let query = format!("email=eq.{}", urlencoding::encode(email));
+        let query = Query::new().eq("email", email);
         self.client.delete("account_locks", &query).await?;
+
+        let window_start = (Utc::now() - Duration::minutes(ATTEMPT_WINDOW_MINUTES)).to_rfc3339();
+        let attempts_query = Query::new()
+            .eq("email", email)
+            .eq("success", false)
+            .gte("attempted_at", window_start);
+        self.client.delete("login_attempts", &attempts_query).await?;
         Ok(())
     }
 
-    /// ログイン失敗を処理（カウント更新 + 必要に応じてロック）
+    /// ログイン失敗を処理（記録 + 必要に応じてIP+メールをロック）
+    /// 集計とロックはRPC内で直列化されるため、複数インスタンスでも正確
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::handle_failed_login")]
     pub async fn handle_failed_login(
         &self,
         email: &str,
         ip_address: &str,
         user_agent: Option<&str>,
     ) -> Result<LoginAttemptResult> {
-        // 試行を記録
-        self.record_attempt(email, ip_address, false, user_agent).await?;
-
-        // 失敗回数を取得
-        let failed_count = self.get_failed_attempts_count(email).await?;
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_email: &'a str,
+            p_ip_address: &'a str,
+            p_user_agent: Option<&'a str>,
+            p_window_minutes: i64,
+            p_lock_threshold: i32,
+            p_lock_minutes: i64,
+        }
 
-        // 最大失敗回数を超えたらロック
-        if failed_count >= MAX_FAILED_ATTEMPTS {
-            let lock = self.lock_account(email, failed_count).await?;
+        let rows: Vec<FailedLoginRow> = self
+            .client
+            .rpc(
+                "record_failed_login",
+                &Params {
+                    p_email: email,
+                    p_ip_address: ip_address,
+                    p_user_agent: user_agent,
+                    p_window_minutes: ATTEMPT_WINDOW_MINUTES,
+                    p_lock_threshold: MAX_FAILED_ATTEMPTS,
+                    p_lock_minutes: LOCKOUT_DURATION_MINUTES,
+                },
+            )
+            .await?;
+
+        let row = rows
+            .into_iter()
+            .next()
+            .ok_or_else(|| AppError::Database("record_failed_login returned no rows".to_string()))?;
+
+        if let Some(until) = row.locked_until {
             return Ok(LoginAttemptResult::Locked {
-                until: lock.locked_until,
-                attempts: failed_count,
+                until,
+                attempts: row.pair_failures,
+                notify_owner: row.newly_locked && row.notify_owner,
             });
         }
 
         Ok(LoginAttemptResult::Failed {
-            attempts: failed_count,
+            attempts: row.pair_failures,
             max_attempts: MAX_FAILED_ATTEMPTS,
         })
     }
 
-    /// ログイン成功を処理（ロック解除 + 記録）
+    /// ログイン成功を記録（このIP+メールの失敗カウントはリセットされる）
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::handle_successful_login")]
     pub async fn handle_successful_login(
         &self,
         email: &str,
         ip_address: &str,
         user_agent: Option<&str>,
     ) -> Result<()> {
-        // 成功を記録
-        self.record_attempt(email, ip_address, true, user_agent).await?;
-
-        // ロックがあれば解除
-        self.unlock_account(email).await?;
-
-        Ok(())
+        self.record_attempt(email, ip_address, true, user_agent).await
     }
 
     /// 古い試行履歴をクリーンアップ
+    #[tracing::instrument(skip_all, name = "LoginAttemptsRepository::cleanup_old_attempts")]
     pub async fn cleanup_old_attempts(&self) -> Result<()> {
         let cutoff = (Utc::now() - Duration::days(30)).to_rfc3339();
-        let query = format!("attempted_at=lt.{}", urlencoding::encode(&cutoff));
+        let query = Query::new().lt("attempted_at", cutoff);
         self.client.delete("login_attempts", &query).await?;
 
         // 期限切れのロックも削除
-        let now = Utc::now().to_rfc3339();
-        let lock_query = format!("locked_until=lt.{}", urlencoding::encode(&now));
+        let lock_query = Query::new().lt("locked_until", Utc::now().to_rfc3339());
         self.client.delete("account_locks", &lock_query).await?;
 
         Ok(())
@@ -178,20 +174,44 @@ pub enum LoginAttemptResult {
         attempts: i32,
         max_attempts: i32,
     },
-    /// アカウントがロックされた
+    /// IP+メールがロックされた
     Locked {
         until: DateTime<Utc>,
         attempts: i32,
+        /// アカウント所有者へ通知すべきか（新規ロック時のみ）
+        notify_owner: bool,
     },
 }
 
-/// アカウントロック情報
-#[derive(Debug)]
-pub struct AccountLock {
-    pub email: String,
-    pub locked_at: DateTime<Utc>,
-    pub locked_until: DateTime<Utc>,
-    pub failed_attempts: i32,
+/// IP+メールのログイン制限状態
+#[derive(Debug, Default, Deserialize)]
+pub struct LoginThrottle {
+    /// このIP+メールの失敗回数（直近の成功以降）
+    pub pair_failures: i32,
+    /// メール単位（全IP）の失敗回数
+    pub email_failures: i32,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginThrottle {
+    /// 次の試行まで待つべき秒数（段階的遅延: 1, 2, 4, ... 秒、上限あり）
+    pub fn retry_after_seconds(&self, now: DateTime<Utc>) -> Option<i64> {
+        if self.pair_failures < DELAY_FREE_ATTEMPTS {
+            return None;
+        }
+        let exponent = (self.pair_failures - DELAY_FREE_ATTEMPTS).min(16) as u32;
+        let delay = 2_i64.pow(exponent).min(MAX_DELAY_SECONDS);
+        let next_allowed = self.last_failed_at? + Duration::seconds(delay);
+        let remaining = (next_allowed - now).num_seconds();
+        (remaining > 0).then_some(remaining)
+    }
+
+    /// CAPTCHAが必要か（複数IPからの分散攻撃を想定し、メール単位で判定）
+    /// メール単位の失敗ではログイン自体は止めない（第三者が本人のログインを妨害できないようにする）
+    pub fn captcha_required(&self) -> bool {
+        self.email_failures >= CAPTCHA_AFTER_FAILURES
+    }
 }
 
 // 内部構造体
@@ -225,35 +245,52 @@ struct LoginAttemptRow {
 }
 
 #[derive(Debug, Deserialize)]
-struct CountRow {
+struct FailedLoginRow {
+    pair_failures: i32,
     #[allow(dead_code)]
-    id: Uuid,
+    email_failures: i32,
+    locked_until: Option<DateTime<Utc>>,
+    newly_locked: bool,
+    notify_owner: bool,
 }
 
-#[derive(Debug, Serialize)]
-struct AccountLockInput {
-    id: Uuid,
-    email: String,
-    locked_at: DateTime<Utc>,
-    locked_until: DateTime<Utc>,
-    reason: String,
-    failed_attempts: i32,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[derive(Debug, Deserialize)]
-struct AccountLockRow {
-    #[allow(dead_code)]
-    id: Uuid,
-    email: String,
-    locked_at: DateTime<Utc>,
-    locked_until: DateTime<Utc>,
-    #[allow(dead_code)]
-    reason: String,
-    failed_attempts: i32,
-    #[allow(dead_code)]
-    created_at: DateTime<Utc>,
-    #[allow(dead_code)]
-    updated_at: DateTime<Utc>,
+    fn throttle(pair_failures: i32, seconds_ago: i64, now: DateTime<Utc>) -> LoginThrottle {
+        LoginThrottle {
+            pair_failures,
+            email_failures: pair_failures,
+            last_failed_at: Some(now - Duration::seconds(seconds_ago)),
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let now = Utc::now();
+        // 許容回数内は遅延なし
+        assert_eq!(throttle(2, 0, now).retry_after_seconds(now), None);
+        // 3回目以降は 1, 2, 4 ... 秒
+        assert_eq!(throttle(3, 0, now).retry_after_seconds(now), Some(1));
+        assert_eq!(throttle(5, 1, now).retry_after_seconds(now), Some(3));
+        assert_eq!(throttle(5, 10, now).retry_after_seconds(now), None);
+        // 上限
+        assert_eq!(throttle(30, 0, now).retry_after_seconds(now), Some(MAX_DELAY_SECONDS));
+    }
+
+    #[test]
+    fn test_captcha_across_ips() {
+        let now = Utc::now();
+        // 各IPの失敗が少なくても、メール単位の合計でCAPTCHAを要求する
+        let spread = LoginThrottle {
+            pair_failures: 1,
+            email_failures: CAPTCHA_AFTER_FAILURES,
+            ..throttle(1, 0, now)
+        };
+        assert!(spread.captcha_required());
+        assert_eq!(spread.retry_after_seconds(now), None);
+        assert!(!throttle(1, 0, now).captcha_required());
+    }
 }
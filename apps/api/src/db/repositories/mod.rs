@@ -6,12 +6,38 @@ pub mod order_repository;
 pub mod review_repository;
 pub mod token_blacklist_repository;
 pub mod login_attempts_repository;
+pub mod session_repository;
+pub mod passkey_repository;
+pub mod oauth_state_repository;
+pub mod checkout_store;
+pub mod domain_event_repository;
+pub mod webhook_repository;
+pub mod wishlist_repository;
+pub mod product_alert_repository;
+pub mod cart_recovery_repository;
+pub mod idempotency_repository;
+pub mod guest_order_access_repository;
+pub mod order_modification_repository;
 
-pub use user_repository::UserRepository;
+pub use user_repository::{AccountAnonymizeResult, UserRepository};
 pub use product_repository::{ProductRepository, ProductUpdateInput, VariantUpdateInput};
 pub use category_repository::CategoryRepository;
 pub use cart_repository::CartRepository;
 pub use order_repository::OrderRepository;
 pub use review_repository::ReviewRepository;
 pub use token_blacklist_repository::TokenBlacklistRepository;
-pub use login_attempts_repository::{LoginAttemptsRepository, LoginAttemptResult, AccountLock};
+pub use login_attempts_repository::{LoginAttemptsRepository, LoginAttemptResult, LoginThrottle};
+pub use session_repository::SessionRepository;
+pub use passkey_repository::PasskeyRepository;
+pub use oauth_state_repository::OAuthStateRepository;
+pub use domain_event_repository::{DomainEventRepository, DomainEventStore};
+pub use webhook_repository::{DeliveryAttempt, WebhookEndpointUpdate, WebhookRepository, WebhookTarget};
+pub use wishlist_repository::WishlistRepository;
+pub use product_alert_repository::{NewProductAlert, ProductAlertRepository};
+pub use cart_recovery_repository::{CartRecoveryRepository, NewCartRecovery};
+pub use idempotency_repository::{IdempotencyClaim, IdempotencyRecord, IdempotencyRepository, IdempotencyStore};
+pub use guest_order_access_repository::GuestOrderAccessRepository;
+pub use order_modification_repository::{
+    ModificationApplyResult, NewOrderModification, OrderModificationRepository,
+};
+pub use checkout_store::{CheckoutStore, OrderActor, PgCheckoutStore, PlaceOrderResult, RestCheckoutStore};
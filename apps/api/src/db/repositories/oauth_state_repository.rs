@@ -0,0 +1,87 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::AuthenticatedClient;
+use crate::error::Result;
+
+/// PKCE状態の有効期限（分）
+pub const STATE_TTL_MINUTES: i64 = 10;
+
+/// 消費したPKCE状態
+#[derive(Debug, Deserialize)]
+pub struct OAuthState {
+    pub provider: String,
+    pub code_verifier: String,
+    pub user_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct OAuthStateRepository {
+    client: AuthenticatedClient,
+}
+
+impl OAuthStateRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// PKCE状態を保存してIDを返す
+    /// `browser_binding_hash` は認可を開始したブラウザに発行したCookie値のハッシュ
+    #[tracing::instrument(skip_all, name = "OAuthStateRepository::create")]
+    pub async fn create(
+        &self,
+        provider: &str,
+        code_verifier: &str,
+        redirect_to: &str,
+        user_id: Option<Uuid>,
+        browser_binding_hash: &str,
+    ) -> Result<Uuid> {
+        let input = OAuthStateInput {
+            id: Uuid::new_v4(),
+            provider: provider.to_string(),
+            code_verifier: code_verifier.to_string(),
+            redirect_to: redirect_to.to_string(),
+            user_id,
+            browser_binding_hash: browser_binding_hash.to_string(),
+            expires_at: Utc::now() + Duration::minutes(STATE_TTL_MINUTES),
+        };
+
+        let row: IdOnly = self.client.insert("oauth_states", &input).await?;
+        Ok(row.id)
+    }
+
+    /// PKCE状態を消費（期限切れ・使用済み・別ブラウザからの消費はNone）
+    #[tracing::instrument(skip_all, name = "OAuthStateRepository::consume")]
+    pub async fn consume(&self, id: Uuid, browser_binding_hash: &str) -> Result<Option<OAuthState>> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_id: Uuid,
+            p_browser_binding_hash: &'a str,
+        }
+
+        let params = Params {
+            p_id: id,
+            p_browser_binding_hash: browser_binding_hash,
+        };
+        let rows: Vec<OAuthState> = self.client.rpc("consume_oauth_state", &params).await?;
+        Ok(rows.into_iter().next())
+    }
+}
+
+// Supabase REST API用の構造体
+#[derive(Debug, Serialize)]
+struct OAuthStateInput {
+    id: Uuid,
+    provider: String,
+    code_verifier: String,
+    redirect_to: String,
+    user_id: Option<Uuid>,
+    browser_binding_hash: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdOnly {
+    id: Uuid,
+}
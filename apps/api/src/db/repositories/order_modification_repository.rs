@@ -0,0 +1,199 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::{AppError, Result};
+use crate::models::{
+    ItemVariantChange, ModificationRefundStatus, OrderAddress, OrderModification, OrderModificationStatus,
+    SortOrder,
+};
+
+/// 注文変更リポジトリ（service_role必須）
+pub struct OrderModificationRepository {
+    client: AuthenticatedClient,
+}
+
+/// 注文変更の作成内容
+#[derive(Debug, Serialize)]
+pub struct NewOrderModification {
+    pub order_id: Uuid,
+    pub requested_by: Option<Uuid>,
+    pub shipping_address: Option<OrderAddress>,
+    pub item_changes: Vec<ItemVariantChange>,
+    pub previous_total: i64,
+    pub subtotal: i64,
+    pub shipping_fee: i64,
+    pub tax: i64,
+    pub total: i64,
+    pub amount_difference: i64,
+    pub status: OrderModificationStatus,
+    /// 追加決済の期限（awaiting_payment のみ。過ぎたらPaymentIntentを取り消して expired にする）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// apply_order_modification の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModificationApplyResult {
+    Applied,
+    /// 適用済み・取り消し済み
+    NotPending,
+    /// 注文の状態・金額・明細が変わった
+    OrderChanged,
+    OutOfStock,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusUpdate {
+    status: OrderModificationStatus,
+    updated_at: DateTime<Utc>,
+}
+
+impl OrderModificationRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// 変更依頼を作成（同じ注文の未適用の依頼は置き換える）
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::create")]
+    pub async fn create(&self, input: &NewOrderModification) -> Result<OrderModification> {
+        let query = Query::new()
+            .eq("order_id", input.order_id)
+            .in_list("status", ["pending", "awaiting_payment"])
+            .select("id");
+        let update = StatusUpdate {
+            status: OrderModificationStatus::Superseded,
+            updated_at: Utc::now(),
+        };
+        let _: Vec<serde_json::Value> = self.client.update("order_modifications", &query, &update).await?;
+
+        self.client.insert("order_modifications", input).await
+    }
+
+    /// IDで取得
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::find_by_id")]
+    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<OrderModification>> {
+        let query = Query::new().eq("id", id);
+        self.client.select_single("order_modifications", &query).await
+    }
+
+    /// 注文の変更履歴（新しい順）
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::find_by_order")]
+    pub async fn find_by_order(&self, order_id: Uuid) -> Result<Vec<OrderModification>> {
+        let query = Query::new()
+            .eq("order_id", order_id)
+            .order("created_at", SortOrder::Desc)
+            .limit(50);
+        self.client.select("order_modifications", &query).await
+    }
+
+    /// 追加決済のPaymentIntent IDを記録
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::set_payment_id")]
+    pub async fn set_payment_id(&self, id: Uuid, payment_id: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            payment_id: &'a str,
+            updated_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id).select("id");
+        let update = Update {
+            payment_id,
+            updated_at: Utc::now(),
+        };
+        let _: Vec<serde_json::Value> = self.client.update("order_modifications", &query, &update).await?;
+        Ok(())
+    }
+
+    /// 変更を適用（在庫移動・明細/金額/配送先の更新を1トランザクションで行う）
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::apply")]
+    pub async fn apply(&self, id: Uuid) -> Result<ModificationApplyResult> {
+        #[derive(Serialize)]
+        struct Params {
+            p_modification_id: Uuid,
+        }
+
+        let result: String = self
+            .client
+            .rpc("apply_order_modification", &Params { p_modification_id: id })
+            .await?;
+        match result.as_str() {
+            "applied" => Ok(ModificationApplyResult::Applied),
+            "not_pending" => Ok(ModificationApplyResult::NotPending),
+            "order_changed" => Ok(ModificationApplyResult::OrderChanged),
+            "out_of_stock" => Ok(ModificationApplyResult::OutOfStock),
+            other => Err(AppError::Internal(format!("Unexpected apply_order_modification result: {}", other))),
+        }
+    }
+
+    /// 期限切れの追加決済待ち（古い順）
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::find_expired_awaiting_payment")]
+    pub async fn find_expired_awaiting_payment(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<OrderModification>> {
+        let query = Query::new()
+            .eq("status", "awaiting_payment")
+            .lt("expires_at", now.to_rfc3339())
+            .order("expires_at", SortOrder::Asc)
+            .limit(limit);
+        self.client.select("order_modifications", &query).await
+    }
+
+    /// 追加決済待ちのまま期限切れにする（Webhookで適用済みなら何もしない）
+    /// 戻り値: この呼び出しで遷移したか
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::mark_expired")]
+    pub async fn mark_expired(&self, id: Uuid) -> Result<bool> {
+        let query = Query::new().eq("id", id).eq("status", "awaiting_payment").select("id");
+        let update = StatusUpdate {
+            status: OrderModificationStatus::Expired,
+            updated_at: Utc::now(),
+        };
+        let rows: Vec<serde_json::Value> = self.client.update("order_modifications", &query, &update).await?;
+        Ok(!rows.is_empty())
+    }
+
+    /// 失敗として記録
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::mark_failed")]
+    pub async fn mark_failed(&self, id: Uuid, reason: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            status: OrderModificationStatus,
+            failure_reason: &'a str,
+            updated_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id).select("id");
+        let update = Update {
+            status: OrderModificationStatus::Failed,
+            failure_reason: reason,
+            updated_at: Utc::now(),
+        };
+        let _: Vec<serde_json::Value> = self.client.update("order_modifications", &query, &update).await?;
+        Ok(())
+    }
+
+    /// 返金結果を記録
+    #[tracing::instrument(skip_all, name = "OrderModificationRepository::update_refund")]
+    pub async fn update_refund(
+        &self,
+        id: Uuid,
+        refund_status: ModificationRefundStatus,
+        refund_id: Option<&str>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            refund_status: ModificationRefundStatus,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            refund_id: Option<&'a str>,
+            updated_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id).select("id");
+        let update = Update {
+            refund_status,
+            refund_id,
+            updated_at: Utc::now(),
+        };
+        let _: Vec<serde_json::Value> = self.client.update("order_modifications", &query, &update).await?;
+        Ok(())
+    }
+}
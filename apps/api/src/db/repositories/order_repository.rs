@@ -1,10 +1,52 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
-use crate::error::Result;
-use crate::models::{Order, OrderItem, OrderStatus, OrderSummary, PaymentMethod};
+use std::future::Future;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::{AppError, Result};
+use crate::models::{
+    generate_order_number, is_awaiting_release, is_ready_for_fulfillment, FulfillmentQueueEntry, Order, OrderItem,
+    OrderStatus, OrderSummary, PaymentMethod, SortOrder,
+};
+
+/// 注文番号が重複した場合の最大試行回数
+const ORDER_NUMBER_ATTEMPTS: usize = 3;
+
+/// 注文番号の一意制約か（登録表 order_numbers の主キー、または旧環境の orders_..._order_number_... 制約）
+fn is_order_number_constraint(constraint: &str) -> bool {
+    constraint == "order_numbers_pkey"
+        || (constraint.starts_with("orders") && constraint.contains("order_number"))
+}
+
+/// 注文番号の一意制約違反時に番号を振り直して再試行する（他の一意制約違反はそのまま返す）
+pub(crate) async fn with_unique_order_number<T, F, Fut>(order: &Order, mut create: F) -> Result<T>
+where
+    F: FnMut(Order) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut order = order.clone();
+    let mut attempt = 1;
+    loop {
+        match create(order.clone()).await {
+            Err(AppError::UniqueViolation(constraint))
+                if is_order_number_constraint(&constraint) && attempt < ORDER_NUMBER_ATTEMPTS =>
+            {
+                tracing::warn!(
+                    "Order number conflict, retrying: order_id={}, order_number={}, attempt={}, constraint={}",
+                    order.id,
+                    order.order_number,
+                    attempt,
+                    constraint
+                );
+                order.order_number = generate_order_number();
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
 
 pub struct OrderRepository {
     client: AuthenticatedClient,
@@ -16,65 +58,29 @@ impl OrderRepository {
     }
 
     /// 注文作成
+    /// 注文番号が重複した場合は振り直すため、戻り値の注文番号を使うこと
+    #[tracing::instrument(skip_all, name = "OrderRepository::create")]
     pub async fn create(&self, order: &Order) -> Result<Order> {
-        // ordersテーブルに挿入
-        let input = OrderInput {
-            id: order.id,
-            user_id: order.user_id,
-            order_number: order.order_number.clone(),
-            status: order.status.to_string(),
-            subtotal: order.subtotal,
-            shipping_fee: order.shipping_fee,
-            tax: order.tax,
-            total: order.total,
-            currency: order.currency.clone(),
-            shipping_address: serde_json::to_value(&order.shipping_address).unwrap_or_default(),
-            billing_address: order.billing_address.as_ref().and_then(|a| serde_json::to_value(a).ok()),
-            payment_method: order.payment_method.to_string(),
-            payment_status: serde_json::to_string(&order.payment_status).unwrap_or_default(),
-            payment_id: order.payment_id.clone(),
-            notes: order.notes.clone(),
-            created_at: order.created_at,
-            updated_at: order.updated_at,
-            // ゲスト注文用フィールド
-            is_guest_order: if order.is_guest_order { Some(true) } else { None },
-            guest_email: order.guest_email.clone(),
-            guest_name: order.guest_name.clone(),
-            guest_phone: order.guest_phone.clone(),
-            guest_access_token_hash: order.guest_access_token_hash.clone(),
-            guest_token_expires_at: order.guest_token_expires_at,
-        };
+        with_unique_order_number(order, |order| async move {
+            // ordersテーブルに挿入
+            let result: OrderRow = self.client.insert("orders", &order_input(&order)).await?;
 
-        let result: OrderRow = self.client.insert("orders", &input).await?;
-
-        // order_itemsテーブルに挿入
-        for item in &order.items {
-            let item_input = OrderItemInput {
-                id: Uuid::new_v4(),
-                order_id: order.id,
-                order_created_at: order.created_at,
-                product_id: Some(item.product_id),
-                product_name: item.product_name.clone(),
-                product_sku: Some(item.product_sku.clone()),
-                price: item.price,
-                quantity: item.quantity,
-                subtotal: item.subtotal,
-                image_url: item.image_url.clone(),
-                variant_id: item.variant_id,
-                size: item.size.clone(),
-            };
-
-            let _: OrderItemRow = self.client.insert("order_items", &item_input).await?;
-        }
+            // order_itemsテーブルに挿入
+            for item_input in order_item_inputs(&order) {
+                let _: OrderItemRow = self.client.insert("order_items", &item_input).await?;
+            }
 
-        let mut created_order = result.into_order();
-        created_order.items = order.items.clone();
-        Ok(created_order)
+            let mut created_order = result.into_order();
+            created_order.items = order.items;
+            Ok(created_order)
+        })
+        .await
     }
 
     /// IDで注文取得
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_id")]
     pub async fn find_by_id(&self, id: Uuid) -> Result<Option<Order>> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let result: Option<OrderRow> = self.client.select_single("orders", &query).await?;
 
         if let Some(row) = result {
@@ -87,8 +93,9 @@ impl OrderRepository {
     }
 
     /// PaymentIntent IDで注文取得（認証済みユーザー用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_payment_id")]
     pub async fn find_by_payment_id(&self, payment_id: &str, user_id: Uuid) -> Result<Option<Order>> {
-        let query = format!("payment_id=eq.{}&user_id=eq.{}", payment_id, user_id);
+        let query = Query::new().eq("payment_id", payment_id).eq("user_id", user_id);
         let result: Option<OrderRow> = self.client.select_single("orders", &query).await?;
 
         if let Some(row) = result {
@@ -101,19 +108,21 @@ impl OrderRepository {
     }
 
     /// PaymentIntent IDで注文存在チェック（Webhook二重処理防止用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::exists_by_payment_id")]
     pub async fn exists_by_payment_id(&self, payment_id: &str) -> Result<bool> {
-        let query = format!("payment_id=eq.{}&select=id", payment_id);
+        let query = Query::new().eq("payment_id", payment_id).select("id");
         let result: Vec<IdOnly> = self.client.select("orders", &query).await?;
         Ok(!result.is_empty())
     }
 
     /// ユーザーの注文履歴取得（N+1問題回避済み、アイテム含む）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_user")]
     pub async fn find_by_user(&self, user_id: Uuid, limit: i32) -> Result<Vec<Order>> {
         // 1. 注文一覧取得
-        let query = format!(
-            "user_id=eq.{}&order=created_at.desc&limit={}",
-            user_id, limit
-        );
+        let query = Query::new()
+            .eq("user_id", user_id)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let orders: Vec<OrderRow> = self.client.select("orders", &query).await?;
 
         if orders.is_empty() {
@@ -121,8 +130,7 @@ impl OrderRepository {
         }
 
         // 2. 全注文のIDを収集してアイテムを一括取得
-        let order_ids: Vec<String> = orders.iter().map(|o| o.id.to_string()).collect();
-        let item_query = format!("order_id=in.({})", order_ids.join(","));
+        let item_query = Query::new().in_list("order_id", orders.iter().map(|o| o.id));
         let item_rows: Vec<OrderItemRowWithOrderId> = self.client.select("order_items", &item_query).await?;
 
         // 3. order_idごとにアイテムをグルーピング
@@ -148,16 +156,18 @@ impl OrderRepository {
     }
 
     /// 注文アイテム取得
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_order_items")]
     async fn find_order_items(&self, order_id: Uuid) -> Result<Vec<OrderItem>> {
-        let query = format!("order_id=eq.{}", order_id);
+        let query = Query::new().eq("order_id", order_id);
         let rows: Vec<OrderItemRow> = self.client.select("order_items", &query).await?;
 
         Ok(rows.into_iter().map(|r| r.into_order_item()).collect())
     }
 
     /// ステータス更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_status")]
     pub async fn update_status(&self, id: Uuid, _user_id: Option<Uuid>, status: OrderStatus, _created_at: i64) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = StatusUpdate {
             status: status.to_string(),
             updated_at: Utc::now(),
@@ -169,8 +179,9 @@ impl OrderRepository {
 
     /// ステータス更新（条件付き：現在ステータスが一致する場合のみ更新する）
     /// - 競合（Webhook/リカバリ/ユーザー操作の同時実行）で二重在庫戻し等を起こさないために使用
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_status_if_current")]
     pub async fn update_status_if_current(&self, id: Uuid, current: OrderStatus, next: OrderStatus) -> Result<bool> {
-        let query = format!("id=eq.{}&status=eq.{}", id, current.to_string());
+        let query = Query::new().eq("id", id).eq("status", current.to_string());
         let update = StatusUpdate {
             status: next.to_string(),
             updated_at: Utc::now(),
@@ -180,26 +191,66 @@ impl OrderRepository {
         Ok(!updated.is_empty())
     }
 
+    /// 条件付きステータス＋決済ステータス更新（1リクエストで更新し、途中失敗で片方だけ反映されるのを防ぐ）
+    #[tracing::instrument(skip_all, name = "OrderRepository::transition_if_current")]
+    pub async fn transition_if_current(
+        &self,
+        id: Uuid,
+        current: OrderStatus,
+        next: OrderStatus,
+        payment_status: crate::models::PaymentStatus,
+    ) -> Result<bool> {
+        #[derive(Serialize)]
+        struct Update {
+            status: String,
+            payment_status: String,
+            updated_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id).eq("status", current.to_string());
+        let update = Update {
+            status: next.to_string(),
+            payment_status: serde_json::to_string(&payment_status).unwrap_or_default(),
+            updated_at: Utc::now(),
+        };
+
+        let updated: Vec<OrderRow> = self.client.update("orders", &query, &update).await?;
+        Ok(!updated.is_empty())
+    }
+
+    /// キャンセル注文の在庫解放（冪等: 解放済みならfalse、service_role必須）
+    #[tracing::instrument(skip_all, name = "OrderRepository::release_stock")]
+    pub async fn release_stock(&self, id: Uuid) -> Result<bool> {
+        #[derive(Serialize)]
+        struct Params {
+            p_order_id: Uuid,
+        }
+
+        self.client.rpc("release_order_stock", &Params { p_order_id: id }).await
+    }
+
     /// リカバリ用：決済待ち（PendingPayment）かつ一定時間経過した注文を取得
     /// - items は含まれないため、必要なら `find_by_id` で取得する
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_pending_payment_for_reconcile")]
     pub async fn find_pending_payment_for_reconcile(
         &self,
         created_before: DateTime<Utc>,
         limit: i32,
     ) -> Result<Vec<OrderReconcileRow>> {
-        let ts = created_before.to_rfc3339();
-        let query = format!(
-            "status=eq.pending_payment&created_at=lt.{}&select=id,created_at,payment_id&order=created_at.asc&limit={}",
-            urlencoding::encode(&ts),
-            limit
-        );
+        let query = Query::new()
+            .eq("status", "pending_payment")
+            .lt("created_at", created_before.to_rfc3339())
+            .select("id,created_at,payment_id")
+            .order("created_at", SortOrder::Asc)
+            .limit(limit.max(0) as usize);
         let rows: Vec<OrderReconcileRow> = self.client.select("orders", &query).await?;
         Ok(rows)
     }
 
     /// 決済ID更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_payment_id")]
     pub async fn update_payment_id(&self, id: Uuid, _user_id: Option<Uuid>, payment_id: &str) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = PaymentIdUpdate {
             payment_id: Some(payment_id.to_string()),
             updated_at: Utc::now(),
@@ -210,8 +261,9 @@ impl OrderRepository {
     }
 
     /// 決済ステータス更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_payment_status")]
     pub async fn update_payment_status(&self, id: Uuid, payment_status: crate::models::PaymentStatus) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = PaymentStatusUpdate {
             payment_status: serde_json::to_string(&payment_status).unwrap_or_default(),
             updated_at: Utc::now(),
@@ -222,9 +274,10 @@ impl OrderRepository {
     }
 
     /// 発送日時更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_shipped_at")]
     pub async fn update_shipped_at(&self, id: Uuid) -> Result<()> {
         let now = Utc::now();
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = ShippedAtUpdate {
             shipped_at: Some(now),
             updated_at: now,
@@ -235,9 +288,10 @@ impl OrderRepository {
     }
 
     /// 配達日時更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_delivered_at")]
     pub async fn update_delivered_at(&self, id: Uuid) -> Result<()> {
         let now = Utc::now();
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = DeliveredAtUpdate {
             delivered_at: Some(now),
             updated_at: now,
@@ -248,11 +302,12 @@ impl OrderRepository {
     }
 
     /// ゲストトークンで注文取得（直接クエリ版 - service_role用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_guest_token")]
     pub async fn find_by_guest_token(&self, token_hash: &str, order_id: Uuid) -> Result<Option<Order>> {
-        let query = format!(
-            "id=eq.{}&guest_access_token_hash=eq.{}&is_guest_order=eq.true",
-            order_id, token_hash
-        );
+        let query = Query::new()
+            .eq("id", order_id)
+            .eq("guest_access_token_hash", token_hash)
+            .eq("is_guest_order", true);
         let result: Option<OrderRow> = self.client.select_single("orders", &query).await?;
 
         if let Some(row) = result {
@@ -272,6 +327,7 @@ impl OrderRepository {
 
     /// ゲストトークンで注文取得（RPC関数版 - anon key用）
     /// SECURITY DEFINER関数を使用してトークン検証と取得を行う
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_guest_token_rpc")]
     pub async fn find_by_guest_token_rpc(&self, token_hash: &str, order_id: Uuid) -> Result<Option<Order>> {
         #[derive(serde::Serialize)]
         struct RpcParams {
@@ -298,6 +354,7 @@ impl OrderRepository {
     }
 
     /// ゲスト注文のアイテム取得（RPC関数版）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_guest_order_items_rpc")]
     async fn find_guest_order_items_rpc(&self, token_hash: &str, order_id: Uuid) -> Result<Vec<OrderItem>> {
         #[derive(serde::Serialize)]
         struct RpcParams {
@@ -315,6 +372,7 @@ impl OrderRepository {
     }
 
     /// ゲスト注文のpayment_id更新（RPC関数版 - トークン検証付き）
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_guest_order_payment_id_rpc")]
     pub async fn update_guest_order_payment_id_rpc(
         &self,
         order_id: Uuid,
@@ -338,6 +396,7 @@ impl OrderRepository {
     }
 
     /// ゲスト注文のステータス更新（RPC関数版 - トークン検証付き）
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_guest_order_status_rpc")]
     pub async fn update_guest_order_status_rpc(
         &self,
         order_id: Uuid,
@@ -364,7 +423,59 @@ impl OrderRepository {
         self.client.rpc("update_guest_order_status", &params).await
     }
 
+    /// 注文番号でゲスト注文を取得（照会用、アイテムなし - service_role用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_guest_by_order_number")]
+    pub async fn find_guest_by_order_number(&self, order_number: &str) -> Result<Option<Order>> {
+        let query = Query::new()
+            .eq("order_number", order_number)
+            .eq("is_guest_order", true)
+            .where_null("user_id");
+        let result: Option<OrderRow> = self.client.select_single("orders", &query).await?;
+        Ok(result.map(|row| row.into_order()))
+    }
+
+    /// ゲストアクセストークンを差し替え（旧トークンは無効になる - service_role用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::rotate_guest_token")]
+    pub async fn rotate_guest_token(&self, id: Uuid, token_hash: &str, expires_at: DateTime<Utc>) -> Result<bool> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            guest_access_token_hash: &'a str,
+            guest_token_expires_at: DateTime<Utc>,
+            updated_at: DateTime<Utc>,
+        }
+
+        let query = Query::new()
+            .eq("id", id)
+            .eq("is_guest_order", true)
+            .where_null("user_id")
+            .select("id");
+        let update = Update {
+            guest_access_token_hash: token_hash,
+            guest_token_expires_at: expires_at,
+            updated_at: Utc::now(),
+        };
+        let updated: Vec<IdOnly> = self.client.update("orders", &query, &update).await?;
+        Ok(!updated.is_empty())
+    }
+
+    /// メールアドレスが一致するゲスト注文をユーザーに紐付け、紐付けた注文IDを返す（service_role必須）
+    #[tracing::instrument(skip_all, name = "OrderRepository::claim_guest_orders")]
+    pub async fn claim_guest_orders(&self, user_id: Uuid, email: &str) -> Result<Vec<Uuid>> {
+        #[derive(Serialize)]
+        struct RpcParams<'a> {
+            p_user_id: Uuid,
+            p_email: &'a str,
+        }
+
+        let rows: Vec<IdOnly> = self
+            .client
+            .rpc("claim_guest_orders", &RpcParams { p_user_id: user_id, p_email: email })
+            .await?;
+        Ok(rows.into_iter().map(|row| row.id).collect())
+    }
+
     /// Webhook用の注文更新（RPC関数版 - ステータス遷移チェック付き）
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_order_from_webhook_rpc")]
     pub async fn update_order_from_webhook_rpc(
         &self,
         order_id: Uuid,
@@ -394,6 +505,7 @@ impl OrderRepository {
     }
 
     /// ゲスト注文作成（RPC関数版 - SECURITY DEFINER、注文とアイテムを同時作成）
+    #[tracing::instrument(skip_all, name = "OrderRepository::create_guest_order_rpc")]
     pub async fn create_guest_order_rpc(&self, order: &Order) -> Result<Order> {
         #[derive(serde::Serialize)]
         struct RpcParams {
@@ -418,41 +530,45 @@ impl OrderRepository {
             p_guest_token_expires_at: Option<DateTime<Utc>>,
         }
 
-        let params = RpcParams {
-            p_order_id: order.id,
-            p_order_number: order.order_number.clone(),
-            p_status: order.status.to_string(),
-            p_items: serde_json::to_value(&order.items).unwrap_or_default(),
-            p_subtotal: order.subtotal,
-            p_shipping_fee: order.shipping_fee,
-            p_tax: order.tax,
-            p_total: order.total,
-            p_currency: order.currency.clone(),
-            p_shipping_address: serde_json::to_value(&order.shipping_address).unwrap_or_default(),
-            p_billing_address: order.billing_address.as_ref().and_then(|a| serde_json::to_value(a).ok()),
-            p_payment_method: order.payment_method.to_string(),
-            p_payment_status: serde_json::to_string(&order.payment_status).unwrap_or_default(),
-            p_notes: order.notes.clone(),
-            p_guest_email: order.guest_email.clone(),
-            p_guest_name: order.guest_name.clone(),
-            p_guest_phone: order.guest_phone.clone(),
-            p_guest_access_token_hash: order.guest_access_token_hash.clone(),
-            p_guest_token_expires_at: order.guest_token_expires_at,
-        };
+        with_unique_order_number(order, |order| async move {
+            let params = RpcParams {
+                p_order_id: order.id,
+                p_order_number: order.order_number.clone(),
+                p_status: order.status.to_string(),
+                p_items: serde_json::to_value(&order.items).unwrap_or_default(),
+                p_subtotal: order.subtotal,
+                p_shipping_fee: order.shipping_fee,
+                p_tax: order.tax,
+                p_total: order.total,
+                p_currency: order.currency.clone(),
+                p_shipping_address: serde_json::to_value(&order.shipping_address).unwrap_or_default(),
+                p_billing_address: order.billing_address.as_ref().and_then(|a| serde_json::to_value(a).ok()),
+                p_payment_method: order.payment_method.to_string(),
+                p_payment_status: serde_json::to_string(&order.payment_status).unwrap_or_default(),
+                p_notes: order.notes.clone(),
+                p_guest_email: order.guest_email.clone(),
+                p_guest_name: order.guest_name.clone(),
+                p_guest_phone: order.guest_phone.clone(),
+                p_guest_access_token_hash: order.guest_access_token_hash.clone(),
+                p_guest_token_expires_at: order.guest_token_expires_at,
+            };
 
-        // 注文とアイテムを同時作成するRPC関数を呼び出し
-        let result: serde_json::Value = self.client.rpc("create_guest_order_with_items", &params).await?;
+            // 注文とアイテムを同時作成するRPC関数を呼び出し
+            let result: serde_json::Value = self.client.rpc("create_guest_order_with_items", &params).await?;
 
-        // RPC関数から返されたJSONBをOrderに変換
-        let order_row: OrderRow = serde_json::from_value(result)
-            .map_err(|e| crate::error::AppError::Database(format!("Parse error: {}", e)))?;
+            // RPC関数から返されたJSONBをOrderに変換
+            let order_row: OrderRow = serde_json::from_value(result)
+                .map_err(|e| AppError::Database(format!("Parse error: {}", e)))?;
 
-        let mut created_order = order_row.into_order();
-        created_order.items = order.items.clone();
-        Ok(created_order)
+            let mut created_order = order_row.into_order();
+            created_order.items = order.items;
+            Ok(created_order)
+        })
+        .await
     }
 
     /// Webhook用の注文取得（RPC関数版）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_by_id_for_webhook")]
     pub async fn find_by_id_for_webhook(&self, order_id: Uuid) -> Result<Option<Order>> {
         #[derive(serde::Serialize)]
         struct RpcParams {
@@ -472,6 +588,7 @@ impl OrderRepository {
     }
 
     /// Webhook用の注文アイテム取得（RPC関数版）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_order_items_for_webhook")]
     async fn find_order_items_for_webhook(&self, order_id: Uuid) -> Result<Vec<OrderItem>> {
         #[derive(serde::Serialize)]
         struct RpcParams {
@@ -484,8 +601,11 @@ impl OrderRepository {
     }
 
     /// 全注文取得（管理者用）
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_all")]
     pub async fn find_all(&self, limit: i32) -> Result<Vec<OrderSummary>> {
-        let query = format!("order=created_at.desc&limit={}", limit);
+        let query = Query::new()
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let orders: Vec<OrderRow> = self.client.select("orders", &query).await?;
 
         if orders.is_empty() {
@@ -493,8 +613,7 @@ impl OrderRepository {
         }
 
         // アイテム数を取得
-        let order_ids: Vec<String> = orders.iter().map(|o| o.id.to_string()).collect();
-        let item_query = format!("order_id=in.({})", order_ids.join(","));
+        let item_query = Query::new().in_list("order_id", orders.iter().map(|o| o.id));
         let items: Vec<OrderItemWithOrderId> = self.client.select("order_items", &item_query).await?;
 
         let mut item_counts: std::collections::HashMap<Uuid, i32> = std::collections::HashMap::new();
@@ -524,18 +643,62 @@ impl OrderRepository {
         Ok(summaries)
     }
 
+    /// 出荷キュー（管理者用、古い順）
+    /// 予約明細の引当待ちで出荷できない注文は含めない
+    #[tracing::instrument(skip_all, name = "OrderRepository::find_fulfillment_queue")]
+    pub async fn find_fulfillment_queue(&self, limit: i32) -> Result<Vec<FulfillmentQueueEntry>> {
+        let query = Query::new()
+            .in_list("status", ["paid", "processing"])
+            .order("created_at", SortOrder::Asc)
+            .limit(limit.max(0) as usize);
+        let orders: Vec<OrderRow> = self.client.select("orders", &query).await?;
+
+        if orders.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let item_query = Query::new().in_list("order_id", orders.iter().map(|o| o.id));
+        let rows: Vec<OrderItemRowWithOrderId> = self.client.select("order_items", &item_query).await?;
+
+        let mut items_by_order: std::collections::HashMap<Uuid, Vec<OrderItem>> = std::collections::HashMap::new();
+        for row in rows {
+            items_by_order.entry(row.order_id).or_default().push(row.into_order_item());
+        }
+
+        let entries = orders
+            .into_iter()
+            .filter_map(|row| {
+                let mut order = row.into_order();
+                order.items = items_by_order.remove(&order.id).unwrap_or_default();
+                if !is_ready_for_fulfillment(&order.items, order.split_shipment) {
+                    return None;
+                }
+                let awaiting_release_items = order.items.iter().filter(|i| is_awaiting_release(i)).count() as i32;
+                Some(FulfillmentQueueEntry {
+                    split_shipment: order.split_shipment,
+                    awaiting_release_items,
+                    order: order.into(),
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
     // ============================================
     // JPYC決済関連メソッド
     // ============================================
 
     /// IDで注文取得（JPYC用、find_by_idのエイリアス）
+    #[tracing::instrument(skip_all, name = "OrderRepository::get_by_id")]
     pub async fn get_by_id(&self, id: Uuid) -> Result<Option<Order>> {
         self.find_by_id(id).await
     }
 
     /// トランザクションハッシュで注文検索（二重使用防止）
+    #[tracing::instrument(skip_all, name = "OrderRepository::get_by_crypto_tx_hash")]
     pub async fn get_by_crypto_tx_hash(&self, tx_hash: &str) -> Result<Option<Order>> {
-        let query = format!("crypto_tx_hash=eq.{}", tx_hash);
+        let query = Query::new().eq("crypto_tx_hash", tx_hash);
         let result: Option<OrderRow> = self.client.select_single("orders", &query).await?;
 
         if let Some(row) = result {
@@ -548,6 +711,7 @@ impl OrderRepository {
     }
 
     /// JPYC決済イベント記録（冪等性保証）
+    #[tracing::instrument(skip_all, name = "OrderRepository::record_jpyc_payment_event")]
     pub async fn record_jpyc_payment_event(
         &self,
         tx_hash: &str,
@@ -599,11 +763,12 @@ impl OrderRepository {
                 is_new: result.is_new,
             })
         } else {
-            Err(crate::error::AppError::Database("Failed to record JPYC payment event".to_string()))
+            Err(AppError::Database("Failed to record JPYC payment event".to_string()))
         }
     }
 
     /// JPYC決済で注文を更新
+    #[tracing::instrument(skip_all, name = "OrderRepository::update_jpyc_payment")]
     pub async fn update_jpyc_payment(
         &self,
         order_id: Uuid,
@@ -611,7 +776,7 @@ impl OrderRepository {
         chain_id: i32,
         sender_address: &str,
     ) -> Result<()> {
-        let query = format!("id=eq.{}", order_id);
+        let query = Query::new().eq("id", order_id);
         let update = JpycPaymentUpdate {
             status: "paid".to_string(),
             payment_status: "\"paid\"".to_string(),
@@ -645,9 +810,64 @@ struct JpycPaymentUpdate {
     updated_at: DateTime<Utc>,
 }
 
+/// ordersテーブルの挿入行（REST・Postgres直接接続で共通）
+pub(crate) fn order_input(order: &Order) -> OrderInput {
+    OrderInput {
+        id: order.id,
+        user_id: order.user_id,
+        order_number: order.order_number.clone(),
+        status: order.status.to_string(),
+        subtotal: order.subtotal,
+        shipping_fee: order.shipping_fee,
+        tax: order.tax,
+        total: order.total,
+        currency: order.currency.clone(),
+        shipping_address: serde_json::to_value(&order.shipping_address).unwrap_or_default(),
+        billing_address: order.billing_address.as_ref().and_then(|a| serde_json::to_value(a).ok()),
+        payment_method: order.payment_method.to_string(),
+        payment_status: serde_json::to_string(&order.payment_status).unwrap_or_default(),
+        payment_id: order.payment_id.clone(),
+        notes: order.notes.clone(),
+        created_at: order.created_at,
+        updated_at: order.updated_at,
+        // ゲスト注文用フィールド
+        is_guest_order: if order.is_guest_order { Some(true) } else { None },
+        guest_email: order.guest_email.clone(),
+        guest_name: order.guest_name.clone(),
+        guest_phone: order.guest_phone.clone(),
+        guest_access_token_hash: order.guest_access_token_hash.clone(),
+        guest_token_expires_at: order.guest_token_expires_at,
+        split_shipment: if order.split_shipment { Some(true) } else { None },
+    }
+}
+
+/// order_itemsテーブルの挿入行
+pub(crate) fn order_item_inputs(order: &Order) -> Vec<OrderItemInput> {
+    order
+        .items
+        .iter()
+        .map(|item| OrderItemInput {
+            id: Uuid::new_v4(),
+            order_id: order.id,
+            order_created_at: order.created_at,
+            product_id: Some(item.product_id),
+            product_name: item.product_name.clone(),
+            product_sku: Some(item.product_sku.clone()),
+            price: item.price,
+            quantity: item.quantity,
+            subtotal: item.subtotal,
+            image_url: item.image_url.clone(),
+            variant_id: item.variant_id,
+            size: item.size.clone(),
+            is_preorder: item.is_preorder,
+            expected_ship_date: item.expected_ship_date,
+        })
+        .collect()
+}
+
 // Supabase REST API用の構造体
 #[derive(Debug, Serialize)]
-struct OrderInput {
+pub(crate) struct OrderInput {
     id: Uuid,
     #[serde(skip_serializing_if = "Option::is_none")]
     user_id: Option<Uuid>,
@@ -679,10 +899,12 @@ struct OrderInput {
     guest_access_token_hash: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     guest_token_expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_shipment: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
-struct OrderItemInput {
+pub(crate) struct OrderItemInput {
     id: Uuid,
     order_id: Uuid,
     order_created_at: DateTime<Utc>,
@@ -697,6 +919,10 @@ struct OrderItemInput {
     variant_id: Option<Uuid>,
     #[serde(skip_serializing_if = "Option::is_none")]
     size: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    is_preorder: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_ship_date: Option<NaiveDate>,
 }
 
 #[derive(Debug, Serialize)]
@@ -767,6 +993,8 @@ struct OrderRow {
     crypto_sender_address: Option<String>,
     #[serde(default)]
     crypto_confirmed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    split_shipment: bool,
 }
 
 impl OrderRow {
@@ -805,6 +1033,7 @@ impl OrderRow {
             crypto_chain_id: self.crypto_chain_id,
             crypto_sender_address: self.crypto_sender_address,
             crypto_confirmed_at: self.crypto_confirmed_at,
+            split_shipment: self.split_shipment,
         }
     }
 }
@@ -824,6 +1053,12 @@ struct OrderItemRow {
     image_url: Option<String>,
     variant_id: Option<Uuid>,
     size: Option<String>,
+    #[serde(default)]
+    is_preorder: bool,
+    #[serde(default)]
+    expected_ship_date: Option<NaiveDate>,
+    #[serde(default)]
+    preorder_released_at: Option<DateTime<Utc>>,
 }
 
 impl OrderItemRow {
@@ -838,13 +1073,15 @@ impl OrderItemRow {
             image_url: self.image_url,
             variant_id: self.variant_id,
             size: self.size,
+            is_preorder: self.is_preorder,
+            expected_ship_date: self.expected_ship_date,
+            preorder_released_at: self.preorder_released_at,
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
 struct IdOnly {
-    #[allow(dead_code)]
     id: Uuid,
 }
 
@@ -869,6 +1106,12 @@ struct OrderItemRowWithOrderId {
     image_url: Option<String>,
     variant_id: Option<Uuid>,
     size: Option<String>,
+    #[serde(default)]
+    is_preorder: bool,
+    #[serde(default)]
+    expected_ship_date: Option<NaiveDate>,
+    #[serde(default)]
+    preorder_released_at: Option<DateTime<Utc>>,
 }
 
 impl OrderItemRowWithOrderId {
@@ -883,6 +1126,9 @@ impl OrderItemRowWithOrderId {
             image_url: self.image_url,
             variant_id: self.variant_id,
             size: self.size,
+            is_preorder: self.is_preorder,
+            expected_ship_date: self.expected_ship_date,
+            preorder_released_at: self.preorder_released_at,
         }
     }
 }
@@ -894,3 +1140,19 @@ pub struct OrderReconcileRow {
     pub created_at: DateTime<Utc>,
     pub payment_id: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_order_number_constraint() {
+        assert!(is_order_number_constraint("order_numbers_pkey"));
+        assert!(is_order_number_constraint("orders_order_number_key"));
+        assert!(is_order_number_constraint("orders_2025_01_order_number_created_at_key"));
+        // 他の一意制約（冪等キー・注文変更など）では番号を振り直さない
+        assert!(!is_order_number_constraint("orders_pkey"));
+        assert!(!is_order_number_constraint("order_modifications_order_id_key"));
+        assert!(!is_order_number_constraint(""));
+    }
+}
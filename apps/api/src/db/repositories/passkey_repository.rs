@@ -0,0 +1,193 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::models::SortOrder;
+use crate::error::Result;
+use crate::services::webauthn::CHALLENGE_TTL_SECONDS;
+
+/// 登録済みパスキー
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasskeyCredential {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub user_id: Uuid,
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub public_key: String,
+    #[serde(skip_serializing)]
+    pub sign_count: i64,
+    pub name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// チャレンジ種別
+#[derive(Debug, Clone, Copy)]
+pub enum WebAuthnCeremony {
+    Registration,
+    Authentication,
+}
+
+impl WebAuthnCeremony {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WebAuthnCeremony::Registration => "registration",
+            WebAuthnCeremony::Authentication => "authentication",
+        }
+    }
+}
+
+/// 消費したチャレンジ
+#[derive(Debug, Deserialize)]
+pub struct ConsumedChallenge {
+    pub challenge: String,
+    pub user_id: Option<Uuid>,
+}
+
+pub struct PasskeyRepository {
+    client: AuthenticatedClient,
+}
+
+impl PasskeyRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// チャレンジを保存してIDを返す
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::create_challenge")]
+    pub async fn create_challenge(
+        &self,
+        challenge: &str,
+        ceremony: WebAuthnCeremony,
+        user_id: Option<Uuid>,
+    ) -> Result<Uuid> {
+        let input = ChallengeInput {
+            id: Uuid::new_v4(),
+            user_id,
+            challenge: challenge.to_string(),
+            ceremony: ceremony.as_str().to_string(),
+            expires_at: Utc::now() + Duration::seconds(CHALLENGE_TTL_SECONDS),
+        };
+
+        let row: ChallengeRow = self.client.insert("webauthn_challenges", &input).await?;
+        Ok(row.id)
+    }
+
+    /// チャレンジを消費（期限切れ・使用済みはNone）
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::consume_challenge")]
+    pub async fn consume_challenge(
+        &self,
+        id: Uuid,
+        ceremony: WebAuthnCeremony,
+    ) -> Result<Option<ConsumedChallenge>> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_id: Uuid,
+            p_ceremony: &'a str,
+        }
+
+        let rows: Vec<ConsumedChallenge> = self
+            .client
+            .rpc("consume_webauthn_challenge", &Params { p_id: id, p_ceremony: ceremony.as_str() })
+            .await?;
+        Ok(rows.into_iter().next())
+    }
+
+    /// 期限切れのチャレンジを削除（消費されずに残ったもの）
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::purge_expired_challenges")]
+    pub async fn purge_expired_challenges(&self) -> Result<()> {
+        let query = Query::new().lt("expires_at", Utc::now().to_rfc3339());
+        self.client.delete("webauthn_challenges", &query).await
+    }
+
+    /// パスキー登録
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::create")]
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        credential_id: &str,
+        public_key: &str,
+        sign_count: u32,
+        name: Option<String>,
+    ) -> Result<PasskeyCredential> {
+        let input = CredentialInput {
+            id: Uuid::new_v4(),
+            user_id,
+            credential_id: credential_id.to_string(),
+            public_key: public_key.to_string(),
+            sign_count: sign_count as i64,
+            name,
+            created_at: Utc::now(),
+        };
+
+        self.client.insert("passkey_credentials", &input).await
+    }
+
+    /// クレデンシャルIDで取得
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::find_by_credential_id")]
+    pub async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<PasskeyCredential>> {
+        let query = Query::new().eq("credential_id", credential_id);
+        self.client.select_single("passkey_credentials", &query).await
+    }
+
+    /// ユーザーのパスキー一覧
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::find_by_user")]
+    pub async fn find_by_user(&self, user_id: Uuid) -> Result<Vec<PasskeyCredential>> {
+        let query = Query::new().eq("user_id", user_id).order("created_at", SortOrder::Desc);
+        self.client.select("passkey_credentials", &query).await
+    }
+
+    /// 認証成功時に署名カウンタと最終利用日時を更新
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::update_usage")]
+    pub async fn update_usage(&self, id: Uuid, sign_count: u32) -> Result<()> {
+        let query = Query::new().eq("id", id);
+        let update = UsageUpdate {
+            sign_count: sign_count as i64,
+            last_used_at: Utc::now(),
+        };
+
+        let _: Vec<PasskeyCredential> = self.client.update("passkey_credentials", &query, &update).await?;
+        Ok(())
+    }
+
+    /// パスキー削除
+    #[tracing::instrument(skip_all, name = "PasskeyRepository::delete")]
+    pub async fn delete(&self, user_id: Uuid, id: Uuid) -> Result<()> {
+        let query = Query::new().eq("user_id", user_id).eq("id", id);
+        self.client.delete("passkey_credentials", &query).await
+    }
+}
+
+// Supabase REST API用の構造体
+#[derive(Debug, Serialize)]
+struct ChallengeInput {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    challenge: String,
+    ceremony: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengeRow {
+    id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+struct CredentialInput {
+    id: Uuid,
+    user_id: Uuid,
+    credential_id: String,
+    public_key: String,
+    sign_count: i64,
+    name: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageUpdate {
+    sign_count: i64,
+    last_used_at: DateTime<Utc>,
+}
@@ -0,0 +1,265 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+use crate::models::{ProductAlert, ProductAlertKind, ProductAlertStatus, SortOrder};
+
+/// 再入荷・値下げ通知リポジトリ（service_role必須）
+pub struct ProductAlertRepository {
+    client: AuthenticatedClient,
+}
+
+/// 購読作成の入力
+#[derive(Debug, Serialize)]
+pub struct NewProductAlert {
+    pub kind: ProductAlertKind,
+    pub product_id: Uuid,
+    pub variant_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub email: String,
+    pub target_price: Option<i64>,
+    /// ゲストは pending_confirmation（確認リンクを開くまで通知しない）
+    pub status: ProductAlertStatus,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct AlertUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<ProductAlertStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queued_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sent_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirmed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+}
+
+/// 未完了（重複購読の判定対象）の状態
+const OPEN_STATUSES: [&str; 4] = ["pending_confirmation", "active", "queued", "sending"];
+
+impl ProductAlertRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// 購読作成
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::create")]
+    pub async fn create(&self, input: &NewProductAlert) -> Result<ProductAlert> {
+        self.client.insert("product_alerts", input).await
+    }
+
+    /// 同じ宛先・商品・バリアント・種別の未完了の購読
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::find_open")]
+    pub async fn find_open(
+        &self,
+        email: &str,
+        kind: ProductAlertKind,
+        product_id: Uuid,
+        variant_id: Option<Uuid>,
+    ) -> Result<Option<ProductAlert>> {
+        let query = Query::new()
+            .eq("email", email)
+            .eq("kind", kind)
+            .eq("product_id", product_id)
+            .in_list("status", OPEN_STATUSES);
+        let query = match variant_id {
+            Some(id) => query.eq("variant_id", id),
+            None => query.where_null("variant_id"),
+        };
+        self.client.select_single("product_alerts", &query).await
+    }
+
+    /// 宛先ごとの未完了の購読数（大量登録の防止）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::count_open_by_email")]
+    pub async fn count_open_by_email(&self, email: &str) -> Result<usize> {
+        let query = Query::new()
+            .eq("email", email)
+            .in_list("status", OPEN_STATUSES)
+            .select("id");
+        let rows: Vec<serde_json::Value> = self.client.select("product_alerts", &query).await?;
+        Ok(rows.len())
+    }
+
+    /// ユーザーの購読一覧（新しい順）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::list_by_user")]
+    pub async fn list_by_user(&self, user_id: Uuid, limit: usize) -> Result<Vec<ProductAlert>> {
+        let query = Query::new()
+            .eq("user_id", user_id)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit);
+        self.client.select("product_alerts", &query).await
+    }
+
+    /// ユーザーの購読を解除（解除できたか）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::cancel_for_user")]
+    pub async fn cancel_for_user(&self, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let query = Query::new()
+            .eq("id", id)
+            .eq("user_id", user_id)
+            .in_list("status", OPEN_STATUSES);
+        self.cancel(&query).await
+    }
+
+    /// 宛先の未完了の購読をすべて解除（解除リンク用、件数を返す）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::cancel_by_email")]
+    pub async fn cancel_by_email(&self, email: &str) -> Result<usize> {
+        let query = Query::new().eq("email", email).in_list("status", OPEN_STATUSES);
+        self.cancel_matching(&query).await
+    }
+
+    /// 購読を取り消す（確認メールを送れなかった等）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::cancel_by_id")]
+    pub async fn cancel_by_id(&self, id: Uuid) -> Result<bool> {
+        let query = Query::new().eq("id", id).in_list("status", OPEN_STATUSES);
+        self.cancel(&query).await
+    }
+
+    /// 確認期限を過ぎた確認待ちの購読を取り消す（件数を返す）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::cancel_unconfirmed")]
+    pub async fn cancel_unconfirmed(&self, created_before: DateTime<Utc>) -> Result<usize> {
+        let query = Query::new()
+            .eq("status", ProductAlertStatus::PendingConfirmation)
+            .lt("created_at", created_before.to_rfc3339());
+        self.cancel_matching(&query).await
+    }
+
+    async fn cancel(&self, query: &Query) -> Result<bool> {
+        Ok(self.cancel_matching(query).await? > 0)
+    }
+
+    async fn cancel_matching(&self, query: &Query) -> Result<usize> {
+        let update = AlertUpdate {
+            status: Some(ProductAlertStatus::Cancelled),
+            ..Default::default()
+        };
+        let rows: Vec<ProductAlert> = self.client.update("product_alerts", query, &update).await?;
+        Ok(rows.len())
+    }
+
+    /// 確認待ちの購読を有効にする（期限切れで取り消し済み・確認済みは None）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::confirm")]
+    pub async fn confirm(&self, id: Uuid) -> Result<Option<ProductAlert>> {
+        let query = Query::new()
+            .eq("id", id)
+            .eq("status", ProductAlertStatus::PendingConfirmation);
+        let update = AlertUpdate {
+            status: Some(ProductAlertStatus::Active),
+            confirmed_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        let mut rows: Vec<ProductAlert> = self.client.update("product_alerts", &query, &update).await?;
+        Ok(rows.pop())
+    }
+
+    /// 再入荷: 待機中の購読を送信待ちにする（件数を返す）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::queue_back_in_stock")]
+    pub async fn queue_back_in_stock(&self, product_id: Uuid, variant_id: Option<Uuid>) -> Result<usize> {
+        let query = Query::new()
+            .eq("status", ProductAlertStatus::Active)
+            .eq("kind", ProductAlertKind::BackInStock)
+            .eq("product_id", product_id);
+        let query = match variant_id {
+            Some(id) => query.eq("variant_id", id),
+            None => query.where_null("variant_id"),
+        };
+        self.queue(&query).await
+    }
+
+    /// 値下げ: 基準価格を下回った購読を送信待ちにする（件数を返す）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::queue_price_drop")]
+    pub async fn queue_price_drop(&self, product_id: Uuid, price: i64) -> Result<usize> {
+        let query = Query::new()
+            .eq("status", ProductAlertStatus::Active)
+            .eq("kind", ProductAlertKind::PriceDrop)
+            .eq("product_id", product_id)
+            .gt("target_price", price);
+        self.queue(&query).await
+    }
+
+    async fn queue(&self, query: &Query) -> Result<usize> {
+        let update = AlertUpdate {
+            status: Some(ProductAlertStatus::Queued),
+            queued_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        let rows: Vec<ProductAlert> = self.client.update("product_alerts", query, &update).await?;
+        Ok(rows.len())
+    }
+
+    /// 送信対象を宛先単位で取得（直近に通知済みの宛先は除外）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::claim_batch")]
+    pub async fn claim_batch(
+        &self,
+        recipients: i32,
+        recipient_interval_seconds: i32,
+        lock_seconds: i32,
+    ) -> Result<Vec<ProductAlert>> {
+        #[derive(Serialize)]
+        struct Params {
+            p_recipients: i32,
+            p_recipient_interval_seconds: i32,
+            p_lock_seconds: i32,
+        }
+
+        self.client
+            .rpc(
+                "claim_product_alerts",
+                &Params {
+                    p_recipients: recipients,
+                    p_recipient_interval_seconds: recipient_interval_seconds,
+                    p_lock_seconds: lock_seconds,
+                },
+            )
+            .await
+    }
+
+    /// 通知済み（購読終了）にする
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::mark_sent")]
+    pub async fn mark_sent(&self, ids: &[Uuid]) -> Result<()> {
+        let update = AlertUpdate {
+            status: Some(ProductAlertStatus::Sent),
+            sent_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        self.update_many(ids, &update).await
+    }
+
+    /// 条件を満たさなくなった購読を待機中に戻す（再入荷直後に売り切れた等）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::reactivate")]
+    pub async fn reactivate(&self, ids: &[Uuid]) -> Result<()> {
+        let update = AlertUpdate {
+            status: Some(ProductAlertStatus::Active),
+            ..Default::default()
+        };
+        self.update_many(ids, &update).await
+    }
+
+    /// 送信失敗を記録（retry: 送信待ちに戻す / false: failed）
+    #[tracing::instrument(skip_all, name = "ProductAlertRepository::mark_failed")]
+    pub async fn mark_failed(&self, ids: &[Uuid], error: &str, retry: bool) -> Result<()> {
+        let update = AlertUpdate {
+            status: Some(if retry {
+                ProductAlertStatus::Queued
+            } else {
+                ProductAlertStatus::Failed
+            }),
+            last_error: Some(error.to_string()),
+            ..Default::default()
+        };
+        self.update_many(ids, &update).await
+    }
+
+    async fn update_many(&self, ids: &[Uuid], update: &AlertUpdate) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let query = Query::new().in_list("id", ids);
+        let _: Vec<ProductAlert> = self.client.update("product_alerts", &query, update).await?;
+        Ok(())
+    }
+}
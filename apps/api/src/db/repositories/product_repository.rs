@@ -1,11 +1,18 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::{AppError, Result};
-use crate::models::{CategorySummary, Product, ProductSummary, ProductVariant};
+use crate::models::{CategorySummary, PreorderSettings, Product, ProductSummary, ProductVariant, SortOrder};
+
+/// 商品詳細の取得カラム（カテゴリを埋め込み）
+const PRODUCT_SELECT: &str = "*,categories(id,slug,name)";
+
+/// 商品一覧の取得カラム
+const PRODUCT_SUMMARY_SELECT: &str =
+    "id,slug,name,price,compare_at_price,currency,images,is_active,categories(id,slug,name)";
 
 pub struct ProductRepository {
     client: AuthenticatedClient,
@@ -17,6 +24,7 @@ impl ProductRepository {
     }
 
     /// 商品作成
+    #[tracing::instrument(skip_all, name = "ProductRepository::create")]
     pub async fn create(&self, product: &Product) -> Result<Product> {
         let input = ProductInput {
             id: product.id,
@@ -35,6 +43,9 @@ impl ProductRepository {
             is_featured: product.is_featured,
             tags: product.tags.clone().unwrap_or_default(),
             metadata: serde_json::to_value(&product.metadata).unwrap_or_default(),
+            preorder_enabled: product.preorder.preorder_enabled,
+            preorder_cap: product.preorder.preorder_cap,
+            expected_ship_date: product.preorder.expected_ship_date,
             created_at: product.created_at,
             updated_at: product.updated_at,
         };
@@ -44,20 +55,21 @@ impl ProductRepository {
     }
 
     /// IDで商品取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_by_id")]
     pub async fn find_by_id(&self, id: Uuid) -> Result<Option<Product>> {
-        let query = format!("id=eq.{}&select=*,categories(id,slug,name)", id);
+        let query = Query::new().eq("id", id).select(PRODUCT_SELECT);
         let result: Option<ProductWithCategory> = self.client.select_single("products", &query).await?;
         Ok(result.map(|r| r.into_product()))
     }
 
     /// 複数IDで商品一括取得（N+1問題回避用）
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_by_ids")]
     pub async fn find_by_ids(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, Product>> {
         if ids.is_empty() {
             return Ok(HashMap::new());
         }
 
-        let ids_str = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
-        let query = format!("id=in.({})", ids_str);
+        let query = Query::new().in_list("id", ids);
         let results: Vec<ProductWithCategory> = self.client.select("products", &query).await?;
 
         Ok(results.into_iter().map(|r| {
@@ -67,45 +79,75 @@ impl ProductRepository {
     }
 
     /// スラッグで商品取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_by_slug")]
     pub async fn find_by_slug(&self, slug: &str) -> Result<Option<Product>> {
-        let query = format!("slug=eq.{}&select=*,categories(id,slug,name)", urlencoding::encode(slug));
+        let query = Query::new().eq("slug", slug).select(PRODUCT_SELECT);
         let result: Option<ProductWithCategory> = self.client.select_single("products", &query).await?;
         Ok(result.map(|r| r.into_product()))
     }
 
     /// カテゴリ別商品一覧取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_by_category")]
     pub async fn find_by_category(&self, category_id: Uuid, limit: i32) -> Result<Vec<ProductSummary>> {
-        let query = format!(
-            "category_id=eq.{}&is_active=eq.true&select=id,slug,name,price,compare_at_price,currency,images,is_active,categories(id,slug,name)&order=created_at.desc&limit={}",
-            category_id, limit
-        );
+        let query = Query::new()
+            .eq("category_id", category_id)
+            .eq("is_active", true)
+            .select(PRODUCT_SUMMARY_SELECT)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let results: Vec<ProductSummaryWithCategory> = self.client.select("products", &query).await?;
         Ok(results.into_iter().map(|r| r.into_product_summary()).collect())
     }
 
+    /// カテゴリ別商品取得（ページ単位、総件数付き）
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_page_by_category")]
+    pub async fn find_page_by_category(
+        &self,
+        category_id: Uuid,
+        offset: i64,
+        limit: i32,
+    ) -> Result<(Vec<ProductSummary>, i64)> {
+        let offset = offset.max(0) as u64;
+        let query = Query::new()
+            .eq("category_id", category_id)
+            .eq("is_active", true)
+            .select(PRODUCT_SUMMARY_SELECT)
+            .order("created_at", SortOrder::Desc)
+            .range(offset, offset + limit.max(1) as u64 - 1);
+        let (results, total): (Vec<ProductSummaryWithCategory>, i64) =
+            self.client.select_with_count("products", &query).await?;
+        Ok((results.into_iter().map(|r| r.into_product_summary()).collect(), total))
+    }
+
     /// 注目商品取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_featured")]
     pub async fn find_featured(&self, limit: i32) -> Result<Vec<ProductSummary>> {
-        let query = format!(
-            "is_featured=eq.true&is_active=eq.true&select=id,slug,name,price,compare_at_price,currency,images,is_active,categories(id,slug,name)&order=created_at.desc&limit={}",
-            limit
-        );
+        let query = Query::new()
+            .eq("is_featured", true)
+            .eq("is_active", true)
+            .select(PRODUCT_SUMMARY_SELECT)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let results: Vec<ProductSummaryWithCategory> = self.client.select("products", &query).await?;
         Ok(results.into_iter().map(|r| r.into_product_summary()).collect())
     }
 
     /// 全商品取得（ページネーション付き）
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_all")]
     pub async fn find_all(&self, limit: i32) -> Result<Vec<Product>> {
-        let query = format!(
-            "is_active=eq.true&select=*,categories(id,slug,name)&order=created_at.desc&limit={}",
-            limit
-        );
+        let query = Query::new()
+            .eq("is_active", true)
+            .select(PRODUCT_SELECT)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let results: Vec<ProductWithCategory> = self.client.select("products", &query).await?;
         Ok(results.into_iter().map(|r| r.into_product()).collect())
     }
 
     /// 在庫更新
+    #[tracing::instrument(skip_all, name = "ProductRepository::update_stock")]
     pub async fn update_stock(&self, id: Uuid, stock: i32) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = StockUpdate {
             stock,
             updated_at: Utc::now(),
@@ -117,6 +159,7 @@ impl ProductRepository {
 
     /// 在庫確保（原子操作: 同時購入で在庫マイナスにならないようにする）
     /// - `items`: [(product_id, quantity), ...]
+    #[tracing::instrument(skip_all, name = "ProductRepository::reserve_stock_bulk")]
     pub async fn reserve_stock_bulk(&self, items: &[(Uuid, i32)]) -> Result<bool> {
         #[derive(Serialize)]
         struct Item {
@@ -142,12 +185,11 @@ impl ProductRepository {
 
         match self.client.rpc::<_, bool>("reserve_stock_bulk", &params).await {
             Ok(ok) => Ok(ok),
-            Err(AppError::Database(msg))
-                if msg.contains("\"code\":\"PGRST202\"") && msg.contains("reserve_stock_bulk") =>
+            Err(AppError::Postgrest(err)) if err.is_not_found_in_schema() =>
             {
                 Err(AppError::Internal(
                     "在庫確保RPC（reserve_stock_bulk）がDBに存在しません。\
-                    Supabase側へ `apps/api/src/db/schema.rs` のSQL（reserve_stock_bulk / release_stock_bulk）を適用してください。\
+                    `spirom-api migrate` でマイグレーション（migrations/000_initial_schema.sql）を適用してください。\
                     適用後、PostgRESTのスキーマキャッシュ反映に少し時間がかかる場合があります。"
                         .to_string(),
                 ))
@@ -157,6 +199,7 @@ impl ProductRepository {
     }
 
     /// 在庫解放（原子操作）
+    #[tracing::instrument(skip_all, name = "ProductRepository::release_stock_bulk")]
     pub async fn release_stock_bulk(&self, items: &[(Uuid, i32)]) -> Result<bool> {
         #[derive(Serialize)]
         struct Item {
@@ -182,12 +225,11 @@ impl ProductRepository {
 
         match self.client.rpc::<_, bool>("release_stock_bulk", &params).await {
             Ok(ok) => Ok(ok),
-            Err(AppError::Database(msg))
-                if msg.contains("\"code\":\"PGRST202\"") && msg.contains("release_stock_bulk") =>
+            Err(AppError::Postgrest(err)) if err.is_not_found_in_schema() =>
             {
                 Err(AppError::Internal(
                     "在庫解放RPC（release_stock_bulk）がDBに存在しません。\
-                    Supabase側へ `apps/api/src/db/schema.rs` のSQL（reserve_stock_bulk / release_stock_bulk）を適用してください。\
+                    `spirom-api migrate` でマイグレーション（migrations/000_initial_schema.sql）を適用してください。\
                     適用後、PostgRESTのスキーマキャッシュ反映に少し時間がかかる場合があります。"
                         .to_string(),
                 ))
@@ -196,15 +238,43 @@ impl ProductRepository {
         }
     }
 
+    /// 予約枠の確保（原子操作: 商品・バリアントの受付上限を超えないようにする）
+    /// - `items`: [(product_id, variant_id, quantity), ...]
+    #[tracing::instrument(skip_all, name = "ProductRepository::reserve_preorder_bulk")]
+    pub async fn reserve_preorder_bulk(&self, items: &[(Uuid, Option<Uuid>, i32)]) -> Result<bool> {
+        self.client.rpc("reserve_preorder_bulk", &PreorderParams::new(items)).await
+    }
+
+    /// 予約枠の解放
+    #[tracing::instrument(skip_all, name = "ProductRepository::release_preorder_bulk")]
+    pub async fn release_preorder_bulk(&self, items: &[(Uuid, Option<Uuid>, i32)]) -> Result<bool> {
+        self.client.rpc("release_preorder_bulk", &PreorderParams::new(items)).await
+    }
+
+    /// 入荷した在庫を予約明細に引き当てる（注文の古い順）。引き当てた明細数を返す
+    #[tracing::instrument(skip_all, name = "ProductRepository::release_preorders")]
+    pub async fn release_preorders(&self, product_id: Uuid) -> Result<i32> {
+        #[derive(Serialize)]
+        struct Params {
+            p_product_id: Uuid,
+        }
+
+        self.client
+            .rpc("release_product_preorders", &Params { p_product_id: product_id })
+            .await
+    }
+
     /// 商品削除
+    #[tracing::instrument(skip_all, name = "ProductRepository::delete")]
     pub async fn delete(&self, id: Uuid) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         self.client.delete("products", &query).await
     }
 
     /// 商品更新
+    #[tracing::instrument(skip_all, name = "ProductRepository::update")]
     pub async fn update(&self, id: Uuid, updates: &ProductUpdateInput) -> Result<Product> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let results: Vec<ProductRow> = self.client.update("products", &query, updates).await?;
         results
             .into_iter()
@@ -216,13 +286,27 @@ impl ProductRepository {
     // ========== バリアント関連 ==========
 
     /// 商品のバリアント一覧取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_variants_by_product")]
     pub async fn find_variants_by_product(&self, product_id: Uuid) -> Result<Vec<ProductVariant>> {
-        let query = format!("product_id=eq.{}&order=sort_order.asc", product_id);
+        let query = Query::new().eq("product_id", product_id).order("sort_order", SortOrder::Asc);
         let rows: Vec<VariantRow> = self.client.select("product_variants", &query).await?;
         Ok(rows.into_iter().map(|r| r.into_variant()).collect())
     }
 
+    /// 複数IDでバリアント一括取得
+    #[tracing::instrument(skip_all, name = "ProductRepository::find_variants_by_ids")]
+    pub async fn find_variants_by_ids(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, ProductVariant>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let query = Query::new().in_list("id", ids);
+        let rows: Vec<VariantRow> = self.client.select("product_variants", &query).await?;
+        Ok(rows.into_iter().map(|r| (r.id, r.into_variant())).collect())
+    }
+
     /// バリアント作成
+    #[tracing::instrument(skip_all, name = "ProductRepository::create_variant")]
     pub async fn create_variant(&self, variant: &ProductVariant) -> Result<ProductVariant> {
         let input = VariantInput {
             id: variant.id,
@@ -237,6 +321,7 @@ impl ProductRepository {
             body_width: variant.body_width,
             shoulder_width: variant.shoulder_width,
             sleeve_length: variant.sleeve_length,
+            preorder_cap: variant.preorder_cap,
             created_at: variant.created_at,
             updated_at: variant.updated_at,
         };
@@ -246,8 +331,9 @@ impl ProductRepository {
     }
 
     /// バリアント更新
+    #[tracing::instrument(skip_all, name = "ProductRepository::update_variant")]
     pub async fn update_variant(&self, id: Uuid, updates: &VariantUpdateInput) -> Result<ProductVariant> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let results: Vec<VariantRow> = self.client.update("product_variants", &query, updates).await?;
         results
             .into_iter()
@@ -257,18 +343,21 @@ impl ProductRepository {
     }
 
     /// バリアント削除
+    #[tracing::instrument(skip_all, name = "ProductRepository::delete_variant")]
     pub async fn delete_variant(&self, id: Uuid) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         self.client.delete("product_variants", &query).await
     }
 
     /// 商品のバリアント一括削除
+    #[tracing::instrument(skip_all, name = "ProductRepository::delete_variants_by_product")]
     pub async fn delete_variants_by_product(&self, product_id: Uuid) -> Result<()> {
-        let query = format!("product_id=eq.{}", product_id);
+        let query = Query::new().eq("product_id", product_id);
         self.client.delete("product_variants", &query).await
     }
 
     /// 商品がorder_itemsに含まれているか確認
+    #[tracing::instrument(skip_all, name = "ProductRepository::has_order_items")]
     pub async fn has_order_items(&self, product_id: Uuid) -> Result<bool> {
         #[derive(Debug, Deserialize)]
         struct CountResult {
@@ -276,14 +365,15 @@ impl ProductRepository {
             id: Uuid,
         }
 
-        let query = format!("product_id=eq.{}&limit=1", product_id);
+        let query = Query::new().eq("product_id", product_id).limit(1);
         let results: Vec<CountResult> = self.client.select("order_items", &query).await?;
         Ok(!results.is_empty())
     }
 
     /// 商品をカートから削除
+    #[tracing::instrument(skip_all, name = "ProductRepository::delete_cart_items_by_product")]
     pub async fn delete_cart_items_by_product(&self, product_id: Uuid) -> Result<()> {
-        let query = format!("product_id=eq.{}", product_id);
+        let query = Query::new().eq("product_id", product_id);
         self.client.delete("cart_items", &query).await
     }
 }
@@ -307,10 +397,40 @@ struct ProductInput {
     is_featured: bool,
     tags: Vec<String>,
     metadata: serde_json::Value,
+    preorder_enabled: bool,
+    preorder_cap: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_ship_date: Option<NaiveDate>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize)]
+struct PreorderItem {
+    product_id: Uuid,
+    variant_id: Option<Uuid>,
+    quantity: i32,
+}
+
+#[derive(Debug, Serialize)]
+struct PreorderParams {
+    p_items: Vec<PreorderItem>,
+}
+
+impl PreorderParams {
+    fn new(items: &[(Uuid, Option<Uuid>, i32)]) -> Self {
+        let p_items = items
+            .iter()
+            .map(|&(product_id, variant_id, quantity)| PreorderItem {
+                product_id,
+                variant_id,
+                quantity,
+            })
+            .collect();
+        Self { p_items }
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct StockUpdate {
     stock: i32,
@@ -335,6 +455,8 @@ struct ProductRow {
     is_featured: bool,
     tags: Vec<String>,
     metadata: serde_json::Value,
+    #[serde(flatten)]
+    preorder: PreorderSettings,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -359,6 +481,7 @@ impl ProductRow {
             tags: Some(self.tags),
             metadata: serde_json::from_value(self.metadata).ok(),
             category: None,
+            preorder: self.preorder,
             created_at: self.created_at,
             updated_at: self.updated_at,
         }
@@ -390,6 +513,8 @@ struct ProductWithCategory {
     is_featured: bool,
     tags: Vec<String>,
     metadata: serde_json::Value,
+    #[serde(flatten)]
+    preorder: PreorderSettings,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     categories: Option<CategoryRef>,
@@ -421,6 +546,7 @@ impl ProductWithCategory {
             tags: Some(self.tags),
             metadata: serde_json::from_value(self.metadata).ok(),
             category,
+            preorder: self.preorder,
             created_at: self.created_at,
             updated_at: self.updated_at,
         }
@@ -483,6 +609,12 @@ pub struct ProductUpdateInput {
     pub is_featured: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preorder_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preorder_cap: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_ship_date: Option<NaiveDate>,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -502,6 +634,8 @@ struct VariantInput {
     body_width: Option<i32>,
     shoulder_width: Option<i32>,
     sleeve_length: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preorder_cap: Option<i32>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -522,6 +656,8 @@ pub struct VariantUpdateInput {
     pub shoulder_width: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sleeve_length: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preorder_cap: Option<i32>,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -539,6 +675,10 @@ struct VariantRow {
     body_width: Option<i32>,
     shoulder_width: Option<i32>,
     sleeve_length: Option<i32>,
+    #[serde(default)]
+    preorder_cap: Option<i32>,
+    #[serde(default)]
+    preorder_allocated: i32,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -558,6 +698,8 @@ impl VariantRow {
             body_width: self.body_width,
             shoulder_width: self.shoulder_width,
             sleeve_length: self.sleeve_length,
+            preorder_cap: self.preorder_cap,
+            preorder_allocated: self.preorder_allocated,
             created_at: self.created_at,
             updated_at: self.updated_at,
         }
@@ -2,9 +2,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::Result;
-use crate::models::{RatingDistribution, Review, ReviewStats};
+use crate::models::{RatingDistribution, Review, ReviewStats, SortOrder};
 
 pub struct ReviewRepository {
     client: AuthenticatedClient,
@@ -16,6 +16,7 @@ impl ReviewRepository {
     }
 
     /// レビュー作成
+    #[tracing::instrument(skip_all, name = "ReviewRepository::create")]
     pub async fn create(&self, review: &Review) -> Result<Review> {
         let input = ReviewInput {
             id: review.id,
@@ -35,11 +36,22 @@ impl ReviewRepository {
     }
 
     /// 商品のレビュー一覧取得（承認済みのみ）
+    #[tracing::instrument(skip_all, name = "ReviewRepository::find_by_product")]
     pub async fn find_by_product(&self, product_id: Uuid, limit: i32) -> Result<Vec<Review>> {
-        let query = format!(
-            "product_id=eq.{}&is_approved=eq.true&order=created_at.desc&limit={}",
-            product_id, limit
-        );
+        let query = Query::new()
+            .eq("product_id", product_id)
+            .eq("is_approved", true)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
+        let results: Vec<ReviewRow> = self.client.select("reviews", &query).await?;
+
+        Ok(results.into_iter().map(|r| r.into_review()).collect())
+    }
+
+    /// ユーザーのレビュー一覧取得（未承認含む、データエクスポート用）
+    #[tracing::instrument(skip_all, name = "ReviewRepository::find_by_user")]
+    pub async fn find_by_user(&self, user_id: Uuid) -> Result<Vec<Review>> {
+        let query = Query::new().eq("user_id", user_id).order("created_at", SortOrder::Desc);
         let results: Vec<ReviewRow> = self.client.select("reviews", &query).await?;
 
         Ok(results.into_iter().map(|r| r.into_review()).collect())
@@ -47,6 +59,7 @@ impl ReviewRepository {
 
     /// レビュー統計取得（SEO用aggregateRating対応）
     /// SupabaseのRPC関数を使用してパフォーマンス向上
+    #[tracing::instrument(skip_all, name = "ReviewRepository::get_stats")]
     pub async fn get_stats(&self, product_id: Uuid) -> Result<ReviewStats> {
         #[derive(Serialize)]
         struct Params {
@@ -94,24 +107,27 @@ impl ReviewRepository {
     }
 
     /// ユーザーが商品を購入済みか確認
+    #[tracing::instrument(skip_all, name = "ReviewRepository::has_purchased")]
     pub async fn has_purchased(&self, user_id: Uuid, product_id: Uuid) -> Result<bool> {
         // 注文アイテムからユーザーが商品を購入済みか確認
         // orders経由でuser_idとorder_itemsのproduct_idを確認
-        let query = format!(
-            "product_id=eq.{}&select=id,orders!inner(user_id,status)&orders.user_id=eq.{}&orders.status=neq.cancelled",
-            product_id, user_id
-        );
+        let query = Query::new()
+            .eq("product_id", product_id)
+            .select("id,orders!inner(user_id,status)")
+            .eq("orders.user_id", user_id)
+            .neq("orders.status", "cancelled");
         let results: Vec<IdOnly> = self.client.select("order_items", &query).await?;
 
         Ok(!results.is_empty())
     }
 
     /// ユーザーが既にレビュー済みか確認
+    #[tracing::instrument(skip_all, name = "ReviewRepository::has_reviewed")]
     pub async fn has_reviewed(&self, user_id: Uuid, product_id: Uuid) -> Result<bool> {
-        let query = format!(
-            "user_id=eq.{}&product_id=eq.{}&select=id",
-            user_id, product_id
-        );
+        let query = Query::new()
+            .eq("user_id", user_id)
+            .eq("product_id", product_id)
+            .select("id");
         let results: Vec<IdOnly> = self.client.select("reviews", &query).await?;
 
         Ok(!results.is_empty())
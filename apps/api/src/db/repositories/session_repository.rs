@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+use crate::models::{SortOrder, UserSession};
+
+pub struct SessionRepository {
+    client: AuthenticatedClient,
+}
+
+impl SessionRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// セッションを記録（ログイン・リフレッシュ時）
+    /// 既存セッションの場合は last_seen_at / IP / User-Agent を更新
+    #[tracing::instrument(skip_all, name = "SessionRepository::touch")]
+    pub async fn touch(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+        device: Option<&str>,
+        user_agent: Option<&str>,
+        ip_address: &str,
+    ) -> Result<()> {
+        let input = SessionInput {
+            id,
+            user_id,
+            device: device.map(|s| s.to_string()),
+            user_agent: user_agent.map(|s| s.to_string()),
+            ip_address: ip_address.to_string(),
+            last_seen_at: Utc::now(),
+        };
+
+        let _: SessionRow = self.client.upsert("user_sessions", &input, "id").await?;
+        Ok(())
+    }
+
+    /// ユーザーの有効なセッション一覧取得
+    #[tracing::instrument(skip_all, name = "SessionRepository::find_active_by_user")]
+    pub async fn find_active_by_user(&self, user_id: Uuid) -> Result<Vec<UserSession>> {
+        let query = Query::new()
+            .eq("user_id", user_id)
+            .where_null("revoked_at")
+            .order("last_seen_at", SortOrder::Desc);
+        let rows: Vec<SessionRow> = self.client.select("user_sessions", &query).await?;
+        Ok(rows.into_iter().map(|r| r.into_session()).collect())
+    }
+
+    /// セッションを失効（該当セッションがなければfalse）
+    #[tracing::instrument(skip_all, name = "SessionRepository::revoke")]
+    pub async fn revoke(&self, user_id: Uuid, session_id: Uuid) -> Result<bool> {
+        #[derive(Serialize)]
+        struct Params {
+            p_user_id: Uuid,
+            p_session_id: Uuid,
+        }
+
+        self.client
+            .rpc("revoke_user_session", &Params { p_user_id: user_id, p_session_id: session_id })
+            .await
+    }
+
+    /// 現在のセッション以外を全て失効（失効件数を返す）
+    #[tracing::instrument(skip_all, name = "SessionRepository::revoke_others")]
+    pub async fn revoke_others(&self, user_id: Uuid, current_session_id: Uuid) -> Result<i32> {
+        #[derive(Serialize)]
+        struct Params {
+            p_user_id: Uuid,
+            p_current_session_id: Uuid,
+        }
+
+        self.client
+            .rpc(
+                "revoke_other_user_sessions",
+                &Params { p_user_id: user_id, p_current_session_id: current_session_id },
+            )
+            .await
+    }
+}
+
+// Supabase REST API用の構造体
+#[derive(Debug, Serialize)]
+struct SessionInput {
+    id: Uuid,
+    user_id: Uuid,
+    device: Option<String>,
+    user_agent: Option<String>,
+    ip_address: String,
+    last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionRow {
+    id: Uuid,
+    #[allow(dead_code)]
+    user_id: Uuid,
+    device: Option<String>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+}
+
+impl SessionRow {
+    fn into_session(self) -> UserSession {
+        UserSession {
+            id: self.id,
+            device: self.device,
+            user_agent: self.user_agent,
+            ip_address: self.ip_address,
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
+            is_current: false,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::Result;
 
 pub struct TokenBlacklistRepository {
@@ -14,6 +14,7 @@ impl TokenBlacklistRepository {
     }
 
     /// トークンをブラックリストに追加
+    #[tracing::instrument(skip_all, name = "TokenBlacklistRepository::add")]
     pub async fn add(&self, jti: &str, user_id: uuid::Uuid, expires_at: DateTime<Utc>) -> Result<()> {
         let input = BlacklistInput {
             jti: jti.to_string(),
@@ -26,22 +27,16 @@ impl TokenBlacklistRepository {
         Ok(())
     }
 
-    /// トークンがブラックリストに存在するか確認
-    pub async fn is_blacklisted(&self, jti: &str) -> Result<bool> {
-        let query = format!("jti=eq.{}", urlencoding::encode(jti));
-        let result: Option<BlacklistRow> = self.client.select_single("token_blacklist", &query).await?;
-        Ok(result.is_some())
-    }
-
     /// 期限切れのブラックリストエントリを削除（クリーンアップ用）
+    #[tracing::instrument(skip_all, name = "TokenBlacklistRepository::cleanup_expired")]
     pub async fn cleanup_expired(&self) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
-        let query = format!("expires_at=lt.{}", urlencoding::encode(&now));
+        let query = Query::new().lt("expires_at", Utc::now().to_rfc3339());
         self.client.delete("token_blacklist", &query).await?;
         Ok(())
     }
 
     /// ユーザーの全トークンをブラックリストに追加（パスワード変更時等）
+    #[tracing::instrument(skip_all, name = "TokenBlacklistRepository::blacklist_all_user_tokens")]
     pub async fn blacklist_all_user_tokens(&self, user_id: uuid::Uuid, expires_at: DateTime<Utc>) -> Result<()> {
         // 特殊なJTIを使って全トークン無効化をマーク
         let input = BlacklistInput {
@@ -56,17 +51,64 @@ impl TokenBlacklistRepository {
     }
 
     /// ユーザーの全トークンがブラックリストされているか確認
+    #[tracing::instrument(skip_all, name = "TokenBlacklistRepository::is_user_blacklisted")]
     pub async fn is_user_blacklisted(&self, user_id: uuid::Uuid) -> Result<bool> {
         let jti = format!("all_tokens_{}", user_id);
-        let now = Utc::now().to_rfc3339();
-        let query = format!(
-            "jti=eq.{}&expires_at=gt.{}",
-            urlencoding::encode(&jti),
-            urlencoding::encode(&now)
-        );
+        let query = Query::new()
+            .eq("jti", &jti)
+            .gt("expires_at", Utc::now().to_rfc3339());
         let result: Option<BlacklistRow> = self.client.select_single("token_blacklist", &query).await?;
         Ok(result.is_some())
     }
+
+    /// トークン・ユーザー・セッションの失効状態をまとめて確認（RPC 1往復）
+    /// 認証ミドルウェアはリクエスト毎に呼ぶため、個別クエリを重ねない
+    #[tracing::instrument(skip_all, name = "TokenBlacklistRepository::check_revocation")]
+    pub async fn check_revocation(
+        &self,
+        user_id: uuid::Uuid,
+        jti: Option<&str>,
+        session_id: Option<uuid::Uuid>,
+    ) -> Result<RevocationStatus> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_user_id: uuid::Uuid,
+            p_jti: Option<&'a str>,
+            p_session_id: Option<uuid::Uuid>,
+        }
+
+        let params = Params {
+            p_user_id: user_id,
+            p_jti: jti,
+            p_session_id: session_id,
+        };
+
+        let results: Vec<RevocationStatus> = self.client.rpc("check_token_revocation", &params).await?;
+        Ok(results.into_iter().next().unwrap_or_default())
+    }
+}
+
+/// 失効チェック結果
+#[derive(Debug, Default, Deserialize)]
+pub struct RevocationStatus {
+    pub token_revoked: bool,
+    pub user_revoked: bool,
+    pub session_revoked: bool,
+}
+
+impl RevocationStatus {
+    /// 失効している場合は拒否メッセージを返す
+    pub fn rejection_message(&self) -> Option<&'static str> {
+        if self.token_revoked {
+            Some("トークンは無効化されています")
+        } else if self.session_revoked {
+            Some("このセッションはログアウトされています")
+        } else if self.user_revoked {
+            Some("再ログインが必要です")
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -2,9 +2,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::AuthenticatedClient;
+use crate::db::{AuthenticatedClient, Query};
 use crate::error::Result;
-use crate::models::{Address, User, UserRole};
+use crate::models::{Address, SortOrder, User, UserRole};
 
 pub struct UserRepository {
     client: AuthenticatedClient,
@@ -16,6 +16,7 @@ impl UserRepository {
     }
 
     /// ユーザー作成
+    #[tracing::instrument(skip_all, name = "UserRepository::create")]
     pub async fn create(&self, user: &User) -> Result<User> {
         let input = UserInput {
             id: user.id,
@@ -36,29 +37,33 @@ impl UserRepository {
     }
 
     /// IDでユーザー取得
+    #[tracing::instrument(skip_all, name = "UserRepository::find_by_id")]
     pub async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let result: Option<UserRow> = self.client.select_single("users", &query).await?;
         Ok(result.map(|r| r.into_user()))
     }
 
     /// メールでユーザー取得
+    #[tracing::instrument(skip_all, name = "UserRepository::find_by_email")]
     pub async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
-        let query = format!("email=eq.{}", urlencoding::encode(email));
+        let query = Query::new().eq("email", email);
         let result: Option<UserRow> = self.client.select_single("users", &query).await?;
         Ok(result.map(|r| r.into_user()))
     }
 
     /// メールが既に存在するか確認
+    #[tracing::instrument(skip_all, name = "UserRepository::email_exists")]
     pub async fn email_exists(&self, email: &str) -> Result<bool> {
-        let query = format!("email=eq.{}&select=id", urlencoding::encode(email));
+        let query = Query::new().eq("email", email).select("id");
         let results: Vec<IdOnly> = self.client.select("users", &query).await?;
         Ok(!results.is_empty())
     }
 
     /// ユーザー更新
+    #[tracing::instrument(skip_all, name = "UserRepository::update")]
     pub async fn update(&self, user: &User) -> Result<()> {
-        let query = format!("id=eq.{}", user.id);
+        let query = Query::new().eq("id", user.id);
         let update = UserUpdate {
             name: user.name.clone(),
             phone: user.phone.clone(),
@@ -73,8 +78,9 @@ impl UserRepository {
     }
 
     /// パスワード更新
+    #[tracing::instrument(skip_all, name = "UserRepository::update_password")]
     pub async fn update_password(&self, id: Uuid, password_hash: &str) -> Result<()> {
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = PasswordUpdate {
             password_hash: password_hash.to_string(),
             updated_at: Utc::now(),
@@ -85,9 +91,10 @@ impl UserRepository {
     }
 
     /// 最終ログイン時刻更新
+    #[tracing::instrument(skip_all, name = "UserRepository::update_last_login")]
     pub async fn update_last_login(&self, id: Uuid) -> Result<()> {
         let now = Utc::now();
-        let query = format!("id=eq.{}", id);
+        let query = Query::new().eq("id", id);
         let update = LastLoginUpdate {
             last_login_at: Some(now),
             updated_at: now,
@@ -98,20 +105,106 @@ impl UserRepository {
     }
 
     /// 全ユーザー取得（管理者用）
+    #[tracing::instrument(skip_all, name = "UserRepository::find_all")]
     pub async fn find_all(&self, limit: i32) -> Result<Vec<User>> {
-        let query = format!("order=created_at.desc&limit={}", limit);
+        let query = Query::new()
+            .order("created_at", SortOrder::Desc)
+            .limit(limit.max(0) as usize);
         let results: Vec<UserRow> = self.client.select("users", &query).await?;
         Ok(results.into_iter().map(|r| r.into_user()).collect())
     }
+
+    /// 退会処理: 個人データを匿名化（service_roleクライアントで呼び出すこと）
+    /// 注文は会計用に匿名化して保持し、住所・カートは削除する
+    #[tracing::instrument(skip_all, name = "UserRepository::anonymize_account")]
+    pub async fn anonymize_account(&self, id: Uuid) -> Result<AccountAnonymizeResult> {
+        #[derive(Serialize)]
+        struct Params {
+            p_user_id: Uuid,
+        }
+
+        let results: Vec<AccountAnonymizeResult> = self
+            .client
+            .rpc("anonymize_user_account", &Params { p_user_id: id })
+            .await?;
+
+        Ok(results.into_iter().next().unwrap_or_default())
+    }
+
+    /// 退会受付を記録（service_roleクライアントで呼び出すこと）
+    /// トークン無効化より前に記録し、以降の手順が失敗しても定期ジョブで再開できるようにする
+    #[tracing::instrument(skip_all, name = "UserRepository::mark_deletion_requested")]
+    pub async fn mark_deletion_requested(&self, id: Uuid) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update {
+            deletion_requested_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id).where_null("deletion_requested_at");
+        let update = Update {
+            deletion_requested_at: Utc::now(),
+        };
+        let _: Vec<UserRow> = self.client.update("users", &query, &update).await?;
+        Ok(())
+    }
+
+    /// Supabase Authユーザーの削除完了を記録
+    #[tracing::instrument(skip_all, name = "UserRepository::mark_auth_user_deleted")]
+    pub async fn mark_auth_user_deleted(&self, id: Uuid) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update {
+            auth_user_deleted_at: DateTime<Utc>,
+        }
+
+        let query = Query::new().eq("id", id);
+        let update = Update {
+            auth_user_deleted_at: Utc::now(),
+        };
+        let _: Vec<UserRow> = self.client.update("users", &query, &update).await?;
+        Ok(())
+    }
+
+    /// 完了していない退会処理を取得（受付が `requested_before` より前のもの）
+    #[tracing::instrument(skip_all, name = "UserRepository::find_pending_deletions")]
+    pub async fn find_pending_deletions(
+        &self,
+        requested_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<PendingAccountDeletion>> {
+        let query = Query::new()
+            .select("id,deleted_at")
+            .lt("deletion_requested_at", requested_before.to_rfc3339())
+            .where_null("auth_user_deleted_at")
+            .order("deletion_requested_at", SortOrder::Asc)
+            .limit(limit);
+        self.client.select("users", &query).await
+    }
+}
+
+/// 完了していない退会処理
+#[derive(Debug, Deserialize)]
+pub struct PendingAccountDeletion {
+    pub id: Uuid,
+    /// 匿名化の完了日時（NULL=未匿名化）
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// 退会時の匿名化結果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccountAnonymizeResult {
+    pub anonymized_orders: i32,
+    pub deleted_addresses: i32,
+    pub deleted_carts: i32,
 }
 
 // 住所リポジトリ
 impl UserRepository {
     /// 住所作成
+    #[tracing::instrument(skip_all, name = "UserRepository::create_address")]
     pub async fn create_address(&self, address: &Address) -> Result<Address> {
         // デフォルト住所の場合、他の住所のデフォルトを解除
         if address.is_default {
-            let query = format!("user_id=eq.{}", address.user_id);
+            let query = Query::new().eq("user_id", address.user_id);
             let update = DefaultUpdate { is_default: false };
             let _: Vec<AddressRow> = self.client.update("addresses", &query, &update).await?;
         }
@@ -136,22 +229,28 @@ impl UserRepository {
     }
 
     /// ユーザーの住所一覧取得
+    #[tracing::instrument(skip_all, name = "UserRepository::find_addresses_by_user")]
     pub async fn find_addresses_by_user(&self, user_id: Uuid) -> Result<Vec<Address>> {
-        let query = format!("user_id=eq.{}&order=is_default.desc,created_at.desc", user_id);
+        let query = Query::new()
+            .eq("user_id", user_id)
+            .order("is_default", SortOrder::Desc)
+            .order("created_at", SortOrder::Desc);
         let results: Vec<AddressRow> = self.client.select("addresses", &query).await?;
         Ok(results.into_iter().map(|r| r.into_address()).collect())
     }
 
     /// 住所取得
+    #[tracing::instrument(skip_all, name = "UserRepository::find_address")]
     pub async fn find_address(&self, user_id: Uuid, id: Uuid) -> Result<Option<Address>> {
-        let query = format!("user_id=eq.{}&id=eq.{}", user_id, id);
+        let query = Query::new().eq("user_id", user_id).eq("id", id);
         let result: Option<AddressRow> = self.client.select_single("addresses", &query).await?;
         Ok(result.map(|r| r.into_address()))
     }
 
     /// 住所削除
+    #[tracing::instrument(skip_all, name = "UserRepository::delete_address")]
     pub async fn delete_address(&self, user_id: Uuid, id: Uuid) -> Result<()> {
-        let query = format!("user_id=eq.{}&id=eq.{}", user_id, id);
+        let query = Query::new().eq("user_id", user_id).eq("id", id);
         self.client.delete("addresses", &query).await
     }
 }
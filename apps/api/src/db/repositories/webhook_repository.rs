@@ -0,0 +1,317 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+use crate::models::{
+    SortOrder, WebhookDelivery, WebhookDeliveryStatus, WebhookEndpoint, WebhookEventType,
+};
+
+/// 外部連携Webhookリポジトリ（service_role必須）
+pub struct WebhookRepository {
+    client: AuthenticatedClient,
+}
+
+/// DB行（secret を含む）
+#[derive(Debug, Deserialize)]
+struct EndpointRow {
+    id: Uuid,
+    url: String,
+    description: Option<String>,
+    secret: String,
+    event_types: Vec<String>,
+    is_active: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl EndpointRow {
+    fn into_endpoint(self) -> WebhookEndpoint {
+        WebhookEndpoint {
+            id: self.id,
+            url: self.url,
+            description: self.description,
+            // 廃止したイベント名が残っていても読み込めるようにする
+            event_types: self
+                .event_types
+                .iter()
+                .filter_map(|t| serde_json::from_value(serde_json::Value::String(t.clone())).ok())
+                .collect(),
+            is_active: self.is_active,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+/// 配信に必要なエンドポイント情報
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub url: String,
+    pub secret: String,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct WebhookEndpointUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_types: Option<Vec<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_active: Option<bool>,
+}
+
+/// 1回の配信試行の結果
+#[derive(Debug, Serialize)]
+pub struct DeliveryAttempt {
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub duration_ms: i32,
+}
+
+impl WebhookRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// エンドポイント一覧
+    #[tracing::instrument(skip_all, name = "WebhookRepository::find_endpoints")]
+    pub async fn find_endpoints(&self) -> Result<Vec<WebhookEndpoint>> {
+        let query = Query::new().order("created_at", SortOrder::Desc);
+        let rows: Vec<EndpointRow> = self.client.select("webhook_endpoints", &query).await?;
+        Ok(rows.into_iter().map(|r| r.into_endpoint()).collect())
+    }
+
+    /// 配信先の取得（secret を含む）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::find_target")]
+    pub async fn find_target(&self, id: Uuid) -> Result<Option<WebhookTarget>> {
+        let row: Option<EndpointRow> = self
+            .client
+            .select_single("webhook_endpoints", &Query::new().eq("id", id))
+            .await?;
+        Ok(row.map(|r| WebhookTarget {
+            url: r.url,
+            secret: r.secret,
+            is_active: r.is_active,
+        }))
+    }
+
+    /// エンドポイント作成
+    #[tracing::instrument(skip_all, name = "WebhookRepository::create_endpoint")]
+    pub async fn create_endpoint(
+        &self,
+        url: &str,
+        description: Option<&str>,
+        event_types: &[WebhookEventType],
+        secret: &str,
+    ) -> Result<WebhookEndpoint> {
+        #[derive(Serialize)]
+        struct Input<'a> {
+            url: &'a str,
+            description: Option<&'a str>,
+            event_types: Vec<&'static str>,
+            secret: &'a str,
+        }
+
+        let row: EndpointRow = self
+            .client
+            .insert(
+                "webhook_endpoints",
+                &Input {
+                    url,
+                    description,
+                    event_types: event_types.iter().map(|t| t.as_str()).collect(),
+                    secret,
+                },
+            )
+            .await?;
+        Ok(row.into_endpoint())
+    }
+
+    /// エンドポイント更新（該当なしはNone）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::update_endpoint")]
+    pub async fn update_endpoint(&self, id: Uuid, update: &WebhookEndpointUpdate) -> Result<Option<WebhookEndpoint>> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            #[serde(flatten)]
+            fields: &'a WebhookEndpointUpdate,
+            updated_at: DateTime<Utc>,
+        }
+
+        let rows: Vec<EndpointRow> = self
+            .client
+            .update(
+                "webhook_endpoints",
+                &Query::new().eq("id", id),
+                &Update {
+                    fields: update,
+                    updated_at: Utc::now(),
+                },
+            )
+            .await?;
+        Ok(rows.into_iter().next().map(|r| r.into_endpoint()))
+    }
+
+    /// エンドポイント削除（配信ログも削除される）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::delete_endpoint")]
+    pub async fn delete_endpoint(&self, id: Uuid) -> Result<()> {
+        self.client
+            .delete("webhook_endpoints", &Query::new().eq("id", id))
+            .await
+    }
+
+    /// イベントを購読中のエンドポイントへ配信登録（冪等、登録件数を返す）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::enqueue")]
+    pub async fn enqueue(&self, event_id: Uuid, event_type: WebhookEventType, body: &serde_json::Value) -> Result<i32> {
+        #[derive(Serialize)]
+        struct Params<'a> {
+            p_event_id: Uuid,
+            p_event_type: &'static str,
+            p_body: &'a serde_json::Value,
+        }
+
+        self.client
+            .rpc(
+                "enqueue_webhook_deliveries",
+                &Params {
+                    p_event_id: event_id,
+                    p_event_type: event_type.as_str(),
+                    p_body: body,
+                },
+            )
+            .await
+    }
+
+    /// 配信対象を取得してロック（status=processing, attempts+1）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::claim_batch")]
+    pub async fn claim_batch(&self, limit: i32, lock_seconds: i32) -> Result<Vec<WebhookDelivery>> {
+        #[derive(Serialize)]
+        struct Params {
+            p_limit: i32,
+            p_lock_seconds: i32,
+        }
+
+        self.client
+            .rpc(
+                "claim_webhook_deliveries",
+                &Params {
+                    p_limit: limit,
+                    p_lock_seconds: lock_seconds,
+                },
+            )
+            .await
+    }
+
+    /// 試行結果を記録
+    /// - 成功: succeeded
+    /// - 失敗: next_attempt_at があれば pending（再試行）、なければ dead
+    #[tracing::instrument(skip_all, name = "WebhookRepository::record_attempt")]
+    pub async fn record_attempt(
+        &self,
+        id: Uuid,
+        attempt: &DeliveryAttempt,
+        succeeded: bool,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        struct Update<'a> {
+            #[serde(flatten)]
+            attempt: &'a DeliveryAttempt,
+            status: WebhookDeliveryStatus,
+            locked_until: Option<DateTime<Utc>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            next_attempt_at: Option<DateTime<Utc>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            delivered_at: Option<DateTime<Utc>>,
+        }
+
+        let status = match (succeeded, next_attempt_at) {
+            (true, _) => WebhookDeliveryStatus::Succeeded,
+            (false, Some(_)) => WebhookDeliveryStatus::Pending,
+            (false, None) => WebhookDeliveryStatus::Dead,
+        };
+
+        let _: Vec<WebhookDelivery> = self
+            .client
+            .update(
+                "webhook_deliveries",
+                &Query::new().eq("id", id),
+                &Update {
+                    attempt,
+                    status,
+                    locked_until: None,
+                    next_attempt_at,
+                    delivered_at: succeeded.then(Utc::now),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// エンドポイントの配信ログ（新しい順）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::find_deliveries")]
+    pub async fn find_deliveries(
+        &self,
+        endpoint_id: Uuid,
+        status: Option<WebhookDeliveryStatus>,
+        limit: usize,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let mut query = Query::new()
+            .eq("endpoint_id", endpoint_id)
+            .order("created_at", SortOrder::Desc)
+            .limit(limit);
+        if let Some(status) = status {
+            query = query.eq("status", status);
+        }
+        self.client.select("webhook_deliveries", &query).await
+    }
+
+    /// 保持期間を過ぎた完了済み（succeeded / dead）の配信ログを削除
+    #[tracing::instrument(skip_all, name = "WebhookRepository::purge_finished")]
+    pub async fn purge_finished(&self, before: DateTime<Utc>) -> Result<()> {
+        let query = Query::new()
+            .in_list("status", [WebhookDeliveryStatus::Succeeded, WebhookDeliveryStatus::Dead])
+            .lt("created_at", before.to_rfc3339());
+        self.client.delete("webhook_deliveries", &query).await
+    }
+
+    /// 配信を同じ内容で再送（新しい配信ログとして登録）
+    #[tracing::instrument(skip_all, name = "WebhookRepository::replay")]
+    pub async fn replay(&self, delivery_id: Uuid) -> Result<Option<WebhookDelivery>> {
+        #[derive(Serialize)]
+        struct Input<'a> {
+            endpoint_id: Uuid,
+            event_id: Uuid,
+            event_type: &'a str,
+            body: &'a serde_json::Value,
+            replay_of: Uuid,
+        }
+
+        let original: Option<WebhookDelivery> = self
+            .client
+            .select_single("webhook_deliveries", &Query::new().eq("id", delivery_id))
+            .await?;
+        let Some(original) = original else {
+            return Ok(None);
+        };
+
+        let delivery: WebhookDelivery = self
+            .client
+            .insert(
+                "webhook_deliveries",
+                &Input {
+                    endpoint_id: original.endpoint_id,
+                    event_id: original.event_id,
+                    event_type: &original.event_type,
+                    body: &original.body,
+                    replay_of: original.id,
+                },
+            )
+            .await?;
+        Ok(Some(delivery))
+    }
+}
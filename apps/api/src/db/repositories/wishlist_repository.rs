@@ -0,0 +1,149 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::{AuthenticatedClient, Query};
+use crate::error::Result;
+use crate::models::{SortOrder, WishlistItem, WishlistOwner};
+
+/// ウィッシュリストリポジトリ（service_role必須。所有者の絞り込みは呼び出し側の WishlistOwner で行う）
+pub struct WishlistRepository {
+    client: AuthenticatedClient,
+}
+
+/// upsert の競合判定カラム
+const ITEM_CONFLICT_COLUMNS: &str = "owner_key,product_id,variant_id";
+
+impl WishlistRepository {
+    pub fn new(client: AuthenticatedClient) -> Self {
+        Self { client }
+    }
+
+    /// アイテム一覧（新しい順）
+    #[tracing::instrument(skip_all, name = "WishlistRepository::list")]
+    pub async fn list(&self, owner: &WishlistOwner) -> Result<Vec<WishlistItem>> {
+        let query = owner_query(owner).order("added_at", SortOrder::Desc);
+        let rows: Vec<WishlistItemRow> = self.client.select("wishlist_items", &query).await?;
+        Ok(rows.into_iter().map(|r| r.into_item()).collect())
+    }
+
+    /// アイテム追加（既にあれば追加日時のみ更新）
+    #[tracing::instrument(skip_all, name = "WishlistRepository::add")]
+    pub async fn add(&self, owner: &WishlistOwner, product_id: Uuid, variant_id: Option<Uuid>) -> Result<()> {
+        let (user_id, session_id) = match owner {
+            WishlistOwner::User(id) => (Some(*id), None),
+            WishlistOwner::Session(sid) => (None, Some(sid.clone())),
+        };
+        let input = WishlistItemInput {
+            user_id,
+            session_id,
+            product_id,
+            variant_id,
+            added_at: Utc::now(),
+        };
+
+        let _: WishlistItemRow = self.client.upsert("wishlist_items", &input, ITEM_CONFLICT_COLUMNS).await?;
+        Ok(())
+    }
+
+    /// アイテム削除（variant_id未指定なら商品の全バリアント）
+    #[tracing::instrument(skip_all, name = "WishlistRepository::remove")]
+    pub async fn remove(&self, owner: &WishlistOwner, product_id: Uuid, variant_id: Option<Uuid>) -> Result<()> {
+        let mut query = owner_query(owner).eq("product_id", product_id);
+        if let Some(variant_id) = variant_id {
+            query = query.eq("variant_id", variant_id);
+        }
+        self.client.delete("wishlist_items", &query).await
+    }
+
+    /// ゲストのアイテムをユーザーへ統合し、ゲスト側を削除
+    #[tracing::instrument(skip_all, name = "WishlistRepository::merge")]
+    pub async fn merge(&self, guest_session_id: &str, user_id: Uuid) -> Result<usize> {
+        let guest = WishlistOwner::Session(guest_session_id.to_string());
+        let items = self.list(&guest).await?;
+        let user = WishlistOwner::User(user_id);
+
+        for item in &items {
+            self.add(&user, item.product_id, item.variant_id).await?;
+        }
+
+        self.client.delete("wishlist_items", &owner_query(&guest)).await?;
+        Ok(items.len())
+    }
+
+    /// 共有トークンを保存（既存のリンクは置き換えで無効になる）
+    #[tracing::instrument(skip_all, name = "WishlistRepository::save_share")]
+    pub async fn save_share(&self, user_id: Uuid, token_hash: &str) -> Result<()> {
+        let input = ShareInput {
+            user_id,
+            token_hash: token_hash.to_string(),
+            created_at: Utc::now(),
+        };
+        let _: ShareRow = self.client.upsert("wishlist_shares", &input, "user_id").await?;
+        Ok(())
+    }
+
+    /// 共有を停止
+    #[tracing::instrument(skip_all, name = "WishlistRepository::revoke_share")]
+    pub async fn revoke_share(&self, user_id: Uuid) -> Result<()> {
+        let query = Query::new().eq("user_id", user_id);
+        self.client.delete("wishlist_shares", &query).await
+    }
+
+    /// 共有トークン（ハッシュ）から所有ユーザーを取得
+    #[tracing::instrument(skip_all, name = "WishlistRepository::find_share_owner")]
+    pub async fn find_share_owner(&self, token_hash: &str) -> Result<Option<Uuid>> {
+        let query = Query::new().eq("token_hash", token_hash);
+        let row: Option<ShareRow> = self.client.select_single("wishlist_shares", &query).await?;
+        Ok(row.map(|r| r.user_id))
+    }
+}
+
+/// 所有者で絞り込むクエリ
+fn owner_query(owner: &WishlistOwner) -> Query {
+    match owner {
+        WishlistOwner::User(id) => Query::new().eq("user_id", id),
+        WishlistOwner::Session(sid) => Query::new().eq("session_id", sid),
+    }
+}
+
+// Supabase REST API用の構造体
+#[derive(Debug, Serialize)]
+struct WishlistItemInput {
+    user_id: Option<Uuid>,
+    session_id: Option<String>,
+    product_id: Uuid,
+    variant_id: Option<Uuid>,
+    added_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WishlistItemRow {
+    id: Uuid,
+    product_id: Uuid,
+    variant_id: Option<Uuid>,
+    added_at: DateTime<Utc>,
+}
+
+impl WishlistItemRow {
+    fn into_item(self) -> WishlistItem {
+        WishlistItem {
+            id: self.id,
+            product_id: self.product_id,
+            variant_id: self.variant_id,
+            added_at: self.added_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ShareInput {
+    user_id: Uuid,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShareRow {
+    user_id: Uuid,
+}
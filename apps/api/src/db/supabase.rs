@@ -1,8 +1,16 @@
 use std::time::Duration;
 
-use reqwest::{Client, header};
+use std::sync::Arc;
+
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode};
 use serde::{de::DeserializeOwned, Serialize};
-use crate::error::{AppError, Result};
+use tracing::Instrument;
+use crate::config::DatabaseConfig;
+use crate::db::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::db::query::{parse_content_range_total, Query};
+use crate::error::{AppError, PostgrestError, Result};
+use crate::middleware::request_id_headers;
+use crate::services::metrics;
 
 /// Supabase REST APIクライアント
 /// anon keyまたはservice_role keyを使用してアクセスを行う
@@ -12,35 +20,37 @@ pub struct SupabaseClient {
     url: String,
     anon_key: String,
     service_role_key: Option<String>,
+    breaker: Arc<CircuitBreaker>,
+    retry: RetryPolicy,
+    /// 認証モードをログ出力する（SUPABASE_DEBUG_AUTH）
+    debug_auth: bool,
 }
 
 impl SupabaseClient {
-    pub fn new(url: &str, anon_key: &str) -> Result<Self> {
-        let service_role_key = std::env::var("SUPABASE_SERVICE_ROLE_KEY").ok();
-        let connect_timeout = std::env::var("SUPABASE_HTTP_CONNECT_TIMEOUT_SECONDS")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(10)
-            .max(1);
-        let request_timeout = std::env::var("SUPABASE_HTTP_TIMEOUT_SECONDS")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(15)
-            .max(1);
+    pub fn new(config: &DatabaseConfig) -> Result<Self> {
+        let service_role_key = Some(config.service_role_key.expose().to_string()).filter(|k| !k.is_empty());
+        let connect_timeout = config.http_connect_timeout_seconds.max(1);
+        let request_timeout = config.http_timeout_seconds.max(1);
 
         let client = Client::builder()
             // Supabase REST が応答しない場合でもアプリ全体の 30s タイムアウトに達する前に失敗させる
-            .connect_timeout(Duration::from_secs(connect_timeout as u64))
-            .timeout(Duration::from_secs(request_timeout as u64))
+            .connect_timeout(Duration::from_secs(connect_timeout))
+            .timeout(Duration::from_secs(request_timeout))
             .pool_idle_timeout(Some(Duration::from_secs(30)))
             .build()
             .map_err(|e| AppError::Database(format!("Failed to create HTTP client: {}", e)))?;
 
         Ok(Self {
             client,
-            url: url.trim_end_matches('/').to_string(),
-            anon_key: anon_key.to_string(),
+            url: config.url.trim_end_matches('/').to_string(),
+            anon_key: config.anon_key.clone(),
             service_role_key,
+            breaker: Arc::new(CircuitBreaker::new(
+                config.circuit_failure_threshold,
+                Duration::from_secs(config.circuit_open_seconds.max(1)),
+            )),
+            retry: RetryPolicy::from_config(config),
+            debug_auth: config.debug_auth,
         })
     }
 
@@ -51,6 +61,9 @@ impl SupabaseClient {
             url: self.url.clone(),
             anon_key: self.anon_key.clone(),
             jwt: Some(jwt.to_string()),
+            breaker: self.breaker.clone(),
+            retry: self.retry,
+            debug_auth: self.debug_auth,
         }
     }
 
@@ -61,6 +74,9 @@ impl SupabaseClient {
             url: self.url.clone(),
             anon_key: self.anon_key.clone(),
             jwt: None,
+            breaker: self.breaker.clone(),
+            retry: self.retry,
+            debug_auth: self.debug_auth,
         }
     }
 
@@ -76,9 +92,17 @@ impl SupabaseClient {
             url: self.url.clone(),
             anon_key: service_key,
             jwt: None,
+            breaker: self.breaker.clone(),
+            retry: self.retry,
+            debug_auth: self.debug_auth,
         }
     }
 
+    /// REST呼び出しのサーキットブレーカー状態
+    pub fn circuit_state(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
     /// ヘルスチェック
     pub async fn health_check(&self) -> Result<bool> {
         let response = self.client
@@ -90,6 +114,63 @@ impl SupabaseClient {
 
         Ok(response.status().is_success())
     }
+
+    /// Supabase Auth のユーザーを削除（Admin API、service_role必須）
+    pub async fn delete_auth_user(&self, user_id: uuid::Uuid) -> Result<()> {
+        let service_key = self.service_role_key.as_deref().ok_or_else(|| {
+            AppError::Internal("SUPABASE_SERVICE_ROLE_KEY is not configured".to_string())
+        })?;
+
+        let response = self.client
+            .delete(format!("{}/auth/v1/admin/users/{}", self.url, user_id))
+            .header("apikey", service_key)
+            .header("Authorization", format!("Bearer {}", service_key))
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalService(format!("Auth user delete failed: {}", e)))?;
+
+        // 既に削除済み（404）は成功扱い（リトライ時の冪等性）
+        if !response.status().is_success() && response.status() != reqwest::StatusCode::NOT_FOUND {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(AppError::ExternalService(format!("Auth user delete error: {}", error_text)));
+        }
+
+        Ok(())
+    }
+
+    /// マジックリンクのトークンハッシュを発行（Admin API、メール送信なし）
+    /// パスキー認証成功後に `/auth/v1/verify` でセッションを発行するために使用
+    pub async fn generate_magic_link_token_hash(&self, email: &str) -> Result<String> {
+        let service_key = self.service_role_key.as_deref().ok_or_else(|| {
+            AppError::Internal("SUPABASE_SERVICE_ROLE_KEY is not configured".to_string())
+        })?;
+
+        let response = self.client
+            .post(format!("{}/auth/v1/admin/generate_link", self.url))
+            .header("apikey", service_key)
+            .header("Authorization", format!("Bearer {}", service_key))
+            .json(&serde_json::json!({ "type": "magiclink", "email": email }))
+            .send()
+            .await
+            .map_err(|e| AppError::ExternalService(format!("Generate link failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(AppError::ExternalService(format!("Generate link error: {}", error_text)));
+        }
+
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| AppError::ExternalService(format!("Generate link parse error: {}", e)))?;
+
+        // GoTrueのバージョンによりトップレベルまたは properties 配下に入る
+        body.get("hashed_token")
+            .or_else(|| body.get("properties").and_then(|p| p.get("hashed_token")))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .ok_or_else(|| AppError::ExternalService("Generate link: hashed_token missing".to_string()))
+    }
 }
 
 /// 認証状態を持つSupabaseクライアント
@@ -99,6 +180,10 @@ pub struct AuthenticatedClient {
     url: String,
     anon_key: String,
     jwt: Option<String>,
+    breaker: Arc<CircuitBreaker>,
+    retry: RetryPolicy,
+    /// 認証モードをログ出力する（SUPABASE_DEBUG_AUTH）
+    debug_auth: bool,
 }
 
 impl AuthenticatedClient {
@@ -129,10 +214,7 @@ impl AuthenticatedClient {
         // - jwt がない場合(anon/service): APIキー自体を Bearer として送る
         let bearer = self.jwt.as_deref().unwrap_or(&self.anon_key);
         // デバッグ用（トークン自体は絶対にログに出さない）
-        let debug_auth = std::env::var("SUPABASE_DEBUG_AUTH")
-            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-            .unwrap_or(false);
-        if debug_auth {
+        if self.debug_auth {
             tracing::info!(
                 "[supabase] auth mode: {}, bearer_len={}",
                 if self.jwt.is_some() { "user_jwt" } else { "api_key_bearer" },
@@ -149,24 +231,139 @@ impl AuthenticatedClient {
         Ok(headers)
     }
 
+    /// テーブルURL（クエリ付き）
+    fn table_url(&self, table: &str, query: &Query) -> String {
+        let query_string = query.to_query_string();
+        if query_string.is_empty() {
+            format!("{}/rest/v1/{}", self.url, table)
+        } else {
+            format!("{}/rest/v1/{}?{}", self.url, table, query_string)
+        }
+    }
+
+    /// 共通ヘッダー + Rangeヘッダー
+    fn query_headers(&self, query: &Query) -> Result<header::HeaderMap> {
+        let mut headers = self.headers()?;
+        if let Some(range) = query.range_header() {
+            headers.insert(
+                "Range-Unit",
+                header::HeaderValue::from_static("items"),
+            );
+            headers.insert(
+                "Range",
+                range
+                    .parse()
+                    .map_err(|e| AppError::Database(format!("Invalid range header value: {}", e)))?,
+            );
+        }
+        Ok(headers)
+    }
+
+    /// リクエスト送信（サーキットブレーカー・リトライ付き）
+    /// idempotent=true の場合のみ、接続エラー・502/503/504 をジッター付き指数バックオフでリトライする
+    /// レイテンシ・エラーはメトリクスに記録する
+    async fn send(
+        &self,
+        operation: &str,
+        idempotent: bool,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        // 許可証はレスポンスを受け取るまで保持する（キャンセルされた試行は失敗として扱われる）
+        let Some(_permit) = self.breaker.allow_request() else {
+            metrics::record_supabase_error(operation, "circuit_open");
+            return Err(AppError::Database(format!(
+                "{} skipped: Supabase circuit breaker is open",
+                operation
+            )));
+        };
+
+        let started = std::time::Instant::now();
+        let span = tracing::info_span!("supabase_request", operation, otel.kind = "client");
+        let result = self.send_with_retry(operation, idempotent, build).instrument(span).await;
+
+        let success = matches!(&result, Ok(response) if response.status().is_success());
+        metrics::record_supabase_call(operation, success, started.elapsed());
+        // HTTPエラーは error_from_response でコード別に記録する
+        if result.is_err() {
+            metrics::record_supabase_error(operation, "transport");
+        }
+        result
+    }
+
+    async fn send_with_retry(
+        &self,
+        operation: &str,
+        idempotent: bool,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        let max_attempts = if idempotent { self.retry.max_retries + 1 } else { 1 };
+        let mut attempt = 1;
+        loop {
+            match build().headers(request_id_headers()).send().await {
+                Ok(response) if is_transient_status(response.status()) => {
+                    self.breaker.record_failure();
+                    if attempt >= max_attempts {
+                        return Ok(response);
+                    }
+                    tracing::warn!(
+                        "{} returned {}, retrying (attempt {}/{})",
+                        operation,
+                        response.status(),
+                        attempt,
+                        max_attempts
+                    );
+                }
+                Ok(response) => {
+                    self.breaker.record_success();
+                    return Ok(response);
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    let transient = e.is_connect() || e.is_timeout() || e.is_request();
+                    if !transient || attempt >= max_attempts {
+                        return Err(AppError::Database(format!("{} failed: {}", operation, e)));
+                    }
+                    tracing::warn!(
+                        "{} failed, retrying (attempt {}/{}): {}",
+                        operation,
+                        attempt,
+                        max_attempts,
+                        e
+                    );
+                }
+            }
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// エラーレスポンスを構造化エラーへ変換
+    async fn error_from_response(operation: &str, response: Response) -> AppError {
+        let status = response.status().as_u16();
+        let body = response.text().await.unwrap_or_default();
+        let error = PostgrestError::parse(operation, status, &body);
+        match error.code() {
+            Some(code) => metrics::record_supabase_error(operation, code),
+            None => metrics::record_supabase_error(operation, &format!("http_{}", status)),
+        }
+        error.into()
+    }
+
     /// SELECT: データ取得
     pub async fn select<T: DeserializeOwned>(
         &self,
         table: &str,
-        query: &str,
+        query: &Query,
     ) -> Result<Vec<T>> {
-        let url = format!("{}/rest/v1/{}?{}", self.url, table, query);
+        let url = self.table_url(table, query);
+        let headers = self.query_headers(query)?;
 
-        let response = self.client
-            .get(&url)
-            .headers(self.headers()?)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Select failed: {}", e)))?;
+        let response = self
+            .send("Select", true, || self.client.get(&url).headers(headers.clone()))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Select error: {}", error_text)));
+            return Err(Self::error_from_response("Select", response).await);
         }
 
         response.json::<Vec<T>>()
@@ -174,15 +371,49 @@ impl AuthenticatedClient {
             .map_err(|e| AppError::Database(format!("Parse error: {}", e)))
     }
 
+    /// SELECT: データ取得 + 総件数（count=exact）
+    pub async fn select_with_count<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        query: &Query,
+    ) -> Result<(Vec<T>, i64)> {
+        let url = self.table_url(table, query);
+        let mut headers = self.query_headers(query)?;
+        headers.insert(
+            "Prefer",
+            header::HeaderValue::from_static("count=exact"),
+        );
+
+        let response = self
+            .send("Select", true, || self.client.get(&url).headers(headers.clone()))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::error_from_response("Select", response).await);
+        }
+
+        let total = response
+            .headers()
+            .get("Content-Range")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range_total);
+
+        let rows = response.json::<Vec<T>>()
+            .await
+            .map_err(|e| AppError::Database(format!("Parse error: {}", e)))?;
+
+        let total = total.unwrap_or(rows.len() as i64);
+        Ok((rows, total))
+    }
+
     /// SELECT: 単一レコード取得
     pub async fn select_single<T: DeserializeOwned>(
         &self,
         table: &str,
-        query: &str,
+        query: &Query,
     ) -> Result<Option<T>> {
-        let url = format!("{}/rest/v1/{}?{}", self.url, table, query);
-
-        let mut headers = self.headers()?;
+        let url = self.table_url(table, query);
+        let mut headers = self.query_headers(query)?;
         headers.insert(
             "Accept",
             "application/vnd.pgrst.object+json"
@@ -190,12 +421,9 @@ impl AuthenticatedClient {
                 .map_err(|e| AppError::Database(format!("Invalid accept header value: {}", e)))?,
         );
 
-        let response = self.client
-            .get(&url)
-            .headers(headers)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Select failed: {}", e)))?;
+        let response = self
+            .send("Select", true, || self.client.get(&url).headers(headers.clone()))
+            .await?;
 
         if response.status().as_u16() == 406 {
             // Not Acceptable = レコードなし
@@ -203,8 +431,7 @@ impl AuthenticatedClient {
         }
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Select error: {}", error_text)));
+            return Err(Self::error_from_response("Select", response).await);
         }
 
         let result = response.json::<T>()
@@ -221,18 +448,14 @@ impl AuthenticatedClient {
         data: &T,
     ) -> Result<R> {
         let url = format!("{}/rest/v1/{}", self.url, table);
+        let headers = self.headers()?;
 
-        let response = self.client
-            .post(&url)
-            .headers(self.headers()?)
-            .json(data)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Insert failed: {}", e)))?;
+        let response = self
+            .send("Insert", false, || self.client.post(&url).headers(headers.clone()).json(data))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Insert error: {}", error_text)));
+            return Err(Self::error_from_response("Insert", response).await);
         }
 
         // 配列で返ってくるので最初の要素を取得
@@ -248,22 +471,18 @@ impl AuthenticatedClient {
     pub async fn update<T: Serialize, R: DeserializeOwned>(
         &self,
         table: &str,
-        query: &str,
+        query: &Query,
         data: &T,
     ) -> Result<Vec<R>> {
-        let url = format!("{}/rest/v1/{}?{}", self.url, table, query);
+        let url = self.table_url(table, query);
+        let headers = self.headers()?;
 
-        let response = self.client
-            .patch(&url)
-            .headers(self.headers()?)
-            .json(data)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Update failed: {}", e)))?;
+        let response = self
+            .send("Update", false, || self.client.patch(&url).headers(headers.clone()).json(data))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Update error: {}", error_text)));
+            return Err(Self::error_from_response("Update", response).await);
         }
 
         response.json::<Vec<R>>()
@@ -272,53 +491,43 @@ impl AuthenticatedClient {
     }
 
     /// DELETE: データ削除
-    pub async fn delete(&self, table: &str, query: &str) -> Result<()> {
-        let url = format!("{}/rest/v1/{}?{}", self.url, table, query);
+    pub async fn delete(&self, table: &str, query: &Query) -> Result<()> {
+        let url = self.table_url(table, query);
+        let headers = self.headers()?;
 
-        let response = self.client
-            .delete(&url)
-            .headers(self.headers()?)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Delete failed: {}", e)))?;
+        let response = self
+            .send("Delete", true, || self.client.delete(&url).headers(headers.clone()))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Delete error: {}", error_text)));
+            return Err(Self::error_from_response("Delete", response).await);
         }
 
         Ok(())
     }
 
-    /// UPSERT: データ挿入または更新
+    /// UPSERT: データ挿入または更新（on_conflict: 競合判定カラム）
     pub async fn upsert<T: Serialize, R: DeserializeOwned>(
         &self,
         table: &str,
         data: &T,
         on_conflict: &str,
     ) -> Result<R> {
-        let url = format!("{}/rest/v1/{}", self.url, table);
+        let url = self.table_url(table, &Query::new().on_conflict(on_conflict));
 
         let mut headers = self.headers()?;
         headers.insert(
             "Prefer",
-            format!("resolution=merge-duplicates,return=representation")
-                .parse()
-                .map_err(|e| AppError::Database(format!("Invalid prefer header value: {}", e)))?,
+            header::HeaderValue::from_static("resolution=merge-duplicates,return=representation"),
         );
 
-        let response = self.client
-            .post(&url)
-            .headers(headers)
-            .header("On-Conflict", on_conflict)
-            .json(data)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("Upsert failed: {}", e)))?;
+        // merge-duplicates は同じデータで再送しても結果が変わらないためリトライ可
+        let response = self
+            .send("Upsert", true, || self.client.post(&url).headers(headers.clone()).json(data))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("Upsert error: {}", error_text)));
+            return Err(Self::error_from_response("Upsert", response).await);
         }
 
         let results: Vec<R> = response.json()
@@ -330,24 +539,21 @@ impl AuthenticatedClient {
     }
 
     /// RPC: ストアドプロシージャ呼び出し
+    /// 副作用のある関数が多いためリトライしない
     pub async fn rpc<T: Serialize, R: DeserializeOwned>(
         &self,
         function_name: &str,
         params: &T,
     ) -> Result<R> {
         let url = format!("{}/rest/v1/rpc/{}", self.url, function_name);
+        let headers = self.headers()?;
 
-        let response = self.client
-            .post(&url)
-            .headers(self.headers()?)
-            .json(params)
-            .send()
-            .await
-            .map_err(|e| AppError::Database(format!("RPC failed: {}", e)))?;
+        let response = self
+            .send("RPC", false, || self.client.post(&url).headers(headers.clone()).json(params))
+            .await?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(AppError::Database(format!("RPC error: {}", error_text)));
+            return Err(Self::error_from_response("RPC", response).await);
         }
 
         response.json::<R>()
@@ -355,3 +561,62 @@ impl AuthenticatedClient {
             .map_err(|e| AppError::Database(format!("Parse error: {}", e)))
     }
 }
+
+/// リトライ設定
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// database.max_retries（上限5）, database.retry_base_delay_ms
+    fn from_config(config: &DatabaseConfig) -> Self {
+        let max_retries = config.max_retries.min(5);
+        let base_delay_ms = config.retry_base_delay_ms.max(1);
+        Self {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+
+    /// attempt回目の失敗後の待機時間（指数バックオフ + フルジッター）
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << attempt.saturating_sub(1).min(10))
+            .min(self.max_delay);
+        exp.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// 一時的な障害とみなすステータス（ゲートウェイ・過負荷）
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff_is_bounded() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        for attempt in 1..=5 {
+            let delay = policy.backoff(attempt);
+            let cap = Duration::from_millis(100 * (1 << (attempt - 1))).min(policy.max_delay);
+            assert!(delay <= cap, "attempt {} delay {:?} > {:?}", attempt, delay, cap);
+        }
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
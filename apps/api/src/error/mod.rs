@@ -6,6 +6,10 @@ use axum::{
 use serde::Serialize;
 use thiserror::Error;
 
+mod postgrest;
+
+pub use postgrest::PostgrestError;
+
 /// アプリケーションエラー
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -24,18 +28,29 @@ pub enum AppError {
     #[error("Conflict: {0}")]
     Conflict(String),
 
+    /// 一意制約違反（違反した制約名。レスポンスは Conflict と同じ）
+    #[error("Unique violation: {0}")]
+    UniqueViolation(String),
+
     #[error("Bad request: {0}")]
     BadRequest(String),
 
     #[error("Too many requests: {0}")]
     TooManyRequests(String),
 
+    #[error("Captcha required: {0}")]
+    CaptchaRequired(String),
+
     #[error("Internal server error: {0}")]
     Internal(String),
 
     #[error("Database error: {0}")]
     Database(String),
 
+    /// PostgRESTが返した構造化エラー（制約違反・RLS違反以外）
+    #[error("Database error: {0}")]
+    Postgrest(Box<PostgrestError>),
+
     #[error("External service error: {0}")]
     ExternalService(String),
 }
@@ -51,6 +66,7 @@ pub enum ErrorCode {
     Conflict,
     BadRequest,
     TooManyRequests,
+    CaptchaRequired,
     InternalError,
     DatabaseError,
     ExternalServiceError,
@@ -75,6 +91,9 @@ pub struct ErrorBody {
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<Vec<ErrorDetail>>,
+    /// 問い合わせ・ログ突合用のリクエストID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl ErrorResponse {
@@ -84,6 +103,7 @@ impl ErrorResponse {
                 code,
                 message: message.into(),
                 details: None,
+                request_id: crate::middleware::current_request_id(),
             },
         }
     }
@@ -98,9 +118,7 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         // 詳細エラーは「明示的に」API_DEBUG_ERRORS=1 のときのみ返す
         // （ENVIRONMENT=local/development でもデフォルトは秘匿して漏えいリスクを下げる）
-        let debug_errors = std::env::var("API_DEBUG_ERRORS")
-            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-            .unwrap_or(false);
+        let debug_errors = crate::config::get().is_some_and(|c| c.server.debug_errors);
 
         let (status, error_response) = match &self {
             AppError::Validation(msg) => (
@@ -123,6 +141,10 @@ impl IntoResponse for AppError {
                 StatusCode::CONFLICT,
                 ErrorResponse::new(ErrorCode::Conflict, msg),
             ),
+            AppError::UniqueViolation(_) => (
+                StatusCode::CONFLICT,
+                ErrorResponse::new(ErrorCode::Conflict, "既に登録されています"),
+            ),
             AppError::BadRequest(msg) => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse::new(ErrorCode::BadRequest, msg),
@@ -131,6 +153,10 @@ impl IntoResponse for AppError {
                 StatusCode::TOO_MANY_REQUESTS,
                 ErrorResponse::new(ErrorCode::TooManyRequests, msg),
             ),
+            AppError::CaptchaRequired(msg) => (
+                StatusCode::FORBIDDEN,
+                ErrorResponse::new(ErrorCode::CaptchaRequired, msg),
+            ),
             AppError::Internal(msg) => {
                 tracing::error!("Internal error: {}", msg);
                 (
@@ -152,6 +178,20 @@ impl IntoResponse for AppError {
                     },
                 )
             }
+            AppError::Postgrest(err) => {
+                tracing::error!("Database error: {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    if debug_errors {
+                        ErrorResponse::new(ErrorCode::DatabaseError, format!("データベースエラー: {}", err))
+                    } else {
+                        ErrorResponse::new(
+                            ErrorCode::DatabaseError,
+                            "データベースエラーが発生しました（API_DEBUG_ERRORS=1 で詳細を表示できます）"
+                        )
+                    },
+                )
+            }
             AppError::ExternalService(msg) => {
                 tracing::error!("External service error: {}", msg);
                 (
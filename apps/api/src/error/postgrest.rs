@@ -0,0 +1,143 @@
+use serde::Deserialize;
+
+use super::AppError;
+
+/// PostgRESTのエラーレスポンス（`{"code","message","details","hint"}`）
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgrestError {
+    /// HTTPステータス
+    #[serde(skip)]
+    pub status: u16,
+    /// 操作名（Select / Insert / RPC 等）
+    #[serde(skip)]
+    pub operation: String,
+    /// PostgreSQLのSQLSTATE または PGRSTxxx
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub details: Option<String>,
+    #[serde(default)]
+    pub hint: Option<String>,
+}
+
+impl PostgrestError {
+    /// レスポンスボディを解析（JSONでない場合は本文をメッセージとして保持）
+    pub fn parse(operation: &str, status: u16, body: &str) -> Self {
+        let mut error = serde_json::from_str::<PostgrestError>(body).unwrap_or_else(|_| Self {
+            status,
+            operation: String::new(),
+            code: None,
+            message: body.to_string(),
+            details: None,
+            hint: None,
+        });
+        error.status = status;
+        error.operation = operation.to_string();
+        error
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// 一意制約違反
+    pub fn is_unique_violation(&self) -> bool {
+        self.code() == Some("23505")
+    }
+
+    /// 違反した制約名（メッセージの `constraint "name"` から取得）
+    pub fn constraint(&self) -> Option<&str> {
+        let (_, rest) = self.message.split_once("constraint \"")?;
+        rest.split_once('"').map(|(name, _)| name)
+    }
+
+    /// 外部キー制約違反
+    pub fn is_foreign_key_violation(&self) -> bool {
+        self.code() == Some("23503")
+    }
+
+    /// 権限不足（RLSポリシー違反を含む）
+    pub fn is_permission_denied(&self) -> bool {
+        self.code() == Some("42501")
+    }
+
+    /// RPC・テーブルがスキーマキャッシュに存在しない
+    pub fn is_not_found_in_schema(&self) -> bool {
+        matches!(self.code(), Some("PGRST202") | Some("PGRST205") | Some("42P01"))
+    }
+}
+
+impl std::fmt::Display for PostgrestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} error (status={}, code={}): {}",
+            self.operation,
+            self.status,
+            self.code().unwrap_or("-"),
+            self.message
+        )?;
+        if let Some(details) = &self.details {
+            write!(f, " details={}", details)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, " hint={}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PostgrestError {}
+
+// 制約違反・RLS違反は500ではなくクライアントエラーとして返す
+impl From<PostgrestError> for AppError {
+    fn from(error: PostgrestError) -> Self {
+        if error.is_unique_violation() {
+            tracing::info!("{}", error);
+            AppError::UniqueViolation(error.constraint().unwrap_or_default().to_string())
+        } else if error.is_foreign_key_violation() {
+            tracing::info!("{}", error);
+            AppError::BadRequest("関連するデータが存在しません".to_string())
+        } else if error.is_permission_denied() {
+            tracing::warn!("{}", error);
+            AppError::Forbidden("この操作を行う権限がありません".to_string())
+        } else {
+            AppError::Postgrest(Box::new(error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_map_postgrest_error() {
+        let error = PostgrestError::parse(
+            "Insert",
+            409,
+            r#"{"code":"23505","details":"Key (email)=(a@example.com) already exists.","hint":null,"message":"duplicate key value violates unique constraint \"users_email_key\""}"#,
+        );
+        assert_eq!(error.status, 409);
+        assert!(error.is_unique_violation());
+        assert_eq!(error.constraint(), Some("users_email_key"));
+        assert!(matches!(AppError::from(error), AppError::UniqueViolation(c) if c == "users_email_key"));
+
+        let error = PostgrestError::parse(
+            "Insert",
+            403,
+            r#"{"code":"42501","details":null,"hint":null,"message":"new row violates row-level security policy for table \"orders\""}"#,
+        );
+        assert!(matches!(AppError::from(error), AppError::Forbidden(_)));
+
+        let error = PostgrestError::parse("RPC", 404, r#"{"code":"PGRST202","message":"not found"}"#);
+        assert!(error.is_not_found_in_schema());
+        assert!(matches!(AppError::from(error), AppError::Postgrest(_)));
+
+        let error = PostgrestError::parse("Select", 502, "Bad Gateway");
+        assert_eq!(error.code(), None);
+        assert_eq!(error.message, "Bad Gateway");
+    }
+}
@@ -1,5 +1,5 @@
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{HeaderMap, header},
     Extension, Json,
 };
@@ -7,13 +7,22 @@ use chrono::Utc;
 use reqwest::Client;
 use serde::Deserialize;
 use serde_json::Value;
+use std::net::SocketAddr;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::config::AppState;
-use crate::db::repositories::{UserRepository, LoginAttemptsRepository, LoginAttemptResult};
+use crate::db::repositories::{UserRepository, LoginAttemptsRepository, LoginAttemptResult, LoginThrottle};
 use crate::error::{AppError, Result};
-use crate::models::{AuthenticatedUser, CreateProfileRequest, DataResponse, User, UserPublic, UserRole};
+use crate::handlers::sessions::record_session;
+use crate::handlers::users::profile_from_user_metadata;
+use crate::middleware::get_client_ip;
+use crate::services::{captcha, email};
+use crate::models::{
+    AuthenticatedUser, CreateProfileRequest, DataResponse, MagicLinkRequest, MagicLinkVerifyRequest,
+    User, UserPublic, UserRole,
+};
+use crate::utils::sanitize::normalize_email;
 
 /// プロファイル作成（Supabase Auth登録後にusersテーブルに追加）
 pub async fn create_profile(
@@ -64,10 +73,10 @@ pub async fn create_profile(
 // =========================
 
 #[derive(Debug, Deserialize)]
-struct SupabaseUser {
+pub(crate) struct SupabaseUser {
     id: String,
     email: String,
-    created_at: String,
+    pub created_at: String,
     #[serde(default)]
     user_metadata: Value,
     #[serde(default)]
@@ -75,15 +84,15 @@ struct SupabaseUser {
 }
 
 #[derive(Debug, Deserialize)]
-struct SupabaseAuthResponse {
-    access_token: String,
+pub(crate) struct SupabaseAuthResponse {
+    pub access_token: String,
     refresh_token: String,
     expires_in: i64,
     token_type: String,
-    user: SupabaseUser,
+    pub user: SupabaseUser,
 }
 
-async fn supabase_auth_request(
+pub(crate) async fn supabase_auth_request(
     client: &Client,
     base_url: &str,
     auth_token: &str,
@@ -124,17 +133,7 @@ fn user_public_from_supabase(repo_user: Option<User>, supa: &SupabaseUser) -> st
         return Ok(UserPublic::from(u));
     }
 
-    let name = supa
-        .user_metadata
-        .get("name")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
-    let phone = supa
-        .user_metadata
-        .get("phone")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    let (name, phone) = profile_from_user_metadata(&supa.user_metadata);
 
     // セキュリティ: UUID.nil()へのフォールバックを防止
     let user_id = parse_supabase_user_id(&supa.id)?;
@@ -200,54 +199,211 @@ pub async fn register(
     Ok(Json(response))
 }
 
-/// クライアントIPを取得
-fn get_client_ip(headers: &HeaderMap) -> String {
-    // X-Real-IP (信頼されたプロキシから)
-    if let Some(ip) = headers.get("x-real-ip").and_then(|v| v.to_str().ok()) {
-        return ip.to_string();
+/// User-Agentを取得
+pub(crate) fn get_user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// 認証後のリダイレクト先を検証（許可オリジン配下のみ、オープンリダイレクト対策）
+pub(crate) fn validate_redirect_to(state: &AppState, redirect_to: &str) -> Result<()> {
+    let allowed = state
+        .config
+        .cors
+        .allowed_origins
+        .iter()
+        .any(|origin| redirect_to == origin || redirect_to.starts_with(&format!("{}/", origin)));
+    if !allowed {
+        return Err(AppError::BadRequest("無効なリダイレクト先です".to_string()));
     }
+    Ok(())
+}
+
+/// アカウントロック中のエラー
+pub(crate) fn account_locked_error(until: chrono::DateTime<Utc>) -> AppError {
+    let remaining_minutes = (until - Utc::now()).num_minutes().max(1);
+    // セキュリティ: 具体的な失敗回数は漏洩しない（アカウント列挙攻撃対策）
+    AppError::TooManyRequests(format!(
+        "ログイン試行回数が上限に達しました。{}分後に再試行してください。",
+        remaining_minutes
+    ))
+}
 
-    // X-Forwarded-For の最初のIP
-    if let Some(xff) = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
-        if let Some(ip) = xff.split(',').next() {
-            return ip.trim().to_string();
+/// ログイン可否チェック（IP+メールのロック・段階的遅延、メール単位のCAPTCHA）
+/// パスワード・OTP・パスキーの全ログイン方式で使う
+pub(crate) async fn check_login_throttle(
+    state: &AppState,
+    login_repo: &LoginAttemptsRepository,
+    email: &str,
+    client_ip: &str,
+    captcha_token: Option<&str>,
+) -> Result<LoginThrottle> {
+    let throttle = login_repo.get_throttle(email, client_ip).await?;
+
+    if let Some(until) = throttle.locked_until {
+        return Err(account_locked_error(until));
+    }
+    if let Some(seconds) = throttle.retry_after_seconds(Utc::now()) {
+        return Err(AppError::TooManyRequests(format!(
+            "ログイン試行が続いています。{}秒後に再試行してください。",
+            seconds
+        )));
+    }
+
+    // 失敗が続いているメールはCAPTCHAを要求（IPを変えながらの試行もメール単位で数える）
+    if throttle.captcha_required() {
+        verify_login_captcha(state, captcha_token, client_ip).await?;
+    }
+
+    Ok(throttle)
+}
+
+/// ログイン失敗記録後のエラー（パスワード・OTP・パスキー共通）
+/// 新規ロック時はアカウント所有者へ通知する
+pub(crate) fn failed_login_error(
+    state: &AppState,
+    result: LoginAttemptResult,
+    email: &str,
+    client_ip: &str,
+    message: &str,
+) -> AppError {
+    match result {
+        LoginAttemptResult::Locked { until, attempts: _, notify_owner } => {
+            if notify_owner {
+                let state = state.clone();
+                let email = email.to_string();
+                let client_ip = client_ip.to_string();
+                tokio::spawn(async move {
+                    notify_login_locked(&state, &email, &client_ip, until).await;
+                });
+            }
+            account_locked_error(until)
+        }
+        // セキュリティ: 残り試行回数は漏洩しない（アカウント列挙攻撃対策）
+        // 攻撃者にロック状況を推測させないため一貫したメッセージを返す
+        LoginAttemptResult::Failed { attempts: _, max_attempts: _ } => {
+            AppError::Unauthorized(message.to_string())
         }
     }
+}
+
+/// ロック発生をアカウント所有者へメール通知（ベストエフォート）
+async fn notify_login_locked(
+    state: &AppState,
+    email: &str,
+    client_ip: &str,
+    until: chrono::DateTime<Utc>,
+) {
+    // 登録済みユーザーのみ通知
+    let user = match UserRepository::new(state.db.service()).find_by_email(email).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return,
+        Err(e) => {
+            tracing::warn!("Lock notification lookup failed: {}", e);
+            return;
+        }
+    };
 
-    "unknown".to_string()
+    let until_jst = until.with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).expect("valid offset"));
+    let body = format!(
+        "{} 様\n\n\
+        お使いのSpiromアカウントで、ログインの失敗が続いたため一部の接続元からのログインを一時的に制限しました。\n\n\
+        接続元IPアドレス: {}\n\
+        制限解除予定: {}\n\n\
+        お心当たりがない場合は、第三者がログインを試みている可能性があります。\n\
+        パスワードの変更と、パスキーの登録をおすすめします。\n\n\
+        ※制限の対象は上記の接続元のみです。別の接続元からはパスワード・マジックリンク・パスキーでログインできます（確認のためCAPTCHAの入力をお願いする場合があります）。\n",
+        if user.name.is_empty() { &user.email } else { &user.name },
+        client_ip,
+        until_jst.format("%Y-%m-%d %H:%M")
+    );
+
+    if let Err(e) = email::send_email(&state.config.email, &user.email, "【Spirom】ログインを一時的に制限しました", &body).await {
+        tracing::warn!("Lock notification failed: user_id={}, error={}", user.id, e);
+    }
 }
 
-/// User-Agentを取得
-fn get_user_agent(headers: &HeaderMap) -> Option<String> {
-    headers
-        .get(header::USER_AGENT)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string())
+/// CAPTCHA（Turnstile）検証
+/// TURNSTILE_SECRET_KEY 未設定時は開発環境のみ検証を省略し、本番環境では拒否する
+async fn verify_login_captcha(state: &AppState, captcha_token: Option<&str>, client_ip: &str) -> Result<()> {
+    let Some(secret) = captcha::get_turnstile_secret(&state.config.captcha) else {
+        if state.config.is_production() {
+            tracing::error!("CAPTCHA required but TURNSTILE_SECRET_KEY is not set in production - rejecting login");
+            return Err(AppError::Internal("TURNSTILE_SECRET_KEY is not configured".to_string()));
+        }
+        tracing::warn!("CAPTCHA required but TURNSTILE_SECRET_KEY is not set; skipping verification");
+        return Ok(());
+    };
+
+    let token = captcha_token
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| AppError::CaptchaRequired("CAPTCHA認証が必要です".to_string()))?;
+
+    let verified = captcha::verify_turnstile(&secret, token, client_ip)
+        .await
+        .map_err(|e| AppError::ExternalService(format!("Turnstile verification failed: {}", e)))?;
+    if !verified {
+        return Err(AppError::CaptchaRequired("CAPTCHA認証に失敗しました".to_string()));
+    }
+
+    Ok(())
+}
+
+/// 認証成功時の共通処理（セッション記録・レスポンス生成）
+/// ログイン方式によらず同じレスポンス形式を返す
+pub(crate) async fn complete_login(
+    state: &AppState,
+    auth_res: SupabaseAuthResponse,
+    client_ip: &str,
+    user_agent: Option<&str>,
+) -> Result<Json<Value>> {
+    // セキュリティ: UUID.nil()へのフォールバックを防止
+    let user_id = parse_supabase_user_id(&auth_res.user.id)?;
+    let repo = UserRepository::new(state.db.service());
+    let repo_user = repo
+        .find_by_id(user_id)
+        .await
+        .ok()
+        .flatten();
+    let user_public = user_public_from_supabase(repo_user, &auth_res.user)?;
+
+    record_session(state, &auth_res.access_token, client_ip, user_agent).await;
+
+    let response = serde_json::json!({
+        "user": user_public,
+        "tokens": {
+            "access_token": auth_res.access_token,
+            "refresh_token": auth_res.refresh_token,
+            "token_type": auth_res.token_type,
+            "expires_in": auth_res.expires_in
+        }
+    });
+
+    Ok(Json(response))
 }
 
 /// Supabase Auth: ログイン（アカウントロック機能付き）
 pub async fn login(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     Json(req): Json<crate::models::LoginRequest>,
 ) -> Result<Json<Value>> {
     req.validate()?;
 
-    let client_ip = get_client_ip(&headers);
+    let client_ip = get_client_ip(&addr, &headers);
     let user_agent = get_user_agent(&headers);
 
+    // 大文字小文字違いで制限を回避されないよう正規化したメールで集計する
+    let login_email = normalize_email(&req.email);
+
     // ログイン試行リポジトリ
     let login_repo = LoginAttemptsRepository::new(state.db.service());
 
-    // アカウントロックチェック
-    if let Some(lock) = login_repo.is_account_locked(&req.email).await? {
-        let remaining_minutes = (lock.locked_until - Utc::now()).num_minutes().max(1);
-        // セキュリティ: 具体的な失敗回数は漏洩しない（アカウント列挙攻撃対策）
-        return Err(AppError::TooManyRequests(format!(
-            "ログイン試行回数が上限に達しました。{}分後に再試行してください。",
-            remaining_minutes
-        )));
-    }
+    // ロック・段階的遅延・CAPTCHAチェック
+    check_login_throttle(&state, &login_repo, &login_email, &client_ip, req.captcha_token.as_deref()).await?;
 
     let client = Client::new();
     let body = serde_json::json!({
@@ -267,56 +423,136 @@ pub async fn login(
 
     match auth_result {
         Ok(auth_res) => {
-            // 成功: ロック解除 & 成功記録
+            // 成功: 成功記録（このIPの失敗カウントをリセット）
             login_repo
-                .handle_successful_login(&req.email, &client_ip, user_agent.as_deref())
+                .handle_successful_login(&login_email, &client_ip, user_agent.as_deref())
                 .await?;
 
-            // セキュリティ: UUID.nil()へのフォールバックを防止
-            let user_id = parse_supabase_user_id(&auth_res.user.id)?;
-            let repo = UserRepository::new(state.db.service());
-            let repo_user = repo
-                .find_by_id(user_id)
-                .await
-                .ok()
-                .flatten();
-            let user_public = user_public_from_supabase(repo_user, &auth_res.user)?;
-
-            let response = serde_json::json!({
-                "user": user_public,
-                "tokens": {
-                    "access_token": auth_res.access_token,
-                    "refresh_token": auth_res.refresh_token,
-                    "token_type": auth_res.token_type,
-                    "expires_in": auth_res.expires_in
-                }
-            });
-
-            Ok(Json(response))
+            complete_login(&state, auth_res, &client_ip, user_agent.as_deref()).await
         }
         Err(_e) => {
             // 失敗: 試行を記録
             let attempt_result = login_repo
-                .handle_failed_login(&req.email, &client_ip, user_agent.as_deref())
+                .handle_failed_login(&login_email, &client_ip, user_agent.as_deref())
                 .await?;
 
-            match attempt_result {
-                LoginAttemptResult::Locked { until, attempts: _ } => {
-                    let remaining_minutes = (until - Utc::now()).num_minutes().max(1);
-                    // セキュリティ: 具体的な試行回数は漏洩しない（アカウント列挙攻撃対策）
-                    Err(AppError::TooManyRequests(format!(
-                        "ログイン試行回数が上限に達しました。{}分後に再試行してください。",
-                        remaining_minutes
-                    )))
-                }
-                LoginAttemptResult::Failed { attempts: _, max_attempts: _ } => {
-                    // セキュリティ: 残り試行回数は漏洩しない（アカウント列挙攻撃対策）
-                    // 攻撃者にロック状況を推測させないため一貫したメッセージを返す
-                    Err(AppError::Unauthorized(
-                        "メールアドレスまたはパスワードが正しくありません。".to_string()
-                    ))
-                }
-            }
+            Err(failed_login_error(
+                &state,
+                attempt_result,
+                &login_email,
+                &client_ip,
+                "メールアドレスまたはパスワードが正しくありません。",
+            ))
+        }
+    }
+}
+
+/// マジックリンク（メールOTP）送信
+/// セキュリティ: アカウントの有無・ロック状態に関わらず同じレスポンスを返す（列挙攻撃対策）
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<MagicLinkRequest>,
+) -> Result<Json<Value>> {
+    req.validate()?;
+
+    let response = Json(serde_json::json!({
+        "message": "ログイン用のメールを送信しました。メールに記載されたコードを入力してください。"
+    }));
+
+    let login_repo = LoginAttemptsRepository::new(state.db.service());
+    let throttle = login_repo
+        .get_throttle(&normalize_email(&req.email), &get_client_ip(&addr, &headers))
+        .await?;
+    if throttle.locked_until.is_some() {
+        return Ok(response);
+    }
+
+    let mut path = "otp".to_string();
+    if let Some(redirect_to) = req.redirect_to.as_deref() {
+        validate_redirect_to(&state, redirect_to)?;
+        path = format!("otp?redirect_to={}", urlencoding::encode(redirect_to));
+    }
+
+    let url = format!("{}/auth/v1/{}", state.config.database.url.trim_end_matches('/'), path);
+    let res = Client::new()
+        .post(url)
+        .header("apikey", &state.config.database.anon_key)
+        .header("Authorization", format!("Bearer {}", state.config.database.anon_key))
+        .json(&serde_json::json!({
+            "email": req.email,
+            // 新規登録はregisterに限定する
+            "create_user": false
+        }))
+        .send()
+        .await
+        .map_err(|e| AppError::ExternalService(format!("OTP request failed: {}", e)))?;
+
+    if !res.status().is_success() {
+        // 未登録メール等。レスポンスは変えずにログのみ
+        let txt = res.text().await.unwrap_or_default();
+        tracing::info!("Magic link not sent: {}", txt);
+    }
+
+    Ok(response)
+}
+
+/// マジックリンク（メールOTP）検証 → ログイン
+/// メール内の6桁コード（token）またはリンクのtoken_hashのどちらかで検証
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<MagicLinkVerifyRequest>,
+) -> Result<Json<Value>> {
+    req.validate()?;
+
+    let client_ip = get_client_ip(&addr, &headers);
+    let user_agent = get_user_agent(&headers);
+
+    let login_email = normalize_email(&req.email);
+    let login_repo = LoginAttemptsRepository::new(state.db.service());
+    check_login_throttle(&state, &login_repo, &login_email, &client_ip, req.captcha_token.as_deref()).await?;
+
+    let body = match (req.token.as_deref(), req.token_hash.as_deref()) {
+        (Some(token), _) => serde_json::json!({ "type": "email", "email": req.email, "token": token }),
+        (None, Some(token_hash)) => serde_json::json!({ "type": "magiclink", "token_hash": token_hash }),
+        (None, None) => {
+            return Err(AppError::BadRequest("token または token_hash が必要です".to_string()))
+        }
+    };
+
+    let auth_result = supabase_auth_request(
+        &Client::new(),
+        &state.config.database.url,
+        &state.config.database.anon_key,
+        "verify",
+        body,
+    )
+    .await;
+
+    match auth_result {
+        // token_hash検証時はリクエストのemailと一致することを確認
+        Ok(auth_res) if normalize_email(&auth_res.user.email) == normalize_email(&req.email) => {
+            login_repo
+                .handle_successful_login(&login_email, &client_ip, user_agent.as_deref())
+                .await?;
+
+            complete_login(&state, auth_res, &client_ip, user_agent.as_deref()).await
+        }
+        _ => {
+            let attempt_result = login_repo
+                .handle_failed_login(&login_email, &client_ip, user_agent.as_deref())
+                .await?;
+
+            Err(failed_login_error(
+                &state,
+                attempt_result,
+                &login_email,
+                &client_ip,
+                "コードが正しくないか、有効期限が切れています。",
+            ))
         }
     }
 }
@@ -324,6 +560,8 @@ pub async fn login(
 /// Supabase Auth: リフレッシュ
 pub async fn refresh_token(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<Value>> {
     let refresh_token = body
@@ -341,25 +579,12 @@ pub async fn refresh_token(
     )
     .await?;
 
-    // セキュリティ: UUID.nil()へのフォールバックを防止
-    let user_id = parse_supabase_user_id(&auth_res.user.id)?;
-    let repo = UserRepository::new(state.db.service());
-    let repo_user = repo
-        .find_by_id(user_id)
-        .await
-        .ok()
-        .flatten();
-    let user_public = user_public_from_supabase(repo_user, &auth_res.user)?;
-
-    let response = serde_json::json!({
-        "user": user_public,
-        "tokens": {
-            "access_token": auth_res.access_token,
-            "refresh_token": auth_res.refresh_token,
-            "token_type": auth_res.token_type,
-            "expires_in": auth_res.expires_in
-        }
-    });
-
-    Ok(Json(response))
+    // 失効済みセッションのリフレッシュはSupabase側で拒否されるため、ここでは最終利用日時のみ更新
+    complete_login(
+        &state,
+        auth_res,
+        &get_client_ip(&addr, &headers),
+        get_user_agent(&headers).as_deref(),
+    )
+    .await
 }
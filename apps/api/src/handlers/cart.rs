@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::HeaderMap,
     Extension, Json,
 };
@@ -9,19 +9,25 @@ use sha2::Sha256;
 use uuid::Uuid;
 use validator::Validate;
 
-use crate::config::AppState;
-use crate::db::repositories::{CartRepository, ProductRepository};
+use crate::config::{AppState, Config};
+use crate::db::repositories::{CartRecoveryRepository, CartRepository, ProductRepository};
 use crate::error::{AppError, Result};
 use crate::middleware::generate_session_id;
 use crate::models::{
-    AddToCartRequest, AuthenticatedUser, CartItem, CartResponse, DataResponse, MergeCartRequest,
-    UpdateCartItemRequest,
+    allocate_line, revalidate_cart_item, AddToCartRequest, AuthenticatedUser, Cart, CartChangeNotice, CartItem,
+    CartItemRevalidation, CartRecoveryStats, CartRecoveryStatsQuery,
+    CartRecoveryStatus, CartResponse, DataResponse, LineAllocation, MergeCartRequest, RecoverCartRequest,
+    Product, ProductVariant, RecoverCartResponse, UpdateCartItemRequest,
 };
+use crate::services::cart_recovery::verify_recovery_token;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// カートに追加できる商品の最大種類数（DoS対策）
+pub(crate) const MAX_CART_ITEMS: usize = 50;
+
 // Authorization: Bearer ... を取り出す
-fn extract_bearer(headers: &HeaderMap) -> Option<String> {
+pub(crate) fn extract_bearer(headers: &HeaderMap) -> Option<String> {
     headers
         .get("authorization")
         .and_then(|h| h.to_str().ok())
@@ -38,6 +44,11 @@ fn sign_session_id(session_id: &str, secret: &str) -> String {
     hex::encode(result.into_bytes())
 }
 
+/// セッションIDのフォーマット検証（マージ元の指定などで署名がない場合）
+pub(crate) fn is_session_id_format(session_id: &str) -> bool {
+    session_id.starts_with("sess_") && session_id.len() >= 37
+}
+
 /// セッションIDの検証（HMAC署名チェック）
 fn verify_session_id(session_id: &str, signature: &str, secret: &str) -> bool {
     // セッションIDのフォーマット検証（sess_uuid形式のみ許可）
@@ -65,11 +76,18 @@ fn verify_session_id(session_id: &str, signature: &str, secret: &str) -> bool {
     diff == 0
 }
 
+/// セッション署名鍵（SESSION_SECRET → JWT_SECRET）
+fn session_secret(config: &Config) -> Result<&str> {
+    let secret = config.session_secret();
+    if secret.is_empty() {
+        return Err(AppError::Internal("SESSION_SECRET is not configured".to_string()));
+    }
+    Ok(secret)
+}
+
 /// セッションID取得（署名付き検証）
-fn get_verified_session_id(headers: &HeaderMap) -> Result<String> {
-    let secret = std::env::var("SESSION_SECRET")
-        .or_else(|_| std::env::var("JWT_SECRET"))
-        .map_err(|_| AppError::Internal("SESSION_SECRET is not configured".to_string()))?;
+pub(crate) fn get_verified_session_id(config: &Config, headers: &HeaderMap) -> Result<String> {
+    let secret = session_secret(config)?;
 
     // ヘッダーからセッションIDと署名を取得
     let session_id = headers
@@ -85,7 +103,7 @@ fn get_verified_session_id(headers: &HeaderMap) -> Result<String> {
     match (session_id, signature) {
         (Some(sid), Some(sig)) => {
             // 署名付きセッションIDの検証
-            if verify_session_id(&sid, &sig, &secret) {
+            if verify_session_id(&sid, &sig, secret) {
                 tracing::debug!("Session verified: {}", &sid[..sid.len().min(15)]);
                 Ok(sid)
             } else {
@@ -120,13 +138,11 @@ fn get_verified_session_id(headers: &HeaderMap) -> Result<String> {
 }
 
 /// 新しいセッションIDと署名を生成
-fn create_signed_session() -> Result<(String, String)> {
-    let secret = std::env::var("SESSION_SECRET")
-        .or_else(|_| std::env::var("JWT_SECRET"))
-        .map_err(|_| AppError::Internal("SESSION_SECRET is not configured".to_string()))?;
+pub(crate) fn create_signed_session(config: &Config) -> Result<(String, String)> {
+    let secret = session_secret(config)?;
 
     let session_id = generate_session_id();
-    let signature = sign_session_id(&session_id, &secret);
+    let signature = sign_session_id(&session_id, secret);
 
     Ok((session_id, signature))
 }
@@ -143,12 +159,62 @@ pub async fn get_cart(
         None => state.db.anonymous(),
     };
 
-    let session_id = get_verified_session_id(&headers)?;
-    let cart_repo = CartRepository::new(client);
+    let session_id = get_verified_session_id(&state.config, &headers)?;
+    let cart_repo = CartRepository::new(client.clone());
 
     let cart = cart_repo.find_by_session(&session_id).await?;
+    if cart.items.is_empty() {
+        return Ok(Json(DataResponse::new(CartResponse::from(cart))));
+    }
 
-    Ok(Json(DataResponse::new(CartResponse::from(cart))))
+    let (cart, changes) = revalidate_cart(&cart_repo, &ProductRepository::new(client), cart).await?;
+    let mut response = CartResponse::from(cart);
+    response.changes = changes;
+
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// 現在の商品情報でカートを再検証し、価格更新・数量調整・削除を反映する
+async fn revalidate_cart(
+    cart_repo: &CartRepository,
+    product_repo: &ProductRepository,
+    mut cart: Cart,
+) -> Result<(Cart, Vec<CartChangeNotice>)> {
+    let product_ids: Vec<Uuid> = cart.items.iter().map(|i| i.product_id).collect();
+    let variant_ids: Vec<Uuid> = cart.items.iter().filter_map(|i| i.variant_id).collect();
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let mut changes = Vec::new();
+    let mut items = Vec::with_capacity(cart.items.len());
+    for item in std::mem::take(&mut cart.items) {
+        let product = products.get(&item.product_id);
+        let variant = item.variant_id.and_then(|id| variants.get(&id));
+        match revalidate_cart_item(&item, product, variant, &mut changes) {
+            CartItemRevalidation::Unchanged => items.push(item),
+            CartItemRevalidation::Updated(updated) => {
+                cart_repo.update_item_snapshot(&cart.session_id, &updated).await?;
+                items.push(updated);
+            }
+            CartItemRevalidation::Removed => {
+                cart_repo
+                    .remove_line(&cart.session_id, item.product_id, item.variant_id)
+                    .await?;
+            }
+        }
+    }
+
+    if !changes.is_empty() {
+        tracing::info!(
+            "Cart revalidated: session={}, changes={}",
+            &cart.session_id[..cart.session_id.len().min(15)],
+            changes.len()
+        );
+    }
+
+    cart.items = items;
+    cart.calculate_totals();
+    Ok((cart, changes))
 }
 
 /// カートに追加
@@ -165,7 +231,7 @@ pub async fn add_to_cart(
         None => state.db.anonymous(),
     };
 
-    let session_id = get_verified_session_id(&headers)?;
+    let session_id = get_verified_session_id(&state.config, &headers)?;
     tracing::info!(
         "add_to_cart: session_id={}, product_id={}",
         &session_id[..session_id.len().min(15)],
@@ -184,7 +250,9 @@ pub async fn add_to_cart(
         return Err(AppError::BadRequest("この商品は現在販売されていません".to_string()));
     }
 
-    if product.stock < req.quantity {
+    // 予約販売の商品は受付枠の範囲で追加できる
+    let variant = find_line_variant(&product_repo, &product, req.variant_id).await?;
+    if allocate_line(&product, variant.as_ref(), req.quantity) == LineAllocation::Unavailable {
         return Err(AppError::BadRequest("在庫が不足しています".to_string()));
     }
 
@@ -192,7 +260,6 @@ pub async fn add_to_cart(
     let existing_cart = cart_repo.find_by_session(&session_id).await?;
 
     // カートアイテム数上限チェック（DoS対策）
-    const MAX_CART_ITEMS: usize = 50;
     let is_new_item = !existing_cart.items.iter().any(|item| item.product_id == req.product_id);
     if is_new_item && existing_cart.items.len() >= MAX_CART_ITEMS {
         return Err(AppError::BadRequest(format!(
@@ -235,6 +302,24 @@ pub async fn add_to_cart(
     Ok(Json(DataResponse::new(CartResponse::from(cart))))
 }
 
+/// 明細のバリアントを取得（商品に属する販売中のもののみ）
+async fn find_line_variant(
+    product_repo: &ProductRepository,
+    product: &Product,
+    variant_id: Option<Uuid>,
+) -> Result<Option<ProductVariant>> {
+    let Some(variant_id) = variant_id else {
+        return Ok(None);
+    };
+    product_repo
+        .find_variants_by_ids(&[variant_id])
+        .await?
+        .remove(&variant_id)
+        .filter(|v| v.product_id == product.id && v.is_active)
+        .map(Some)
+        .ok_or_else(|| AppError::BadRequest("選択したサイズは現在販売されていません".to_string()))
+}
+
 /// カートアイテム数量更新
 pub async fn update_cart_item(
     State(state): State<AppState>,
@@ -250,7 +335,7 @@ pub async fn update_cart_item(
         None => state.db.anonymous(),
     };
 
-    let session_id = get_verified_session_id(&headers)?;
+    let session_id = get_verified_session_id(&state.config, &headers)?;
     let cart_repo = CartRepository::new(client.clone());
     let product_repo = ProductRepository::new(client.clone());
 
@@ -260,7 +345,15 @@ pub async fn update_cart_item(
         .await?
         .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
 
-    if product.stock < req.quantity {
+    let variant_id = cart_repo
+        .find_by_session(&session_id)
+        .await?
+        .items
+        .iter()
+        .find(|item| item.product_id == product_id)
+        .and_then(|item| item.variant_id);
+    let variant = find_line_variant(&product_repo, &product, variant_id).await?;
+    if allocate_line(&product, variant.as_ref(), req.quantity) == LineAllocation::Unavailable {
         return Err(AppError::BadRequest("在庫が不足しています".to_string()));
     }
 
@@ -285,7 +378,7 @@ pub async fn remove_from_cart(
         None => state.db.anonymous(),
     };
 
-    let session_id = get_verified_session_id(&headers)?;
+    let session_id = get_verified_session_id(&state.config, &headers)?;
     let cart_repo = CartRepository::new(client);
 
     cart_repo.remove_item(&session_id, product_id).await?;
@@ -306,7 +399,7 @@ pub async fn clear_cart(
         None => state.db.anonymous(),
     };
 
-    let session_id = get_verified_session_id(&headers)?;
+    let session_id = get_verified_session_id(&state.config, &headers)?;
     let cart_repo = CartRepository::new(client);
 
     cart_repo.clear(&session_id).await?;
@@ -321,12 +414,12 @@ pub async fn merge_cart(
     headers: HeaderMap,
     Json(req): Json<MergeCartRequest>,
 ) -> Result<Json<DataResponse<CartResponse>>> {
-    let user_session_id = get_verified_session_id(&headers)?;
+    let user_session_id = get_verified_session_id(&state.config, &headers)?;
     let cart_repo = CartRepository::new(state.db.service());
 
     // ゲストセッションIDも検証が必要
     // ただしマージ元は既存セッションなので、フォーマットのみ検証
-    if !req.guest_session_id.starts_with("sess_") || req.guest_session_id.len() < 37 {
+    if !is_session_id_format(&req.guest_session_id) {
         return Err(AppError::BadRequest("無効なセッションIDです".to_string()));
     }
 
@@ -338,3 +431,99 @@ pub async fn merge_cart(
 
     Ok(Json(DataResponse::new(CartResponse::from(cart))))
 }
+
+/// 回復リンクからカートを復元（新しいセッションに作成し、その署名を返す）
+pub async fn recover_cart(
+    State(state): State<AppState>,
+    Json(req): Json<RecoverCartRequest>,
+) -> Result<Json<DataResponse<RecoverCartResponse>>> {
+    let invalid = || AppError::BadRequest("カートの復元リンクが無効か、有効期限が切れています".to_string());
+    let recovery_id = verify_recovery_token(req.token.trim(), session_secret(&state.config)?, Utc::now()).ok_or_else(invalid)?;
+
+    let recovery_repo = CartRecoveryRepository::new(state.db.service());
+    let recovery = recovery_repo
+        .find_by_id(recovery_id)
+        .await?
+        .filter(|r| {
+            matches!(
+                r.status,
+                CartRecoveryStatus::Sent | CartRecoveryStatus::Recovered | CartRecoveryStatus::Converted
+            )
+        })
+        .ok_or_else(invalid)?;
+
+    let product_repo = ProductRepository::new(state.db.service());
+    let product_ids: Vec<Uuid> = recovery.items.iter().map(|i| i.product_id).collect();
+    let variant_ids: Vec<Uuid> = recovery.items.iter().filter_map(|i| i.variant_id).collect();
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let (session_id, session_signature) = create_signed_session(&state.config)?;
+    let cart_repo = CartRepository::new(state.db.service());
+    let mut unavailable_items = Vec::new();
+
+    for item in &recovery.items {
+        // 価格・在庫は復元時点の商品情報を使う（在庫が減っていれば数量を減らす）
+        let product = products.get(&item.product_id).filter(|p| p.is_active);
+        let stock = match (product, item.variant_id) {
+            (Some(_), Some(variant_id)) => variants
+                .get(&variant_id)
+                .filter(|v| v.is_active)
+                .map(|v| v.stock)
+                .unwrap_or(0),
+            (Some(product), None) => product.stock,
+            (None, _) => 0,
+        };
+        let Some(product) = product.filter(|_| stock > 0) else {
+            unavailable_items.push(item.product_name.clone());
+            continue;
+        };
+
+        let quantity = item.quantity.min(stock);
+        cart_repo
+            .add_item(
+                &session_id,
+                &CartItem {
+                    product_id: product.id,
+                    product_name: product.name.clone(),
+                    product_slug: product.slug.clone(),
+                    price: product.price,
+                    quantity,
+                    subtotal: product.price * quantity as i64,
+                    image_url: product.images.first().cloned(),
+                    added_at: Utc::now(),
+                    variant_id: item.variant_id,
+                    size: item.size.clone(),
+                },
+            )
+            .await?;
+    }
+
+    recovery_repo.mark_recovered(recovery.id, &session_id).await?;
+    tracing::info!(
+        "Cart recovered: recovery_id={}, new_session={}",
+        recovery.id,
+        &session_id[..session_id.len().min(15)]
+    );
+
+    let cart = cart_repo.find_by_session(&session_id).await?;
+    Ok(Json(DataResponse::new(RecoverCartResponse {
+        session_id,
+        session_signature,
+        cart: CartResponse::from(cart),
+        unavailable_items,
+    })))
+}
+
+/// カゴ落ち回復の集計（管理者専用）
+pub async fn get_recovery_stats_admin(
+    State(state): State<AppState>,
+    Query(query): Query<CartRecoveryStatsQuery>,
+) -> Result<Json<DataResponse<CartRecoveryStats>>> {
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+    let counts = CartRecoveryRepository::new(state.db.service())
+        .stats(Utc::now() - chrono::Duration::days(days))
+        .await?;
+
+    Ok(Json(DataResponse::new(CartRecoveryStats::new(days, counts))))
+}
@@ -84,12 +84,10 @@ pub async fn get_category_products(
         .await?
         .ok_or_else(|| AppError::NotFound("カテゴリが見つかりません".to_string()))?;
 
-    let products = product_repo
-        .find_by_category(category.id, query.limit())
+    let (products, total) = product_repo
+        .find_page_by_category(category.id, query.offset(), query.limit())
         .await?;
 
-    let total = products.len() as i64;
-
     Ok(Json(PaginatedResponse::new(
         products,
         query.page,
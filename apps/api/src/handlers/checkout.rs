@@ -0,0 +1,152 @@
+use axum::{extract::State, http::HeaderMap, Extension, Json};
+use chrono::Utc;
+use validator::Validate;
+
+use crate::config::AppState;
+use crate::db::repositories::{CartRepository, ProductRepository, UserRepository};
+use crate::error::{AppError, Result};
+use crate::handlers::cart::get_verified_session_id;
+use crate::handlers::orders::build_order_items;
+use crate::models::{
+    calculate_shipping_fee, calculate_tax, shipping_rate, sign_quote_token, verify_quote_token,
+    AuthenticatedUser, CheckoutQuoteRequest, CheckoutQuoteResponse, DataResponse, OrderItemRequest,
+    QuoteClaims, QuoteDiscount, QuotedLine, ShippingOption, TaxLine, QUOTE_TTL_MINUTES,
+    TAX_RATE_PERCENT,
+};
+
+/// 見積もり（注文を作成せずに送料・税・合計を計算し、短時間有効な見積もりトークンを返す）
+pub async fn create_quote(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    Json(req): Json<CheckoutQuoteRequest>,
+) -> Result<Json<DataResponse<CheckoutQuoteResponse>>> {
+    req.validate()?;
+
+    let db_service = state.db.service();
+
+    // リクエストボディのitemsを優先的に使用、なければカートから取得
+    let source_items = if let Some(ref items) = req.items {
+        for item in items {
+            item.validate()?;
+        }
+        items.clone()
+    } else {
+        let session_id = get_verified_session_id(&state.config, &headers)?;
+        let cart = CartRepository::new(db_service.clone()).find_by_session(&session_id).await?;
+        if cart.items.is_empty() {
+            return Err(AppError::BadRequest("カートが空です".to_string()));
+        }
+        cart.items
+            .iter()
+            .map(|item| OrderItemRequest {
+                product_id: item.product_id,
+                quantity: item.quantity,
+                variant_id: item.variant_id,
+                size: item.size.clone(),
+            })
+            .collect()
+    };
+
+    // 配送先の国（登録済み住所は本人のもののみ）
+    let country = match (req.shipping_address_id, req.country) {
+        (Some(address_id), _) => {
+            let Some(Extension(user)) = auth_user else {
+                return Err(AppError::Unauthorized("登録済み住所を使うにはログインが必要です".to_string()));
+            };
+            UserRepository::new(db_service.clone())
+                .find_address(user.id, address_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound("配送先住所が見つかりません".to_string()))?
+                .country
+        }
+        (None, Some(country)) => country,
+        (None, None) => {
+            return Err(AppError::BadRequest("配送先の国または住所を指定してください".to_string()));
+        }
+    };
+
+    // 金額計算（注文作成と同じ関数を使う）
+    let product_ids: Vec<_> = source_items.iter().map(|i| i.product_id).collect();
+    let product_repo = ProductRepository::new(db_service);
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variant_ids: Vec<_> = source_items.iter().filter_map(|i| i.variant_id).collect();
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+    let (items, subtotal) = build_order_items(&source_items, &products, &variants, None)?;
+
+    let rate = shipping_rate(&country);
+    let shipping_fee = calculate_shipping_fee(subtotal, &country);
+    let tax = calculate_tax(subtotal);
+    let total = subtotal + shipping_fee + tax;
+
+    let mut discounts = Vec::new();
+    if shipping_fee == 0 && rate.fee > 0 {
+        discounts.push(QuoteDiscount {
+            code: "free_shipping".to_string(),
+            description: format!("送料無料（¥{}以上のご注文）", rate.free_shipping_threshold),
+            amount: rate.fee,
+        });
+    }
+
+    let expires_at = Utc::now() + chrono::Duration::minutes(QUOTE_TTL_MINUTES);
+    let claims = QuoteClaims {
+        exp: expires_at.timestamp(),
+        country: country.clone(),
+        lines: items
+            .iter()
+            .map(|item| QuotedLine {
+                product_id: item.product_id,
+                variant_id: item.variant_id,
+                quantity: item.quantity,
+                price: item.price,
+            })
+            .collect(),
+    };
+    let quote_token = sign_quote_token(&claims, state.config.session_secret());
+
+    Ok(Json(DataResponse::new(CheckoutQuoteResponse {
+        items,
+        subtotal,
+        shipping_options: vec![ShippingOption {
+            method: "standard".to_string(),
+            zone: rate.zone.to_string(),
+            fee: shipping_fee,
+            base_fee: rate.fee,
+            free_shipping_threshold: rate.free_shipping_threshold,
+            selected: true,
+        }],
+        shipping_fee,
+        taxes: vec![TaxLine {
+            name: "消費税".to_string(),
+            rate_percent: TAX_RATE_PERCENT,
+            taxable_amount: subtotal,
+            amount: tax,
+        }],
+        tax,
+        discounts,
+        total,
+        currency: "JPY".to_string(),
+        country,
+        quote_token,
+        expires_at,
+    })))
+}
+
+/// 注文作成時の見積もりトークン検証（期限切れ・内容や配送先の変更は再見積もりを求める）
+pub(crate) fn verify_order_quote(
+    state: &AppState,
+    token: &str,
+    items: &[OrderItemRequest],
+    country: &str,
+) -> Result<QuoteClaims> {
+    let claims = verify_quote_token(token, state.config.session_secret(), Utc::now()).ok_or_else(|| {
+        AppError::BadRequest("見積もりの有効期限が切れています。もう一度お見積もりください".to_string())
+    })?;
+
+    if !claims.matches(items, country) {
+        return Err(AppError::BadRequest(
+            "見積もり後に注文内容または配送先が変更されました。もう一度お見積もりください".to_string(),
+        ));
+    }
+    Ok(claims)
+}
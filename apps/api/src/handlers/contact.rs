@@ -9,8 +9,9 @@ use uuid::Uuid;
 use validator::Validate;
 
 use crate::config::AppState;
+use crate::db::Query as RestQuery;
 use crate::error::{AppError, Result};
-use crate::models::AuthenticatedUser;
+use crate::models::{AuthenticatedUser, SortOrder};
 
 /// お問い合わせ種別
 const VALID_INQUIRY_TYPES: [&str; 5] = ["order", "product", "shipping", "return", "other"];
@@ -148,7 +149,7 @@ pub async fn submit_contact(
     // RLSポリシーで本人のみ閲覧可能
     let db = state.db.with_auth(&token);
     let user_info: Option<UserInfo> = db
-        .select_single("users", &format!("id=eq.{}&select=name,email", auth_user.id))
+        .select_single("users", &RestQuery::new().eq("id", auth_user.id).select("name,email"))
         .await
         .map_err(|e| {
             tracing::error!("Failed to fetch user info: {}", e);
@@ -309,21 +310,22 @@ pub async fn list_contacts(
     let offset = query.offset.unwrap_or(0);
 
     // クエリを構築
-    let mut query_str = format!(
-        "select=*&order=created_at.desc&limit={}&offset={}",
-        limit, offset
-    );
+    let mut rest_query = RestQuery::new()
+        .select("*")
+        .order("created_at", SortOrder::Desc)
+        .limit(limit as usize)
+        .offset(offset as usize);
 
     if let Some(status) = &query.status {
         // SQLインジェクション対策: ホワイトリストによるバリデーション
         if !VALID_STATUSES.contains(&status.as_str()) {
             return Err(AppError::BadRequest("無効なステータスです".to_string()));
         }
-        query_str.push_str(&format!("&status=eq.{}", status));
+        rest_query = rest_query.eq("status", status);
     }
 
     let submissions: Vec<ContactSubmission> = db
-        .select("contact_submissions", &query_str)
+        .select("contact_submissions", &rest_query)
         .await
         .map_err(|e| AppError::Internal(format!("Failed to fetch contacts: {}", e)))?;
 
@@ -343,7 +345,7 @@ pub async fn get_contact(
     let db = state.db.with_auth(&token);
 
     let submission: Option<ContactSubmission> = db
-        .select_single("contact_submissions", &format!("id=eq.{}&select=*", id))
+        .select_single("contact_submissions", &RestQuery::new().eq("id", &id).select("*"))
         .await
         .map_err(|e| AppError::Internal(format!("Failed to fetch contact: {}", e)))?;
 
@@ -392,7 +394,7 @@ pub async fn update_contact_status(
     };
 
     let results: Vec<ContactSubmission> = db
-        .update("contact_submissions", &format!("id=eq.{}", id), &update_data)
+        .update("contact_submissions", &RestQuery::new().eq("id", &id), &update_data)
         .await
         .map_err(|e| AppError::Internal(format!("Failed to update contact: {}", e)))?;
 
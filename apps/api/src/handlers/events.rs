@@ -0,0 +1,38 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::DomainEventRepository;
+use crate::error::{AppError, Result};
+use crate::models::{DataResponse, DomainEvent, DomainEventListQuery};
+
+/// ドメインイベント一覧（管理者専用）
+/// domain_events はRLSポリシーなしのため service_role で参照する（admin_middleware で権限確認済み）
+pub async fn list_events_admin(
+    State(state): State<AppState>,
+    Query(query): Query<DomainEventListQuery>,
+) -> Result<Json<DataResponse<Vec<DomainEvent>>>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let events = DomainEventRepository::new(state.db.service())
+        .find_all(query.status, limit)
+        .await?;
+
+    Ok(Json(DataResponse::new(events)))
+}
+
+/// デッドレター等のイベントを再配信（管理者専用）
+pub async fn retry_event_admin(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DataResponse<DomainEvent>>> {
+    let event = DomainEventRepository::new(state.db.service())
+        .requeue(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("再配信可能なイベントが見つかりません".to_string()))?;
+
+    tracing::info!("Domain event requeued by admin: id={}, type={:?}", event.id, event.event_type);
+    Ok(Json(DataResponse::new(event)))
+}
@@ -2,7 +2,10 @@ use axum::{extract::State, Json};
 use serde::Serialize;
 
 use crate::config::AppState;
-use crate::error::Result;
+use crate::db::migrations::latest_version;
+use crate::db::{CircuitState, Query};
+use crate::models::SortOrder;
+use crate::error::{AppError, Result};
 
 #[derive(Serialize)]
 pub struct HealthResponse {
@@ -14,6 +17,10 @@ pub struct HealthResponse {
 pub struct ReadinessResponse {
     pub status: String,
     pub database: String,
+    /// Supabase REST呼び出しのサーキットブレーカー状態（closed / open / half_open）
+    pub circuit_breaker: String,
+    /// マイグレーション状態（up_to_date / pending / unknown）
+    pub migrations: String,
     pub rpc_status: RpcStatus,
 }
 
@@ -22,6 +29,7 @@ pub struct RpcStatus {
     pub reserve_stock_bulk: String,
     pub release_stock_bulk: String,
     pub record_stripe_event: String,
+    pub check_token_revocation: String,
 }
 
 /// ヘルスチェック
@@ -54,22 +62,33 @@ pub async fn readiness(State(state): State<AppState>) -> Result<Json<ReadinessRe
     let reserve_stock_status = check_rpc_exists(&db, "reserve_stock_bulk").await;
     let release_stock_status = check_rpc_exists(&db, "release_stock_bulk").await;
     let record_stripe_event_status = check_rpc_exists(&db, "record_stripe_event").await;
+    let check_token_revocation_status = check_rpc_exists(&db, "check_token_revocation").await;
 
     let rpc_status = RpcStatus {
         reserve_stock_bulk: reserve_stock_status.clone(),
         release_stock_bulk: release_stock_status.clone(),
         record_stripe_event: record_stripe_event_status.clone(),
+        check_token_revocation: check_token_revocation_status.clone(),
     };
 
     // 全てのRPCが利用可能かチェック
     let all_rpcs_available = reserve_stock_status == "available"
         && release_stock_status == "available"
-        && record_stripe_event_status == "available";
+        && record_stripe_event_status == "available"
+        && check_token_revocation_status == "available";
 
-    let overall_status = if db_status == "connected" && all_rpcs_available {
+    let migrations_status = check_migrations(&db).await;
+
+    // ブレーカーが開いている間はAPIリクエストが即時失敗するため degraded とする
+    let circuit_state = state.db.circuit_state();
+
+    let overall_status = if db_status == "connected" && all_rpcs_available
+        && circuit_state == CircuitState::Closed
+        && migrations_status == "up_to_date"
+    {
         "ready"
     } else if db_status == "connected" {
-        "degraded" // DBは接続できるがRPCが不足
+        "degraded" // DBは接続できるがRPC・マイグレーションが不足、またはブレーカーが開いている
     } else {
         "not_ready"
     };
@@ -77,20 +96,42 @@ pub async fn readiness(State(state): State<AppState>) -> Result<Json<ReadinessRe
     // RPCが不足している場合は警告ログ
     if !all_rpcs_available {
         tracing::warn!(
-            "RPC health check failed: reserve_stock={}, release_stock={}, record_stripe_event={}",
+            "RPC health check failed: reserve_stock={}, release_stock={}, record_stripe_event={}, check_token_revocation={}",
             reserve_stock_status,
             release_stock_status,
-            record_stripe_event_status
+            record_stripe_event_status,
+            check_token_revocation_status
         );
     }
 
     Ok(Json(ReadinessResponse {
         status: overall_status.to_string(),
         database: db_status,
+        circuit_breaker: circuit_state.as_str().to_string(),
+        migrations: migrations_status.to_string(),
         rpc_status,
     }))
 }
 
+/// 適用済みマイグレーションがバイナリの最新バージョンに達しているか
+async fn check_migrations(db: &crate::db::AuthenticatedClient) -> &'static str {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        version: i32,
+    }
+
+    let query = Query::new()
+        .select("version")
+        .order("version", SortOrder::Desc)
+        .limit(1);
+    match db.select::<Row>("schema_migrations", &query).await {
+        Ok(rows) if rows.first().map(|r| r.version) >= Some(latest_version()) => "up_to_date",
+        Ok(_) => "pending",
+        // 未導入（migrate未実行）・接続エラー
+        Err(_) => "unknown",
+    }
+}
+
 /// RPCの存在確認
 async fn check_rpc_exists(db: &crate::db::AuthenticatedClient, rpc_name: &str) -> String {
     // ダミーパラメータでRPCを呼び出し、エラーの種類で判定
@@ -100,18 +141,13 @@ async fn check_rpc_exists(db: &crate::db::AuthenticatedClient, rpc_name: &str) -
 
     match result {
         Ok(_) => "available".to_string(),
-        Err(e) => {
-            let err_str = e.to_string();
-            if err_str.contains("PGRST202") {
-                // RPC not found
-                "not_found".to_string()
-            } else if err_str.contains("PGRST204") || err_str.contains("argument") {
-                // 引数エラー = RPCは存在する
-                "available".to_string()
-            } else {
-                // その他のエラー（接続エラー等）
-                "error".to_string()
-            }
+        // RPC not found
+        Err(AppError::Postgrest(e)) if e.code() == Some("PGRST202") => "not_found".to_string(),
+        // 引数エラー = RPCは存在する
+        Err(AppError::Postgrest(e)) if e.code() == Some("PGRST204") || e.message.contains("argument") => {
+            "available".to_string()
         }
+        // その他のエラー（接続エラー等）
+        Err(_) => "error".to_string(),
     }
 }
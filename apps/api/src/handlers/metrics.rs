@@ -0,0 +1,38 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use subtle::ConstantTimeEq;
+
+use crate::config::AppState;
+use crate::error::{AppError, Result};
+use crate::services::metrics::metrics;
+
+/// Prometheusメトリクス
+/// METRICS_TOKEN 未設定時は無効（404）、設定時は `Authorization: Bearer <token>` を要求する
+pub async fn export_metrics(State(state): State<AppState>, headers: HeaderMap) -> Result<Response> {
+    let token = state
+        .config
+        .metrics
+        .token
+        .as_ref()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.expose())
+        .ok_or_else(|| AppError::NotFound("Not found".to_string()))?;
+
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !bool::from(provided.as_bytes().ct_eq(token.as_bytes())) {
+        return Err(AppError::Unauthorized("Invalid metrics token".to_string()));
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(),
+    )
+        .into_response())
+}
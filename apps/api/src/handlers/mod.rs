@@ -1,6 +1,10 @@
 pub mod health;
+pub mod metrics;
 pub mod auth;
+pub mod passkeys;
+pub mod oauth;
 pub mod users;
+pub mod sessions;
 pub mod products;
 pub mod categories;
 pub mod cart;
@@ -10,3 +14,9 @@ pub mod payments;
 pub mod mfa;
 pub mod contact;
 pub mod jpyc;
+pub mod events;
+pub mod webhooks;
+pub mod wishlist;
+pub mod product_alerts;
+pub mod checkout;
+pub mod order_modifications;
@@ -0,0 +1,410 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::AppState;
+use crate::db::repositories::oauth_state_repository::STATE_TTL_MINUTES;
+use crate::db::repositories::{OAuthStateRepository, UserRepository};
+use crate::error::{AppError, Result};
+use crate::handlers::auth::{
+    complete_login, get_user_agent, supabase_auth_request, validate_redirect_to,
+};
+use crate::handlers::users::{ensure_user_profile, fetch_supabase_auth_user};
+use crate::middleware::{get_client_ip, validate_supabase_token};
+use crate::models::{
+    AuthenticatedUser, DataResponse, LinkedIdentity, OAuthAuthorizeQuery, OAuthCallbackRequest,
+    OAuthProvider,
+};
+use crate::services::webauthn::{encode_b64url, generate_challenge};
+use crate::utils::sanitize::normalize_email;
+
+/// 認可を開始したブラウザに紐付けるCookie（ログインCSRF対策）
+const BINDING_COOKIE: &str = "spirom_oauth_binding";
+/// 紐付けCookieを送るパス（コールバックのみ）
+const BINDING_COOKIE_PATH: &str = "/api/v1/auth/oauth";
+
+/// PKCE: code_verifier から code_challenge（S256）を生成
+fn pkce_challenge(code_verifier: &str) -> String {
+    encode_b64url(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// フロントエンドのコールバックURLに状態IDを付与
+fn callback_url(redirect_to: &str, state_id: Uuid) -> String {
+    let separator = if redirect_to.contains('?') { '&' } else { '?' };
+    format!("{}{}oauth_state={}", redirect_to, separator, state_id)
+}
+
+fn hash_binding(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/// 紐付けCookieのSet-Cookie値（max_age=0で削除）
+/// 本番はAPIが別オリジンのため SameSite=None; Secure で送る
+fn binding_cookie(value: &str, max_age: i64, secure: bool) -> String {
+    let attrs = if secure { "SameSite=None; Secure" } else { "SameSite=Lax" };
+    format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; {}",
+        BINDING_COOKIE, value, BINDING_COOKIE_PATH, max_age, attrs
+    )
+}
+
+/// リクエストのCookieヘッダから値を取得
+fn read_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// PKCE状態を作成し、ブラウザ紐付けCookieを返す
+async fn create_state(
+    state: &AppState,
+    provider: OAuthProvider,
+    code_verifier: &str,
+    redirect_to: &str,
+    user_id: Option<Uuid>,
+) -> Result<(Uuid, String)> {
+    let binding = generate_challenge();
+    let state_id = OAuthStateRepository::new(state.db.service())
+        .create(&provider.to_string(), code_verifier, redirect_to, user_id, &hash_binding(&binding))
+        .await?;
+    let cookie = binding_cookie(&binding, STATE_TTL_MINUTES * 60, state.config.is_production());
+    Ok((state_id, cookie))
+}
+
+/// 今回のコード交換で新規作成されたSupabase Authユーザーを削除（プロフィール未作成のまま残さない）
+async fn discard_new_auth_user(
+    state: &AppState,
+    user_id: Uuid,
+    user_created_at: &str,
+    state_created_at: DateTime<Utc>,
+) {
+    let created_in_this_flow = user_created_at
+        .parse::<DateTime<Utc>>()
+        .map(|created_at| created_at >= state_created_at)
+        .unwrap_or(false);
+    if !created_in_this_flow {
+        return;
+    }
+    if let Err(e) = state.db.delete_auth_user(user_id).await {
+        tracing::warn!("OAuth: failed to delete dangling auth user {}: {}", user_id, e);
+    }
+}
+
+fn parse_provider(provider: &str) -> Result<OAuthProvider> {
+    provider
+        .parse()
+        .map_err(|_| AppError::BadRequest("未対応のログインプロバイダです".to_string()))
+}
+
+/// ソーシャルログイン開始（認可URLを返す）
+/// フロントエンドは authorize_url へ遷移し、戻ってきた code と oauth_state を callback に送る
+pub async fn authorize(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthAuthorizeQuery>,
+) -> Result<impl IntoResponse> {
+    query.validate()?;
+    let provider = parse_provider(&provider)?;
+    validate_redirect_to(&state, &query.redirect_to)?;
+
+    let code_verifier = generate_challenge();
+    let (state_id, cookie) =
+        create_state(&state, provider, &code_verifier, &query.redirect_to, None).await?;
+
+    let authorize_url = format!(
+        "{}/auth/v1/authorize?provider={}&redirect_to={}&code_challenge={}&code_challenge_method=s256",
+        state.config.database.url.trim_end_matches('/'),
+        urlencoding::encode(&provider.supabase_provider(&state.config.oauth)),
+        urlencoding::encode(&callback_url(&query.redirect_to, state_id)),
+        pkce_challenge(&code_verifier),
+    );
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(serde_json::json!({
+            "authorize_url": authorize_url,
+            "state": state_id
+        })),
+    ))
+}
+
+/// ソーシャルログイン・アカウント連携のコールバック（認可コードをセッションに交換）
+/// 認可を開始したブラウザの紐付けCookieが必要（他人の認可コードでログインさせる攻撃を防ぐ）
+pub async fn callback(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<OAuthCallbackRequest>,
+) -> Result<impl IntoResponse> {
+    req.validate()?;
+
+    let invalid_state = || AppError::BadRequest("ログイン状態が無効か期限切れです".to_string());
+    let binding = read_cookie(&headers, BINDING_COOKIE).ok_or_else(invalid_state)?;
+    let oauth_state = OAuthStateRepository::new(state.db.service())
+        .consume(req.state, &hash_binding(binding))
+        .await?
+        .ok_or_else(invalid_state)?;
+
+    let auth_res = supabase_auth_request(
+        &Client::new(),
+        &state.config.database.url,
+        &state.config.database.anon_key,
+        "token?grant_type=pkce",
+        serde_json::json!({
+            "auth_code": req.code,
+            "code_verifier": oauth_state.code_verifier
+        }),
+    )
+    .await?;
+
+    let claims = validate_supabase_token(&auth_res.access_token, &state.config.jwt)?;
+    let auth_user: AuthenticatedUser = claims
+        .try_into()
+        .map_err(|e: &str| AppError::Unauthorized(e.to_string()))?;
+
+    // アカウント連携フローの場合、連携を開始したユーザー本人であること
+    if let Some(linking_user_id) = oauth_state.user_id {
+        if linking_user_id != auth_user.id {
+            discard_new_auth_user(&state, auth_user.id, &auth_res.user.created_at, oauth_state.created_at)
+                .await;
+            return Err(AppError::Conflict(
+                "このアカウントは既に別のユーザーに連携されています".to_string(),
+            ));
+        }
+    }
+
+    // 既存ユーザーとの紐付け
+    // 確認済みメールが一致する場合はSupabase Authが同一ユーザーに自動でリンクする。
+    // 別IDで同じメールのプロフィールが存在する = 未確認メールのため自動リンクしない
+    let user_repo = UserRepository::new(state.db.service());
+    if user_repo.find_by_id(auth_user.id).await?.is_none() {
+        if let Some(existing) = user_repo.find_by_email(&normalize_email(&auth_user.email)).await? {
+            if existing.id != auth_user.id {
+                tracing::warn!(
+                    "OAuth login with email already registered: provider={}, new_user_id={}, existing_user_id={}",
+                    oauth_state.provider,
+                    auth_user.id,
+                    existing.id
+                );
+                discard_new_auth_user(&state, auth_user.id, &auth_res.user.created_at, oauth_state.created_at)
+                .await;
+                return Err(AppError::Conflict(
+                    "このメールアドレスは既に登録されています。既存の方法でログイン後、アカウント設定から連携してください".to_string(),
+                ));
+            }
+        }
+
+        // プロバイダのクレーム（氏名等）からプロフィールを作成
+        ensure_user_profile(&state, &auth_user, &auth_res.access_token).await?;
+    }
+
+    tracing::info!(
+        "OAuth {} succeeded: provider={}, user_id={}",
+        if oauth_state.user_id.is_some() { "link" } else { "login" },
+        oauth_state.provider,
+        auth_user.id
+    );
+
+    let response = complete_login(
+        &state,
+        auth_res,
+        &get_client_ip(&addr, &headers),
+        get_user_agent(&headers).as_deref(),
+    )
+    .await?;
+
+    let clear_cookie = binding_cookie("", 0, state.config.is_production());
+    Ok(([(header::SET_COOKIE, clear_cookie)], response))
+}
+
+/// 連携済みIDプロバイダ一覧
+pub async fn list_identities(
+    State(state): State<AppState>,
+    Extension(token): Extension<String>,
+) -> Result<Json<DataResponse<Vec<LinkedIdentity>>>> {
+    let identities = fetch_identities(&state, &token).await?;
+    Ok(Json(DataResponse::new(identities)))
+}
+
+/// IDプロバイダ連携開始（認可URLを返す）
+pub async fn link_identity(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthAuthorizeQuery>,
+) -> Result<impl IntoResponse> {
+    query.validate()?;
+    let provider = parse_provider(&provider)?;
+    validate_redirect_to(&state, &query.redirect_to)?;
+
+    let code_verifier = generate_challenge();
+    let (state_id, cookie) =
+        create_state(&state, provider, &code_verifier, &query.redirect_to, Some(auth_user.id)).await?;
+
+    // Supabase Auth: 手動リンク（ユーザーJWTで呼び出し、リダイレクトせずURLを受け取る）
+    let url = format!(
+        "{}/auth/v1/user/identities/authorize?provider={}&redirect_to={}&code_challenge={}&code_challenge_method=s256&skip_http_redirect=true",
+        state.config.database.url.trim_end_matches('/'),
+        urlencoding::encode(&provider.supabase_provider(&state.config.oauth)),
+        urlencoding::encode(&callback_url(&query.redirect_to, state_id)),
+        pkce_challenge(&code_verifier),
+    );
+
+    let res = Client::new()
+        .get(url)
+        .header("apikey", &state.config.database.anon_key)
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| AppError::ExternalService(format!("Identity link request failed: {}", e)))?;
+
+    if !res.status().is_success() {
+        let txt = res.text().await.unwrap_or_default();
+        return Err(AppError::ExternalService(format!("Identity link failed: {}", txt)));
+    }
+
+    let body: Value = res
+        .json()
+        .await
+        .map_err(|e| AppError::ExternalService(format!("Identity link parse failed: {}", e)))?;
+    let authorize_url = body
+        .get("url")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| AppError::ExternalService("Identity link: url missing".to_string()))?;
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(serde_json::json!({
+            "authorize_url": authorize_url,
+            "state": state_id
+        })),
+    ))
+}
+
+/// IDプロバイダ連携解除
+/// ログイン手段がなくなるのを防ぐため、最後の1つは解除できない
+pub async fn unlink_identity(
+    State(state): State<AppState>,
+    Extension(token): Extension<String>,
+    Path(identity_id): Path<String>,
+) -> Result<Json<Value>> {
+    let identities = fetch_identities(&state, &token).await?;
+
+    if !identities.iter().any(|i| i.identity_id == identity_id) {
+        return Err(AppError::NotFound("連携が見つかりません".to_string()));
+    }
+    if identities.len() <= 1 {
+        return Err(AppError::BadRequest(
+            "最後のログイン方法は解除できません".to_string(),
+        ));
+    }
+
+    let url = format!(
+        "{}/auth/v1/user/identities/{}",
+        state.config.database.url.trim_end_matches('/'),
+        urlencoding::encode(&identity_id)
+    );
+    let res = Client::new()
+        .delete(url)
+        .header("apikey", &state.config.database.anon_key)
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| AppError::ExternalService(format!("Identity unlink request failed: {}", e)))?;
+
+    if !res.status().is_success() {
+        let txt = res.text().await.unwrap_or_default();
+        return Err(AppError::ExternalService(format!("Identity unlink failed: {}", txt)));
+    }
+
+    Ok(Json(serde_json::json!({ "message": "連携を解除しました" })))
+}
+
+/// Supabase Auth から連携済みIDプロバイダを取得
+async fn fetch_identities(state: &AppState, token: &str) -> Result<Vec<LinkedIdentity>> {
+    let supa_user = fetch_supabase_auth_user(
+        &state.config.database.url,
+        &state.config.database.anon_key,
+        token,
+    )
+    .await?;
+
+    let identities = supa_user
+        .identities
+        .iter()
+        .filter_map(|identity| {
+            let text = |key: &str| identity.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+            Some(LinkedIdentity {
+                identity_id: text("identity_id").or_else(|| text("id"))?,
+                provider: text("provider")?,
+                email: identity
+                    .get("identity_data")
+                    .and_then(|d| d.get("email"))
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string()),
+                created_at: text("created_at"),
+                last_sign_in_at: text("last_sign_in_at"),
+            })
+        })
+        .collect();
+
+    Ok(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636 Appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_binding_cookie() {
+        assert_eq!(
+            binding_cookie("abc", 600, true),
+            "spirom_oauth_binding=abc; Path=/api/v1/auth/oauth; Max-Age=600; HttpOnly; SameSite=None; Secure"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, "other=1; spirom_oauth_binding=abc".parse().unwrap());
+        assert_eq!(read_cookie(&headers, BINDING_COOKIE), Some("abc"));
+        headers.insert(header::COOKIE, "spirom_oauth_binding=".parse().unwrap());
+        assert_eq!(read_cookie(&headers, BINDING_COOKIE), None);
+    }
+
+    #[test]
+    fn test_callback_url() {
+        let id = Uuid::nil();
+        assert_eq!(
+            callback_url("https://spirom.com/auth/callback", id),
+            format!("https://spirom.com/auth/callback?oauth_state={}", id)
+        );
+        assert_eq!(
+            callback_url("https://spirom.com/auth/callback?next=/cart", id),
+            format!("https://spirom.com/auth/callback?next=/cart&oauth_state={}", id)
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! 発送前の注文変更（配送先の変更・同一商品内のサイズ交換）
+//! 差額がマイナスなら適用後に一部返金、プラスなら追加決済（PaymentIntent）の完了Webhookで適用する
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::AppState;
+use crate::db::repositories::{
+    ModificationApplyResult, NewOrderModification, OrderModificationRepository, OrderRepository,
+    ProductRepository, UserRepository,
+};
+use crate::error::{AppError, Result};
+use crate::handlers::orders::GetGuestOrderParams;
+use crate::handlers::payments::stripe_provider;
+use crate::models::{
+    hash_guest_token, is_order_modifiable, recalculate_order_totals, swapped_item_price, AuthenticatedUser,
+    DataResponse, ItemVariantChange, ModificationPayment, ModificationRefundStatus, ModifyOrderRequest, Order,
+    OrderAddress, OrderModification, OrderModificationResponse, OrderModificationStatus, PaymentMethod,
+    PaymentStatus,
+};
+use crate::services::payment::{CreateIntentParams, PaymentProvider, RefundStatus, StripePaymentProvider};
+
+/// 注文変更（ログインユーザー）
+pub async fn modify_order(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<ModifyOrderRequest>,
+) -> Result<Json<DataResponse<OrderModificationResponse>>> {
+    req.validate()?;
+
+    let order = OrderRepository::new(state.db.with_auth(&token))
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("注文が見つかりません".to_string()))?;
+    if order.user_id != Some(auth_user.id) {
+        return Err(AppError::Forbidden("この注文にアクセスする権限がありません".to_string()));
+    }
+
+    // 登録済み住所は本人のもののみ
+    let shipping_address = match (req.shipping_address_id, &req.shipping_address) {
+        (Some(address_id), _) => {
+            let address = UserRepository::new(state.db.with_auth(&token))
+                .find_address(auth_user.id, address_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound("配送先住所が見つかりません".to_string()))?;
+            Some(OrderAddress::from_address(&address, order.shipping_address.name.clone()))
+        }
+        (None, Some(address)) => Some(address.to_order_address()),
+        (None, None) => None,
+    };
+
+    let response = request_modification(
+        &state,
+        order,
+        Some(auth_user.id),
+        &auth_user.email,
+        shipping_address,
+        &req,
+    )
+    .await?;
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// 注文変更（ゲスト、トークンで認可）
+pub async fn modify_guest_order(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    axum::extract::Query(params): axum::extract::Query<GetGuestOrderParams>,
+    Json(req): Json<ModifyOrderRequest>,
+) -> Result<Json<DataResponse<OrderModificationResponse>>> {
+    req.validate()?;
+    if req.shipping_address_id.is_some() {
+        return Err(AppError::BadRequest("配送先の住所を入力してください".to_string()));
+    }
+
+    let order = OrderRepository::new(state.db.anonymous())
+        .find_by_guest_token_rpc(&hash_guest_token(&params.token), id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("注文が見つかりません".to_string()))?;
+
+    let email = order.guest_email.clone().unwrap_or_default();
+    let shipping_address = req.shipping_address.as_ref().map(|a| a.to_order_address());
+    let response = request_modification(&state, order, None, &email, shipping_address, &req).await?;
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// 注文の変更履歴（ログインユーザー）
+pub async fn list_order_modifications(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DataResponse<Vec<OrderModification>>>> {
+    let order = OrderRepository::new(state.db.with_auth(&token))
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("注文が見つかりません".to_string()))?;
+    if order.user_id != Some(auth_user.id) {
+        return Err(AppError::Forbidden("この注文にアクセスする権限がありません".to_string()));
+    }
+
+    let modifications = OrderModificationRepository::new(state.db.service())
+        .find_by_order(order.id)
+        .await?;
+    Ok(Json(DataResponse::new(modifications)))
+}
+
+/// 変更内容の検証・差額計算・精算
+async fn request_modification(
+    state: &AppState,
+    order: Order,
+    requested_by: Option<Uuid>,
+    customer_email: &str,
+    shipping_address: Option<OrderAddress>,
+    req: &ModifyOrderRequest,
+) -> Result<OrderModificationResponse> {
+    if !is_order_modifiable(&order) || order.payment_status != PaymentStatus::Paid {
+        return Err(AppError::BadRequest(
+            "この注文は変更できません（支払い済みで発送前の注文のみ変更できます）".to_string(),
+        ));
+    }
+    if shipping_address.is_none() && req.item_changes.is_empty() {
+        return Err(AppError::BadRequest("変更内容を指定してください".to_string()));
+    }
+
+    let db_service = state.db.service();
+    let item_changes = build_item_changes(&ProductRepository::new(db_service.clone()), &order, req).await?;
+    let country = shipping_address
+        .as_ref()
+        .map_or(order.shipping_address.country.as_str(), |a| a.country.as_str());
+    let totals = recalculate_order_totals(&order, &item_changes, country);
+    let amount_difference = totals.total - order.total;
+
+    // 差額の自動精算はカード決済（Stripe）のみ
+    if amount_difference != 0 && (order.payment_method != PaymentMethod::CreditCard || order.payment_id.is_none()) {
+        return Err(AppError::BadRequest(
+            "この支払い方法では金額が変わる変更はできません。サポートにお問い合わせください".to_string(),
+        ));
+    }
+
+    let repo = OrderModificationRepository::new(db_service.clone());
+    let modification = repo
+        .create(&NewOrderModification {
+            order_id: order.id,
+            requested_by,
+            shipping_address,
+            item_changes,
+            previous_total: order.total,
+            subtotal: totals.subtotal,
+            shipping_fee: totals.shipping_fee,
+            tax: totals.tax,
+            total: totals.total,
+            amount_difference,
+            status: if amount_difference > 0 {
+                OrderModificationStatus::AwaitingPayment
+            } else {
+                OrderModificationStatus::Pending
+            },
+            // 追加決済は決済待ちの注文と同じ期限で取り消す（決済リコンサイラが expired にする）
+            expires_at: (amount_difference > 0).then(|| {
+                Utc::now() + Duration::seconds(state.config.reconciler.payment_intent_max_age_seconds.max(60))
+            }),
+        })
+        .await?;
+
+    let payment_provider = if amount_difference != 0 {
+        Some(stripe_provider(&state.config)?)
+    } else {
+        None
+    };
+
+    // 追加決済: 支払い完了のWebhookで適用する
+    if let (true, Some(provider)) = (amount_difference > 0, &payment_provider) {
+        let metadata = HashMap::from([("order_modification_id".to_string(), modification.id.to_string())]);
+        let intent = match provider
+            .create_intent(CreateIntentParams {
+                order_id: order.id,
+                amount: amount_difference,
+                currency: order.currency.clone(),
+                customer_email: customer_email.to_string(),
+                customer_name: Some(order.shipping_address.name.clone()),
+                description: Some(format!("注文 {} の変更差額", order.order_number)),
+                metadata: Some(metadata),
+                shipping_address: None,
+                idempotency_key: Some(format!("pi_order_mod_{}", modification.id)),
+            })
+            .await
+        {
+            Ok(intent) => intent,
+            Err(e) => {
+                repo.mark_failed(modification.id, "payment_intent_failed").await?;
+                return Err(AppError::Internal(format!("追加決済の作成に失敗しました: {}", e)));
+            }
+        };
+        repo.set_payment_id(modification.id, &intent.id).await?;
+        tracing::info!(
+            "Order modification awaiting payment: order={}, modification={}, amount={}",
+            order.id,
+            modification.id,
+            amount_difference
+        );
+
+        return Ok(OrderModificationResponse {
+            modification,
+            order,
+            payment: Some(ModificationPayment {
+                payment_intent_id: intent.id,
+                client_secret: intent.client_secret,
+                amount: amount_difference,
+            }),
+        });
+    }
+
+    match repo.apply(modification.id).await? {
+        ModificationApplyResult::Applied => {}
+        ModificationApplyResult::OutOfStock => {
+            return Err(AppError::BadRequest("交換先のサイズの在庫が不足しています".to_string()));
+        }
+        ModificationApplyResult::OrderChanged | ModificationApplyResult::NotPending => {
+            return Err(AppError::Conflict(
+                "注文の状態が変わったため変更できませんでした。もう一度お試しください".to_string(),
+            ));
+        }
+    }
+    tracing::info!("Order modification applied: order={}, modification={}", order.id, modification.id);
+
+    // 差額の一部返金（失敗しても変更は適用済み。返金状態を記録して管理者が対応する）
+    if let (true, Some(provider), Some(payment_id)) = (amount_difference < 0, &payment_provider, &order.payment_id) {
+        refund_difference(&repo, provider, &modification, payment_id, -amount_difference).await?;
+    }
+
+    let order = OrderRepository::new(db_service)
+        .find_by_id(order.id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("注文が見つかりません".to_string()))?;
+    let modification = repo.find_by_id(modification.id).await?.unwrap_or(modification);
+
+    Ok(OrderModificationResponse {
+        modification,
+        order,
+        payment: None,
+    })
+}
+
+/// サイズ交換の内容を検証して適用内容に変換
+async fn build_item_changes(
+    product_repo: &ProductRepository,
+    order: &Order,
+    req: &ModifyOrderRequest,
+) -> Result<Vec<ItemVariantChange>> {
+    if req.item_changes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let variant_ids: Vec<Uuid> = req
+        .item_changes
+        .iter()
+        .flat_map(|c| [c.from_variant_id, c.to_variant_id])
+        .collect();
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let mut changes: Vec<ItemVariantChange> = Vec::with_capacity(req.item_changes.len());
+    // 同じサイズへの交換は数量を合算して在庫を確認する
+    let mut demand: HashMap<Uuid, i32> = HashMap::new();
+    for swap in &req.item_changes {
+        if swap.from_variant_id == swap.to_variant_id {
+            continue;
+        }
+        if changes
+            .iter()
+            .any(|c| c.product_id == swap.product_id && c.from_variant_id == swap.from_variant_id)
+        {
+            return Err(AppError::BadRequest("同じ明細が複数回指定されています".to_string()));
+        }
+
+        let item = order
+            .items
+            .iter()
+            .find(|i| i.product_id == swap.product_id && i.variant_id == Some(swap.from_variant_id))
+            .ok_or_else(|| AppError::BadRequest("交換元の商品が注文に含まれていません".to_string()))?;
+
+        let to = variants
+            .get(&swap.to_variant_id)
+            .filter(|v| v.product_id == swap.product_id && v.is_active)
+            .ok_or_else(|| AppError::BadRequest("交換先のサイズは選択できません".to_string()))?;
+        let needed = demand.entry(to.id).or_default();
+        *needed += item.quantity;
+        if to.stock < *needed {
+            return Err(AppError::BadRequest(format!(
+                "「{}」のサイズ{}は在庫が不足しています",
+                item.product_name, to.size
+            )));
+        }
+
+        let from_adjustment = variants.get(&swap.from_variant_id).map_or(0, |v| v.price_adjustment);
+        changes.push(ItemVariantChange {
+            product_id: swap.product_id,
+            from_variant_id: swap.from_variant_id,
+            to_variant_id: to.id,
+            to_size: to.size.clone(),
+            quantity: item.quantity,
+            price: swapped_item_price(item.price, from_adjustment, to.price_adjustment),
+        });
+    }
+
+    Ok(changes)
+}
+
+/// 差額を一部返金して結果を記録
+async fn refund_difference(
+    repo: &OrderModificationRepository,
+    provider: &StripePaymentProvider,
+    modification: &OrderModification,
+    payment_id: &str,
+    amount: i64,
+) -> Result<()> {
+    match provider.refund(payment_id, Some(amount)).await {
+        Ok(refund) => {
+            let status = match refund.status {
+                RefundStatus::Succeeded => ModificationRefundStatus::Succeeded,
+                RefundStatus::Pending => ModificationRefundStatus::Pending,
+                RefundStatus::Failed => ModificationRefundStatus::Failed,
+            };
+            tracing::info!(
+                "Order modification refund: modification={}, refund_id={}, amount={}",
+                modification.id,
+                refund.id,
+                amount
+            );
+            repo.update_refund(modification.id, status, Some(&refund.id)).await
+        }
+        Err(e) => {
+            tracing::error!(
+                "Order modification refund failed (manual refund required): modification={}, amount={}, error={}",
+                modification.id,
+                amount,
+                e
+            );
+            repo.update_refund(modification.id, ModificationRefundStatus::Failed, None).await
+        }
+    }
+}
+
+/// 追加決済の完了Webhook（payment_intent.succeeded の metadata.order_modification_id）
+/// 適用できなかった場合（在庫切れ・注文の変更・置き換え済み・期限切れ・DBエラー）は追加決済を全額返金する。
+/// Stripeイベントは処理済みとして記録済みで再送されないため、エラーは呼び出し元に返さない
+pub(crate) async fn handle_modification_payment(
+    state: &AppState,
+    provider: &StripePaymentProvider,
+    modification_id: Uuid,
+    payment_id: &str,
+    amount: i64,
+) {
+    let repo = OrderModificationRepository::new(state.db.service());
+    let modification = match repo.find_by_id(modification_id).await {
+        Ok(Some(modification)) => modification,
+        Ok(None) => {
+            tracing::warn!("Order modification not found for payment: modification={}", modification_id);
+            refund_unapplied_payment(provider, modification_id, payment_id).await;
+            return;
+        }
+        Err(e) => {
+            tracing::error!(
+                "!!! 返金トリガー: 注文変更の取得失敗 !!! modification={}, payment_id={}, error={}",
+                modification_id,
+                payment_id,
+                e
+            );
+            refund_unapplied_payment(provider, modification_id, payment_id).await;
+            return;
+        }
+    };
+    if modification.status == OrderModificationStatus::Applied {
+        return;
+    }
+
+    let paid_as_requested =
+        modification.payment_id.as_deref() == Some(payment_id) && amount == modification.amount_difference;
+    let result = if paid_as_requested {
+        match repo.apply(modification.id).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!(
+                    "!!! 返金トリガー: 注文変更の適用失敗 !!! modification={}, payment_id={}, error={}",
+                    modification.id,
+                    payment_id,
+                    e
+                );
+                ModificationApplyResult::NotPending
+            }
+        }
+    } else {
+        tracing::error!(
+            "Order modification payment mismatch: modification={}, payment_id={}, amount={}",
+            modification.id,
+            payment_id,
+            amount
+        );
+        if let Err(e) = repo.mark_failed(modification.id, "payment_mismatch").await {
+            tracing::warn!("Failed to mark order modification failed: modification={}, error={}", modification.id, e);
+        }
+        ModificationApplyResult::OrderChanged
+    };
+
+    if result == ModificationApplyResult::Applied {
+        tracing::info!(
+            "Order modification applied after payment: order={}, modification={}",
+            modification.order_id,
+            modification.id
+        );
+        return;
+    }
+
+    // 適用結果が不明（エラー・適用済みの可能性）の場合は状態を確かめ、適用済みなら返金しない
+    if result == ModificationApplyResult::NotPending
+        && matches!(
+            repo.find_by_id(modification.id).await,
+            Ok(Some(current)) if current.status == OrderModificationStatus::Applied
+        )
+    {
+        return;
+    }
+
+    tracing::warn!(
+        "Order modification could not be applied ({:?}); refunding additional payment: modification={}",
+        result,
+        modification.id
+    );
+    if let Err(e) = refund_difference(&repo, provider, &modification, payment_id, amount).await {
+        tracing::error!(
+            "Failed to record order modification refund: modification={}, error={}",
+            modification.id,
+            e
+        );
+    }
+}
+
+/// 注文変更を確認できない追加決済を返金（記録先がないためログのみ）
+async fn refund_unapplied_payment(provider: &StripePaymentProvider, modification_id: Uuid, payment_id: &str) {
+    match provider.refund(payment_id, None).await {
+        Ok(refund) => tracing::info!(
+            "Refunded unapplied order modification payment: modification={}, refund_id={}",
+            modification_id,
+            refund.id
+        ),
+        Err(e) => tracing::error!(
+            "Order modification refund failed (manual refund required): modification={}, payment_id={}, error={}",
+            modification_id,
+            payment_id,
+            e
+        ),
+    }
+}
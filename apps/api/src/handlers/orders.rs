@@ -4,21 +4,32 @@ use axum::{
     Extension, Json,
 };
 use chrono::Utc;
+use std::collections::HashMap;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::config::AppState;
-use crate::db::repositories::{CartRepository, OrderRepository, ProductRepository, UserRepository};
+use crate::db::repositories::{
+    CartRepository, GuestOrderAccessRepository, OrderActor, OrderRepository, PlaceOrderResult, ProductRepository,
+    UserRepository,
+};
 use crate::error::{AppError, Result};
 use crate::middleware::generate_session_id;
 use crate::models::{
-    AuthenticatedUser, CreateOrderRequest, CreateGuestOrderRequest, DataResponse, Order, OrderAddress, OrderItem,
-    OrderStatus, OrderSummary, PaginatedResponse, PaymentStatus,
-    calculate_shipping_fee, calculate_tax, generate_order_number,
-    generate_guest_access_token, guest_token_expiry, hash_guest_token,
+    AuthenticatedUser, CreateOrderRequest, CreateGuestOrderRequest, DataResponse, GuestOrderAccessRequest,
+    FulfillmentQueueEntry, GuestOrderLookupRequest, LineAllocation, Order, OrderAddress, OrderItem,
+    OrderItemRequest, OrderStatus, OrderSummary, PaginatedResponse, PaymentStatus, Product, ProductVariant,
+    QuoteClaims, allocate_line, calculate_shipping_fee, has_mixed_preorder, is_awaiting_release, calculate_tax,
+    generate_order_number, guest_contact_matches, split_reservations, generate_guest_access_token, guest_token_expiry, hash_guest_token, is_legacy_order_number,
+    is_valid_order_number,
 };
+use crate::services::cart_recovery::record_conversion;
+use crate::services::email::send_email;
+use crate::services::metrics;
 use crate::services::payment::{PaymentProvider, StripePaymentProvider};
-use crate::handlers::users::ensure_user_profile;
+use crate::handlers::checkout::verify_order_quote;
+use crate::handlers::users::{ensure_user_profile, fetch_supabase_auth_user};
+use crate::utils::sanitize::normalize_email;
 
 /// 注文作成
 pub async fn create_order(
@@ -30,10 +41,7 @@ pub async fn create_order(
 ) -> Result<Json<DataResponse<Order>>> {
     req.validate()?;
     // デバッグ（トークン自体は出さない）
-    if std::env::var("API_DEBUG_AUTH")
-        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-        .unwrap_or(false)
-    {
+    if state.config.server.debug_auth {
         tracing::info!(
             "[orders] create_order: auth_user_id={}, token_len={}",
             auth_user.id,
@@ -47,12 +55,11 @@ pub async fn create_order(
         .map(|s| s.to_string())
         .unwrap_or_else(generate_session_id);
 
-    // 注文作成は「本人トークン」でINSERTし、RLSポリシー（auth.uid = user_id）を正しく通す。
-    // 在庫確保/商品更新など管理系操作のみ service_role を使う。
+    // 注文作成は「本人トークン」（Postgres直接接続では authenticated ロール）でINSERTし、
+    // RLSポリシー（auth.uid = user_id）を正しく通す。在庫確保など管理系操作のみ service_role を使う。
     let db_service = state.db.service();
     let cart_repo = CartRepository::new(db_service.clone());
     let product_repo = ProductRepository::new(db_service);
-    let order_repo = OrderRepository::new(state.db.with_auth(&token));
     let user_repo = UserRepository::new(state.db.with_auth(&token));
 
     // リクエストボディのitemsを優先的に使用、なければカートから取得
@@ -104,52 +111,21 @@ pub async fn create_order(
         None
     };
 
+    // 見積もりトークンがあれば内容が一致することを確認
+    let quote = req
+        .quote_token
+        .as_deref()
+        .map(|t| verify_order_quote(&state, t, &source_items, &shipping_address.country))
+        .transpose()?;
+
     // 在庫確認と注文アイテム作成（N+1問題回避：一括取得）
     let product_ids: Vec<_> = source_items.iter().map(|i| i.product_id).collect();
     let products = product_repo.find_by_ids(&product_ids).await?;
+    let variant_ids: Vec<_> = source_items.iter().filter_map(|i| i.variant_id).collect();
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
 
-    let mut order_items = Vec::new();
-    let mut subtotal = 0i64;
-    let mut stock_reserve_items: Vec<(Uuid, i32)> = Vec::new();
-
-    for item_req in &source_items {
-        let product = products
-            .get(&item_req.product_id)
-            .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
-
-        if !product.is_active {
-            return Err(AppError::BadRequest(format!(
-                "「{}」は現在販売されていません",
-                product.name
-            )));
-        }
-
-        if product.stock < item_req.quantity {
-            return Err(AppError::BadRequest(format!(
-                "「{}」の在庫が不足しています",
-                product.name
-            )));
-        }
-
-        // 価格は常にサーバー側の最新の商品価格を採用（クライアント/カートの価格は信頼しない）
-        let item_price = product.price;
-        let item_subtotal = item_price * item_req.quantity as i64;
-        subtotal += item_subtotal;
-
-        order_items.push(OrderItem {
-            product_id: product.id,
-            product_name: product.name.clone(),
-            product_sku: product.sku.clone(),
-            price: item_price,
-            quantity: item_req.quantity,
-            subtotal: item_subtotal,
-            image_url: product.images.first().cloned(),
-            variant_id: item_req.variant_id,
-            size: item_req.size.clone(),
-        });
-
-        stock_reserve_items.push((product.id, item_req.quantity));
-    }
+    let (order_items, subtotal) = build_order_items(&source_items, &products, &variants, quote.as_ref())?;
+    let split_shipment = check_preorder_mix(&order_items, req.split_shipment)?;
 
     // 金額計算（国別送料対応）
     let shipping_fee = calculate_shipping_fee(subtotal, &shipping_address.country);
@@ -193,26 +169,163 @@ pub async fn create_order(
         crypto_chain_id: None,
         crypto_sender_address: None,
         crypto_confirmed_at: None,
+        split_shipment,
     };
 
-    // 在庫を原子的に確保（同時購入で在庫マイナスになるのを防ぐ）
-    let reserved = product_repo.reserve_stock_bulk(&stock_reserve_items).await?;
-    if !reserved {
-        return Err(AppError::BadRequest("在庫が不足しています".to_string()));
+    // 在庫・予約枠の確保 → 注文作成 → カートクリア（DATABASE_BACKEND=postgres では1トランザクション）
+    // カートから注文した場合のみクリア（リクエストボディのitemsを使った場合はクリアしない）
+    let actor = OrderActor::User { token: &token, user_id: auth_user.id };
+    let cart_session_id = req.items.is_none().then_some(session_id.as_str());
+    let result = state.checkout.place_order(&order, actor, cart_session_id).await?;
+    let order = placed_order(result, "order")?;
+    record_conversion(&state, &session_id, &order).await;
+
+    Ok(Json(DataResponse::new(order)))
+}
+
+/// 注文アイテムの作成と小計計算（販売状態・在庫を確認する）
+/// 価格は常にサーバー側の最新の商品価格を採用し、クライアント/カートの価格は信頼しない。
+/// 見積もりがあれば見積もり時の価格を上限とする（見積もり後の値上げは反映しない）
+pub(crate) fn build_order_items(
+    source_items: &[OrderItemRequest],
+    products: &HashMap<Uuid, Product>,
+    variants: &HashMap<Uuid, ProductVariant>,
+    quote: Option<&QuoteClaims>,
+) -> Result<(Vec<OrderItem>, i64)> {
+    let mut order_items = Vec::with_capacity(source_items.len());
+    let mut subtotal = 0i64;
+
+    for item_req in source_items {
+        let product = products
+            .get(&item_req.product_id)
+            .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
+
+        if !product.is_active {
+            return Err(AppError::BadRequest(format!(
+                "「{}」は現在販売されていません",
+                product.name
+            )));
+        }
+
+        let variant = match item_req.variant_id {
+            Some(variant_id) => Some(
+                variants
+                    .get(&variant_id)
+                    .filter(|v| v.product_id == product.id && v.is_active)
+                    .ok_or_else(|| {
+                        AppError::BadRequest(format!("「{}」の選択したサイズは現在販売されていません", product.name))
+                    })?,
+            ),
+            None => None,
+        };
+
+        // 在庫が足りなければ予約販売の受付枠で受ける
+        let is_preorder = match allocate_line(product, variant, item_req.quantity) {
+            LineAllocation::InStock => false,
+            LineAllocation::Preorder => true,
+            LineAllocation::Unavailable if product.preorder.preorder_enabled => {
+                return Err(AppError::BadRequest(format!(
+                    "「{}」は予約の受付上限に達しました",
+                    product.name
+                )));
+            }
+            LineAllocation::Unavailable => {
+                return Err(AppError::BadRequest(format!(
+                    "「{}」の在庫が不足しています",
+                    product.name
+                )));
+            }
+        };
+
+        let item_price = quote
+            .and_then(|q| q.quoted_price(product.id, item_req.variant_id))
+            .map_or(product.price, |quoted| quoted.min(product.price));
+        let item_subtotal = item_price * item_req.quantity as i64;
+        subtotal += item_subtotal;
+
+        order_items.push(OrderItem {
+            product_id: product.id,
+            product_name: product.name.clone(),
+            product_sku: product.sku.clone(),
+            price: item_price,
+            quantity: item_req.quantity,
+            subtotal: item_subtotal,
+            image_url: product.images.first().cloned(),
+            variant_id: item_req.variant_id,
+            size: item_req.size.clone(),
+            is_preorder,
+            expected_ship_date: product.preorder.expected_ship_date.filter(|_| is_preorder),
+            preorder_released_at: None,
+        });
+    }
+
+    Ok((order_items, subtotal))
+}
+
+/// 予約明細と在庫明細の混在を確認し、注文に保存する分割発送の指定を返す
+/// 混在する注文は分割発送を選んだ場合のみ受け付ける（予約の入荷まで在庫分の出荷を止めないため）
+pub(crate) fn check_preorder_mix(items: &[OrderItem], split_shipment: bool) -> Result<bool> {
+    if !has_mixed_preorder(items) {
+        return Ok(false);
     }
+    if !split_shipment {
+        return Err(AppError::BadRequest(
+            "予約商品と在庫のある商品は同じ注文にできません。分割発送を選択するか、別々にご注文ください".to_string(),
+        ));
+    }
+    Ok(true)
+}
 
-    // 注文作成（失敗したら在庫を戻す）
-    if let Err(e) = order_repo.create(&order).await {
-        let _ = product_repo.release_stock_bulk(&stock_reserve_items).await;
-        return Err(e);
+/// 注文確定の結果を作成した注文に変換（在庫・予約枠が確保できなければ400）
+fn placed_order(result: PlaceOrderResult, source: &str) -> Result<Order> {
+    match result {
+        PlaceOrderResult::Created(order) => Ok(*order),
+        PlaceOrderResult::OutOfStock => {
+            metrics::record_stock_reservation_failure(source);
+            Err(AppError::BadRequest(
+                "在庫が不足しているか、予約の受付上限に達しました".to_string(),
+            ))
+        }
     }
+}
 
-    // カートから注文した場合のみクリア（リクエストボディのitemsを使った場合はクリアしない）
-    if req.items.is_none() {
-        cart_repo.clear(&session_id).await?;
+/// 在庫明細の在庫と予約明細の予約枠を確保する（片方に失敗したら確保済みの分を戻す）
+pub(crate) async fn reserve_order_items(product_repo: &ProductRepository, items: &[OrderItem], source: &str) -> Result<()> {
+    let (stock_items, preorder_items) = split_reservations(items);
+
+    if !stock_items.is_empty() && !product_repo.reserve_stock_bulk(&stock_items).await? {
+        metrics::record_stock_reservation_failure(source);
+        return Err(AppError::BadRequest("在庫が不足しています".to_string()));
     }
 
-    Ok(Json(DataResponse::new(order)))
+    if !preorder_items.is_empty() {
+        let reserved = match product_repo.reserve_preorder_bulk(&preorder_items).await {
+            Ok(reserved) => reserved,
+            Err(e) => {
+                let _ = product_repo.release_stock_bulk(&stock_items).await;
+                return Err(e);
+            }
+        };
+        if !reserved {
+            if !stock_items.is_empty() {
+                let _ = product_repo.release_stock_bulk(&stock_items).await;
+            }
+            metrics::record_stock_reservation_failure(source);
+            return Err(AppError::BadRequest("予約の受付上限に達しました".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// reserve_order_items で確保した在庫・予約枠を戻す（注文作成の失敗時）
+pub(crate) async fn release_order_items(product_repo: &ProductRepository, items: &[OrderItem]) {
+    let (stock_items, preorder_items) = split_reservations(items);
+    if !stock_items.is_empty() {
+        let _ = product_repo.release_stock_bulk(&stock_items).await;
+    }
+    if !preorder_items.is_empty() {
+        let _ = product_repo.release_preorder_bulk(&preorder_items).await;
+    }
 }
 
 /// 注文履歴取得（アイテム情報含む）
@@ -253,16 +366,12 @@ pub async fn get_order(
         && (order.payment_status == PaymentStatus::Pending || order.payment_status == PaymentStatus::Succeeded);
     if is_pending {
         if let Some(payment_id) = order.payment_id.clone() {
-            if let Ok(stripe_key) = std::env::var("STRIPE_SECRET_KEY") {
-                let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-                let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-                let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+            if let Some(payment_provider) = StripePaymentProvider::from_config(&state.config) {
 
                 if let Ok(pi) = payment_provider.retrieve_intent(&payment_id).await {
                     // anon + RPC関数で更新（service_roleを使わない）
+                    // キャンセル時の在庫解放はOrderCancelledイベントの購読者が行う
                     let reconcile_order_repo = OrderRepository::new(state.db.anonymous());
-                    // 在庫操作はRPCベースなのでservice_role
-                    let product_repo = ProductRepository::new(state.db.service());
 
                     match pi.status {
                         crate::services::payment::PaymentResultStatus::Succeeded => {
@@ -282,37 +391,35 @@ pub async fn get_order(
                                 let refund_provider = payment_provider.clone();
                                 let pid = payment_id.clone();
                                 tokio::spawn(async move {
-                                    let _ = refund_provider.refund(&pid, None).await;
+                                    if let Err(e) = refund_provider.refund(&pid, None).await {
+                                        tracing::error!("Refund failed (reconcile): payment_id={}, error={}", pid, e);
+                                    }
                                 });
 
                                 // RPC関数で更新（ステータス遷移チェック付き）
-                                let updated = reconcile_order_repo
+                                if let Err(e) = reconcile_order_repo
                                     .update_order_from_webhook_rpc(order.id, None, Some("cancelled"), Some("\"failed\""))
                                     .await
-                                    .unwrap_or(false);
-                                if updated {
-                                    // 在庫復旧
-                                    let release_items: Vec<(Uuid, i32)> =
-                                        order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                                    let _ = product_repo.release_stock_bulk(&release_items).await;
+                                {
+                                    tracing::warn!("Reconcile cancel failed: order_id={}, error={}", order.id, e);
                                 }
                             } else {
                                 // 正常確定（RPC関数で更新）
-                                let _ = reconcile_order_repo
+                                if let Err(e) = reconcile_order_repo
                                     .update_order_from_webhook_rpc(order.id, None, Some("paid"), Some("\"paid\""))
-                                    .await;
+                                    .await
+                                {
+                                    tracing::warn!("Reconcile paid update failed: order_id={}, error={}", order.id, e);
+                                }
                             }
                         }
                         crate::services::payment::PaymentResultStatus::Failed => {
-                            // 失敗確定 -> キャンセル + 在庫復旧（RPC関数で更新）
-                            let updated = reconcile_order_repo
+                            // 失敗確定 -> キャンセル（RPC関数で更新）
+                            if let Err(e) = reconcile_order_repo
                                 .update_order_from_webhook_rpc(order.id, None, Some("cancelled"), Some("\"failed\""))
                                 .await
-                                .unwrap_or(false);
-                            if updated {
-                                let release_items: Vec<(Uuid, i32)> =
-                                    order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                                let _ = product_repo.release_stock_bulk(&release_items).await;
+                            {
+                                tracing::warn!("Reconcile cancel failed: order_id={}, error={}", order.id, e);
                             }
                         }
                         crate::services::payment::PaymentResultStatus::Pending => {
@@ -341,8 +448,6 @@ pub async fn cancel_order(
 ) -> Result<Json<DataResponse<Order>>> {
     let db = state.db.with_auth(&token);
     let order_repo = OrderRepository::new(db.clone());
-    // 在庫操作は service_role のRPCでのみ許可（クライアント直叩き防止）
-    let product_repo = ProductRepository::new(state.db.service());
 
     let order = order_repo
         .find_by_id(id)
@@ -359,19 +464,11 @@ pub async fn cancel_order(
         return Err(AppError::BadRequest("この注文はキャンセルできません".to_string()));
     }
 
-    // ステータス更新
-    // 競合（Webhook/回収タスク等）で二重在庫戻しにならないよう条件付き更新にする
-    let updated = order_repo
+    // ステータス更新（条件付き更新で競合を防ぐ）
+    // 在庫はOrderCancelledイベントの購読者が解放する（アウトボックス経由、冪等）
+    order_repo
         .update_status_if_current(id, OrderStatus::PendingPayment, OrderStatus::Cancelled)
         .await?;
-    if !updated {
-        let updated_order = order_repo.find_by_id(id).await?.unwrap();
-        return Ok(Json(DataResponse::new(updated_order)));
-    }
-
-    // 在庫を戻す（原子操作）
-    let release_items: Vec<(Uuid, i32)> = order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-    let _ = product_repo.release_stock_bulk(&release_items).await?;
 
     // 更新後の注文を取得
     let updated_order = order_repo.find_by_id(id).await?.unwrap();
@@ -394,6 +491,19 @@ pub async fn list_orders_admin(
     Ok(Json(DataResponse::new(orders)))
 }
 
+/// 出荷キュー（管理者専用、古い順）
+/// 予約商品の入荷待ちで出荷できない注文は含めない
+pub async fn list_fulfillment_queue_admin(
+    State(state): State<AppState>,
+    Extension(token): Extension<String>,
+) -> Result<Json<DataResponse<Vec<FulfillmentQueueEntry>>>> {
+    let order_repo = OrderRepository::new(state.db.with_auth(&token));
+
+    let entries = order_repo.find_fulfillment_queue(100).await?;
+
+    Ok(Json(DataResponse::new(entries)))
+}
+
 /// 注文詳細取得（管理者専用）
 /// RLSポリシーで管理者のみ閲覧可能
 pub async fn get_order_admin(
@@ -440,6 +550,13 @@ pub async fn update_order_status_admin(
     // ステータス遷移のバリデーション
     validate_status_transition(&order.status, &new_status)?;
 
+    // 予約明細は在庫の引当前に発送済みにしない（分割発送の場合も最後の発送で発送済みにする）
+    if new_status == OrderStatus::Shipped && order.items.iter().any(is_awaiting_release) {
+        return Err(AppError::BadRequest(
+            "入荷待ちの予約商品があるため、発送済みにできません".to_string(),
+        ));
+    }
+
     // ステータス更新
     order_repo.update_status(id, order.user_id, new_status.clone(), order.created_at.timestamp()).await?;
 
@@ -507,13 +624,10 @@ pub async fn create_guest_order(
         .map(|s| s.to_string())
         .unwrap_or_else(generate_session_id);
 
-    // ゲスト注文はRPC関数（SECURITY DEFINER）で作成
-    // RPC関数がRLSをバイパスし、かつanon keyで呼び出せる
-    let db_anon = state.db.anonymous();
+    // ゲスト注文は anon で作成する（REST版はRPC関数 create_guest_order_with_items を使う）
     let db_service = state.db.service();
     let cart_repo = CartRepository::new(db_service.clone());
-    let product_repo = ProductRepository::new(db_service.clone());
-    let order_repo = OrderRepository::new(db_anon);
+    let product_repo = ProductRepository::new(db_service);
 
     // リクエストボディのitemsを優先的に使用、なければカートから取得
     let source_items = if let Some(ref items) = req.items {
@@ -541,51 +655,20 @@ pub async fn create_guest_order(
             .collect()
     };
 
+    let quote = req
+        .quote_token
+        .as_deref()
+        .map(|t| verify_order_quote(&state, t, &source_items, &req.shipping_address.country))
+        .transpose()?;
+
     // 在庫確認と注文アイテム作成
     let product_ids: Vec<_> = source_items.iter().map(|i| i.product_id).collect();
     let products = product_repo.find_by_ids(&product_ids).await?;
+    let variant_ids: Vec<_> = source_items.iter().filter_map(|i| i.variant_id).collect();
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
 
-    let mut order_items = Vec::new();
-    let mut subtotal = 0i64;
-    let mut stock_reserve_items: Vec<(Uuid, i32)> = Vec::new();
-
-    for item_req in &source_items {
-        let product = products
-            .get(&item_req.product_id)
-            .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
-
-        if !product.is_active {
-            return Err(AppError::BadRequest(format!(
-                "「{}」は現在販売されていません",
-                product.name
-            )));
-        }
-
-        if product.stock < item_req.quantity {
-            return Err(AppError::BadRequest(format!(
-                "「{}」の在庫が不足しています",
-                product.name
-            )));
-        }
-
-        let item_price = product.price;
-        let item_subtotal = item_price * item_req.quantity as i64;
-        subtotal += item_subtotal;
-
-        order_items.push(OrderItem {
-            product_id: product.id,
-            product_name: product.name.clone(),
-            product_sku: product.sku.clone(),
-            price: item_price,
-            quantity: item_req.quantity,
-            subtotal: item_subtotal,
-            image_url: product.images.first().cloned(),
-            variant_id: item_req.variant_id,
-            size: item_req.size.clone(),
-        });
-
-        stock_reserve_items.push((product.id, item_req.quantity));
-    }
+    let (order_items, subtotal) = build_order_items(&source_items, &products, &variants, quote.as_ref())?;
+    let split_shipment = check_preorder_mix(&order_items, req.split_shipment)?;
 
     // 金額計算（国別送料対応）
     let shipping_fee = calculate_shipping_fee(subtotal, &req.shipping_address.country);
@@ -636,27 +719,14 @@ pub async fn create_guest_order(
         crypto_chain_id: None,
         crypto_sender_address: None,
         crypto_confirmed_at: None,
+        split_shipment,
     };
 
-    // 在庫を原子的に確保
-    let reserved = product_repo.reserve_stock_bulk(&stock_reserve_items).await?;
-    if !reserved {
-        return Err(AppError::BadRequest("在庫が不足しています".to_string()));
-    }
-
-    // 注文とアイテムを同時作成（RPC関数を使用、失敗したら在庫を戻す）
-    let created_order = match order_repo.create_guest_order_rpc(&order).await {
-        Ok(o) => o,
-        Err(e) => {
-            let _ = product_repo.release_stock_bulk(&stock_reserve_items).await;
-            return Err(e);
-        }
-    };
-
-    // カートから注文した場合のみクリア
-    if req.items.is_none() {
-        cart_repo.clear(&session_id).await?;
-    }
+    // 在庫・予約枠の確保 → 注文作成 → カートクリア（カートから注文した場合のみクリア）
+    let cart_session_id = req.items.is_none().then_some(session_id.as_str());
+    let result = state.checkout.place_order(&order, OrderActor::Guest, cart_session_id).await?;
+    let created_order = placed_order(result, "guest_order")?;
+    record_conversion(&state, &session_id, &created_order).await;
 
     Ok(Json(DataResponse::new(CreateGuestOrderResponse {
         order: created_order,
@@ -689,6 +759,166 @@ pub async fn get_guest_order(
     Ok(Json(DataResponse::new(order)))
 }
 
+/// ゲスト注文照会レスポンス（注文の有無にかかわらず同じ内容）
+#[derive(Debug, serde::Serialize)]
+pub struct GuestOrderLookupResponse {
+    pub message: String,
+}
+
+/// ゲスト注文の照会（注文番号 + メールアドレス/電話番号）
+/// 一致した場合のみ注文時のメールアドレス宛てに1回限りの再アクセスリンクを送る。
+/// 注文の有無を推測されないよう、一致しなくても同じレスポンスを返す
+pub async fn lookup_guest_order(
+    State(state): State<AppState>,
+    Json(req): Json<GuestOrderLookupRequest>,
+) -> Result<Json<DataResponse<GuestOrderLookupResponse>>> {
+    req.validate()?;
+    if req.email.is_none() && req.phone.is_none() {
+        return Err(AppError::BadRequest("メールアドレスまたは電話番号を入力してください".to_string()));
+    }
+
+    // チェックデジットで入力ミスを先に弾く（導入前の番号はそのまま照会）
+    let order_number = req.order_number.trim().to_uppercase();
+    if !is_valid_order_number(&order_number) && !is_legacy_order_number(&order_number) {
+        return Err(AppError::BadRequest(
+            "注文番号に誤りがあります。ご確認のうえ再度入力してください".to_string(),
+        ));
+    }
+
+    let order = OrderRepository::new(state.db.service())
+        .find_guest_by_order_number(&order_number)
+        .await?
+        .filter(|order| guest_contact_matches(order, req.email.as_deref(), req.phone.as_deref()));
+
+    match order {
+        // 送信はバックグラウンドで行い、応答時間の差で一致を推測されないようにする
+        Some(order) => {
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = send_guest_access_link(&state, &order).await {
+                    tracing::warn!("guest_order_lookup: failed to send access link: order={}, error={:#}", order.id, e);
+                }
+            });
+        }
+        None => tracing::info!("guest_order_lookup: no matching order"),
+    }
+
+    Ok(Json(DataResponse::new(GuestOrderLookupResponse {
+        message: "ご入力の内容と一致する注文がある場合、ご注文時のメールアドレスに確認用リンクを送信しました".to_string(),
+    })))
+}
+
+/// 再アクセスリンクを発行してメールで送る（同じ注文の未使用リンクは無効になる）
+async fn send_guest_access_link(state: &AppState, order: &Order) -> anyhow::Result<()> {
+    let config = &state.config.guest_order;
+    let Some(email) = order.guest_email.as_deref() else {
+        tracing::info!("guest_order_lookup: order {} has no email; link not sent", order.id);
+        return Ok(());
+    };
+    if config.access_link_base_url.is_empty() {
+        tracing::warn!("GUEST_ORDER_ACCESS_LINK_BASE_URL is not set; access link not sent: order={}", order.id);
+        return Ok(());
+    }
+
+    let repo = GuestOrderAccessRepository::new(state.db.service());
+    let (token, token_hash) = generate_guest_access_token();
+    let expires_at = Utc::now() + chrono::Duration::seconds(config.access_link_ttl_seconds);
+    repo.create(order.id, &token_hash, expires_at).await?;
+
+    let body = format!(
+        "ご注文（{}）の確認用リンクをお送りします。\n\n以下のリンクから注文内容を確認できます（{}まで・1回限り有効）。\nリンクを開くと、以前の確認用URLは使えなくなります。\n{}?token={}\n\nお心当たりのない場合は、このメールを破棄してください。\n",
+        order.order_number,
+        expires_at
+            .with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).expect("valid offset"))
+            .format("%Y/%m/%d %H:%M"),
+        config.access_link_base_url,
+        token
+    );
+    send_email(&state.config.email, email, "【Spirom】ご注文確認用リンク", &body).await?;
+    tracing::info!("guest_order_lookup: sent access link: order={}", order.id);
+
+    if let Err(e) = repo.purge_expired().await {
+        tracing::warn!("guest_order_lookup: failed to purge expired links: {}", e);
+    }
+    Ok(())
+}
+
+/// 再アクセスリンクの使用（1回限り）
+/// ゲストアクセストークンを再発行して返す。旧トークンは無効になる
+pub async fn access_guest_order(
+    State(state): State<AppState>,
+    Json(req): Json<GuestOrderAccessRequest>,
+) -> Result<Json<DataResponse<CreateGuestOrderResponse>>> {
+    req.validate()?;
+
+    let db_service = state.db.service();
+    let invalid_link = || AppError::NotFound("リンクが無効か、有効期限が切れています".to_string());
+    let order_id = GuestOrderAccessRepository::new(db_service.clone())
+        .consume(&hash_guest_token(&req.token))
+        .await?
+        .ok_or_else(invalid_link)?;
+
+    // アカウントに紐付け済みの注文は再発行しない
+    let order_repo = OrderRepository::new(db_service);
+    let (guest_access_token, guest_access_token_hash) = generate_guest_access_token();
+    if !order_repo
+        .rotate_guest_token(order_id, &guest_access_token_hash, guest_token_expiry())
+        .await?
+    {
+        return Err(invalid_link());
+    }
+
+    let order = order_repo.find_by_id(order_id).await?.ok_or_else(invalid_link)?;
+    tracing::info!("guest_order_lookup: access token reissued: order={}", order_id);
+
+    Ok(Json(DataResponse::new(CreateGuestOrderResponse {
+        order,
+        guest_access_token,
+    })))
+}
+
+/// ゲスト注文の紐付けレスポンス
+#[derive(Debug, serde::Serialize)]
+pub struct ClaimGuestOrdersResponse {
+    pub claimed: usize,
+    pub order_ids: Vec<Uuid>,
+}
+
+/// ゲスト注文をアカウントに紐付け（確認済みのメールアドレスと注文時のメールアドレスが一致するもの）
+pub async fn claim_guest_orders(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+) -> Result<Json<DataResponse<ClaimGuestOrdersResponse>>> {
+    // 確認状態はトークンのクレームではなくSupabase Authの最新情報で判定
+    let supa_user = fetch_supabase_auth_user(
+        &state.config.database.url,
+        &state.config.database.anon_key,
+        &token,
+    )
+    .await?;
+    if supa_user.email_confirmed_at.is_none() || supa_user.email.trim().is_empty() {
+        return Err(AppError::Forbidden(
+            "メールアドレスの確認後にゲスト注文を紐付けできます".to_string(),
+        ));
+    }
+
+    // orders.user_id の参照先を先に用意
+    ensure_user_profile(&state, &auth_user, &token).await?;
+
+    let order_ids = OrderRepository::new(state.db.service())
+        .claim_guest_orders(auth_user.id, &normalize_email(&supa_user.email))
+        .await?;
+    if !order_ids.is_empty() {
+        tracing::info!("Guest orders claimed: user={}, count={}", auth_user.id, order_ids.len());
+    }
+
+    Ok(Json(DataResponse::new(ClaimGuestOrdersResponse {
+        claimed: order_ids.len(),
+        order_ids,
+    })))
+}
+
 /// PaymentIntent IDで注文取得（支払い完了後のリダイレクト用）
 pub async fn get_order_by_payment_intent(
     State(state): State<AppState>,
@@ -0,0 +1,302 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::{
+    passkey_repository::{PasskeyCredential, WebAuthnCeremony},
+    LoginAttemptsRepository, PasskeyRepository, UserRepository,
+};
+use crate::error::{AppError, Result};
+use crate::handlers::auth::{
+    check_login_throttle, complete_login, failed_login_error, get_user_agent, supabase_auth_request,
+};
+use crate::middleware::get_client_ip;
+use crate::handlers::users::ensure_user_profile;
+use crate::models::{AuthenticatedUser, DataResponse};
+use crate::services::webauthn::{
+    decode_b64url, encode_b64url, generate_challenge, get_webauthn_config, CHALLENGE_TTL_SECONDS,
+    COSE_ALG_ES256,
+};
+
+/// 登録レスポンス（PublicKeyCredential.toJSON() 形式）
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// パスキー登録リクエスト
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegisterRequest {
+    pub challenge_id: Uuid,
+    pub credential: RegistrationCredential,
+    pub name: Option<String>,
+}
+
+/// 認証レスポンス（PublicKeyCredential.toJSON() 形式）
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+/// パスキーログインリクエスト
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginRequest {
+    pub challenge_id: Uuid,
+    pub credential: AuthenticationCredential,
+    /// Turnstileトークン（CAPTCHA要求時のみ）
+    #[serde(default)]
+    pub captcha_token: Option<String>,
+}
+
+/// パスキー登録オプション取得（navigator.credentials.create 用）
+pub async fn registration_options(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+) -> Result<Json<Value>> {
+    let user = ensure_user_profile(&state, &auth_user, &token).await?;
+    let config = get_webauthn_config(&state.config);
+
+    let repo = PasskeyRepository::new(state.db.service());
+    let existing = repo.find_by_user(auth_user.id).await?;
+
+    let challenge = generate_challenge();
+    let challenge_id = repo
+        .create_challenge(&challenge, WebAuthnCeremony::Registration, Some(auth_user.id))
+        .await?;
+
+    let display_name = if user.name.is_empty() { user.email.clone() } else { user.name.clone() };
+
+    Ok(Json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "public_key": {
+            "challenge": challenge,
+            "rp": { "id": config.rp_id, "name": config.rp_name },
+            "user": {
+                "id": encode_b64url(auth_user.id.as_bytes()),
+                "name": user.email,
+                "displayName": display_name
+            },
+            "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ALG_ES256 }],
+            "timeout": CHALLENGE_TTL_SECONDS * 1000,
+            "attestation": "none",
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "userVerification": "required"
+            },
+            "excludeCredentials": existing
+                .iter()
+                .map(|c| serde_json::json!({ "type": "public-key", "id": c.credential_id }))
+                .collect::<Vec<_>>()
+        }
+    })))
+}
+
+/// パスキー登録
+pub async fn register(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Json(req): Json<PasskeyRegisterRequest>,
+) -> Result<Json<DataResponse<PasskeyCredential>>> {
+    let repo = PasskeyRepository::new(state.db.service());
+
+    let challenge = repo
+        .consume_challenge(req.challenge_id, WebAuthnCeremony::Registration)
+        .await?
+        .filter(|c| c.user_id == Some(auth_user.id))
+        .ok_or_else(|| AppError::BadRequest("チャレンジが無効か期限切れです".to_string()))?;
+
+    let verified = get_webauthn_config(&state.config)
+        .verify_registration(
+            &challenge.challenge,
+            &req.credential.response.client_data_json,
+            &req.credential.response.attestation_object,
+        )
+        .map_err(|e| {
+            tracing::warn!("Passkey registration rejected: user_id={}, reason={}", auth_user.id, e);
+            AppError::BadRequest("パスキーの登録に失敗しました".to_string())
+        })?;
+
+    if verified.credential_id != req.credential.id.trim_end_matches('=') {
+        return Err(AppError::BadRequest("クレデンシャルIDが一致しません".to_string()));
+    }
+
+    let name = req
+        .name
+        .map(|n| n.chars().take(100).collect::<String>())
+        .filter(|n| !n.trim().is_empty());
+
+    let credential = repo
+        .create(
+            auth_user.id,
+            &verified.credential_id,
+            &verified.public_key,
+            verified.sign_count,
+            name,
+        )
+        .await?;
+
+    Ok(Json(DataResponse::new(credential)))
+}
+
+/// 登録済みパスキー一覧
+pub async fn list_passkeys(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+) -> Result<Json<DataResponse<Vec<PasskeyCredential>>>> {
+    let repo = PasskeyRepository::new(state.db.with_auth(&token));
+    let credentials = repo.find_by_user(auth_user.id).await?;
+
+    Ok(Json(DataResponse::new(credentials)))
+}
+
+/// パスキー削除
+pub async fn delete_passkey(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    let repo = PasskeyRepository::new(state.db.with_auth(&token));
+    repo.delete(auth_user.id, id).await?;
+
+    Ok(Json(serde_json::json!({ "message": "パスキーを削除しました" })))
+}
+
+/// パスキーログインオプション取得（navigator.credentials.get 用）
+/// discoverable credential を使うため allowCredentials は返さない（アカウント列挙対策）
+pub async fn authentication_options(State(state): State<AppState>) -> Result<Json<Value>> {
+    let config = get_webauthn_config(&state.config);
+    let repo = PasskeyRepository::new(state.db.service());
+
+    let challenge = generate_challenge();
+    let challenge_id = repo
+        .create_challenge(&challenge, WebAuthnCeremony::Authentication, None)
+        .await?;
+
+    Ok(Json(serde_json::json!({
+        "challenge_id": challenge_id,
+        "public_key": {
+            "challenge": challenge,
+            "rpId": config.rp_id,
+            "timeout": CHALLENGE_TTL_SECONDS * 1000,
+            "userVerification": "required"
+        }
+    })))
+}
+
+/// パスキーでログイン
+/// 検証成功後、Supabase Authのマジックリンクトークンでセッションを発行する
+pub async fn authenticate(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<PasskeyLoginRequest>,
+) -> Result<Json<Value>> {
+    let client_ip = get_client_ip(&addr, &headers);
+    let user_agent = get_user_agent(&headers);
+    let invalid = || AppError::Unauthorized("パスキーによる認証に失敗しました".to_string());
+
+    let repo = PasskeyRepository::new(state.db.service());
+    let challenge = repo
+        .consume_challenge(req.challenge_id, WebAuthnCeremony::Authentication)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("チャレンジが無効か期限切れです".to_string()))?;
+
+    let credential = repo
+        .find_by_credential_id(req.credential.id.trim_end_matches('='))
+        .await?
+        .ok_or_else(invalid)?;
+
+    // userHandle がある場合はクレデンシャルの所有者と一致すること
+    if let Some(user_handle) = req.credential.response.user_handle.as_deref() {
+        let handle = decode_b64url(user_handle).map_err(|_| invalid())?;
+        if handle != credential.user_id.as_bytes() {
+            return Err(invalid());
+        }
+    }
+
+    let user = UserRepository::new(state.db.service())
+        .find_by_id(credential.user_id)
+        .await?
+        .filter(|u| u.is_active)
+        .ok_or_else(invalid)?;
+
+    let login_repo = LoginAttemptsRepository::new(state.db.service());
+    check_login_throttle(&state, &login_repo, &user.email, &client_ip, req.captcha_token.as_deref()).await?;
+
+    let response = &req.credential.response;
+    let verified = get_webauthn_config(&state.config).verify_assertion(
+        &challenge.challenge,
+        &credential.public_key,
+        credential.sign_count.clamp(0, u32::MAX as i64) as u32,
+        &response.client_data_json,
+        &response.authenticator_data,
+        &response.signature,
+    );
+
+    let sign_count = match verified {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            tracing::warn!("Passkey assertion rejected: user_id={}, reason={}", user.id, e);
+            let attempt_result = login_repo
+                .handle_failed_login(&user.email, &client_ip, user_agent.as_deref())
+                .await?;
+            return Err(failed_login_error(
+                &state,
+                attempt_result,
+                &user.email,
+                &client_ip,
+                "パスキーによる認証に失敗しました",
+            ));
+        }
+    };
+
+    repo.update_usage(credential.id, sign_count).await?;
+    login_repo
+        .handle_successful_login(&user.email, &client_ip, user_agent.as_deref())
+        .await?;
+
+    // Supabase Authセッション発行（メールは送信しない）
+    let token_hash = state.db.generate_magic_link_token_hash(&user.email).await?;
+    let auth_res = supabase_auth_request(
+        &Client::new(),
+        &state.config.database.url,
+        &state.config.database.anon_key,
+        "verify",
+        serde_json::json!({ "type": "magiclink", "token_hash": token_hash }),
+    )
+    .await?;
+
+    complete_login(&state, auth_res, &client_ip, user_agent.as_deref()).await
+}
@@ -7,21 +7,34 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
-use crate::config::AppState;
-use crate::db::repositories::{CartRepository, OrderRepository, ProductRepository, UserRepository};
+use crate::config::{AppState, Config};
+use crate::db::repositories::{
+    CartRepository, OrderActor, OrderRepository, PlaceOrderResult, ProductRepository, UserRepository,
+};
 use crate::middleware::generate_session_id;
 use crate::handlers::users::ensure_user_profile;
 use crate::error::{AppError, Result};
 use crate::models::{
-    AuthenticatedUser, DataResponse, Order, OrderAddress, OrderItem, OrderStatus, PaymentMethod, PaymentStatus, UserRole,
+    AuthenticatedUser, DataResponse, Order, OrderAddress, OrderItem, OrderItemRequest, OrderStatus, PaymentMethod,
+    PaymentStatus, UserRole,
     calculate_shipping_fee, calculate_tax, generate_order_number,
     generate_guest_access_token, guest_token_expiry, GuestShippingAddress,
 };
 use crate::services::payment::{
-    CreateIntentParams, PaymentProvider, ShippingAddress, StripePaymentProvider, WebhookEventType,
+    CreateIntentParams, PaymentProvider, ShippingAddress, StripePaymentProvider, WebhookEvent, WebhookEventType,
 };
+use crate::handlers::order_modifications::handle_modification_payment;
+use crate::handlers::orders::{build_order_items, check_preorder_mix};
+use crate::services::cart_recovery::record_conversion;
+use crate::services::metrics;
+
+/// 設定からStripeプロバイダを作成（未設定時は500）
+pub(crate) fn stripe_provider(config: &Config) -> Result<StripePaymentProvider> {
+    StripePaymentProvider::from_config(config)
+        .ok_or_else(|| AppError::Internal("Stripe APIキーが設定されていません".to_string()))
+}
 
-fn stripe_event_summary(event: &crate::services::payment::WebhookEvent) -> serde_json::Value {
+fn stripe_event_summary(event: &WebhookEvent) -> serde_json::Value {
     // PIIや巨大payloadを避け、検証に必要な最小限だけ保存する
     // - amount/currency/status は PaymentIntent から取得
     let obj = &event.data["data"]["object"];
@@ -53,6 +66,9 @@ pub struct CreatePaymentIntentRequest {
     /// 備考
     #[validate(length(max = 500))]
     pub notes: Option<String>,
+    /// 予約商品と在庫のある商品を分けて発送する（混在する注文に必要）
+    #[serde(default)]
+    pub split_shipment: bool,
 }
 
 /// PaymentIntent metadataに保存する注文アイテム
@@ -64,6 +80,40 @@ pub struct PaymentMetadataItem {
     pub variant_id: Option<Uuid>,
     #[serde(default)]
     pub size: Option<String>,
+    /// 予約明細（PaymentIntent作成時にサーバー側で判定し、Webhookで予約枠を確保する）
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_preorder: bool,
+}
+
+/// 決済前の明細確認（販売状態・在庫・予約枠の割り当て）
+/// 注文作成と同じ build_order_items を使い、metadata用の明細・小計・分割発送の指定を返す
+async fn prepare_intent_items(
+    product_repo: &ProductRepository,
+    source_items: &[OrderItemRequest],
+    split_shipment: bool,
+) -> Result<(Vec<PaymentMetadataItem>, i64, bool)> {
+    for item in source_items {
+        item.validate()?;
+    }
+    let product_ids: Vec<_> = source_items.iter().map(|i| i.product_id).collect();
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variant_ids: Vec<_> = source_items.iter().filter_map(|i| i.variant_id).collect();
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let (order_items, subtotal) = build_order_items(source_items, &products, &variants, None)?;
+    let split_shipment = check_preorder_mix(&order_items, split_shipment)?;
+
+    let items = order_items
+        .into_iter()
+        .map(|item| PaymentMetadataItem {
+            product_id: item.product_id,
+            quantity: item.quantity,
+            variant_id: item.variant_id,
+            size: item.size,
+            is_preorder: item.is_preorder,
+        })
+        .collect();
+    Ok((items, subtotal, split_shipment))
 }
 
 /// PaymentIntent作成レスポンス
@@ -133,43 +183,19 @@ pub async fn create_payment_intent(
         None
     };
 
-    // 商品情報取得・金額計算
-    let product_ids: Vec<_> = cart.items.iter().map(|i| i.product_id).collect();
-    let products = product_repo.find_by_ids(&product_ids).await?;
-
-    let mut items_for_metadata: Vec<PaymentMetadataItem> = Vec::new();
-    let mut subtotal = 0i64;
-
-    for cart_item in &cart.items {
-        let product = products
-            .get(&cart_item.product_id)
-            .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
-
-        if !product.is_active {
-            return Err(AppError::BadRequest(format!(
-                "「{}」は現在販売されていません",
-                product.name
-            )));
-        }
-
-        // 在庫確認（予約はWebhookで行う）
-        if product.stock < cart_item.quantity {
-            return Err(AppError::BadRequest(format!(
-                "「{}」の在庫が不足しています",
-                product.name
-            )));
-        }
-
-        let item_price = product.price;
-        subtotal += item_price * cart_item.quantity as i64;
-
-        items_for_metadata.push(PaymentMetadataItem {
-            product_id: cart_item.product_id,
-            quantity: cart_item.quantity,
-            variant_id: cart_item.variant_id,
-            size: cart_item.size.clone(),
-        });
-    }
+    // 販売状態・在庫・予約枠の確認と金額計算（在庫・予約枠の確保はWebhookで行う）
+    let source_items: Vec<OrderItemRequest> = cart
+        .items
+        .iter()
+        .map(|item| OrderItemRequest {
+            product_id: item.product_id,
+            quantity: item.quantity,
+            variant_id: item.variant_id,
+            size: item.size.clone(),
+        })
+        .collect();
+    let (items_for_metadata, subtotal, split_shipment) =
+        prepare_intent_items(&product_repo, &source_items, req.split_shipment).await?;
 
     // 金額計算
     let shipping_fee = calculate_shipping_fee(subtotal, &shipping_address.country);
@@ -177,12 +203,7 @@ pub async fn create_payment_intent(
     let total = subtotal + shipping_fee + tax;
 
     // Stripe PaymentIntent作成
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // metadataに注文作成に必要な情報を含める
     let mut metadata = std::collections::HashMap::new();
@@ -193,9 +214,14 @@ pub async fn create_payment_intent(
         metadata.insert("billing_address_id".to_string(), billing_id.to_string());
     }
     metadata.insert("payment_method".to_string(), format!("{}", req.payment_method));
+    // 注文確定時にクリアするカート
+    metadata.insert("cart_session_id".to_string(), session_id.clone());
     if let Some(ref notes) = req.notes {
         metadata.insert("notes".to_string(), notes.clone());
     }
+    if split_shipment {
+        metadata.insert("split_shipment".to_string(), "true".to_string());
+    }
     // カート情報をJSONで保存
     let items_json = serde_json::to_string(&items_for_metadata)
         .map_err(|_| AppError::Internal("カート情報のシリアライズに失敗しました".to_string()))?;
@@ -289,6 +315,9 @@ pub struct CreateGuestPaymentIntentRequest {
     /// 備考
     #[validate(length(max = 500))]
     pub notes: Option<String>,
+    /// 予約商品と在庫のある商品を分けて発送する（混在する注文に必要）
+    #[serde(default)]
+    pub split_shipment: bool,
 }
 
 /// ゲスト用PaymentIntent作成（認証不要、注文はWebhookで作成）
@@ -314,43 +343,19 @@ pub async fn create_payment_intent_guest(
     let db_service = state.db.service();
     let product_repo = ProductRepository::new(db_service);
 
-    // 商品情報取得・金額計算
-    let product_ids: Vec<_> = req.items.iter().map(|i| i.product_id).collect();
-    let products = product_repo.find_by_ids(&product_ids).await?;
-
-    let mut items_for_metadata: Vec<PaymentMetadataItem> = Vec::new();
-    let mut subtotal = 0i64;
-
-    for item in &req.items {
-        let product = products
-            .get(&item.product_id)
-            .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
-
-        if !product.is_active {
-            return Err(AppError::BadRequest(format!(
-                "「{}」は現在販売されていません",
-                product.name
-            )));
-        }
-
-        // 在庫確認（予約はWebhookで行う）
-        if product.stock < item.quantity {
-            return Err(AppError::BadRequest(format!(
-                "「{}」の在庫が不足しています",
-                product.name
-            )));
-        }
-
-        let item_price = product.price;
-        subtotal += item_price * item.quantity as i64;
-
-        items_for_metadata.push(PaymentMetadataItem {
+    // 販売状態・在庫・予約枠の確認と金額計算（予約明細かどうかはクライアントの指定を使わず判定する）
+    let source_items: Vec<OrderItemRequest> = req
+        .items
+        .iter()
+        .map(|item| OrderItemRequest {
             product_id: item.product_id,
             quantity: item.quantity,
             variant_id: item.variant_id,
             size: item.size.clone(),
-        });
-    }
+        })
+        .collect();
+    let (items_for_metadata, subtotal, split_shipment) =
+        prepare_intent_items(&product_repo, &source_items, req.split_shipment).await?;
 
     // 金額計算
     let shipping_fee = calculate_shipping_fee(subtotal, &req.shipping_address.country);
@@ -358,12 +363,7 @@ pub async fn create_payment_intent_guest(
     let total = subtotal + shipping_fee + tax;
 
     // Stripe PaymentIntent作成
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // metadataに注文作成に必要な情報を含める（認証ユーザーと同じ形式）
     let mut metadata = std::collections::HashMap::new();
@@ -372,9 +372,14 @@ pub async fn create_payment_intent_guest(
     metadata.insert("guest_name".to_string(), req.shipping_address.name.clone());
     metadata.insert("guest_phone".to_string(), req.shipping_address.phone.clone());
     metadata.insert("payment_method".to_string(), format!("{}", req.payment_method));
+    // 注文確定時にクリアするカート
+    metadata.insert("cart_session_id".to_string(), session_id.clone());
     if let Some(ref notes) = req.notes {
         metadata.insert("notes".to_string(), notes.clone());
     }
+    if split_shipment {
+        metadata.insert("split_shipment".to_string(), "true".to_string());
+    }
     // カート情報をJSONで保存
     let items_json = serde_json::to_string(&items_for_metadata)
         .map_err(|_| AppError::Internal("カート情報のシリアライズに失敗しました".to_string()))?;
@@ -485,12 +490,7 @@ pub async fn create_payment_intent_for_guest_order(
     }
 
     // Stripe PaymentIntent作成
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // metadataに注文情報を含める
     let mut metadata = std::collections::HashMap::new();
@@ -517,6 +517,7 @@ pub async fn create_payment_intent_for_guest_order(
             quantity: item.quantity,
             variant_id: item.variant_id,
             size: item.size.clone(),
+            is_preorder: item.is_preorder,
         }
     }).collect();
     let items_json = serde_json::to_string(&items_for_metadata)
@@ -593,18 +594,14 @@ pub async fn handle_webhook(
     tracing::debug!("Webhook signature: {}", &signature[..signature.len().min(50)]);
 
     // Stripe PaymentProvider初期化
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // Webhook検証
     let event = payment_provider
         .verify_webhook(&body, signature)
         .map_err(|e| {
             tracing::error!("Webhook verification failed: {}", e);
+            metrics::record_payment_webhook("unknown", "invalid_signature");
             AppError::BadRequest(format!("Webhook検証に失敗しました: {}", e))
         })?;
 
@@ -618,6 +615,18 @@ pub async fn handle_webhook(
         event.event_type
     );
 
+    let event_type = format!("{:?}", event.event_type);
+    let result = process_webhook_event(&state, &payment_provider, event).await;
+    metrics::record_payment_webhook(&event_type, if result.is_ok() { "processed" } else { "error" });
+    result
+}
+
+/// 検証済みWebhookイベントの処理（冪等性チェック → イベント種別ごとの処理）
+async fn process_webhook_event(
+    state: &AppState,
+    payment_provider: &StripePaymentProvider,
+    event: WebhookEvent,
+) -> Result<StatusCode> {
     // Webhook処理用のクライアント
     // - record_stripe_event RPC は service_role のみ許可されている
     // - 注文作成も service_role で行う（RLSバイパス必要）
@@ -685,6 +694,16 @@ pub async fn handle_webhook(
                 metadata
             );
 
+            // 注文変更の追加決済（注文は作成済み。変更を適用する）
+            if let Some(modification_id) = metadata["order_modification_id"].as_str() {
+                let modification_id: Uuid = modification_id
+                    .parse()
+                    .map_err(|_| AppError::BadRequest("order_modification_idが不正です".to_string()))?;
+                handle_modification_payment(state, payment_provider, modification_id, &event.payment_id, stripe_amount)
+                    .await;
+                return Ok(StatusCode::OK);
+            }
+
             let is_guest = metadata["is_guest_order"].as_str() == Some("true");
 
             // ゲストも認証ユーザーも同じフロー：metadataから注文を作成
@@ -798,6 +817,7 @@ pub async fn handle_webhook(
                 );
 
                 // 注文アイテム作成（productsは既に取得済み）
+                // 予約明細はPaymentIntent作成時の割り当て（metadata）に従い、注文確定時に予約枠を確保する
                 let mut order_items = Vec::new();
                 let mut stock_reserve_items: Vec<(Uuid, i32)> = Vec::new();
 
@@ -816,25 +836,14 @@ pub async fn handle_webhook(
                         image_url: product.images.first().cloned(),
                         variant_id: item.variant_id,
                         size: item.size.clone(),
+                        is_preorder: item.is_preorder,
+                        expected_ship_date: product.preorder.expected_ship_date.filter(|_| item.is_preorder),
+                        preorder_released_at: None,
                     });
 
                     stock_reserve_items.push((product.id, item.quantity));
                 }
 
-                // 在庫確保
-                tracing::info!("在庫確保開始: items={:?}", stock_reserve_items);
-                let reserved = product_repo.reserve_stock_bulk(&stock_reserve_items).await?;
-                tracing::info!("在庫確保結果: reserved={}", reserved);
-                if !reserved {
-                    tracing::error!("!!! 返金トリガー: 在庫確保失敗 !!! payment_id={}, items={:?}", event.payment_id, stock_reserve_items);
-                    let refund_provider = payment_provider.clone();
-                    let payment_id = event.payment_id.clone();
-                    tokio::spawn(async move {
-                        let _ = refund_provider.refund(&payment_id, None).await;
-                    });
-                    return Ok(StatusCode::OK);
-                }
-
                 let now = chrono::Utc::now();
                 let order = Order {
                     id: Uuid::new_v4(),
@@ -868,23 +877,45 @@ pub async fn handle_webhook(
                     crypto_chain_id: None,
                     crypto_sender_address: None,
                     crypto_confirmed_at: None,
+                    split_shipment: metadata["split_shipment"].as_str() == Some("true"),
                 };
 
-                // service_roleで注文作成
-                tracing::info!("注文作成開始: order_id={}, is_guest={}, user_id={:?}", order.id, is_guest, user_id);
-                let service_order_repo = OrderRepository::new(state.db.service());
-                if let Err(e) = service_order_repo.create(&order).await {
-                    tracing::error!("!!! 返金トリガー: 注文作成失敗 !!! error={}, payment_id={}, order_id={}", e, event.payment_id, order.id);
-                    let _ = product_repo.release_stock_bulk(&stock_reserve_items).await;
-                    let refund_provider = payment_provider.clone();
-                    let payment_id = event.payment_id.clone();
-                    tokio::spawn(async move {
-                        let _ = refund_provider.refund(&payment_id, None).await;
-                    });
-                    return Ok(StatusCode::OK);
-                }
+                // 在庫確保 → 注文作成 → カートクリア（DATABASE_BACKEND=postgres では1トランザクション）
+                let cart_session_id = metadata["cart_session_id"].as_str();
+                tracing::info!(
+                    "注文確定開始: order_id={}, is_guest={}, user_id={:?}, items={:?}, backend={}",
+                    order.id, is_guest, user_id, stock_reserve_items, state.checkout.backend()
+                );
+                let order = match state.checkout.place_order(&order, OrderActor::Service, cart_session_id).await {
+                    Ok(PlaceOrderResult::Created(created)) => *created,
+                    Ok(PlaceOrderResult::OutOfStock) => {
+                        tracing::error!("!!! 返金トリガー: 在庫・予約枠の確保失敗 !!! payment_id={}, items={:?}", event.payment_id, stock_reserve_items);
+                        metrics::record_stock_reservation_failure("stripe_webhook");
+                        let refund_provider = payment_provider.clone();
+                        let payment_id = event.payment_id.clone();
+                        tokio::spawn(async move {
+                            let _ = refund_provider.refund(&payment_id, None).await;
+                        });
+                        return Ok(StatusCode::OK);
+                    }
+                    Err(e) => {
+                        tracing::error!("!!! 返金トリガー: 注文作成失敗 !!! error={}, payment_id={}, order_id={}", e, event.payment_id, order.id);
+                        let refund_provider = payment_provider.clone();
+                        let payment_id = event.payment_id.clone();
+                        tokio::spawn(async move {
+                            let _ = refund_provider.refund(&payment_id, None).await;
+                        });
+                        return Ok(StatusCode::OK);
+                    }
+                };
 
-                tracing::info!("注文作成成功: order_id={}, payment_id={}, is_guest={}", order.id, event.payment_id, is_guest);
+                tracing::info!(
+                    "注文作成成功: order_id={}, order_number={}, payment_id={}, is_guest={}",
+                    order.id, order.order_number, event.payment_id, is_guest
+                );
+                if let Some(session_id) = cart_session_id {
+                    record_conversion(state, session_id, &order).await;
+                }
             }
         }
         WebhookEventType::PaymentFailed => {
@@ -893,6 +924,18 @@ pub async fn handle_webhook(
             tracing::warn!("決済失敗: payment_id={} (注文未作成のため処理不要)", event.payment_id);
         }
         WebhookEventType::RefundSucceeded => {
+            // 一部返金（注文変更の差額など）と注文変更の追加決済の返金では注文を返金済みにしない
+            let charge = &event.data["data"]["object"];
+            let fully_refunded = charge["refunded"].as_bool().unwrap_or(true);
+            let is_modification_payment = charge["metadata"]["order_modification_id"].is_string();
+            if !fully_refunded || is_modification_payment {
+                tracing::info!(
+                    "一部返金または注文変更の返金のため注文ステータスは変更しません: payment_id={}",
+                    event.payment_id
+                );
+                return Ok(StatusCode::OK);
+            }
+
             if let Some(order_id) = event.order_id {
                 // 返金完了状態に更新（RPC関数）
                 order_repo
@@ -923,7 +966,7 @@ pub struct ConfirmPaymentRequest {
 /// 決済確認
 /// セキュリティ: テスト用エンドポイント - 厳格な環境チェックを適用
 pub async fn confirm_payment(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Extension(_auth_user): Extension<AuthenticatedUser>,
     Json(req): Json<ConfirmPaymentRequest>,
 ) -> Result<Json<DataResponse<()>>> {
@@ -931,24 +974,16 @@ pub async fn confirm_payment(
 
     // セキュリティ: テスト用エンドポイントは開発環境のみ許可
     // 明示的に "development" または "local" が設定されている場合のみ許可
-    // その他の値（空文字、"staging"、"production" 等）はすべて拒否
-    let env = std::env::var("ENVIRONMENT").unwrap_or_default();
-    let allowed_envs = ["development", "local"];
-    if !allowed_envs.contains(&env.as_str()) {
+    // その他の値（"staging"、"production" 等）はすべて拒否
+    if !state.config.is_development() {
         tracing::warn!(
-            "Test endpoint access denied: environment={}, allowed={:?}",
-            env,
-            allowed_envs
+            "Test endpoint access denied: environment={}, allowed=[\"development\", \"local\"]",
+            state.config.environment
         );
         return Err(AppError::Forbidden("このエンドポイントは開発環境のみ利用可能です".to_string()));
     }
 
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // 決済確認
     let result = payment_provider
@@ -1013,12 +1048,7 @@ pub async fn create_refund(
         .payment_id
         .ok_or_else(|| AppError::BadRequest("決済IDが見つかりません".to_string()))?;
 
-    let stripe_key = std::env::var("STRIPE_SECRET_KEY")
-        .map_err(|_| AppError::Internal("Stripe APIキーが設定されていません".to_string()))?;
-    let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-    let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-
-    let payment_provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
+    let payment_provider = stripe_provider(&state.config)?;
 
     // 返金実行
     let refund = payment_provider
@@ -0,0 +1,185 @@
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use chrono::Utc;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::AppState;
+use crate::db::repositories::{NewProductAlert, ProductAlertRepository, ProductRepository};
+use crate::error::{AppError, Result};
+use crate::models::{
+    AuthenticatedUser, DataResponse, ProductAlert, ProductAlertKind, ProductAlertStatus,
+    ProductAlertTokenRequest, SubscribeProductAlertRequest,
+};
+use crate::services::product_alerts::{
+    send_confirmation_email, verify_confirmation_token, verify_unsubscribe_token,
+};
+
+/// 再入荷・値下げ通知の購読（ログイン中はアカウントのメールアドレス宛て）
+/// ゲストは確認メールのリンクを開くまで通知しない（status=pending_confirmation）
+pub async fn subscribe(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    Path(product_id): Path<Uuid>,
+    Json(req): Json<SubscribeProductAlertRequest>,
+) -> Result<Json<DataResponse<ProductAlert>>> {
+    req.validate()?;
+
+    let config = &state.config.product_alerts;
+    if config.unsubscribe_link_base_url.is_empty() || (auth_user.is_none() && config.confirm_link_base_url.is_empty()) {
+        return Err(AppError::BadRequest("現在、通知の登録は受け付けていません".to_string()));
+    }
+
+    let email = match (&auth_user, &req.email) {
+        (Some(user), _) => user.email.clone(),
+        (None, Some(email)) => email.clone(),
+        (None, None) => return Err(AppError::BadRequest("メールアドレスを入力してください".to_string())),
+    }
+    .trim()
+    .to_lowercase();
+
+    let product_repo = ProductRepository::new(state.db.service());
+    let alert_repo = ProductAlertRepository::new(state.db.service());
+
+    let product = product_repo
+        .find_by_id(product_id)
+        .await?
+        .filter(|p| p.is_active)
+        .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
+
+    let variant = match req.variant_id {
+        Some(variant_id) => Some(
+            product_repo
+                .find_variants_by_ids(&[variant_id])
+                .await?
+                .remove(&variant_id)
+                .filter(|v| v.product_id == product.id && v.is_active)
+                .ok_or_else(|| AppError::NotFound("バリアントが見つかりません".to_string()))?,
+        ),
+        None => None,
+    };
+    let stock = variant.as_ref().map(|v| v.stock).unwrap_or(product.stock);
+
+    let target_price = match req.kind {
+        ProductAlertKind::BackInStock if stock > 0 => {
+            return Err(AppError::BadRequest("この商品は在庫があります".to_string()));
+        }
+        ProductAlertKind::BackInStock => None,
+        ProductAlertKind::PriceDrop => Some(product.price),
+    };
+
+    if alert_repo
+        .find_open(&email, req.kind, product.id, req.variant_id)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::Conflict("この商品の通知は登録済みです".to_string()));
+    }
+
+    let max = state.config.product_alerts.max_subscriptions_per_email;
+    if alert_repo.count_open_by_email(&email).await? >= max {
+        return Err(AppError::BadRequest(format!("通知を登録できる商品は最大{}件までです", max)));
+    }
+
+    // ログイン中はアカウントのメールアドレス（確認済み）なので確認メールは不要
+    let (status, confirmed_at) = match auth_user {
+        Some(_) => (ProductAlertStatus::Active, Some(Utc::now())),
+        None => (ProductAlertStatus::PendingConfirmation, None),
+    };
+    let alert = alert_repo
+        .create(&NewProductAlert {
+            kind: req.kind,
+            product_id: product.id,
+            variant_id: req.variant_id,
+            user_id: auth_user.as_ref().map(|u| u.id),
+            email,
+            target_price,
+            status,
+            confirmed_at,
+        })
+        .await?;
+
+    if alert.status == ProductAlertStatus::PendingConfirmation {
+        let size = variant.as_ref().map(|v| v.size.as_str());
+        if let Err(e) = send_confirmation_email(&state, &alert, &product, size).await {
+            tracing::warn!("Product alert confirmation email failed: id={}, error={:#}", alert.id, e);
+            // 再登録できるように取り消す
+            let _ = alert_repo.cancel_by_id(alert.id).await;
+            return Err(AppError::ExternalService("確認メールを送信できませんでした".to_string()));
+        }
+    }
+
+    tracing::info!(
+        "Product alert subscribed: id={}, kind={}, product_id={}, status={}",
+        alert.id,
+        alert.kind,
+        product.id,
+        alert.status
+    );
+    Ok(Json(DataResponse::new(alert)))
+}
+
+/// 確認メールのリンクで購読を有効にする（ゲスト用）
+pub async fn confirm(
+    State(state): State<AppState>,
+    Json(req): Json<ProductAlertTokenRequest>,
+) -> Result<Json<DataResponse<ProductAlert>>> {
+    let invalid_link = || AppError::NotFound("リンクが無効か、有効期限が切れています".to_string());
+    let id = verify_confirmation_token(req.token.trim(), state.config.session_secret(), Utc::now())
+        .ok_or_else(invalid_link)?;
+
+    let alert = ProductAlertRepository::new(state.db.service())
+        .confirm(id)
+        .await?
+        .ok_or_else(invalid_link)?;
+
+    tracing::info!("Product alert confirmed: id={}", alert.id);
+    Ok(Json(DataResponse::new(alert)))
+}
+
+/// メールの解除リンクで、その宛先の購読をすべて解除
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Json(req): Json<ProductAlertTokenRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let email = verify_unsubscribe_token(req.token.trim(), state.config.session_secret())
+        .ok_or_else(|| AppError::NotFound("リンクが無効です".to_string()))?;
+
+    // 解除済みでも同じ応答にする（リンクの再クリック）
+    let cancelled = ProductAlertRepository::new(state.db.service())
+        .cancel_by_email(&email)
+        .await?;
+    tracing::info!("Product alerts unsubscribed by link: cancelled={}", cancelled);
+
+    Ok(Json(serde_json::json!({ "message": "通知を解除しました" })))
+}
+
+/// 自分の購読一覧
+pub async fn list_my_alerts(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<Json<DataResponse<Vec<ProductAlert>>>> {
+    let alerts = ProductAlertRepository::new(state.db.service())
+        .list_by_user(auth_user.id, 100)
+        .await?;
+
+    Ok(Json(DataResponse::new(alerts)))
+}
+
+/// 自分の購読を解除
+pub async fn cancel_my_alert(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>> {
+    let cancelled = ProductAlertRepository::new(state.db.service())
+        .cancel_for_user(id, auth_user.id)
+        .await?;
+    if !cancelled {
+        return Err(AppError::NotFound("解除できる通知が見つかりません".to_string()));
+    }
+
+    Ok(Json(serde_json::json!({ "message": "通知を解除しました" })))
+}
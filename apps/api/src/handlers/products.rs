@@ -10,8 +10,8 @@ use crate::db::repositories::{CategoryRepository, ProductRepository, ProductUpda
 use crate::error::{AppError, Result};
 use crate::models::{
     AdminCreateProductRequest, AdminUpdateProductRequest, CategorySummary, CreateVariantsRequest,
-    DataResponse, PaginatedResponse, Product, ProductQuery, ProductSummary, ProductVariant,
-    UpdateVariantRequest,
+    DataResponse, PaginatedResponse, PreorderReleaseResponse, PreorderSettings, Product, ProductQuery,
+    ProductSummary, ProductVariant, UpdateVariantRequest,
 };
 
 /// 商品一覧取得
@@ -134,6 +134,10 @@ pub async fn create_product(
 ) -> Result<Json<DataResponse<Product>>> {
     let product_repo = ProductRepository::new(state.db.with_auth(&token));
 
+    if req.preorder_cap < 0 {
+        return Err(AppError::BadRequest("予約の受付上限は0以上で指定してください".to_string()));
+    }
+
     let now = Utc::now();
     let product = Product {
         id: Uuid::new_v4(),
@@ -153,6 +157,12 @@ pub async fn create_product(
         is_featured: req.is_featured,
         tags: Some(req.tags),
         metadata: None,
+        preorder: PreorderSettings {
+            preorder_enabled: req.preorder_enabled,
+            preorder_cap: req.preorder_cap,
+            preorder_allocated: 0,
+            expected_ship_date: req.expected_ship_date,
+        },
         created_at: now,
         updated_at: now,
     };
@@ -177,6 +187,10 @@ pub async fn update_product(
         .await?
         .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
 
+    if req.preorder_cap.is_some_and(|cap| cap < 0) {
+        return Err(AppError::BadRequest("予約の受付上限は0以上で指定してください".to_string()));
+    }
+
     let updates = ProductUpdateInput {
         name: req.name,
         slug: req.slug,
@@ -187,6 +201,9 @@ pub async fn update_product(
         is_active: req.is_active,
         is_featured: req.is_featured,
         images: req.images,
+        preorder_enabled: req.preorder_enabled,
+        preorder_cap: req.preorder_cap,
+        expected_ship_date: req.expected_ship_date,
         updated_at: Utc::now(),
     };
 
@@ -195,6 +212,31 @@ pub async fn update_product(
     Ok(Json(DataResponse::new(updated)))
 }
 
+/// 入荷した在庫を予約注文に引き当てる（管理者専用）
+/// 先に在庫数を入荷分で更新してから呼ぶ。在庫が足りない分は次回の引当まで出荷キューに出ない
+pub async fn release_preorders(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DataResponse<PreorderReleaseResponse>>> {
+    // 他の注文の明細も更新するため service_role で実行（管理者認可はルートで済んでいる）
+    let product_repo = ProductRepository::new(state.db.service());
+
+    product_repo
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
+
+    let released_items = product_repo.release_preorders(id).await?;
+    tracing::info!("Preorders released: product_id={}, items={}", id, released_items);
+
+    let product = product_repo
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::Internal("商品の再取得に失敗しました".to_string()))?;
+
+    Ok(Json(DataResponse::new(PreorderReleaseResponse { released_items, product })))
+}
+
 /// バリアント一覧取得
 pub async fn list_variants(
     State(state): State<AppState>,
@@ -245,6 +287,8 @@ pub async fn create_variants(
             body_width: input.body_width,
             shoulder_width: input.shoulder_width,
             sleeve_length: input.sleeve_length,
+            preorder_cap: input.preorder_cap,
+            preorder_allocated: 0,
             created_at: now,
             updated_at: now,
         };
@@ -279,6 +323,7 @@ pub async fn update_variant(
         body_width: req.body_width,
         shoulder_width: req.shoulder_width,
         sleeve_length: req.sleeve_length,
+        preorder_cap: req.preorder_cap,
         updated_at: Utc::now(),
     };
 
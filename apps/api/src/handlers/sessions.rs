@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::SessionRepository;
+use crate::error::{AppError, Result};
+use crate::middleware::validate_supabase_token;
+use crate::models::{AuthenticatedUser, DataResponse, UserSession};
+
+/// ログイン・リフレッシュ時にセッションを記録
+/// 失敗してもログイン自体は成功させる（記録はベストエフォート）
+pub async fn record_session(
+    state: &AppState,
+    access_token: &str,
+    client_ip: &str,
+    user_agent: Option<&str>,
+) {
+    let claims = match validate_supabase_token(access_token, &state.config.jwt) {
+        Ok(claims) => claims,
+        Err(_) => return,
+    };
+    let (Some(user_id), Some(session_id)) = (claims.user_id(), claims.session_uuid()) else {
+        return;
+    };
+
+    let device = user_agent.map(describe_device);
+    let repo = SessionRepository::new(state.db.service());
+    if let Err(e) = repo
+        .touch(session_id, user_id, device.as_deref(), user_agent, client_ip)
+        .await
+    {
+        tracing::warn!("Failed to record session: user_id={}, error={}", user_id, e);
+    }
+}
+
+/// User-Agentから端末の表示名を生成（例: "iPhone / Safari"）
+fn describe_device(user_agent: &str) -> String {
+    let os = if user_agent.contains("iPhone") {
+        "iPhone"
+    } else if user_agent.contains("iPad") {
+        "iPad"
+    } else if user_agent.contains("Android") {
+        "Android"
+    } else if user_agent.contains("Windows") {
+        "Windows"
+    } else if user_agent.contains("Macintosh") || user_agent.contains("Mac OS X") {
+        "Mac"
+    } else if user_agent.contains("Linux") {
+        "Linux"
+    } else {
+        "不明な端末"
+    };
+
+    // 判定順に注意（Edge/LINEのUAにはChrome/Safariも含まれる）
+    let browser = if user_agent.contains(" Line/") {
+        Some("LINE")
+    } else if user_agent.contains("Edg/") {
+        Some("Edge")
+    } else if user_agent.contains("Firefox/") || user_agent.contains("FxiOS/") {
+        Some("Firefox")
+    } else if user_agent.contains("Chrome/") || user_agent.contains("CriOS/") {
+        Some("Chrome")
+    } else if user_agent.contains("Safari/") {
+        Some("Safari")
+    } else {
+        None
+    };
+
+    match browser {
+        Some(browser) => format!("{} / {}", os, browser),
+        None => os.to_string(),
+    }
+}
+
+/// ログイン中のセッション一覧
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+) -> Result<Json<DataResponse<Vec<UserSession>>>> {
+    let repo = SessionRepository::new(state.db.with_auth(&token));
+
+    let sessions = repo
+        .find_active_by_user(auth_user.id)
+        .await?
+        .into_iter()
+        .map(|mut s| {
+            s.is_current = Some(s.id) == auth_user.session_id;
+            s
+        })
+        .collect();
+
+    Ok(Json(DataResponse::new(sessions)))
+}
+
+/// 指定したセッションをログアウト
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if Some(id) == auth_user.session_id {
+        return Err(AppError::BadRequest(
+            "現在のセッションはこの操作でログアウトできません".to_string(),
+        ));
+    }
+
+    let repo = SessionRepository::new(state.db.service());
+    if !repo.revoke(auth_user.id, id).await? {
+        return Err(AppError::NotFound("セッションが見つかりません".to_string()));
+    }
+
+    Ok(Json(serde_json::json!({ "message": "セッションをログアウトしました" })))
+}
+
+/// 現在のセッション以外を全てログアウト
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<Json<Value>> {
+    let current = auth_user.session_id.ok_or_else(|| {
+        AppError::BadRequest("現在のセッションを特定できません".to_string())
+    })?;
+
+    let repo = SessionRepository::new(state.db.service());
+    let revoked = repo.revoke_others(auth_user.id, current).await?;
+
+    Ok(Json(serde_json::json!({
+        "message": "他の端末からログアウトしました",
+        "revoked": revoked
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_device() {
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+        assert_eq!(describe_device(iphone), "iPhone / Safari");
+
+        let edge = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36 Edg/120.0";
+        assert_eq!(describe_device(edge), "Windows / Edge");
+
+        let line = "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Mobile Safari/537.36 Line/13.20.0";
+        assert_eq!(describe_device(line), "Android / LINE");
+
+        assert_eq!(describe_device("curl/8.0"), "不明な端末");
+    }
+}
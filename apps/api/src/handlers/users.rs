@@ -1,8 +1,10 @@
 use axum::{
     extract::{Path, State},
+    http::header,
+    response::IntoResponse,
     Extension, Json,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::Deserialize;
 use serde_json::Value;
@@ -10,27 +12,74 @@ use uuid::Uuid;
 use validator::Validate;
 
 use crate::config::AppState;
-use crate::db::repositories::UserRepository;
+use crate::db::Query;
+use crate::db::repositories::{
+    LoginAttemptsRepository, OrderRepository, ReviewRepository, UserRepository,
+};
 use crate::error::{AppError, Result};
+use crate::services::account_deletion;
 use crate::models::{
-    Address, AuthenticatedUser, CreateAddressRequest, DataResponse, UpdateAddressRequest,
-    UpdateUserRequest, User, UserPublic, UserRole,
+    AccountExport, Address, AuthenticatedUser, CreateAddressRequest, DataResponse,
+    DeleteAccountRequest, Order, OrderStatus, Review, SortOrder, UpdateAddressRequest, UpdateUserRequest, User, UserPublic,
+    UserRole,
 };
+use crate::utils::sanitize::normalize_email;
+
+/// エクスポート対象の注文件数上限
+const EXPORT_ORDER_LIMIT: i32 = 1000;
 
 /// Supabase Auth のユーザー情報
 #[derive(Debug, Deserialize)]
-struct SupabaseAuthUser {
-    id: String,
-    email: String,
-    created_at: String,
+pub(crate) struct SupabaseAuthUser {
+    pub id: String,
+    pub email: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub user_metadata: Value,
     #[serde(default)]
-    user_metadata: Value,
+    pub email_confirmed_at: Option<String>,
+    /// 連携済みIDプロバイダ（email / google / apple / LINE等）
     #[serde(default)]
-    email_confirmed_at: Option<String>,
+    pub identities: Vec<Value>,
+}
+
+/// Supabase Authのuser_metadata（ソーシャルログインのプロバイダクレーム含む）から氏名・電話番号を取得
+/// - 氏名: name → full_name → family_name + given_name（日本語順）→ nickname
+/// - 電話番号: phone → phone_number
+pub(crate) fn profile_from_user_metadata(metadata: &Value) -> (String, Option<String>) {
+    let get = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+    };
+
+    let name = get("name")
+        .or_else(|| get("full_name"))
+        .map(|s| s.to_string())
+        .or_else(|| match (get("family_name"), get("given_name")) {
+            (Some(family), Some(given)) => Some(format!("{} {}", family, given)),
+            (Some(one), None) | (None, Some(one)) => Some(one.to_string()),
+            (None, None) => None,
+        })
+        .or_else(|| get("nickname").map(|s| s.to_string()))
+        .unwrap_or_default()
+        .chars()
+        .take(100)
+        .collect();
+
+    let phone = get("phone")
+        .or_else(|| get("phone_number"))
+        .map(|s| s.chars().take(20).collect());
+
+    (name, phone)
 }
 
 /// Supabase Auth から現在のユーザー情報を取得
-async fn fetch_supabase_auth_user(base_url: &str, anon_key: &str, token: &str) -> Result<SupabaseAuthUser> {
+pub(crate) async fn fetch_supabase_auth_user(base_url: &str, anon_key: &str, token: &str) -> Result<SupabaseAuthUser> {
     let url = format!("{}/auth/v1/user", base_url.trim_end_matches('/'));
 
     let res = Client::new()
@@ -73,17 +122,9 @@ pub async fn ensure_user_profile(
     .await?;
 
     let now = Utc::now();
-    let name = supa_user
-        .user_metadata
-        .get("name")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
-    let phone = supa_user
-        .user_metadata
-        .get("phone")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    // ソーシャルログインの場合はプロバイダのクレームから補完
+    let (name, metadata_phone) = profile_from_user_metadata(&supa_user.user_metadata);
+    let phone = metadata_phone.or_else(|| supa_user.phone.clone().filter(|p| !p.is_empty()));
 
     let user = User {
         id: auth_user.id,
@@ -276,6 +317,137 @@ pub async fn delete_address(
     Ok(Json(serde_json::json!({ "message": "住所を削除しました" })))
 }
 
+// ========== 個人データ・退会 ==========
+
+/// 個人データエクスポート（プロフィール・住所・注文・レビュー・お問い合わせ）
+/// JSONファイルとしてダウンロードさせる
+pub async fn export_me(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+) -> Result<impl IntoResponse> {
+    let user = ensure_user_profile(&state, &auth_user, &token).await?;
+
+    let db = state.db.with_auth(&token);
+    let addresses = UserRepository::new(db.clone())
+        .find_addresses_by_user(auth_user.id)
+        .await?;
+    let orders = OrderRepository::new(db.clone())
+        .find_by_user(auth_user.id, EXPORT_ORDER_LIMIT)
+        .await?;
+    let reviews = ReviewRepository::new(db.clone())
+        .find_by_user(auth_user.id)
+        .await?;
+    // 管理者メモ・IPアドレスは運営側の記録のため含めない
+    let contact_submissions: Vec<Value> = db
+        .select(
+            "contact_submissions",
+            &Query::new()
+                .eq("user_id", auth_user.id)
+                .select("id,name,email,inquiry_type,order_number,message,status,created_at")
+                .order("created_at", SortOrder::Desc),
+        )
+        .await?;
+
+    let export = build_account_export(user, addresses, orders, reviews, contact_submissions, Utc::now());
+    let filename = format!(
+        "attachment; filename=\"spirom-account-{}.json\"",
+        export.exported_at.format("%Y%m%d")
+    );
+
+    Ok((
+        [
+            (header::CONTENT_DISPOSITION, filename),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Json(DataResponse::new(export)),
+    ))
+}
+
+/// エクスポートデータを組み立てる（プロフィールは公開情報のみ。パスワードハッシュ等は含めない）
+fn build_account_export(
+    user: User,
+    addresses: Vec<Address>,
+    orders: Vec<Order>,
+    reviews: Vec<Review>,
+    contact_submissions: Vec<Value>,
+    exported_at: DateTime<Utc>,
+) -> AccountExport {
+    AccountExport {
+        exported_at,
+        profile: UserPublic::from(user),
+        addresses,
+        orders,
+        reviews,
+        contact_submissions,
+    }
+}
+
+/// 退会（アカウント削除）
+/// - 進行中の注文がある場合は拒否（配送・返金対応に個人情報が必要なため）
+/// - 注文は会計用に匿名化して保持、住所・カートは削除
+/// - 発行済みトークンを全て無効化し、Supabase Authのユーザーを削除
+/// - 途中で失敗した場合は services::account_deletion の定期ジョブが再開する
+pub async fn delete_me(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<Json<Value>> {
+    req.validate()?;
+
+    let user = ensure_user_profile(&state, &auth_user, &token).await?;
+    if normalize_email(&req.confirm_email) != normalize_email(&user.email) {
+        return Err(AppError::BadRequest(
+            "確認用メールアドレスが一致しません".to_string(),
+        ));
+    }
+
+    let order_repo = OrderRepository::new(state.db.with_auth(&token));
+    let orders = order_repo.find_by_user(auth_user.id, EXPORT_ORDER_LIMIT).await?;
+    let has_active_order = orders.iter().any(|o| {
+        matches!(
+            o.status,
+            OrderStatus::PendingPayment | OrderStatus::Paid | OrderStatus::Processing | OrderStatus::Shipped
+        )
+    });
+    if has_active_order {
+        return Err(AppError::Conflict(
+            "処理中の注文があるため退会できません。注文完了後に再度お試しください".to_string(),
+        ));
+    }
+
+    // 0. 退会受付を記録（以降の手順が失敗した場合は定期ジョブで再開）
+    UserRepository::new(state.db.service())
+        .mark_deletion_requested(auth_user.id)
+        .await?;
+
+    // 1. 全トークン無効化（以降のリクエストを即座に拒否）
+    account_deletion::revoke_all_tokens(&state, auth_user.id).await?;
+
+    // 2. 個人データの匿名化・削除
+    let result = account_deletion::anonymize(&state, auth_user.id).await?;
+
+    tracing::info!(
+        "Account anonymized: user_id={}, anonymized_orders={}, deleted_addresses={}, deleted_carts={}",
+        auth_user.id,
+        result.anonymized_orders,
+        result.deleted_addresses,
+        result.deleted_carts
+    );
+
+    // 3. Supabase Authユーザー削除（失敗時は個人データ削除済みのため完了扱いとし、定期ジョブで再試行）
+    if let Err(e) = account_deletion::delete_auth_user(&state, auth_user.id).await {
+        tracing::warn!(
+            "Auth user delete failed, will be retried: user_id={}, error={}",
+            auth_user.id,
+            e
+        );
+    }
+
+    Ok(Json(serde_json::json!({ "message": "退会処理が完了しました" })))
+}
+
 // ========== 管理者専用エンドポイント ==========
 
 /// ユーザー一覧取得（管理者専用）
@@ -325,3 +497,110 @@ pub async fn update_user_admin(
 
     Ok(Json(DataResponse::new(UserPublic::from(user))))
 }
+
+/// ログイン制限の解除（管理者専用）
+/// 全接続元のロックと直近の失敗履歴をリセットする
+pub async fn unlock_user_login_admin(
+    State(state): State<AppState>,
+    Extension(admin): Extension<AuthenticatedUser>,
+    Extension(token): Extension<String>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    let user = UserRepository::new(state.db.with_auth(&token))
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("ユーザーが見つかりません".to_string()))?;
+
+    LoginAttemptsRepository::new(state.db.service())
+        .unlock_account(&normalize_email(&user.email))
+        .await?;
+
+    tracing::info!("Login lock cleared by admin: user_id={}, admin_id={}", user.id, admin.id);
+
+    Ok(Json(serde_json::json!({ "message": "ログイン制限を解除しました" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_from_user_metadata() {
+        // Google
+        let google = serde_json::json!({ "full_name": "Taro Yamada", "email_verified": true });
+        assert_eq!(profile_from_user_metadata(&google), ("Taro Yamada".to_string(), None));
+
+        // 姓名が分かれている場合は日本語順
+        let split = serde_json::json!({ "family_name": "山田", "given_name": "太郎" });
+        assert_eq!(profile_from_user_metadata(&split).0, "山田 太郎");
+
+        // メール登録（register時のdata）
+        let email = serde_json::json!({ "name": "山田太郎", "phone": "09012345678" });
+        assert_eq!(
+            profile_from_user_metadata(&email),
+            ("山田太郎".to_string(), Some("09012345678".to_string()))
+        );
+
+        // クレームなし
+        assert_eq!(profile_from_user_metadata(&Value::Null), (String::new(), None));
+    }
+
+    #[test]
+    fn test_account_export_shape() {
+        let now = Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "taro@example.com".to_string(),
+            password_hash: "secret-hash".to_string(),
+            name: "山田太郎".to_string(),
+            phone: Some("09012345678".to_string()),
+            is_active: true,
+            is_verified: true,
+            role: UserRole::User,
+            created_at: now,
+            updated_at: now,
+            last_login_at: Some(now),
+        };
+        let address = Address {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            label: None,
+            country: "JP".to_string(),
+            postal_code: "1000001".to_string(),
+            prefecture: "東京都".to_string(),
+            city: "千代田区".to_string(),
+            address_line1: "千代田1-1".to_string(),
+            address_line2: None,
+            phone: None,
+            is_default: true,
+            created_at: now,
+        };
+        let contact = serde_json::json!({ "id": Uuid::new_v4(), "message": "問い合わせ" });
+
+        let export = build_account_export(user.clone(), vec![address], vec![], vec![], vec![contact.clone()], now);
+        let json = serde_json::to_value(DataResponse::new(export)).unwrap();
+        let data = &json["data"];
+
+        let mut keys: Vec<&str> = data.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            ["addresses", "contact_submissions", "exported_at", "orders", "profile", "reviews"]
+        );
+
+        // プロフィールは公開情報のみ
+        let mut profile_keys: Vec<&str> = data["profile"].as_object().unwrap().keys().map(String::as_str).collect();
+        profile_keys.sort();
+        assert_eq!(
+            profile_keys,
+            ["created_at", "email", "id", "is_verified", "name", "phone", "role"]
+        );
+        assert_eq!(data["profile"]["id"], serde_json::json!(user.id));
+        assert!(!json.to_string().contains("secret-hash"));
+
+        assert_eq!(data["addresses"][0]["user_id"], serde_json::json!(user.id));
+        assert_eq!(data["orders"], serde_json::json!([]));
+        assert_eq!(data["reviews"], serde_json::json!([]));
+        assert_eq!(data["contact_submissions"], serde_json::json!([contact]));
+    }
+}
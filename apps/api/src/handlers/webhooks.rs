@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::AppState;
+use crate::db::repositories::{WebhookEndpointUpdate, WebhookRepository};
+use crate::error::{AppError, Result};
+use crate::models::{
+    CreateWebhookEndpointRequest, CreatedWebhookEndpoint, DataResponse, UpdateWebhookEndpointRequest,
+    WebhookDelivery, WebhookDeliveryListQuery, WebhookEndpoint,
+};
+use crate::services::webhooks::{check_endpoint_url, generate_secret};
+
+// webhook_endpoints / webhook_deliveries はRLSポリシーなしのため service_role で操作する
+// （admin_middleware で権限確認済み）
+
+/// Webhookエンドポイント一覧（管理者専用）
+pub async fn list_webhooks(State(state): State<AppState>) -> Result<Json<DataResponse<Vec<WebhookEndpoint>>>> {
+    let endpoints = WebhookRepository::new(state.db.service()).find_endpoints().await?;
+    Ok(Json(DataResponse::new(endpoints)))
+}
+
+/// Webhookエンドポイント登録（管理者専用）
+/// 署名用シークレットはこのレスポンスでのみ返す
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Json(req): Json<CreateWebhookEndpointRequest>,
+) -> Result<(StatusCode, Json<DataResponse<CreatedWebhookEndpoint>>)> {
+    req.validate()
+        .map_err(|e| AppError::BadRequest(format!("入力エラー: {}", e)))?;
+    check_endpoint_url(&req.url, state.config.is_production()).await?;
+
+    let secret = generate_secret();
+    let endpoint = WebhookRepository::new(state.db.service())
+        .create_endpoint(&req.url, req.description.as_deref(), &req.event_types, &secret)
+        .await?;
+
+    tracing::info!("Webhook endpoint created: id={}, url={}", endpoint.id, endpoint.url);
+    Ok((
+        StatusCode::CREATED,
+        Json(DataResponse::new(CreatedWebhookEndpoint { endpoint, secret })),
+    ))
+}
+
+/// Webhookエンドポイント更新（管理者専用）
+pub async fn update_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateWebhookEndpointRequest>,
+) -> Result<Json<DataResponse<WebhookEndpoint>>> {
+    req.validate()
+        .map_err(|e| AppError::BadRequest(format!("入力エラー: {}", e)))?;
+    if let Some(url) = &req.url {
+        check_endpoint_url(url, state.config.is_production()).await?;
+    }
+
+    let update = WebhookEndpointUpdate {
+        url: req.url,
+        description: req.description,
+        event_types: req
+            .event_types
+            .map(|types| types.iter().map(|t| t.as_str()).collect()),
+        is_active: req.is_active,
+    };
+
+    let endpoint = WebhookRepository::new(state.db.service())
+        .update_endpoint(id, &update)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Webhookエンドポイントが見つかりません".to_string()))?;
+
+    Ok(Json(DataResponse::new(endpoint)))
+}
+
+/// Webhookエンドポイント削除（管理者専用、配信ログも削除）
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>> {
+    let repo = WebhookRepository::new(state.db.service());
+
+    repo.find_target(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Webhookエンドポイントが見つかりません".to_string()))?;
+    repo.delete_endpoint(id).await?;
+
+    tracing::info!("Webhook endpoint deleted: id={}", id);
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// 配信ログ一覧（管理者専用）
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WebhookDeliveryListQuery>,
+) -> Result<Json<DataResponse<Vec<WebhookDelivery>>>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let deliveries = WebhookRepository::new(state.db.service())
+        .find_deliveries(id, query.status, limit)
+        .await?;
+
+    Ok(Json(DataResponse::new(deliveries)))
+}
+
+/// 配信を同じ内容で再送（管理者専用）
+pub async fn replay_delivery(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<DataResponse<WebhookDelivery>>)> {
+    let delivery = WebhookRepository::new(state.db.service())
+        .replay(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("配信ログが見つかりません".to_string()))?;
+
+    tracing::info!("Webhook delivery replayed: original={}, replay={}", id, delivery.id);
+    Ok((StatusCode::ACCEPTED, Json(DataResponse::new(delivery))))
+}
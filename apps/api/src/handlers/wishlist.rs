@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use chrono::Utc;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{AppState, Config};
+use crate::db::repositories::{CartRepository, ProductRepository, WishlistRepository};
+use crate::error::{AppError, Result};
+use crate::handlers::cart::{extract_bearer, get_verified_session_id, is_session_id_format, MAX_CART_ITEMS};
+use crate::models::{
+    generate_share_token, hash_share_token, is_valid_share_token, AddToWishlistRequest, AuthenticatedUser,
+    CartItem, CartResponse, DataResponse, MergeWishlistRequest, MoveToCartRequest, WishlistItemParams,
+    WishlistItemView, WishlistOwner, WishlistResponse, WishlistShareResponse,
+};
+
+/// ウィッシュリストに保存できる最大件数（DoS対策）
+const MAX_WISHLIST_ITEMS: usize = 100;
+
+/// 所有者を決定（ログイン中はユーザー、それ以外は署名付きセッションID）
+fn resolve_owner(config: &Config, auth_user: Option<&AuthenticatedUser>, headers: &HeaderMap) -> Result<WishlistOwner> {
+    match auth_user {
+        Some(user) => Ok(WishlistOwner::User(user.id)),
+        None => Ok(WishlistOwner::Session(get_verified_session_id(config, headers)?)),
+    }
+}
+
+/// 保存内容に商品情報・在庫を付けてレスポンスを組み立てる
+async fn build_response(
+    state: &AppState,
+    owner: &WishlistOwner,
+    include_inactive: bool,
+) -> Result<WishlistResponse> {
+    let items = WishlistRepository::new(state.db.service()).list(owner).await?;
+
+    let product_repo = ProductRepository::new(state.db.service());
+    let product_ids: Vec<Uuid> = items.iter().map(|i| i.product_id).collect();
+    let variant_ids: Vec<Uuid> = items.iter().filter_map(|i| i.variant_id).collect();
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let views: Vec<WishlistItemView> = items
+        .into_iter()
+        .filter_map(|item| {
+            let product = products.get(&item.product_id)?;
+            if !product.is_active && !include_inactive {
+                return None;
+            }
+            let variant = item.variant_id.and_then(|id| variants.get(&id));
+            let stock = variant.map(|v| v.stock).unwrap_or(product.stock);
+            let available = product.is_active && variant.map(|v| v.is_active).unwrap_or(true);
+            Some(WishlistItemView {
+                product_id: product.id,
+                product_name: product.name.clone(),
+                product_slug: product.slug.clone(),
+                price: product.price,
+                image_url: product.images.first().cloned(),
+                variant_id: item.variant_id,
+                size: variant.map(|v| v.size.clone()),
+                in_stock: available && stock > 0,
+                added_at: item.added_at,
+            })
+        })
+        .collect();
+
+    Ok(WishlistResponse {
+        session_id: match owner {
+            WishlistOwner::Session(sid) => Some(sid.clone()),
+            WishlistOwner::User(_) => None,
+        },
+        item_count: views.len(),
+        items: views,
+    })
+}
+
+/// ウィッシュリスト取得
+pub async fn get_wishlist(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+) -> Result<Json<DataResponse<WishlistResponse>>> {
+    let owner = resolve_owner(&state.config, auth_user.as_deref(), &headers)?;
+    let response = build_response(&state, &owner, true).await?;
+
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// ウィッシュリストに追加
+pub async fn add_to_wishlist(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    Json(req): Json<AddToWishlistRequest>,
+) -> Result<Json<DataResponse<WishlistResponse>>> {
+    let owner = resolve_owner(&state.config, auth_user.as_deref(), &headers)?;
+    let product_repo = ProductRepository::new(state.db.service());
+    let wishlist_repo = WishlistRepository::new(state.db.service());
+
+    let product = product_repo
+        .find_by_id(req.product_id)
+        .await?
+        .filter(|p| p.is_active)
+        .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
+
+    if let Some(variant_id) = req.variant_id {
+        let variants = product_repo.find_variants_by_ids(&[variant_id]).await?;
+        if !variants.get(&variant_id).is_some_and(|v| v.product_id == product.id && v.is_active) {
+            return Err(AppError::NotFound("バリアントが見つかりません".to_string()));
+        }
+    }
+
+    let existing = wishlist_repo.list(&owner).await?;
+    let is_new_item = !existing
+        .iter()
+        .any(|i| i.product_id == req.product_id && i.variant_id == req.variant_id);
+    if is_new_item && existing.len() >= MAX_WISHLIST_ITEMS {
+        return Err(AppError::BadRequest(format!(
+            "ウィッシュリストに保存できる商品は最大{}件までです",
+            MAX_WISHLIST_ITEMS
+        )));
+    }
+
+    wishlist_repo.add(&owner, req.product_id, req.variant_id).await?;
+
+    let response = build_response(&state, &owner, true).await?;
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// ウィッシュリストから削除
+pub async fn remove_from_wishlist(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    Path(product_id): Path<Uuid>,
+    Query(params): Query<WishlistItemParams>,
+) -> Result<Json<DataResponse<WishlistResponse>>> {
+    let owner = resolve_owner(&state.config, auth_user.as_deref(), &headers)?;
+
+    WishlistRepository::new(state.db.service())
+        .remove(&owner, product_id, params.variant_id)
+        .await?;
+
+    let response = build_response(&state, &owner, true).await?;
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// ウィッシュリストからカートへ移動（バリアント在庫を確認）
+pub async fn move_to_cart(
+    State(state): State<AppState>,
+    auth_user: Option<Extension<AuthenticatedUser>>,
+    headers: HeaderMap,
+    Path(product_id): Path<Uuid>,
+    Json(req): Json<MoveToCartRequest>,
+) -> Result<Json<DataResponse<CartResponse>>> {
+    req.validate()?;
+
+    let owner = resolve_owner(&state.config, auth_user.as_deref(), &headers)?;
+    let wishlist_repo = WishlistRepository::new(state.db.service());
+    let product_repo = ProductRepository::new(state.db.service());
+
+    // ウィッシュリストに保存されているアイテムのみ移動可能
+    let saved = wishlist_repo.list(&owner).await?;
+    if !saved
+        .iter()
+        .any(|i| i.product_id == product_id && i.variant_id == req.variant_id)
+    {
+        return Err(AppError::NotFound("ウィッシュリストにこの商品はありません".to_string()));
+    }
+
+    let product = product_repo
+        .find_by_id(product_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("商品が見つかりません".to_string()))?;
+
+    if !product.is_active {
+        return Err(AppError::BadRequest("この商品は現在販売されていません".to_string()));
+    }
+
+    // バリアントがある商品はサイズ指定必須、在庫はバリアント単位で確認
+    let variants: HashMap<Uuid, _> = product_repo
+        .find_variants_by_product(product_id)
+        .await?
+        .into_iter()
+        .map(|v| (v.id, v))
+        .collect();
+    let variant = match req.variant_id {
+        Some(id) => Some(
+            variants
+                .get(&id)
+                .filter(|v| v.is_active)
+                .ok_or_else(|| AppError::BadRequest("このサイズは現在販売されていません".to_string()))?,
+        ),
+        None if variants.values().any(|v| v.is_active) => {
+            return Err(AppError::BadRequest("サイズを選択してください".to_string()));
+        }
+        None => None,
+    };
+    let available_stock = variant.map(|v| v.stock).unwrap_or(product.stock);
+
+    // カートはログイン有無に関わらずセッション単位
+    let client = match extract_bearer(&headers) {
+        Some(t) => state.db.with_auth(&t),
+        None => state.db.anonymous(),
+    };
+    let session_id = get_verified_session_id(&state.config, &headers)?;
+    let cart_repo = CartRepository::new(client);
+    let existing_cart = cart_repo.find_by_session(&session_id).await?;
+
+    let existing_item = existing_cart
+        .items
+        .iter()
+        .find(|item| item.product_id == product_id && item.variant_id == req.variant_id);
+    if existing_item.is_none()
+        && !existing_cart.items.iter().any(|item| item.product_id == product_id)
+        && existing_cart.items.len() >= MAX_CART_ITEMS
+    {
+        return Err(AppError::BadRequest(format!(
+            "カートに追加できる商品は最大{}種類までです",
+            MAX_CART_ITEMS
+        )));
+    }
+
+    let new_quantity = existing_item.map(|item| item.quantity).unwrap_or(0) + req.quantity;
+    if available_stock < new_quantity {
+        return Err(AppError::BadRequest("在庫が不足しています".to_string()));
+    }
+
+    let cart_item = CartItem {
+        product_id: product.id,
+        product_name: product.name.clone(),
+        product_slug: product.slug.clone(),
+        price: product.price,
+        quantity: new_quantity,
+        subtotal: product.price * new_quantity as i64,
+        image_url: product.images.first().cloned(),
+        added_at: Utc::now(),
+        variant_id: req.variant_id,
+        size: variant.map(|v| v.size.clone()),
+    };
+    cart_repo.add_item(&session_id, &cart_item).await?;
+
+    // カート追加に成功してからウィッシュリストから外す
+    wishlist_repo.remove(&owner, product_id, req.variant_id).await?;
+
+    let cart = cart_repo.find_by_session(&session_id).await?;
+    Ok(Json(DataResponse::new(CartResponse::from(cart))))
+}
+
+/// ウィッシュリスト統合（ログイン時）
+pub async fn merge_wishlist(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Json(req): Json<MergeWishlistRequest>,
+) -> Result<Json<DataResponse<WishlistResponse>>> {
+    // マージ元は既存のゲストセッションなので、フォーマットのみ検証（merge_cart と同様）
+    if !is_session_id_format(&req.guest_session_id) {
+        return Err(AppError::BadRequest("無効なセッションIDです".to_string()));
+    }
+
+    let merged = WishlistRepository::new(state.db.service())
+        .merge(&req.guest_session_id, auth_user.id)
+        .await?;
+    tracing::info!(user_id = %auth_user.id, merged, "Wishlist merged");
+
+    let response = build_response(&state, &WishlistOwner::User(auth_user.id), true).await?;
+    Ok(Json(DataResponse::new(response)))
+}
+
+/// 共有リンク発行（再発行すると以前のリンクは無効になる）
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<Json<DataResponse<WishlistShareResponse>>> {
+    let (token, token_hash) = generate_share_token();
+
+    WishlistRepository::new(state.db.service())
+        .save_share(auth_user.id, &token_hash)
+        .await?;
+
+    Ok(Json(DataResponse::new(WishlistShareResponse {
+        path: format!("/api/v1/wishlists/shared/{}", token),
+        token,
+    })))
+}
+
+/// 共有リンク停止
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<Json<serde_json::Value>> {
+    WishlistRepository::new(state.db.service())
+        .revoke_share(auth_user.id)
+        .await?;
+
+    Ok(Json(serde_json::json!({ "message": "共有リンクを停止しました" })))
+}
+
+/// 共有されたウィッシュリストの閲覧（公開・販売中の商品のみ）
+pub async fn get_shared_wishlist(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<DataResponse<WishlistResponse>>> {
+    let not_found = || AppError::NotFound("ウィッシュリストが見つかりません".to_string());
+    if !is_valid_share_token(&token) {
+        return Err(not_found());
+    }
+
+    let user_id = WishlistRepository::new(state.db.service())
+        .find_share_owner(&hash_share_token(&token))
+        .await?
+        .ok_or_else(not_found)?;
+
+    let response = build_response(&state, &WishlistOwner::User(user_id), false).await?;
+    Ok(Json(DataResponse::new(response)))
+}
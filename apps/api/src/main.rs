@@ -1,12 +1,12 @@
 use axum::middleware as axum_middleware;
 use std::net::SocketAddr;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod config;
 mod db;
@@ -16,11 +16,16 @@ mod middleware;
 mod models;
 mod routes;
 mod services;
+mod telemetry;
 mod utils;
 
-use config::{AppState, Config};
-use db::SupabaseClient;
-use middleware::{security_headers_middleware, hsts_middleware, init_rate_limiter, rate_limiter_middleware};
+use config::{AppState, Config, DatabaseBackend};
+use db::repositories::{CheckoutStore, PgCheckoutStore, RestCheckoutStore};
+use db::{PostgresClient, SupabaseClient};
+use middleware::{
+    security_headers_middleware, hsts_middleware, http_metrics_middleware, init_rate_limiter, rate_limiter_middleware,
+    request_id_middleware, REQUEST_ID_HEADER,
+};
 use routes::create_router;
 use services::payment::spawn_payment_reconciler;
 
@@ -29,22 +34,36 @@ async fn main() -> anyhow::Result<()> {
     // 環境変数の読み込み
     dotenvy::dotenv().ok();
 
-    // トレーシングの初期化
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "spirom_api=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // トレーシングの初期化（OTEL_EXPORTER_OTLP_ENDPOINT 設定時はOTLPへもエクスポート）
+    let tracer_provider = telemetry::init_tracing();
 
-    // 設定読み込み
-    let config = Config::from_env()?;
-    tracing::info!("Configuration loaded");
+    // サブコマンド: `spirom-api migrate ...`（サーバーは起動しない）
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|s| s.as_str()) == Some("migrate") {
+        return db::migrations::run_cli(&args[1..]).await;
+    }
+
+    // 設定読み込み（`--config <path>` または CONFIG_FILE のTOML + 環境変数）
+    let config_file = args
+        .iter()
+        .position(|a| a == "--config")
+        .and_then(|i| args.get(i + 1))
+        .map(std::path::PathBuf::from);
+    let config = Config::load(config_file.as_deref())?;
+
+    // `--print-config`: 秘密値を伏せた設定を出力して終了
+    if args.iter().any(|a| a == "--print-config") {
+        print!("{}", config.to_redacted_toml()?);
+        return Ok(());
+    }
+
+    let config = Arc::new(config);
+    config::init(config.clone());
+    tracing::info!("Configuration loaded (environment={})", config.environment);
 
     // Supabaseクライアント作成
     tracing::info!("Connecting to Supabase at {}", config.database.url);
-    let supabase = SupabaseClient::new(&config.database.url, &config.database.anon_key)?;
+    let supabase = SupabaseClient::new(&config.database)?;
 
     // ヘルスチェック
     match supabase.health_check().await {
@@ -53,10 +72,31 @@ async fn main() -> anyhow::Result<()> {
         Err(e) => tracing::warn!("Supabase health check failed: {}", e),
     }
 
+    let supabase = Arc::new(supabase);
+
+    // 注文確定のバックエンド（DATABASE_BACKEND=postgres で直接接続・トランザクション）
+    let checkout: Arc<dyn CheckoutStore> = match config.database.backend {
+        DatabaseBackend::Rest => Arc::new(RestCheckoutStore::new(supabase.clone())),
+        DatabaseBackend::Postgres => {
+            let url = config.database.postgres_url.as_ref().map(|u| u.expose()).unwrap_or_default();
+            let pg = PostgresClient::connect(url, config.database.postgres_max_connections).await?;
+            Arc::new(PgCheckoutStore::new(pg))
+        }
+    };
+    tracing::info!("Checkout backend: {}", checkout.backend());
+
     // アプリケーション状態
-    let state = AppState::new(config.clone(), supabase);
+    let state = AppState::new(config.clone(), supabase, checkout);
     // Webhook不達/遅延のリカバリ（バックグラウンド回収）
     spawn_payment_reconciler(state.clone());
+    services::outbox::spawn_outbox_dispatcher(state.clone());
+    services::webhooks::spawn_webhook_dispatcher(state.clone());
+    services::webhooks::spawn_retention_cleanup(state.clone());
+    services::product_alerts::spawn_product_alert_sender(state.clone());
+    services::cart_recovery::spawn_cart_recovery_job(state.clone());
+    services::account_deletion::spawn_account_deletion_job(state.clone());
+    middleware::spawn_idempotency_cleanup(state.clone());
+    services::webauthn::spawn_challenge_cleanup(state.clone());
 
     // CORSの設定（許可リスト方式）
     let allowed_origins: Vec<axum::http::HeaderValue> = config
@@ -81,45 +121,46 @@ async fn main() -> anyhow::Result<()> {
             axum::http::header::ACCEPT,
             axum::http::HeaderName::from_static("x-session-id"),
             axum::http::HeaderName::from_static("x-session-signature"),
+            axum::http::HeaderName::from_static("idempotency-key"),
             axum::http::HeaderName::from_static("x-dev-bypass-token"),
         ]))
         .allow_credentials(true);
 
     // DoS対策設定
-    let request_body_limit = std::env::var("REQUEST_BODY_LIMIT_BYTES")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(1024 * 1024); // デフォルト1MB
-
-    let request_timeout_seconds = std::env::var("REQUEST_TIMEOUT_SECONDS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(30); // デフォルト30秒
-
-    // レート制限の初期化（デフォルト: 60秒間に200リクエスト）
-    let rate_limit_window = std::env::var("RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(60);
-    let rate_limit_max = std::env::var("RATE_LIMIT_MAX_REQUESTS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(200);
-    init_rate_limiter(rate_limit_window, rate_limit_max);
-    tracing::info!("Rate limiter initialized: {} requests per {} seconds", rate_limit_max, rate_limit_window);
+    let request_body_limit = config.server.request_body_limit_bytes;
+    let request_timeout_seconds = config.server.request_timeout_seconds;
+
+    // レート制限の初期化（ルートグループごとのポリシー・バックエンドは設定で指定）
+    init_rate_limiter(&config, state.db.clone());
 
     // ルーターの構築
     // 注意: layerは逆順に適用される（最後に追加したものが最初に実行される）
-    let app = create_router(state)
+    let app = create_router(state.clone())
         .layer(axum_middleware::from_fn(security_headers_middleware))
-        .layer(axum_middleware::from_fn(hsts_middleware))
+        .layer(axum_middleware::from_fn_with_state(state, hsts_middleware))
         .layer(axum_middleware::from_fn(rate_limiter_middleware))
         .layer(cors)
         // リクエストボディサイズ制限（メモリ枯渇対策）
         .layer(RequestBodyLimitLayer::new(request_body_limit))
         // リクエストタイムアウト（SlowLoris対策）
         .layer(TimeoutLayer::new(Duration::from_secs(request_timeout_seconds)))
-        .layer(TraceLayer::new_for_http());
+        // リクエスト数・レイテンシ（レート制限・タイムアウトによる応答も含めて計測）
+        .layer(axum_middleware::from_fn(http_metrics_middleware))
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!(
+                "http_request",
+                method = %request.method(),
+                uri = %request.uri().path(),
+                request_id = %request_id,
+            )
+        }))
+        // リクエストID（最外側: 全ログ・エラーレスポンス・外部呼び出しで同じIDを使う）
+        .layer(axum_middleware::from_fn(request_id_middleware));
 
     // サーバーの起動（HOST/PORT を尊重）
     let host: IpAddr = config.server.host.parse()?;
@@ -133,5 +174,9 @@ async fn main() -> anyhow::Result<()> {
     )
     .await?;
 
+    if let Some(provider) = tracer_provider {
+        let _ = provider.shutdown();
+    }
+
     Ok(())
 }
@@ -26,19 +26,9 @@ pub async fn auth_middleware(
     let user: AuthenticatedUser = claims.clone().try_into()
         .map_err(|e: &str| AppError::Unauthorized(e.to_string()))?;
 
-    // トークンブラックリストをチェック（ログアウト済みトークンを拒否）
-    let blacklist_repo = TokenBlacklistRepository::new(state.db.service());
-
-    // 個別トークンのブラックリストチェック（jtiがある場合）
-    if let Some(ref jti) = claims.jti {
-        if blacklist_repo.is_blacklisted(jti).await.unwrap_or(false) {
-            return Err(AppError::Unauthorized("トークンは無効化されています".to_string()));
-        }
-    }
-
-    // ユーザー全体のブラックリストチェック（パスワード変更時等）
-    if blacklist_repo.is_user_blacklisted(user.id).await.unwrap_or(false) {
-        return Err(AppError::Unauthorized("再ログインが必要です".to_string()));
+    // 失効チェック（ログアウト済みトークン・全トークン無効化・端末ログアウト）
+    if let Some(message) = check_revocation(&state, &claims, &user).await {
+        return Err(AppError::Unauthorized(message.to_string()));
     }
 
     request.extensions_mut().insert(user);
@@ -56,22 +46,8 @@ pub async fn optional_auth_middleware(
     if let Ok(token) = extract_token(&request) {
         if let Ok(claims) = validate_supabase_token(&token, &state.config.jwt) {
             if let Ok(user) = AuthenticatedUser::try_from(claims.clone()) {
-                // ブラックリストチェック（オプショナルなのでエラーは無視して認証なし扱い）
-                let blacklist_repo = TokenBlacklistRepository::new(state.db.service());
-
-                // 非同期でブラックリストチェック（block_on廃止）
-                let is_blacklisted = if let Some(jti) = claims.jti.as_ref() {
-                    blacklist_repo.is_blacklisted(jti).await.unwrap_or(false)
-                } else {
-                    false
-                };
-
-                let is_user_blacklisted = blacklist_repo
-                    .is_user_blacklisted(user.id)
-                    .await
-                    .unwrap_or(false);
-
-                if !is_blacklisted && !is_user_blacklisted {
+                // 失効チェック（オプショナルなので失効時は認証なし扱い）
+                if check_revocation(&state, &claims, &user).await.is_none() {
                     request.extensions_mut().insert(token);
                     request.extensions_mut().insert(user);
                 }
@@ -95,17 +71,9 @@ pub async fn admin_middleware(
     let mut user: AuthenticatedUser = claims.clone().try_into()
         .map_err(|e: &str| AppError::Unauthorized(e.to_string()))?;
 
-    // トークンブラックリストをチェック
-    let blacklist_repo = TokenBlacklistRepository::new(state.db.service());
-
-    if let Some(ref jti) = claims.jti {
-        if blacklist_repo.is_blacklisted(jti).await.unwrap_or(false) {
-            return Err(AppError::Unauthorized("トークンは無効化されています".to_string()));
-        }
-    }
-
-    if blacklist_repo.is_user_blacklisted(user.id).await.unwrap_or(false) {
-        return Err(AppError::Unauthorized("再ログインが必要です".to_string()));
+    // 失効チェック
+    if let Some(message) = check_revocation(&state, &claims, &user).await {
+        return Err(AppError::Unauthorized(message.to_string()));
     }
 
     // データベースから実際のロールを取得（JWTメタデータではなくDBを信頼）
@@ -131,6 +99,26 @@ pub async fn admin_middleware(
     Ok(next.run(request).await)
 }
 
+/// トークンの失効状態を確認し、失効していれば拒否メッセージを返す
+/// DBエラー時は従来どおり通過させる（Supabase障害で全リクエストを止めない）
+async fn check_revocation(
+    state: &AppState,
+    claims: &Claims,
+    user: &AuthenticatedUser,
+) -> Option<&'static str> {
+    let blacklist_repo = TokenBlacklistRepository::new(state.db.service());
+    match blacklist_repo
+        .check_revocation(user.id, claims.jti.as_deref(), user.session_id)
+        .await
+    {
+        Ok(status) => status.rejection_message(),
+        Err(e) => {
+            tracing::warn!("Token revocation check failed: {}", e);
+            None
+        }
+    }
+}
+
 /// リクエストヘッダーからトークンを抽出
 fn extract_token(request: &Request<Body>) -> Result<String, AppError> {
     let auth_header = request
@@ -147,8 +135,8 @@ fn extract_token(request: &Request<Body>) -> Result<String, AppError> {
 }
 
 /// Supabase Auth JWTトークンの検証
-fn validate_supabase_token(token: &str, jwt_config: &JwtConfig) -> Result<Claims, AppError> {
-    let decoding_key = DecodingKey::from_secret(jwt_config.secret.as_bytes());
+pub(crate) fn validate_supabase_token(token: &str, jwt_config: &JwtConfig) -> Result<Claims, AppError> {
+    let decoding_key = DecodingKey::from_secret(jwt_config.secret.expose().as_bytes());
 
     // Supabase AuthはHS256を使用
     let mut validation = Validation::new(Algorithm::HS256);
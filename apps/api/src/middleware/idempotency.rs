@@ -0,0 +1,397 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+use crate::config::AppState;
+use crate::db::repositories::{IdempotencyClaim, IdempotencyRecord, IdempotencyRepository, IdempotencyStore};
+use crate::error::AppError;
+use crate::middleware::rate_limiter::get_client_ip;
+use crate::middleware::session::VerifiedSessionId;
+use crate::models::AuthenticatedUser;
+
+/// 保存したレスポンスの保持期間
+const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+/// 処理中のまま残ったキー（プロセス停止など）を引き継ぐまでの秒数
+const IN_PROGRESS_TIMEOUT_SECONDS: i64 = 120;
+
+/// Idempotency-Key の形式（1〜255文字の表示可能なASCII）
+pub fn is_valid_idempotency_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 255 && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// 同じキーが同じリクエストに使われているかの判定用ハッシュ
+pub fn request_fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// キーの名前空間（ログインユーザー → 署名検証済みセッション → クライアントIP）
+/// 未検証の X-Session-ID は使わない（他人のセッションを名乗って保存済みレスポンスを取得できないように）
+fn idempotency_scope(request: &Request<Body>) -> Option<String> {
+    let extensions = request.extensions();
+    if let Some(user) = extensions.get::<AuthenticatedUser>() {
+        return Some(format!("user:{}", user.id));
+    }
+    if let Some(VerifiedSessionId(session_id)) = extensions.get::<VerifiedSessionId>() {
+        return Some(format!("session:{}", session_id));
+    }
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", get_client_ip(addr, request.headers())))
+}
+
+/// 保存済みレスポンスの再生
+fn replay_response(record: &IdempotencyRecord) -> Response {
+    let status = record
+        .response_status
+        .and_then(|s| StatusCode::from_u16(s as u16).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut response = (status, record.response_body.clone().unwrap_or_default()).into_response();
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+        .headers_mut()
+        .insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+    response
+}
+
+/// Idempotency-Key ミドルウェア（注文作成・決済開始など）
+/// ヘッダーがある場合のみ、キーとレスポンスを24時間保存して再送時は同じレスポンスを返す。
+/// 認証ミドルウェアより内側に置く（ユーザー単位でキーを分けるため）
+pub async fn idempotency_middleware(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, AppError> {
+    let safe_method = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let Some(key) = request
+        .headers()
+        .get("Idempotency-Key")
+        .map(|h| h.to_str().unwrap_or_default().to_string())
+        .filter(|_| !safe_method)
+    else {
+        return Ok(next.run(request).await);
+    };
+    if !is_valid_idempotency_key(&key) {
+        return Err(AppError::BadRequest(
+            "Idempotency-Key は1〜255文字の英数字・記号で指定してください".to_string(),
+        ));
+    }
+
+    let Some(scope) = idempotency_scope(&request) else {
+        // 名前空間を決められない場合は共有せず、キーなしとして処理する
+        tracing::warn!("Idempotency-Key ignored: no user, session or client address");
+        return Ok(next.run(request).await);
+    };
+
+    let repo = IdempotencyRepository::new(state.db.service());
+    let body_limit = state.config.server.request_body_limit_bytes;
+    process_idempotent(&repo, &scope, &key, request, body_limit, |request| next.run(request)).await
+}
+
+/// キーを確保してハンドラーを1回だけ実行し、レスポンスを保存する（既に保存済みなら再生）
+async fn process_idempotent<F, Fut>(
+    repo: &dyn IdempotencyStore,
+    scope: &str,
+    key: &str,
+    request: Request<Body>,
+    body_limit: usize,
+    handler: F,
+) -> Result<Response, AppError>
+where
+    F: FnOnce(Request<Body>) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, body_limit)
+        .await
+        .map_err(|_| AppError::BadRequest("リクエストボディが大きすぎます".to_string()))?;
+    let fingerprint = request_fingerprint(&parts.method, parts.uri.path(), &body);
+
+    let expires_at = Utc::now() + chrono::Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS);
+    let mut claim = repo.claim(scope, key, &fingerprint, expires_at).await?;
+
+    // 期限切れ・処理中のまま放置されたキーは引き継ぐ
+    if let IdempotencyClaim::Existing(record) = &claim {
+        let stale_in_progress = !record.is_completed()
+            && record.created_at < Utc::now() - chrono::Duration::seconds(IN_PROGRESS_TIMEOUT_SECONDS);
+        if record.expires_at < Utc::now() || stale_in_progress {
+            repo.release(record.id).await?;
+            claim = repo.claim(scope, key, &fingerprint, expires_at).await?;
+        }
+    }
+
+    let id = match claim {
+        IdempotencyClaim::Acquired(id) => id,
+        IdempotencyClaim::Existing(record) => {
+            if record.request_hash != fingerprint {
+                return Err(AppError::BadRequest(
+                    "この Idempotency-Key は別の内容のリクエストで使用されています".to_string(),
+                ));
+            }
+            if !record.is_completed() {
+                return Err(AppError::Conflict(
+                    "同じ Idempotency-Key のリクエストを処理中です".to_string(),
+                ));
+            }
+            tracing::info!("Idempotent replay: scope={}, key={}", scope, key);
+            return Ok(replay_response(&record));
+        }
+    };
+
+    let response = handler(Request::from_parts(parts, Body::from(body))).await;
+
+    // サーバーエラーは保存せず、同じキーで再試行できるようにする
+    let status = response.status();
+    if status.is_server_error() {
+        if let Err(e) = repo.release(id).await {
+            tracing::warn!("Failed to release idempotency key: {}", e);
+        }
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            let _ = repo.release(id).await;
+            return Err(AppError::Internal(format!("Failed to read response body: {}", e)));
+        }
+    };
+    if let Err(e) = repo.complete(id, status.as_u16(), &String::from_utf8_lossy(&body)).await {
+        tracing::warn!("Failed to store idempotent response: {}", e);
+    }
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// 期限切れキーの定期削除
+pub fn spawn_idempotency_cleanup(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = IdempotencyRepository::new(state.db.service()).purge_expired().await {
+                tracing::warn!("Failed to purge expired idempotency keys: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use uuid::Uuid;
+
+    use crate::error::Result;
+
+    /// メモリ上のキー保存先（(scope, key) ごとに1件）
+    #[derive(Default)]
+    struct MemoryStore {
+        records: Mutex<HashMap<(String, String), IdempotencyRecord>>,
+    }
+
+    #[async_trait]
+    impl IdempotencyStore for MemoryStore {
+        async fn claim(
+            &self,
+            scope: &str,
+            key: &str,
+            request_hash: &str,
+            expires_at: chrono::DateTime<Utc>,
+        ) -> Result<IdempotencyClaim> {
+            let mut records = self.records.lock().unwrap();
+            if let Some(record) = records.get(&(scope.to_string(), key.to_string())) {
+                return Ok(IdempotencyClaim::Existing(record.clone()));
+            }
+            let record = IdempotencyRecord {
+                id: Uuid::new_v4(),
+                request_hash: request_hash.to_string(),
+                status: "in_progress".to_string(),
+                response_status: None,
+                response_body: None,
+                created_at: Utc::now(),
+                expires_at,
+            };
+            let id = record.id;
+            records.insert((scope.to_string(), key.to_string()), record);
+            Ok(IdempotencyClaim::Acquired(id))
+        }
+
+        async fn complete(&self, id: Uuid, response_status: u16, response_body: &str) -> Result<()> {
+            let mut records = self.records.lock().unwrap();
+            if let Some(record) = records.values_mut().find(|r| r.id == id) {
+                record.status = "completed".to_string();
+                record.response_status = Some(response_status as i32);
+                record.response_body = Some(response_body.to_string());
+            }
+            Ok(())
+        }
+
+        async fn release(&self, id: Uuid) -> Result<()> {
+            self.records.lock().unwrap().retain(|_, r| r.id != id);
+            Ok(())
+        }
+    }
+
+    fn order_request(body: &str) -> Request<Body> {
+        Request::post("/api/v1/orders").body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// 同じ Idempotency-Key でリクエストを送る
+    async fn send<F, Fut>(store: &MemoryStore, scope: &str, body: &str, handler: F) -> Result<Response>
+    where
+        F: FnOnce(Request<Body>) -> Fut,
+        Fut: Future<Output = Response>,
+    {
+        process_idempotent(store, scope, "key-1", order_request(body), 1024, handler).await
+    }
+
+    async fn body_text(response: Response) -> String {
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    /// 呼び出し回数を数えるハンドラー
+    fn counting_handler(
+        calls: &AtomicUsize,
+        status: StatusCode,
+    ) -> impl FnOnce(Request<Body>) -> std::future::Ready<Response> + '_ {
+        move |_| {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            std::future::ready((status, format!(r#"{{"call":{}}}"#, n)).into_response())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replays_completed_response() {
+        let store = MemoryStore::default();
+        let calls = AtomicUsize::new(0);
+
+        let first = send(&store, "user:1", "{}", counting_handler(&calls, StatusCode::CREATED))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(body_text(first).await, r#"{"call":1}"#);
+
+        let replay = send(&store, "user:1", "{}", counting_handler(&calls, StatusCode::CREATED))
+            .await
+            .unwrap();
+        assert_eq!(replay.status(), StatusCode::CREATED);
+        assert_eq!(replay.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert_eq!(body_text(replay).await, r#"{"call":1}"#);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // 名前空間が違えば別のキー
+        send(&store, "ip:203.0.113.7", "{}", counting_handler(&calls, StatusCode::CREATED))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_key_reused_with_different_body() {
+        let store = MemoryStore::default();
+        let calls = AtomicUsize::new(0);
+
+        send(&store, "user:1", r#"{"a":1}"#, counting_handler(&calls, StatusCode::CREATED))
+            .await
+            .unwrap();
+        let result = send(&store, "user:1", r#"{"a":2}"#, counting_handler(&calls, StatusCode::CREATED)).await;
+
+        let status = result.unwrap_err().into_response().status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_concurrent_request_in_flight() {
+        let store = MemoryStore::default();
+        let (release_first, first_may_finish) = tokio::sync::oneshot::channel::<()>();
+
+        let first = send(&store, "user:1", "{}", |_| async move {
+            first_may_finish.await.unwrap();
+            StatusCode::CREATED.into_response()
+        });
+        let second = async {
+            let result = send(&store, "user:1", "{}", |_| async {
+                panic!("handler must not run while the first request is in flight")
+            })
+            .await;
+            release_first.send(()).unwrap();
+            result
+        };
+        let (first, second) = tokio::join!(first, second);
+
+        assert_eq!(first.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(second.unwrap_err().into_response().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_releases_key_on_server_error() {
+        let store = MemoryStore::default();
+        let calls = AtomicUsize::new(0);
+
+        let failed = send(&store, "user:1", "{}", counting_handler(&calls, StatusCode::BAD_GATEWAY))
+            .await
+            .unwrap();
+        assert_eq!(failed.status(), StatusCode::BAD_GATEWAY);
+        assert!(store.records.lock().unwrap().is_empty());
+
+        let retried = send(&store, "user:1", "{}", counting_handler(&calls, StatusCode::CREATED))
+            .await
+            .unwrap();
+        assert_eq!(retried.status(), StatusCode::CREATED);
+        assert!(retried.headers().get("Idempotent-Replayed").is_none());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_idempotency_scope_ignores_unverified_session() {
+        let addr: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+
+        let mut request = order_request("{}");
+        request.headers_mut().insert("X-Session-ID", HeaderValue::from_static("victim-session"));
+        request.extensions_mut().insert(ConnectInfo(addr));
+        assert_eq!(idempotency_scope(&request).as_deref(), Some("ip:203.0.113.7"));
+
+        request.extensions_mut().insert(VerifiedSessionId("own-session".to_string()));
+        assert_eq!(idempotency_scope(&request).as_deref(), Some("session:own-session"));
+
+        assert_eq!(idempotency_scope(&order_request("{}")), None);
+    }
+
+    #[test]
+    fn test_idempotency_key_and_fingerprint() {
+        assert!(is_valid_idempotency_key("3f1c2a9e-8d2b-4d8f-9a3e-1b2c3d4e5f60"));
+        assert!(!is_valid_idempotency_key(""));
+        assert!(!is_valid_idempotency_key("has space"));
+        assert!(!is_valid_idempotency_key(&"a".repeat(256)));
+
+        let a = request_fingerprint(&Method::POST, "/api/v1/orders", br#"{"a":1}"#);
+        assert_eq!(a, request_fingerprint(&Method::POST, "/api/v1/orders", br#"{"a":1}"#));
+        assert_ne!(a, request_fingerprint(&Method::POST, "/api/v1/orders", br#"{"a":2}"#));
+        assert_ne!(a, request_fingerprint(&Method::POST, "/api/v1/orders/guest", br#"{"a":1}"#));
+    }
+}
@@ -0,0 +1,27 @@
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+
+use crate::services::metrics::record_http_request;
+
+/// HTTPリクエスト数・レイテンシの計測ミドルウェア
+/// ルートはパステンプレート（例: /api/v1/orders/:id）で集計し、ラベルの種類が増えないようにする
+pub async fn http_metrics_middleware(request: Request<Body>, next: Next) -> Response<Body> {
+    let method = request.method().as_str().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    record_http_request(&method, &route, response.status().as_u16(), started.elapsed());
+
+    response
+}
@@ -1,9 +1,15 @@
 pub mod auth;
+pub mod idempotency;
+pub mod metrics;
 pub mod rate_limiter;
+pub mod request_id;
 pub mod security_headers;
 pub mod session;
 
 pub use auth::*;
+pub use idempotency::*;
+pub use metrics::*;
 pub use rate_limiter::*;
+pub use request_id::*;
 pub use security_headers::*;
 pub use session::*;
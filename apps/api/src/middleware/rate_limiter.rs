@@ -1,174 +1,410 @@
+use async_trait::async_trait;
 use axum::{
     body::Body,
     extract::ConnectInfo,
     http::{Request, Response, StatusCode},
     middleware::Next,
 };
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
-/// レート制限エントリ
+use crate::config::{Config, RateLimitConfig, RateLimitGroupConfig};
+use crate::db::{Query, SupabaseClient};
+use crate::error::{AppError, Result};
+
+/// レート制限の判定結果
 #[derive(Debug, Clone)]
-struct RateLimitEntry {
-    count: u32,
-    window_start: Instant,
+pub struct RateLimitResult {
+    pub allowed: bool,
+    pub remaining: u32,
+    pub limit: u32,
+    /// 拒否時、次に許可されるまでの時間
+    pub retry_after: Duration,
 }
 
-/// インメモリレート制限ストア
-/// 注意: 分散環境では各インスタンスが独立したカウンターを持つため、
-/// 本番環境では Redis 等の分散キャッシュを使用することを推奨
+/// ルートグループごとのレート制限ポリシー
+/// window 内に max_requests 回まで（GCRAにより均等に回復する）
 #[derive(Debug, Clone)]
-pub struct RateLimiterStore {
-    entries: Arc<RwLock<HashMap<String, RateLimitEntry>>>,
-    window_size: Duration,
-    max_requests: u32,
+pub struct RateLimitPolicy {
+    pub window: Duration,
+    pub max_requests: u32,
 }
 
-impl RateLimiterStore {
+impl RateLimitPolicy {
     pub fn new(window_seconds: u64, max_requests: u32) -> Self {
+        Self {
+            window: Duration::from_secs(window_seconds.max(1)),
+            max_requests: max_requests.max(1),
+        }
+    }
+
+    /// 設定（rate_limit.<group>）から読み込み、未指定の項目は既定値
+    fn from_config(config: &RateLimitGroupConfig, default_window: u64, default_max: u32) -> Self {
+        Self::new(
+            config.window_seconds.unwrap_or(default_window),
+            config.max_requests.unwrap_or(default_max),
+        )
+    }
+
+    fn window_ms(&self) -> i64 {
+        self.window.as_millis() as i64
+    }
+
+    /// 1リクエストあたりの回復間隔（ミリ秒）
+    fn emission_interval_ms(&self) -> i64 {
+        (self.window_ms() / self.max_requests as i64).max(1)
+    }
+}
+
+/// GCRA（Generic Cell Rate Algorithm）
+/// tat（理論到着時刻）のみを保持し、固定ウィンドウ境界でのバーストを防ぐ
+/// 戻り値: (判定結果, 更新後のtat。拒否時はNone)
+fn gcra(policy: &RateLimitPolicy, now_ms: i64, tat_ms: Option<i64>) -> (RateLimitResult, Option<i64>) {
+    let interval = policy.emission_interval_ms();
+    let window = policy.window_ms();
+    let tat = tat_ms.unwrap_or(now_ms).max(now_ms);
+    let new_tat = tat + interval;
+
+    if new_tat - now_ms > window {
+        let retry_after_ms = (new_tat - window - now_ms).max(0) as u64;
+        return (
+            RateLimitResult {
+                allowed: false,
+                remaining: 0,
+                limit: policy.max_requests,
+                retry_after: Duration::from_millis(retry_after_ms),
+            },
+            None,
+        );
+    }
+
+    let remaining = ((window - (new_tat - now_ms)) / interval) as u32;
+    (
+        RateLimitResult {
+            allowed: true,
+            remaining: remaining.min(policy.max_requests),
+            limit: policy.max_requests,
+            retry_after: Duration::ZERO,
+        },
+        Some(new_tat),
+    )
+}
+
+/// レート制限ストア
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// リクエストを1件消費して判定
+    async fn check(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitResult>;
+
+    /// 期限切れエントリの削除
+    async fn cleanup(&self) -> Result<()>;
+
+    /// ストア名（ログ用）
+    fn name(&self) -> &'static str;
+}
+
+/// インメモリレート制限ストア
+/// 注意: 分散環境では各インスタンスが独立したカウンターを持つ（上限がインスタンス数倍になる）
+#[derive(Debug, Clone)]
+pub struct MemoryRateLimitStore {
+    /// キー → tat（epochからのミリ秒）
+    entries: Arc<RwLock<HashMap<String, i64>>>,
+    epoch: Instant,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
         Self {
             entries: Arc::new(RwLock::new(HashMap::new())),
-            window_size: Duration::from_secs(window_seconds),
-            max_requests,
+            epoch: Instant::now(),
         }
     }
 
-    /// クリーンアップ（古いエントリを削除）
-    pub async fn cleanup(&self) {
-        let now = Instant::now();
+    fn now_ms(&self) -> i64 {
+        self.epoch.elapsed().as_millis() as i64
+    }
+}
+
+impl Default for MemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn check(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitResult> {
+        let now = self.now_ms();
         let mut entries = self.entries.write().await;
-        entries.retain(|_, entry| now.duration_since(entry.window_start) < self.window_size * 2);
+
+        let (result, new_tat) = gcra(policy, now, entries.get(key).copied());
+        if let Some(tat) = new_tat {
+            entries.insert(key.to_string(), tat);
+        }
+
+        Ok(result)
     }
 
-    /// レート制限チェック
-    pub async fn check(&self, key: &str) -> RateLimitResult {
-        let now = Instant::now();
+    async fn cleanup(&self) -> Result<()> {
+        // tatを過ぎたエントリは初期状態と同じなので削除してよい
+        let now = self.now_ms();
         let mut entries = self.entries.write().await;
+        entries.retain(|_, tat| *tat > now);
+        Ok(())
+    }
 
-        let entry = entries.entry(key.to_string()).or_insert(RateLimitEntry {
-            count: 0,
-            window_start: now,
-        });
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+}
 
-        // ウィンドウが古い場合はリセット
-        if now.duration_since(entry.window_start) >= self.window_size {
-            entry.count = 0;
-            entry.window_start = now;
+/// Postgresレート制限ストア（Supabase RPC経由）
+/// 全インスタンスでカウンターを共有する。DBエラー時はインメモリにフォールバック
+pub struct PostgresRateLimitStore {
+    db: Arc<SupabaseClient>,
+    fallback: MemoryRateLimitStore,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(db: Arc<SupabaseClient>) -> Self {
+        Self {
+            db,
+            fallback: MemoryRateLimitStore::new(),
         }
+    }
+}
 
-        entry.count += 1;
-        let allowed = entry.count <= self.max_requests;
-        let remaining = if allowed {
-            self.max_requests - entry.count
-        } else {
-            0
+#[derive(Debug, Serialize)]
+struct RateLimitCheckParams<'a> {
+    p_key: &'a str,
+    p_window_ms: i64,
+    p_max_requests: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitCheckRow {
+    allowed: bool,
+    remaining: i32,
+    retry_after_ms: i64,
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    async fn check(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitResult> {
+        let params = RateLimitCheckParams {
+            p_key: key,
+            p_window_ms: policy.window_ms(),
+            p_max_requests: policy.max_requests,
         };
 
-        let reset_at = entry.window_start + self.window_size;
+        let rows: Result<Vec<RateLimitCheckRow>> =
+            self.db.service().rpc("rate_limit_check", &params).await;
+
+        match rows.and_then(|rows| {
+            rows.into_iter()
+                .next()
+                .ok_or_else(|| AppError::Database("rate_limit_check returned no rows".to_string()))
+        }) {
+            Ok(row) => Ok(RateLimitResult {
+                allowed: row.allowed,
+                remaining: row.remaining.max(0) as u32,
+                limit: policy.max_requests,
+                retry_after: Duration::from_millis(row.retry_after_ms.max(0) as u64),
+            }),
+            Err(e) => {
+                tracing::warn!("Rate limit store unavailable, using local fallback: {}", e);
+                self.fallback.check(key, policy).await
+            }
+        }
+    }
 
-        RateLimitResult {
-            allowed,
-            remaining,
-            reset_at,
-            limit: self.max_requests,
+    async fn cleanup(&self) -> Result<()> {
+        self.fallback.cleanup().await?;
+        let now = Utc::now() - ChronoDuration::seconds(1);
+        let query = Query::new().lt("tat", now.to_rfc3339());
+        self.db.service().delete("rate_limit_buckets", &query).await
+    }
+
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+}
+
+/// ルートグループ
+#[derive(Debug, Clone, Copy)]
+enum RateLimitGroup {
+    /// 全API共通
+    Global,
+    /// 決済（カードテスティング対策）
+    Payment,
+    /// お問い合わせフォーム（スパム対策）
+    Contact,
+    /// ゲスト注文（DoS/在庫枯渇攻撃対策）
+    GuestOrder,
+    /// パスキーログイン（未認証でのチャレンジ大量発行対策）
+    PasskeyLogin,
+}
+
+impl RateLimitGroup {
+    fn config(self, config: &RateLimitConfig) -> &RateLimitGroupConfig {
+        match self {
+            Self::Global => &config.global,
+            Self::Payment => &config.payment,
+            Self::Contact => &config.contact,
+            Self::GuestOrder => &config.guest_order,
+            Self::PasskeyLogin => &config.passkey_login,
+        }
+    }
+
+    /// ストアのキープレフィックス（グループ間でカウンターを分離）
+    fn key_prefix(self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Payment => "payment",
+            Self::Contact => "contact",
+            Self::GuestOrder => "guest_order",
+            Self::PasskeyLogin => "passkey_login",
+        }
+    }
+
+    fn error_code(self) -> &'static str {
+        match self {
+            Self::Global => "RATE_LIMITED",
+            Self::Payment => "PAYMENT_RATE_LIMITED",
+            Self::Contact => "CONTACT_RATE_LIMITED",
+            Self::GuestOrder => "GUEST_ORDER_RATE_LIMITED",
+            Self::PasskeyLogin => "PASSKEY_LOGIN_RATE_LIMITED",
+        }
+    }
+
+    fn error_message(self) -> &'static str {
+        match self {
+            Self::Global => "リクエストが多すぎます。しばらくしてから再試行してください。",
+            Self::Payment => "決済リクエストが多すぎます。しばらくしてから再試行してください。",
+            Self::Contact => "お問い合わせの送信回数が上限に達しました。しばらくしてから再度お試しください。",
+            Self::GuestOrder => "注文リクエストが多すぎます。しばらくしてから再試行してください。",
+            Self::PasskeyLogin => "ログインの試行回数が多すぎます。しばらくしてから再試行してください。",
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct RateLimitResult {
-    pub allowed: bool,
-    pub remaining: u32,
-    pub reset_at: Instant,
-    pub limit: u32,
+/// グループごとのポリシーとストア
+struct GroupLimiter {
+    policy: RateLimitPolicy,
+    store: Arc<dyn RateLimitStore>,
+}
+
+struct RateLimiters {
+    global: GroupLimiter,
+    payment: GroupLimiter,
+    contact: GroupLimiter,
+    guest_order: GroupLimiter,
+    passkey_login: GroupLimiter,
+    /// X-Forwarded-For を信頼するプロキシ
+    trusted_proxies: Vec<String>,
 }
 
-/// グローバルレート制限ストア（API起動時に初期化）
-static RATE_LIMITER: std::sync::OnceLock<RateLimiterStore> = std::sync::OnceLock::new();
-
-/// 決済エンドポイント専用レート制限ストア（より厳しい制限）
-/// カードテスティング攻撃対策: 1IPあたり1分間に5回まで
-static PAYMENT_RATE_LIMITER: std::sync::OnceLock<RateLimiterStore> = std::sync::OnceLock::new();
-
-/// お問い合わせフォーム専用レート制限ストア
-/// スパム対策: 1IPあたり1時間に5回まで
-static CONTACT_RATE_LIMITER: std::sync::OnceLock<RateLimiterStore> = std::sync::OnceLock::new();
-
-/// ゲスト注文専用レート制限ストア
-/// DoS/在庫枯渇攻撃対策: 1IPあたり60秒間に3回まで
-static GUEST_ORDER_RATE_LIMITER: std::sync::OnceLock<RateLimiterStore> = std::sync::OnceLock::new();
-
-/// レート制限ストアを初期化
-pub fn init_rate_limiter(window_seconds: u64, max_requests: u32) {
-    let _ = RATE_LIMITER.set(RateLimiterStore::new(window_seconds, max_requests));
-
-    // 決済エンドポイント専用: 60秒間に5リクエストまで（カードテスティング対策）
-    let payment_window = std::env::var("PAYMENT_RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(60);
-    let payment_max = std::env::var("PAYMENT_RATE_LIMIT_MAX_REQUESTS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(5);
-    let _ = PAYMENT_RATE_LIMITER.set(RateLimiterStore::new(payment_window, payment_max));
-
-    // お問い合わせフォーム専用: 1時間に5リクエストまで（スパム対策）
-    let contact_window = std::env::var("CONTACT_RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(3600); // 1時間
-    let contact_max = std::env::var("CONTACT_RATE_LIMIT_MAX_REQUESTS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(5);
-    let _ = CONTACT_RATE_LIMITER.set(RateLimiterStore::new(contact_window, contact_max));
-
-    // ゲスト注文専用: 60秒間に3リクエストまで（DoS/在庫枯渇攻撃対策）
-    let guest_order_window = std::env::var("GUEST_ORDER_RATE_LIMIT_WINDOW_SECONDS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(60);
-    let guest_order_max = std::env::var("GUEST_ORDER_RATE_LIMIT_MAX_REQUESTS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(3);
-    let _ = GUEST_ORDER_RATE_LIMITER.set(RateLimiterStore::new(guest_order_window, guest_order_max));
+impl RateLimiters {
+    fn get(&self, group: RateLimitGroup) -> &GroupLimiter {
+        match group {
+            RateLimitGroup::Global => &self.global,
+            RateLimitGroup::Payment => &self.payment,
+            RateLimitGroup::Contact => &self.contact,
+            RateLimitGroup::GuestOrder => &self.guest_order,
+            RateLimitGroup::PasskeyLogin => &self.passkey_login,
+        }
+    }
+}
+
+/// レート制限ストア（API起動時に初期化）
+static RATE_LIMITERS: std::sync::OnceLock<RateLimiters> = std::sync::OnceLock::new();
+
+/// レート制限を初期化
+/// バックエンドは rate_limit.backend（memory | postgres）で選択し、
+/// rate_limit.<group>.backend でグループ単位に上書きできる
+pub fn init_rate_limiter(app_config: &Config, db: Arc<SupabaseClient>) {
+    let config = &app_config.rate_limit;
+    let memory: Arc<dyn RateLimitStore> = Arc::new(MemoryRateLimitStore::new());
+    let postgres: Arc<dyn RateLimitStore> = Arc::new(PostgresRateLimitStore::new(db));
+
+    let build = |group: RateLimitGroup, default_window: u64, default_max: u32| {
+        let group_config = group.config(config);
+        let policy = RateLimitPolicy::from_config(group_config, default_window, default_max);
+        let backend = group_config.backend.as_deref().unwrap_or(&config.backend);
+        let store = match backend {
+            "postgres" => postgres.clone(),
+            "memory" => memory.clone(),
+            other => {
+                tracing::warn!("Unknown rate limit backend '{}', using memory", other);
+                memory.clone()
+            }
+        };
+        tracing::info!(
+            "Rate limit [{}]: {} requests per {} seconds (backend={})",
+            group.key_prefix(),
+            policy.max_requests,
+            policy.window.as_secs(),
+            store.name()
+        );
+        GroupLimiter { policy, store }
+    };
+
+    let limiters = RateLimiters {
+        // 全API共通: 60秒間に200リクエストまで
+        global: build(RateLimitGroup::Global, 60, 200),
+        // 決済: 60秒間に5リクエストまで（カードテスティング対策）
+        payment: build(RateLimitGroup::Payment, 60, 5),
+        // お問い合わせ: 1時間に5リクエストまで（スパム対策）
+        contact: build(RateLimitGroup::Contact, 3600, 5),
+        // ゲスト注文: 60秒間に3リクエストまで（DoS/在庫枯渇攻撃対策）
+        guest_order: build(RateLimitGroup::GuestOrder, 60, 3),
+        // パスキーログイン: 60秒間に10リクエストまで（チャレンジの大量発行対策）
+        passkey_login: build(RateLimitGroup::PasskeyLogin, 60, 10),
+        trusted_proxies: get_trusted_proxies(app_config),
+    };
+    // DBのバケット削除は postgres バックエンドを使うグループがある場合のみ行う
+    let uses_postgres = [
+        &limiters.global,
+        &limiters.payment,
+        &limiters.contact,
+        &limiters.guest_order,
+        &limiters.passkey_login,
+    ]
+    .iter()
+    .any(|limiter| Arc::ptr_eq(&limiter.store, &postgres));
+    let _ = RATE_LIMITERS.set(limiters);
+
+    let stores = if uses_postgres { vec![memory, postgres] } else { vec![memory] };
 
     // 定期的なクリーンアップタスクを起動
-    let store = RATE_LIMITER.get().unwrap().clone();
-    let payment_store = PAYMENT_RATE_LIMITER.get().unwrap().clone();
-    let contact_store = CONTACT_RATE_LIMITER.get().unwrap().clone();
-    let guest_order_store = GUEST_ORDER_RATE_LIMITER.get().unwrap().clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(60));
         loop {
             interval.tick().await;
-            store.cleanup().await;
-            payment_store.cleanup().await;
-            contact_store.cleanup().await;
-            guest_order_store.cleanup().await;
+            for store in &stores {
+                if let Err(e) = store.cleanup().await {
+                    tracing::warn!("Rate limit cleanup failed ({}): {}", store.name(), e);
+                }
+            }
         }
     });
 }
 
 /// 信頼できるプロキシIPリストを取得
 /// セキュリティ: 本番環境では必ず TRUSTED_PROXY_IPS を明示的に設定すること
-fn get_trusted_proxies() -> Vec<String> {
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-
-    // 環境変数で明示的に設定されている場合はそれを使用
-    if let Ok(proxies) = std::env::var("TRUSTED_PROXY_IPS") {
-        if !proxies.is_empty() {
-            return proxies.split(',').map(|ip| ip.trim().to_string()).collect();
-        }
+fn get_trusted_proxies(config: &Config) -> Vec<String> {
+    // 明示的に設定されている場合はそれを使用
+    if !config.rate_limit.trusted_proxy_ips.is_empty() {
+        return config.rate_limit.trusted_proxy_ips.clone();
     }
 
     // 本番環境でTRUSTED_PROXY_IPSが未設定の場合は警告を出力
-    if env == "production" {
+    if config.is_production() {
         tracing::warn!(
             "TRUSTED_PROXY_IPS is not set in production. Using restricted defaults. \
              Consider setting this to your load balancer/CDN IPs for better security."
@@ -196,13 +432,17 @@ fn is_valid_ip(ip: &str) -> bool {
     ip.parse::<IpAddr>().is_ok()
 }
 
-/// クライアントIPを安全に取得
+/// クライアントIPを安全に取得（ログイン保護等、IP単位で制限するハンドラーもこれを使う）
 /// - 直接接続の場合: ソケットアドレスを使用
 /// - 信頼できるプロキシ経由の場合のみ: X-Forwarded-Forを使用
 /// セキュリティ: 不正なIPアドレス形式は拒否し、直接IPにフォールバック
-fn get_client_ip(addr: &SocketAddr, headers: &axum::http::HeaderMap) -> String {
+pub(crate) fn get_client_ip(addr: &SocketAddr, headers: &axum::http::HeaderMap) -> String {
     let direct_ip = addr.ip().to_string();
-    let trusted_proxies = get_trusted_proxies();
+    // 未初期化の場合はどのプロキシも信頼しない
+    let trusted_proxies = RATE_LIMITERS
+        .get()
+        .map(|l| l.trusted_proxies.as_slice())
+        .unwrap_or_default();
 
     // 直接接続が信頼できるプロキシからでない場合は、直接IPを使用
     let is_from_trusted_proxy = trusted_proxies.iter().any(|trusted| {
@@ -263,36 +503,58 @@ fn get_client_ip(addr: &SocketAddr, headers: &axum::http::HeaderMap) -> String {
     direct_ip
 }
 
-/// レート制限ミドルウェア
-pub async fn rate_limiter_middleware(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+/// グループのレート制限を適用
+async fn enforce_rate_limit(
+    group: RateLimitGroup,
+    addr: SocketAddr,
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
-    let Some(store) = RATE_LIMITER.get() else {
+    let Some(limiter) = RATE_LIMITERS.get().map(|l| l.get(group)) else {
         // レート制限が初期化されていない場合はスキップ
         return next.run(request).await;
     };
 
     // クライアントIP取得（信頼できるプロキシ経由のみX-Forwarded-Forを使用）
     let client_ip = get_client_ip(&addr, request.headers());
-
-    let result = store.check(&client_ip).await;
+    let key = format!("{}:{}", group.key_prefix(), client_ip);
+
+    let result = match limiter.store.check(&key, &limiter.policy).await {
+        Ok(result) => result,
+        Err(e) => {
+            // ストア障害でAPI全体を止めない
+            tracing::error!("Rate limit check failed: {}", e);
+            return next.run(request).await;
+        }
+    };
 
     if !result.allowed {
+        crate::services::metrics::record_rate_limit_rejection(group.key_prefix());
+        if !matches!(group, RateLimitGroup::Global) {
+            tracing::warn!(
+                "{} rate limit exceeded: ip={}, limit={}, window={}s",
+                group.key_prefix(),
+                client_ip,
+                result.limit,
+                limiter.policy.window.as_secs()
+            );
+        }
+
         let body = serde_json::json!({
             "error": {
-                "code": "RATE_LIMITED",
-                "message": "リクエストが多すぎます。しばらくしてから再試行してください。"
+                "code": group.error_code(),
+                "message": group.error_message()
             }
         });
+        // 秒単位に切り上げ
+        let retry_after = result.retry_after.as_millis().div_ceil(1000).max(1);
 
         return Response::builder()
             .status(StatusCode::TOO_MANY_REQUESTS)
             .header("Content-Type", "application/json")
             .header("X-RateLimit-Limit", result.limit.to_string())
             .header("X-RateLimit-Remaining", "0")
-            .header("Retry-After", "60")
+            .header("Retry-After", retry_after.to_string())
             .body(Body::from(serde_json::to_string(&body).unwrap()))
             .unwrap();
     }
@@ -313,6 +575,15 @@ pub async fn rate_limiter_middleware(
     response
 }
 
+/// レート制限ミドルウェア
+pub async fn rate_limiter_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    enforce_rate_limit(RateLimitGroup::Global, addr, request, next).await
+}
+
 /// 決済エンドポイント専用レート制限ミドルウェア
 /// カードテスティング攻撃対策として、より厳しい制限を適用
 pub async fn payment_rate_limiter_middleware(
@@ -320,172 +591,64 @@ pub async fn payment_rate_limiter_middleware(
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
-    let Some(store) = PAYMENT_RATE_LIMITER.get() else {
-        // レート制限が初期化されていない場合はスキップ
-        return next.run(request).await;
-    };
-
-    // クライアントIP取得（信頼できるプロキシ経由のみX-Forwarded-Forを使用）
-    let client_ip = get_client_ip(&addr, request.headers());
-
-    // 決済エンドポイント専用のキー（通常のレート制限と分離）
-    let key = format!("payment:{}", client_ip);
-    let result = store.check(&key).await;
-
-    if !result.allowed {
-        tracing::warn!(
-            "Payment rate limit exceeded: ip={}, limit={}, window=60s",
-            client_ip,
-            result.limit
-        );
-
-        let body = serde_json::json!({
-            "error": {
-                "code": "PAYMENT_RATE_LIMITED",
-                "message": "決済リクエストが多すぎます。しばらくしてから再試行してください。"
-            }
-        });
-
-        return Response::builder()
-            .status(StatusCode::TOO_MANY_REQUESTS)
-            .header("Content-Type", "application/json")
-            .header("X-RateLimit-Limit", result.limit.to_string())
-            .header("X-RateLimit-Remaining", "0")
-            .header("Retry-After", "60")
-            .body(Body::from(serde_json::to_string(&body).unwrap()))
-            .unwrap();
-    }
-
-    let mut response = next.run(request).await;
-
-    // レート制限ヘッダーを追加
-    let headers = response.headers_mut();
-    headers.insert(
-        "X-RateLimit-Limit",
-        result.limit.to_string().parse().unwrap(),
-    );
-    headers.insert(
-        "X-RateLimit-Remaining",
-        result.remaining.to_string().parse().unwrap(),
-    );
-
-    response
+    enforce_rate_limit(RateLimitGroup::Payment, addr, request, next).await
 }
 
 /// ゲスト注文専用レート制限ミドルウェア
-/// DoS/在庫枯渇攻撃対策として、60秒に3回までに制限
+/// DoS/在庫枯渇攻撃対策
 pub async fn guest_order_rate_limiter_middleware(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
-    let Some(store) = GUEST_ORDER_RATE_LIMITER.get() else {
-        // レート制限が初期化されていない場合はスキップ
-        return next.run(request).await;
-    };
-
-    // クライアントIP取得
-    let client_ip = get_client_ip(&addr, request.headers());
-
-    // ゲスト注文専用のキー
-    let key = format!("guest_order:{}", client_ip);
-    let result = store.check(&key).await;
-
-    if !result.allowed {
-        tracing::warn!(
-            "Guest order rate limit exceeded: ip={}, limit={}, window=60s",
-            client_ip,
-            result.limit
-        );
-
-        let body = serde_json::json!({
-            "error": {
-                "code": "GUEST_ORDER_RATE_LIMITED",
-                "message": "注文リクエストが多すぎます。しばらくしてから再試行してください。"
-            }
-        });
-
-        return Response::builder()
-            .status(StatusCode::TOO_MANY_REQUESTS)
-            .header("Content-Type", "application/json")
-            .header("X-RateLimit-Limit", result.limit.to_string())
-            .header("X-RateLimit-Remaining", "0")
-            .header("Retry-After", "60")
-            .body(Body::from(serde_json::to_string(&body).unwrap()))
-            .unwrap();
-    }
-
-    let mut response = next.run(request).await;
-
-    // レート制限ヘッダーを追加
-    let headers = response.headers_mut();
-    headers.insert(
-        "X-RateLimit-Limit",
-        result.limit.to_string().parse().unwrap(),
-    );
-    headers.insert(
-        "X-RateLimit-Remaining",
-        result.remaining.to_string().parse().unwrap(),
-    );
-
-    response
+    enforce_rate_limit(RateLimitGroup::GuestOrder, addr, request, next).await
 }
 
 /// お問い合わせフォーム専用レート制限ミドルウェア
-/// スパム対策として、1時間に5回までに制限
+/// スパム対策
 pub async fn contact_rate_limiter_middleware(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
-    let Some(store) = CONTACT_RATE_LIMITER.get() else {
-        // レート制限が初期化されていない場合はスキップ
-        return next.run(request).await;
-    };
-
-    // クライアントIP取得
-    let client_ip = get_client_ip(&addr, request.headers());
+    enforce_rate_limit(RateLimitGroup::Contact, addr, request, next).await
+}
 
-    // お問い合わせ専用のキー
-    let key = format!("contact:{}", client_ip);
-    let result = store.check(&key).await;
+/// パスキーログイン専用レート制限ミドルウェア
+/// 未認証で発行できるチャレンジの大量作成・検証の総当たり対策
+pub async fn passkey_login_rate_limiter_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    enforce_rate_limit(RateLimitGroup::PasskeyLogin, addr, request, next).await
+}
 
-    if !result.allowed {
-        tracing::warn!(
-            "Contact rate limit exceeded: ip={}, limit={}, window=3600s",
-            client_ip,
-            result.limit
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gcra() {
+        // 60秒間に5回 → 12秒ごとに1回回復
+        let policy = RateLimitPolicy::new(60, 5);
+        let mut tat = None;
+        for expected_remaining in (0..5).rev() {
+            let (result, new_tat) = gcra(&policy, 0, tat);
+            assert!(result.allowed);
+            assert_eq!(result.remaining, expected_remaining);
+            tat = new_tat;
+        }
 
-        let body = serde_json::json!({
-            "error": {
-                "code": "CONTACT_RATE_LIMITED",
-                "message": "お問い合わせの送信回数が上限に達しました。しばらくしてから再度お試しください。"
-            }
-        });
+        // 6回目は拒否（12秒後に回復）
+        let (result, new_tat) = gcra(&policy, 0, tat);
+        assert!(!result.allowed);
+        assert_eq!(result.retry_after, Duration::from_secs(12));
+        assert!(new_tat.is_none());
 
-        return Response::builder()
-            .status(StatusCode::TOO_MANY_REQUESTS)
-            .header("Content-Type", "application/json")
-            .header("X-RateLimit-Limit", result.limit.to_string())
-            .header("X-RateLimit-Remaining", "0")
-            .header("Retry-After", "3600")
-            .body(Body::from(serde_json::to_string(&body).unwrap()))
-            .unwrap();
+        // 12秒後は1回だけ許可
+        let (result, _) = gcra(&policy, 12_000, tat);
+        assert!(result.allowed);
+        assert_eq!(result.remaining, 0);
     }
-
-    let mut response = next.run(request).await;
-
-    // レート制限ヘッダーを追加
-    let headers = response.headers_mut();
-    headers.insert(
-        "X-RateLimit-Limit",
-        result.limit.to_string().parse().unwrap(),
-    );
-    headers.insert(
-        "X-RateLimit-Remaining",
-        result.remaining.to_string().parse().unwrap(),
-    );
-
-    response
 }
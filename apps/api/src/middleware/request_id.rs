@@ -0,0 +1,78 @@
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+/// リクエストIDヘッダー（BFFの ApiProxy が転送する）
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// 処理中リクエストのID（リクエスト外・spawnしたタスクではNone）
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// 外部サービス（Supabase/Stripe）へ送るリクエストIDヘッダー
+pub fn request_id_headers() -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(value) = current_request_id().and_then(|id| id.parse().ok()) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    headers
+}
+
+/// 受信したリクエストIDを採用してよいか（ログ汚染・ヘッダーインジェクション対策）
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// リクエストIDミドルウェア
+/// - `x-request-id` が妥当ならそれを使い、なければ生成する
+/// - 後続のハンドラー・エラーレスポンス・外部呼び出しから参照できるようにし、レスポンスヘッダーにも付与する
+pub async fn request_id_middleware(mut request: Request<Body>, next: Next) -> Response<Body> {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // 生成した場合も後段（TraceLayerのspan等）から参照できるようにリクエストヘッダーへ設定
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    if let Some(value) = &header_value {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    }
+
+    let mut response = REQUEST_ID.scope(request_id, next.run(request)).await;
+
+    if let Some(value) = header_value {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_request_id() {
+        assert!(is_valid_request_id("0f8fad5b-d9cb-469f-a165-70867728950e"));
+        assert!(is_valid_request_id("bff:1700000000.42"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("id with spaces"));
+        assert!(!is_valid_request_id("id\r\nx-injected: 1"));
+        assert!(!is_valid_request_id(&"a".repeat(129)));
+    }
+}
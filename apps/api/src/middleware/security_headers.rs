@@ -1,9 +1,12 @@
 use axum::{
     body::Body,
+    extract::State,
     http::{Request, Response},
     middleware::Next,
 };
 
+use crate::config::AppState;
+
 /// セキュリティヘッダーを追加するミドルウェア
 pub async fn security_headers_middleware(
     request: Request<Body>,
@@ -68,14 +71,14 @@ pub async fn security_headers_middleware(
 /// HSTS（HTTP Strict Transport Security）ヘッダーを追加
 /// 本番環境のみで使用すること
 pub async fn hsts_middleware(
+    State(state): State<AppState>,
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
     let mut response = next.run(request).await;
 
     // 本番環境でのみHSTSを有効化
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-    if env == "production" {
+    if state.config.is_production() {
         response.headers_mut().insert(
             "Strict-Transport-Security",
             "max-age=31536000; includeSubDomains; preload".parse().unwrap(),
@@ -1,5 +1,6 @@
 use axum::{
     body::Body,
+    extract::State,
     http::{Request, StatusCode},
     middleware::Next,
     response::Response,
@@ -7,20 +8,28 @@ use axum::{
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 
+use crate::config::{AppState, Config};
+
 type HmacSha256 = Hmac<Sha256>;
 
+/// 署名検証済みのセッションID（session_signature_middleware がリクエストに付与する）
+#[derive(Debug, Clone)]
+pub struct VerifiedSessionId(pub String);
+
 /// セッションID署名検証ミドルウェア
 /// Next.jsプロキシから付与されたセッションID署名を検証し、
 /// 直接API叩きによるセッションID偽装を防ぐ
 pub async fn session_signature_middleware(
-    request: Request<Body>,
+    State(state): State<AppState>,
+    mut request: Request<Body>,
     next: Next,
 ) -> Result<Response, (StatusCode, &'static str)> {
     // セッションIDと署名を取得
     let session_id = request
         .headers()
         .get("X-Session-ID")
-        .and_then(|h| h.to_str().ok());
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
 
     let session_signature = request
         .headers()
@@ -28,7 +37,7 @@ pub async fn session_signature_middleware(
         .and_then(|h| h.to_str().ok());
 
     // セッションIDがある場合のみ署名を検証
-    if let Some(session_id) = session_id {
+    if let Some(session_id) = session_id.as_deref() {
         // 署名がない場合は拒否
         let signature = session_signature.ok_or((
             StatusCode::FORBIDDEN,
@@ -36,7 +45,7 @@ pub async fn session_signature_middleware(
         ))?;
 
         // 署名を検証
-        if !verify_session_signature(session_id, signature) {
+        if !verify_session_signature(&state.config, session_id, signature) {
             tracing::warn!(
                 "Invalid session signature: session_id={}, signature={}",
                 &session_id[..session_id.len().min(10)],
@@ -45,18 +54,20 @@ pub async fn session_signature_middleware(
             return Err((StatusCode::FORBIDDEN, "Invalid session signature"));
         }
     }
+    if let Some(session_id) = session_id {
+        request.extensions_mut().insert(VerifiedSessionId(session_id));
+    }
 
     Ok(next.run(request).await)
 }
 
 /// セッションID署名を検証
-fn verify_session_signature(session_id: &str, signature: &str) -> bool {
-    let secret = get_session_secret();
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
+fn verify_session_signature(config: &Config, session_id: &str, signature: &str) -> bool {
+    let secret = config.session_secret();
 
     if secret.is_empty() {
         // 本番環境では必ずSESSION_SECRETが必要
-        if env == "production" {
+        if config.is_production() {
             tracing::error!("SESSION_SECRET is not set in production - rejecting request");
             return false;
         }
@@ -81,13 +92,6 @@ fn verify_session_signature(session_id: &str, signature: &str) -> bool {
     constant_time_compare(&expected_signature, signature)
 }
 
-/// SESSION_SECRETを取得（JWT_SECRETにフォールバック）
-fn get_session_secret() -> String {
-    std::env::var("SESSION_SECRET")
-        .or_else(|_| std::env::var("JWT_SECRET"))
-        .unwrap_or_default()
-}
-
 /// 定数時間で文字列を比較（タイミング攻撃対策）
 fn constant_time_compare(a: &str, b: &str) -> bool {
     if a.len() != b.len() {
@@ -104,18 +108,18 @@ fn constant_time_compare(a: &str, b: &str) -> bool {
 /// BFFプロキシトークン検証ミドルウェア
 /// 本番環境では、Next.jsプロキシ経由のリクエストのみを許可
 pub async fn bff_proxy_token_middleware(
+    State(state): State<AppState>,
     request: Request<Body>,
     next: Next,
 ) -> Result<Response, (StatusCode, &'static str)> {
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-    let is_prod = env == "production";
+    let config = &state.config;
 
     // 開発環境では検証をスキップ
-    if !is_prod {
+    if !config.is_production() {
         return Ok(next.run(request).await);
     }
 
-    let expected_token = std::env::var("BFF_PROXY_TOKEN").unwrap_or_default();
+    let expected_token = config.session.bff_proxy_token.expose();
 
     // 本番環境でトークンが設定されていない場合は拒否（セキュリティ強化）
     if expected_token.is_empty() {
@@ -129,7 +133,7 @@ pub async fn bff_proxy_token_middleware(
         .and_then(|h| h.to_str().ok())
         .unwrap_or_default();
 
-    if !constant_time_compare(&expected_token, provided_token) {
+    if !constant_time_compare(expected_token, provided_token) {
         tracing::warn!("Invalid or missing BFF proxy token");
         return Err((StatusCode::FORBIDDEN, "Access denied"));
     }
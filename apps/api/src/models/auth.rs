@@ -1,8 +1,10 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
 use super::{UserPublic, UserRole};
+use crate::config::OAuthConfig;
 
 /// ユーザー登録リクエスト（レガシー、Supabase Auth移行後は不使用）
 #[derive(Debug, Clone, Deserialize, Validate)]
@@ -24,6 +26,35 @@ pub struct LoginRequest {
     pub email: String,
     #[validate(length(min = 1))]
     pub password: String,
+    /// Turnstileトークン（CAPTCHA要求時のみ）
+    #[serde(default)]
+    pub captcha_token: Option<String>,
+}
+
+/// マジックリンク（メールOTP）送信リクエスト
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct MagicLinkRequest {
+    #[validate(email)]
+    pub email: String,
+    /// メール内リンクの遷移先（許可オリジン配下のみ）
+    #[validate(length(max = 500))]
+    pub redirect_to: Option<String>,
+}
+
+/// マジックリンク（メールOTP）検証リクエスト
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct MagicLinkVerifyRequest {
+    #[validate(email)]
+    pub email: String,
+    /// メールに記載された6桁コード
+    #[validate(length(min = 6, max = 10))]
+    pub token: Option<String>,
+    /// メール内リンクのトークンハッシュ
+    #[validate(length(min = 1, max = 200))]
+    pub token_hash: Option<String>,
+    /// Turnstileトークン（CAPTCHA要求時のみ）
+    #[serde(default)]
+    pub captcha_token: Option<String>,
 }
 
 /// トークンレスポンス
@@ -83,6 +114,8 @@ pub struct Claims {
     #[serde(default)]
     pub jti: Option<String>,   // JWT ID（トークンブラックリスト用）
     #[serde(default)]
+    pub session_id: Option<String>, // Supabase AuthのセッションID（端末単位のログアウト用）
+    #[serde(default)]
     pub email: Option<String>,
     #[serde(default)]
     pub role: Option<String>,  // "authenticated" | "anon"
@@ -104,6 +137,11 @@ impl Claims {
         Uuid::parse_str(&self.sub).ok()
     }
 
+    /// セッションIDをUuidとして取得
+    pub fn session_uuid(&self) -> Option<Uuid> {
+        self.session_id.as_deref().and_then(|s| Uuid::parse_str(s).ok())
+    }
+
     /// メールアドレスを取得
     pub fn email(&self) -> String {
         self.email.clone().unwrap_or_default()
@@ -137,6 +175,7 @@ pub struct AuthenticatedUser {
     pub id: Uuid,
     pub email: String,
     pub role: UserRole,
+    pub session_id: Option<Uuid>,
 }
 
 impl TryFrom<Claims> for AuthenticatedUser {
@@ -148,6 +187,7 @@ impl TryFrom<Claims> for AuthenticatedUser {
             id,
             email: claims.email(),
             role: claims.user_role(),
+            session_id: claims.session_uuid(),
         })
     }
 }
@@ -160,3 +200,93 @@ pub struct CreateProfileRequest {
     #[validate(length(max = 20))]
     pub phone: Option<String>,
 }
+
+/// ログインセッション（端末）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSession {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// リクエスト元のセッションかどうか
+    #[serde(default)]
+    pub is_current: bool,
+}
+
+/// ソーシャルログインのプロバイダ
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuthProvider {
+    Line,
+    Google,
+    Apple,
+}
+
+impl OAuthProvider {
+    /// Supabase Auth側のプロバイダ名
+    /// LINEはSupabase標準プロバイダにないため、カスタムOIDCプロバイダ名を設定（oauth.line_provider）で指定する
+    pub fn supabase_provider(&self, config: &OAuthConfig) -> String {
+        match self {
+            OAuthProvider::Line => config.line_provider.clone(),
+            OAuthProvider::Google => "google".to_string(),
+            OAuthProvider::Apple => "apple".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for OAuthProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OAuthProvider::Line => write!(f, "line"),
+            OAuthProvider::Google => write!(f, "google"),
+            OAuthProvider::Apple => write!(f, "apple"),
+        }
+    }
+}
+
+impl std::str::FromStr for OAuthProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "line" => Ok(OAuthProvider::Line),
+            "google" => Ok(OAuthProvider::Google),
+            "apple" => Ok(OAuthProvider::Apple),
+            _ => Err(format!("Unknown provider: {}", s)),
+        }
+    }
+}
+
+/// ソーシャルログイン開始クエリ
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct OAuthAuthorizeQuery {
+    /// 認可後に戻るフロントエンドのURL（許可オリジン配下のみ）
+    #[validate(length(min = 1, max = 500))]
+    pub redirect_to: String,
+}
+
+/// ソーシャルログインコールバックリクエスト
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct OAuthCallbackRequest {
+    #[validate(length(min = 1, max = 500))]
+    pub code: String,
+    pub state: Uuid,
+}
+
+/// 連携済みIDプロバイダ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub identity_id: String,
+    pub provider: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sign_in_at: Option<String>,
+}
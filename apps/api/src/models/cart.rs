@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
+use super::{orderable_quantity, Product, ProductVariant};
+
 /// カートアイテム
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CartItem {
@@ -61,6 +63,9 @@ pub struct CartResponse {
     pub items: Vec<CartItem>,
     pub subtotal: i64,
     pub item_count: i32,
+    /// 再検証で変更した内容（価格変更・数量調整・削除）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<CartChangeNotice>,
 }
 
 impl From<Cart> for CartResponse {
@@ -70,10 +75,142 @@ impl From<Cart> for CartResponse {
             items: cart.items,
             subtotal: cart.subtotal,
             item_count: cart.item_count,
+            changes: vec![],
         }
     }
 }
 
+/// カート再検証での変更の種類
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CartChangeKind {
+    /// 価格が変わった
+    PriceChanged,
+    /// 在庫に合わせて数量を減らした
+    QuantityReduced,
+    /// 在庫切れのため削除した
+    OutOfStock,
+    /// 販売終了のため削除した
+    Unavailable,
+}
+
+/// カート再検証の変更通知
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CartChangeNotice {
+    pub kind: CartChangeKind,
+    pub product_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<Uuid>,
+    pub product_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_price: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_price: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_quantity: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_quantity: Option<i32>,
+    /// 表示用メッセージ
+    pub message: String,
+}
+
+/// カートアイテム1件の再検証結果
+#[derive(Debug, Clone)]
+pub enum CartItemRevalidation {
+    Unchanged,
+    /// 価格・商品情報・数量を更新する
+    Updated(CartItem),
+    Removed,
+}
+
+/// 現在の商品情報でカートアイテムを再検証（価格は注文時と同じく商品価格を使う）
+pub fn revalidate_cart_item(
+    item: &CartItem,
+    product: Option<&Product>,
+    variant: Option<&ProductVariant>,
+    changes: &mut Vec<CartChangeNotice>,
+) -> CartItemRevalidation {
+    let notice = |kind: CartChangeKind, message: String| CartChangeNotice {
+        kind,
+        product_id: item.product_id,
+        variant_id: item.variant_id,
+        product_name: item.product_name.clone(),
+        previous_price: None,
+        current_price: None,
+        previous_quantity: None,
+        current_quantity: None,
+        message,
+    };
+
+    let variant_available = item.variant_id.is_none() || variant.is_some_and(|v| v.is_active);
+    let Some(product) = product.filter(|p| p.is_active && variant_available) else {
+        changes.push(notice(
+            CartChangeKind::Unavailable,
+            format!("「{}」は販売を終了したため、カートから削除しました", item.product_name),
+        ));
+        return CartItemRevalidation::Removed;
+    };
+
+    // 予約販売の商品は予約の残り受付数まで残す
+    let stock = orderable_quantity(product, variant.filter(|_| item.variant_id.is_some()));
+    if stock <= 0 {
+        changes.push(notice(
+            CartChangeKind::OutOfStock,
+            format!("「{}」は在庫切れのため、カートから削除しました", item.product_name),
+        ));
+        return CartItemRevalidation::Removed;
+    }
+
+    if product.price != item.price {
+        changes.push(CartChangeNotice {
+            previous_price: Some(item.price),
+            current_price: Some(product.price),
+            ..notice(
+                CartChangeKind::PriceChanged,
+                format!(
+                    "「{}」の価格が¥{}から¥{}に変更されました",
+                    item.product_name, item.price, product.price
+                ),
+            )
+        });
+    }
+
+    let quantity = item.quantity.min(stock);
+    if quantity < item.quantity {
+        changes.push(CartChangeNotice {
+            previous_quantity: Some(item.quantity),
+            current_quantity: Some(quantity),
+            ..notice(
+                CartChangeKind::QuantityReduced,
+                format!(
+                    "「{}」は在庫が{}点のため、数量を{}点に変更しました",
+                    item.product_name, stock, quantity
+                ),
+            )
+        });
+    }
+
+    let image_url = product.images.first().cloned();
+    if product.price == item.price
+        && quantity == item.quantity
+        && product.name == item.product_name
+        && product.slug == item.product_slug
+        && image_url == item.image_url
+    {
+        return CartItemRevalidation::Unchanged;
+    }
+
+    CartItemRevalidation::Updated(CartItem {
+        product_name: product.name.clone(),
+        product_slug: product.slug.clone(),
+        price: product.price,
+        quantity,
+        subtotal: product.price * quantity as i64,
+        image_url,
+        ..item.clone()
+    })
+}
+
 /// カートに追加リクエスト
 #[derive(Debug, Clone, Deserialize, Validate)]
 pub struct AddToCartRequest {
@@ -98,3 +235,54 @@ pub struct UpdateCartItemRequest {
 pub struct MergeCartRequest {
     pub guest_session_id: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(product: &Product, price: i64, quantity: i32) -> CartItem {
+        CartItem {
+            product_id: product.id,
+            product_name: product.name.clone(),
+            product_slug: product.slug.clone(),
+            price,
+            quantity,
+            subtotal: price * quantity as i64,
+            image_url: None,
+            added_at: Utc::now(),
+            variant_id: None,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn test_revalidate_cart_item() {
+        let mut changes = vec![];
+
+        let p = Product { price: 1000, stock: 10, ..Product::test_fixture() };
+        let result = revalidate_cart_item(&item(&p, 1000, 2), Some(&p), None, &mut changes);
+        assert!(matches!(result, CartItemRevalidation::Unchanged));
+        assert!(changes.is_empty());
+
+        // 価格変更と在庫による数量調整
+        let p = Product { price: 1200, stock: 3, ..Product::test_fixture() };
+        match revalidate_cart_item(&item(&p, 1000, 5), Some(&p), None, &mut changes) {
+            CartItemRevalidation::Updated(updated) => {
+                assert_eq!((updated.price, updated.quantity, updated.subtotal), (1200, 3, 3600));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        let kinds: Vec<_> = changes.drain(..).map(|c| c.kind).collect();
+        assert_eq!(kinds, vec![CartChangeKind::PriceChanged, CartChangeKind::QuantityReduced]);
+
+        let p = Product { price: 1000, stock: 0, ..Product::test_fixture() };
+        let result = revalidate_cart_item(&item(&p, 1000, 1), Some(&p), None, &mut changes);
+        assert!(matches!(result, CartItemRevalidation::Removed));
+        assert_eq!(changes.pop().map(|c| c.kind), Some(CartChangeKind::OutOfStock));
+
+        let p = Product { price: 1000, stock: 10, is_active: false, ..Product::test_fixture() };
+        let result = revalidate_cart_item(&item(&p, 1000, 1), Some(&p), None, &mut changes);
+        assert!(matches!(result, CartItemRevalidation::Removed));
+        assert_eq!(changes.pop().map(|c| c.kind), Some(CartChangeKind::Unavailable));
+    }
+}
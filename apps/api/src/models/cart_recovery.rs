@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{CartItem, CartResponse};
+
+/// 回復リンクの状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CartRecoveryStatus {
+    /// 作成済み・送信前
+    Pending,
+    Sent,
+    /// リンクからカートを復元した
+    Recovered,
+    /// 復元したセッションで注文された
+    Converted,
+    Failed,
+}
+
+impl std::fmt::Display for CartRecoveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartRecoveryStatus::Pending => write!(f, "pending"),
+            CartRecoveryStatus::Sent => write!(f, "sent"),
+            CartRecoveryStatus::Recovered => write!(f, "recovered"),
+            CartRecoveryStatus::Converted => write!(f, "converted"),
+            CartRecoveryStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// 回復リンク（cart_recoveries テーブル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CartRecovery {
+    pub id: Uuid,
+    pub session_id: String,
+    pub user_id: Option<Uuid>,
+    pub email: String,
+    pub cart_updated_at: DateTime<Utc>,
+    pub items: Vec<CartItem>,
+    pub item_count: i32,
+    pub subtotal: i64,
+    pub status: CartRecoveryStatus,
+    pub expires_at: DateTime<Utc>,
+    pub recovered_session_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 放置カート（find_abandoned_carts の結果）
+#[derive(Debug, Clone, Deserialize)]
+pub struct AbandonedCart {
+    pub session_id: String,
+    pub user_id: Uuid,
+    pub email: String,
+    pub cart_updated_at: DateTime<Utc>,
+}
+
+/// カート復元リクエスト
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecoverCartRequest {
+    pub token: String,
+}
+
+/// カート復元レスポンス（以降は新しいセッションIDと署名を X-Session-ID / X-Session-Signature に使う）
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoverCartResponse {
+    pub session_id: String,
+    pub session_signature: String,
+    pub cart: CartResponse,
+    /// 販売終了・在庫切れで復元できなかった商品名
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unavailable_items: Vec<String>,
+}
+
+/// 回復統計クエリ（管理者用）
+#[derive(Debug, Deserialize)]
+pub struct CartRecoveryStatsQuery {
+    /// 集計期間（日、デフォルト30）
+    pub days: Option<i64>,
+}
+
+/// cart_recovery_stats の結果
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CartRecoveryCounts {
+    pub sent: i64,
+    pub recovered: i64,
+    pub converted: i64,
+    pub failed: i64,
+    pub abandoned_value: i64,
+    pub recovered_revenue: i64,
+}
+
+/// 回復統計（管理者用）
+#[derive(Debug, Clone, Serialize)]
+pub struct CartRecoveryStats {
+    pub days: i64,
+    pub sent: i64,
+    pub recovered: i64,
+    pub converted: i64,
+    pub failed: i64,
+    /// 回復リンクを送ったカートの合計金額
+    pub abandoned_value: i64,
+    /// 回復経由の注文の合計金額
+    pub recovered_revenue: i64,
+    /// 復元率（recovered / sent）
+    pub recovery_rate: f64,
+    /// コンバージョン率（converted / sent）
+    pub conversion_rate: f64,
+}
+
+impl CartRecoveryStats {
+    pub fn new(days: i64, counts: CartRecoveryCounts) -> Self {
+        let rate = |n: i64| {
+            if counts.sent > 0 {
+                (n as f64 / counts.sent as f64 * 10000.0).round() / 10000.0
+            } else {
+                0.0
+            }
+        };
+        Self {
+            days,
+            recovery_rate: rate(counts.recovered),
+            conversion_rate: rate(counts.converted),
+            sent: counts.sent,
+            recovered: counts.recovered,
+            converted: counts.converted,
+            failed: counts.failed,
+            abandoned_value: counts.abandoned_value,
+            recovered_revenue: counts.recovered_revenue,
+        }
+    }
+}
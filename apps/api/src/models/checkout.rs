@@ -0,0 +1,198 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+use validator::Validate;
+
+use super::{OrderItem, OrderItemRequest};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 見積もりの有効期間（分）
+pub const QUOTE_TTL_MINUTES: i64 = 15;
+
+/// 見積もりリクエスト（itemsがなければセッションのカート。配送先は国コードか登録済み住所IDで指定）
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct CheckoutQuoteRequest {
+    #[serde(default)]
+    #[validate(length(min = 1, max = 50, message = "注文アイテムは1〜50件で指定してください"))]
+    pub items: Option<Vec<OrderItemRequest>>,
+    /// 国コード（ISO 3166-1 alpha-2）
+    #[serde(default)]
+    #[validate(length(equal = 2, message = "国コードは2文字で入力してください"))]
+    pub country: Option<String>,
+    /// 登録済み住所（ログイン時のみ）
+    #[serde(default)]
+    pub shipping_address_id: Option<Uuid>,
+}
+
+/// 配送方法
+#[derive(Debug, Clone, Serialize)]
+pub struct ShippingOption {
+    pub method: String,
+    pub zone: String,
+    /// この見積もりでの送料
+    pub fee: i64,
+    /// 基本送料
+    pub base_fee: i64,
+    /// この小計以上で送料無料
+    pub free_shipping_threshold: i64,
+    pub selected: bool,
+}
+
+/// 税額の内訳
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxLine {
+    pub name: String,
+    pub rate_percent: i64,
+    pub taxable_amount: i64,
+    pub amount: i64,
+}
+
+/// 割引（金額は shipping_fee / total に反映済み）
+#[derive(Debug, Clone, Serialize)]
+pub struct QuoteDiscount {
+    pub code: String,
+    pub description: String,
+    pub amount: i64,
+}
+
+/// 見積もりレスポンス
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckoutQuoteResponse {
+    pub items: Vec<OrderItem>,
+    pub subtotal: i64,
+    pub shipping_options: Vec<ShippingOption>,
+    pub shipping_fee: i64,
+    pub taxes: Vec<TaxLine>,
+    pub tax: i64,
+    pub discounts: Vec<QuoteDiscount>,
+    pub total: i64,
+    pub currency: String,
+    pub country: String,
+    /// 注文作成時に quote_token として渡す
+    pub quote_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// 見積もり済みの商品
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuotedLine {
+    pub product_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<Uuid>,
+    pub quantity: i32,
+    pub price: i64,
+}
+
+/// 見積もりトークンの内容
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuoteClaims {
+    pub exp: i64,
+    pub country: String,
+    pub lines: Vec<QuotedLine>,
+}
+
+impl QuoteClaims {
+    /// 注文内容（商品・数量・配送先の国）が見積もりと一致するか
+    pub fn matches(&self, items: &[OrderItemRequest], country: &str) -> bool {
+        self.country == country
+            && items.len() == self.lines.len()
+            && items.iter().all(|item| {
+                self.lines.iter().any(|line| {
+                    line.product_id == item.product_id
+                        && line.variant_id == item.variant_id
+                        && line.quantity == item.quantity
+                })
+            })
+    }
+
+    /// 見積もり時の単価
+    pub fn quoted_price(&self, product_id: Uuid, variant_id: Option<Uuid>) -> Option<i64> {
+        self.lines
+            .iter()
+            .find(|line| line.product_id == product_id && line.variant_id == variant_id)
+            .map(|line| line.price)
+    }
+}
+
+fn quote_mac(payload: &str, secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(b"checkout_quote.");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 見積もりトークンを生成（`{base64url(JSON)}.{HMAC}`）
+pub fn sign_quote_token(claims: &QuoteClaims, secret: &str) -> String {
+    let json = serde_json::to_vec(claims).expect("quote claims are serializable");
+    let payload = URL_SAFE_NO_PAD.encode(json);
+    let mac = quote_mac(&payload, secret);
+    format!("{}.{}", payload, mac)
+}
+
+/// 見積もりトークンを検証（署名不一致・期限切れはNone）
+pub fn verify_quote_token(token: &str, secret: &str, now: DateTime<Utc>) -> Option<QuoteClaims> {
+    let (payload, signature) = token.split_once('.')?;
+    let expected = quote_mac(payload, secret);
+    if !bool::from(expected.as_bytes().ct_eq(signature.as_bytes())) {
+        return None;
+    }
+
+    let json = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let claims: QuoteClaims = serde_json::from_slice(&json).ok()?;
+    if now.timestamp() > claims.exp {
+        return None;
+    }
+    Some(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_token_roundtrip() {
+        let now = Utc::now();
+        let item = OrderItemRequest {
+            product_id: Uuid::new_v4(),
+            quantity: 2,
+            variant_id: None,
+            size: None,
+        };
+        let claims = QuoteClaims {
+            exp: (now + chrono::Duration::minutes(QUOTE_TTL_MINUTES)).timestamp(),
+            country: "JP".to_string(),
+            lines: vec![QuotedLine {
+                product_id: item.product_id,
+                variant_id: None,
+                quantity: 2,
+                price: 1000,
+            }],
+        };
+        let token = sign_quote_token(&claims, "secret");
+
+        let verified = verify_quote_token(&token, "secret", now).unwrap();
+        assert_eq!(verified, claims);
+        assert!(verified.matches(std::slice::from_ref(&item), "JP"));
+        assert!(!verified.matches(std::slice::from_ref(&item), "US"));
+        assert!(!verified.matches(&[OrderItemRequest { quantity: 3, ..item.clone() }], "JP"));
+        assert_eq!(verified.quoted_price(item.product_id, None), Some(1000));
+
+        assert!(verify_quote_token(&token, "other", now).is_none());
+        assert!(verify_quote_token(&token, "secret", now + chrono::Duration::minutes(QUOTE_TTL_MINUTES + 1)).is_none());
+
+        // 価格の改ざん
+        let forged = QuoteClaims {
+            lines: vec![QuotedLine { price: 1, ..claims.lines[0].clone() }],
+            ..claims
+        };
+        let forged_payload = sign_quote_token(&forged, "other");
+        let (_, mac) = token.split_once('.').unwrap();
+        let (payload, _) = forged_payload.split_once('.').unwrap();
+        assert!(verify_quote_token(&format!("{}.{}", payload, mac), "secret", now).is_none());
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// ドメインイベント種別（DBトリガーが書き込む event_type と一致）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DomainEventType {
+    OrderCreated,
+    OrderPaid,
+    OrderCancelled,
+    OrderShipped,
+    RefundIssued,
+    StockLow,
+    StockChanged,
+    /// 在庫が0から補充された（payload.variant_id がある場合はバリアント）
+    BackInStock,
+    PriceDropped,
+    /// 未知の種別（新しいトリガーを追加した直後の旧バイナリ等）
+    #[serde(other)]
+    Unknown,
+}
+
+/// アウトボックスの配信状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainEventStatus {
+    Pending,
+    Processing,
+    Delivered,
+    /// 最大試行回数を超えた（管理画面から再実行可能）
+    Dead,
+}
+
+impl std::fmt::Display for DomainEventStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomainEventStatus::Pending => write!(f, "pending"),
+            DomainEventStatus::Processing => write!(f, "processing"),
+            DomainEventStatus::Delivered => write!(f, "delivered"),
+            DomainEventStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+/// ドメインイベント（domain_events テーブル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainEvent {
+    pub id: Uuid,
+    pub event_type: DomainEventType,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub payload: serde_json::Value,
+    pub status: DomainEventStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(default)]
+    pub completed_subscribers: Vec<String>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// イベント一覧クエリ（管理者用）
+#[derive(Debug, Deserialize)]
+pub struct DomainEventListQuery {
+    pub status: Option<DomainEventStatus>,
+    pub limit: Option<usize>,
+}
@@ -7,6 +7,14 @@ pub mod review;
 pub mod address;
 pub mod auth;
 pub mod common;
+pub mod event;
+pub mod webhook;
+pub mod wishlist;
+pub mod product_alert;
+pub mod cart_recovery;
+pub mod checkout;
+pub mod order_modification;
+pub mod preorder;
 
 pub use product::*;
 pub use category::*;
@@ -17,3 +25,11 @@ pub use review::*;
 pub use address::*;
 pub use auth::*;
 pub use common::*;
+pub use event::*;
+pub use webhook::*;
+pub use wishlist::*;
+pub use product_alert::*;
+pub use cart_recovery::*;
+pub use checkout::*;
+pub use order_modification::*;
+pub use preorder::*;
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
@@ -141,6 +141,9 @@ pub struct Order {
     pub crypto_sender_address: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub crypto_confirmed_at: Option<DateTime<Utc>>,
+    /// 予約明細と在庫明細を分けて発送する
+    #[serde(default)]
+    pub split_shipment: bool,
 }
 
 /// 注文アイテム
@@ -158,6 +161,13 @@ pub struct OrderItem {
     pub variant_id: Option<Uuid>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<String>,
+    /// 予約明細（在庫の引当後に出荷）
+    #[serde(default)]
+    pub is_preorder: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_ship_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preorder_released_at: Option<DateTime<Utc>>,
 }
 
 /// 注文サマリ（一覧用）
@@ -220,6 +230,12 @@ pub struct CreateOrderRequest {
     pub payment_method: PaymentMethod,
     #[validate(length(max = 500))]
     pub notes: Option<String>,
+    /// 見積もりトークン（有効期間内は見積もり時の価格を上限として使う）
+    #[serde(default)]
+    pub quote_token: Option<String>,
+    /// 予約商品と在庫のある商品を分けて発送する（混在する注文に必要）
+    #[serde(default)]
+    pub split_shipment: bool,
 }
 
 /// 注文作成リクエストのバリデーション
@@ -244,20 +260,71 @@ fn validate_create_order_request(req: &CreateOrderRequest) -> Result<(), validat
     Ok(())
 }
 
-/// 注文番号生成
+/// 注文番号生成（ORD-YYYYMMDD-ランダム7桁+チェックデジット）
+/// 連番ではないため注文数を推測されない。重複は一意制約で検出して振り直す（OrderRepository）
 pub fn generate_order_number() -> String {
-    let now = chrono::Utc::now();
-    let random: u32 = rand::random::<u32>() % 1000;
-    format!(
-        "ORD-{}-{:03}",
-        now.format("%Y%m%d%H%M%S"),
-        random
-    )
+    use rand::Rng;
+
+    let date = Utc::now().format("%Y%m%d").to_string();
+    let random: u32 = rand::thread_rng().gen_range(0..10_000_000);
+    let body = format!("{}{:07}", date, random);
+    let check = luhn_check_digit(&body).expect("order number body is numeric");
+    format!("ORD-{}-{:07}{}", date, random, check)
+}
+
+/// Luhnチェックデジット（数字以外を含む場合はNone）
+pub fn luhn_check_digit(digits: &str) -> Option<u32> {
+    let mut sum = 0;
+    for (i, c) in digits.chars().rev().enumerate() {
+        let mut d = c.to_digit(10)?;
+        if i % 2 == 0 {
+            d *= 2;
+            if d > 9 {
+                d -= 9;
+            }
+        }
+        sum += d;
+    }
+    Some((10 - sum % 10) % 10)
+}
+
+/// 注文番号の形式とチェックデジットを検証（入力ミスの早期検出用）
+pub fn is_valid_order_number(order_number: &str) -> bool {
+    let Some((date, tail)) = order_number
+        .strip_prefix("ORD-")
+        .and_then(|rest| rest.split_once('-'))
+    else {
+        return false;
+    };
+    if date.len() != 8 || tail.len() != 8 || !tail.is_ascii() {
+        return false;
+    }
+    let (body, check) = tail.split_at(7);
+    match (luhn_check_digit(&format!("{}{}", date, body)), check.parse::<u32>()) {
+        (Some(expected), Ok(check)) => expected == check,
+        _ => false,
+    }
+}
+
+/// チェックデジット導入前の注文番号（ORD-YYYYMMDDHHMMSS-NNN）
+pub fn is_legacy_order_number(order_number: &str) -> bool {
+    let Some((timestamp, random)) = order_number
+        .strip_prefix("ORD-")
+        .and_then(|rest| rest.split_once('-'))
+    else {
+        return false;
+    };
+    timestamp.len() == 14
+        && random.len() == 3
+        && timestamp.bytes().chain(random.bytes()).all(|b| b.is_ascii_digit())
 }
 
+/// 消費税率（%）
+pub const TAX_RATE_PERCENT: i64 = 10;
+
 /// 税率計算（10%）
 pub fn calculate_tax(subtotal: i64) -> i64 {
-    (subtotal as f64 * 0.1).round() as i64
+    (subtotal as f64 * TAX_RATE_PERCENT as f64 / 100.0).round() as i64
 }
 
 /// 国コードからEMS地帯を取得
@@ -311,34 +378,38 @@ fn get_ems_zone(country_code: &str) -> &'static str {
 ///
 /// 参考: 日本郵便EMS料金表 https://www.post.japanpost.jp/int/charge/list/
 pub fn calculate_shipping_fee(subtotal: i64, country_code: &str) -> i64 {
+    let rate = shipping_rate(country_code);
+    if subtotal >= rate.free_shipping_threshold { 0 } else { rate.fee }
+}
+
+/// 地帯別の送料（送料無料になる小計を含む）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShippingRate {
+    pub zone: &'static str,
+    pub fee: i64,
+    pub free_shipping_threshold: i64,
+}
+
+/// 国コードから送料表を引く
+pub fn shipping_rate(country_code: &str) -> ShippingRate {
     let zone = get_ems_zone(country_code);
 
-    match zone {
+    let (fee, free_shipping_threshold) = match zone {
         // 国内配送（ゆうパック60サイズ全国平均）
-        "domestic" => {
-            if subtotal >= 10000 { 0 } else { 700 }
-        }
+        "domestic" => (700, 10000),
         // 第1地帯: 東アジア（EMS 1kg: ¥2,200）
-        "zone1_east_asia" => {
-            if subtotal >= 20000 { 0 } else { 2000 }
-        }
+        "zone1_east_asia" => (2000, 20000),
         // 第2地帯: その他アジア（EMS 1kg: ¥3,150）
-        "zone2_asia" => {
-            if subtotal >= 30000 { 0 } else { 3000 }
-        }
+        "zone2_asia" => (3000, 30000),
         // 第3地帯: ヨーロッパ・オセアニア・カナダ・中近東（EMS 1kg: ¥4,400）
-        "zone3_europe" | "zone3_oceania" | "zone3_north_america" | "zone3_middle_east" => {
-            if subtotal >= 50000 { 0 } else { 4500 }
-        }
+        "zone3_europe" | "zone3_oceania" | "zone3_north_america" | "zone3_middle_east" => (4500, 50000),
         // 第4地帯: アメリカ（EMS 1kg: ¥5,300）
-        "zone4_usa" => {
-            if subtotal >= 50000 { 0 } else { 5500 }
-        }
+        "zone4_usa" => (5500, 50000),
         // 第5地帯: 南米・アフリカ等（EMS 1kg: ¥5,100）
-        _ => {
-            if subtotal >= 60000 { 0 } else { 5500 }
-        }
-    }
+        _ => (5500, 60000),
+    };
+
+    ShippingRate { zone, fee, free_shipping_threshold }
 }
 
 // ============================================
@@ -410,6 +481,12 @@ pub struct CreateGuestOrderRequest {
     /// 注文アイテム（指定された場合はカートではなくこちらを使用）
     #[serde(default)]
     pub items: Option<Vec<OrderItemRequest>>,
+    /// 見積もりトークン（有効期間内は見積もり時の価格を上限として使う）
+    #[serde(default)]
+    pub quote_token: Option<String>,
+    /// 予約商品と在庫のある商品を分けて発送する（混在する注文に必要）
+    #[serde(default)]
+    pub split_shipment: bool,
 }
 
 /// ゲスト注文作成リクエストのバリデーション
@@ -434,6 +511,42 @@ fn validate_create_guest_order_request(req: &CreateGuestOrderRequest) -> Result<
     Ok(())
 }
 
+/// ゲスト注文の照会リクエスト（メールアドレスか電話番号のどちらかが必要）
+#[derive(Debug, Deserialize, Validate)]
+pub struct GuestOrderLookupRequest {
+    #[validate(length(min = 1, max = 40, message = "注文番号を入力してください"))]
+    pub order_number: String,
+    #[serde(default)]
+    #[validate(email(message = "正しいメールアドレスを入力してください"))]
+    pub email: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 20, message = "電話番号は20文字以内で入力してください"))]
+    pub phone: Option<String>,
+}
+
+/// ゲスト注文の再アクセスリクエスト（メールのリンクに含まれるトークン）
+#[derive(Debug, Deserialize, Validate)]
+pub struct GuestOrderAccessRequest {
+    #[validate(length(min = 1, max = 100, message = "トークンが不正です"))]
+    pub token: String,
+}
+
+/// 照会で入力された連絡先が注文時のものと一致するか（メールは大文字小文字、電話番号は記号・空白を無視）
+pub fn guest_contact_matches(order: &Order, email: Option<&str>, phone: Option<&str>) -> bool {
+    let email_matches = match (email, order.guest_email.as_deref()) {
+        (Some(input), Some(stored)) => input.trim().to_lowercase() == stored.trim().to_lowercase(),
+        _ => false,
+    };
+
+    let digits = |s: &str| s.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+    let phone_matches = match (phone.map(digits), order.guest_phone.as_deref().map(digits)) {
+        (Some(input), Some(stored)) => input.len() >= 6 && input == stored,
+        _ => false,
+    };
+
+    email_matches || phone_matches
+}
+
 /// ゲストアクセストークンを生成
 pub fn generate_guest_access_token() -> (String, String) {
     let token = Uuid::new_v4().to_string();
@@ -460,3 +573,84 @@ pub fn verify_guest_token(token: &str, stored_hash: &str) -> bool {
 pub fn guest_token_expiry() -> DateTime<Utc> {
     Utc::now() + chrono::Duration::days(7)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_number_check_digit() {
+        assert_eq!(luhn_check_digit("7992739871"), Some(3));
+        assert_eq!(luhn_check_digit("12a"), None);
+
+        let number = generate_order_number();
+        assert!(is_valid_order_number(&number), "{}", number);
+
+        // 1桁の入力ミスを検出
+        let mut typo = number.clone().into_bytes();
+        let last = typo.len() - 2;
+        typo[last] = if typo[last] == b'9' { b'0' } else { typo[last] + 1 };
+        assert!(!is_valid_order_number(&String::from_utf8(typo).unwrap()));
+
+        assert!(!is_valid_order_number("ORD-20240101120000-123"));
+        assert!(!is_valid_order_number("ORD-2024-01"));
+        assert!(is_legacy_order_number("ORD-20240101120000-123"));
+        assert!(!is_legacy_order_number(&number));
+    }
+
+    fn guest_order(email: Option<&str>, phone: Option<&str>) -> Order {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "order_number": generate_order_number(),
+            "status": "pending_payment",
+            "items": [],
+            "subtotal": 1000,
+            "shipping_fee": 0,
+            "tax": 100,
+            "total": 1100,
+            "currency": "JPY",
+            "shipping_address": OrderAddress::default(),
+            "payment_method": "credit_card",
+            "payment_status": "pending",
+            "created_at": Utc::now(),
+            "updated_at": Utc::now(),
+            "is_guest_order": true,
+            "guest_email": email,
+            "guest_phone": phone,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_guest_contact_matches() {
+        let order = guest_order(Some("Guest@Example.com"), Some("090-1234-5678"));
+
+        // メールは大文字小文字・前後の空白を無視
+        assert!(guest_contact_matches(&order, Some(" guest@example.COM "), None));
+        // 電話番号は記号・空白を無視
+        assert!(guest_contact_matches(&order, None, Some("(090) 1234 5678")));
+        assert!(guest_contact_matches(&order, None, Some("09012345678")));
+        // 片方が一致すれば照会できる
+        assert!(guest_contact_matches(&order, Some("other@example.com"), Some("09012345678")));
+
+        // 連絡先の指定なし・不一致は拒否
+        assert!(!guest_contact_matches(&order, None, None));
+        assert!(!guest_contact_matches(&order, Some("other@example.com"), None));
+        assert!(!guest_contact_matches(&order, None, Some("090-1234-0000")));
+        assert!(!guest_contact_matches(&order, None, Some("")));
+    }
+
+    #[test]
+    fn test_guest_contact_phone_min_digits() {
+        // 数字が6桁未満の電話番号は、注文時の番号と一致しても照会に使えない
+        let order = guest_order(None, Some("12-345"));
+        assert!(!guest_contact_matches(&order, None, Some("12345")));
+        assert!(!guest_contact_matches(&order, None, Some("1-2-3-4-5")));
+
+        let order = guest_order(None, Some("123-456"));
+        assert!(guest_contact_matches(&order, None, Some("123456")));
+
+        // 注文時の連絡先がない項目は一致しない
+        assert!(!guest_contact_matches(&order, Some("guest@example.com"), None));
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use super::{calculate_shipping_fee, calculate_tax, GuestShippingAddress, Order, OrderAddress, OrderStatus};
+
+/// 注文変更の状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderModificationStatus {
+    /// 適用待ち（差額なし・返金）
+    Pending,
+    /// 追加決済待ち（支払い完了のWebhookで適用）
+    AwaitingPayment,
+    Applied,
+    /// 新しい変更依頼で置き換えられた
+    Superseded,
+    Failed,
+    /// 追加決済の期限切れ（PaymentIntentは取り消し済み）
+    Expired,
+}
+
+impl std::fmt::Display for OrderModificationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderModificationStatus::Pending => write!(f, "pending"),
+            OrderModificationStatus::AwaitingPayment => write!(f, "awaiting_payment"),
+            OrderModificationStatus::Applied => write!(f, "applied"),
+            OrderModificationStatus::Superseded => write!(f, "superseded"),
+            OrderModificationStatus::Failed => write!(f, "failed"),
+            OrderModificationStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// 注文変更で返金する場合の状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModificationRefundStatus {
+    NotRequired,
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl std::fmt::Display for ModificationRefundStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModificationRefundStatus::NotRequired => write!(f, "not_required"),
+            ModificationRefundStatus::Pending => write!(f, "pending"),
+            ModificationRefundStatus::Succeeded => write!(f, "succeeded"),
+            ModificationRefundStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// サイズ交換（同じ商品のバリアント間のみ）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariantSwapRequest {
+    pub product_id: Uuid,
+    pub from_variant_id: Uuid,
+    pub to_variant_id: Uuid,
+}
+
+/// 注文変更リクエスト（配送先・サイズ交換の少なくとも一方）
+/// 配送先は住所の直接入力か、ログイン時のみ登録済み住所IDで指定
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct ModifyOrderRequest {
+    #[serde(default)]
+    #[validate(nested)]
+    pub shipping_address: Option<GuestShippingAddress>,
+    #[serde(default)]
+    pub shipping_address_id: Option<Uuid>,
+    #[serde(default)]
+    #[validate(length(max = 50, message = "サイズ交換は50件までです"))]
+    pub item_changes: Vec<VariantSwapRequest>,
+}
+
+/// 適用するサイズ交換（order_modifications.item_changes）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ItemVariantChange {
+    pub product_id: Uuid,
+    pub from_variant_id: Uuid,
+    pub to_variant_id: Uuid,
+    pub to_size: String,
+    pub quantity: i32,
+    /// 交換後の単価
+    pub price: i64,
+}
+
+/// 注文変更（order_modifications テーブル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderModification {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub requested_by: Option<Uuid>,
+    pub shipping_address: Option<OrderAddress>,
+    pub item_changes: Vec<ItemVariantChange>,
+    pub previous_total: i64,
+    pub subtotal: i64,
+    pub shipping_fee: i64,
+    pub tax: i64,
+    pub total: i64,
+    /// 正: 追加決済、負: 返金
+    pub amount_difference: i64,
+    pub status: OrderModificationStatus,
+    #[serde(skip_serializing)]
+    pub payment_id: Option<String>,
+    pub refund_status: ModificationRefundStatus,
+    pub failure_reason: Option<String>,
+    /// 追加決済の期限（awaiting_payment のみ）
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+/// 追加決済の情報（クライアントで決済を完了すると変更が適用される）
+#[derive(Debug, Clone, Serialize)]
+pub struct ModificationPayment {
+    pub payment_intent_id: String,
+    pub client_secret: String,
+    pub amount: i64,
+}
+
+/// 注文変更レスポンス
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderModificationResponse {
+    pub modification: OrderModification,
+    pub order: Order,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment: Option<ModificationPayment>,
+}
+
+/// 変更後の金額
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModifiedTotals {
+    pub subtotal: i64,
+    pub shipping_fee: i64,
+    pub tax: i64,
+    pub total: i64,
+}
+
+/// 購入者が変更できる状態か（支払い済みで発送前）
+pub fn is_order_modifiable(order: &Order) -> bool {
+    matches!(order.status, OrderStatus::Paid | OrderStatus::Processing)
+}
+
+/// 交換後の単価（支払い済みの単価にバリアント間の価格調整の差を反映）
+pub fn swapped_item_price(paid_price: i64, from_adjustment: i64, to_adjustment: i64) -> i64 {
+    (paid_price + to_adjustment - from_adjustment).max(0)
+}
+
+/// 変更後の金額を再計算
+/// 小計も配送先の国も変わらない場合は、注文時の送料・税をそのまま使う（料金表の変更で差額が出ないように）
+pub fn recalculate_order_totals(order: &Order, changes: &[ItemVariantChange], country: &str) -> ModifiedTotals {
+    let subtotal: i64 = order
+        .items
+        .iter()
+        .map(|item| {
+            let price = changes
+                .iter()
+                .find(|c| c.product_id == item.product_id && Some(c.from_variant_id) == item.variant_id)
+                .map_or(item.price, |c| c.price);
+            price * item.quantity as i64
+        })
+        .sum();
+
+    if subtotal == order.subtotal && country == order.shipping_address.country {
+        return ModifiedTotals {
+            subtotal,
+            shipping_fee: order.shipping_fee,
+            tax: order.tax,
+            total: order.total,
+        };
+    }
+
+    let shipping_fee = calculate_shipping_fee(subtotal, country);
+    let tax = calculate_tax(subtotal);
+    ModifiedTotals {
+        subtotal,
+        shipping_fee,
+        tax,
+        total: subtotal + shipping_fee + tax,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{OrderItem, PaymentMethod, PaymentStatus};
+
+    fn order(country: &str, items: Vec<OrderItem>) -> Order {
+        let subtotal: i64 = items.iter().map(|i| i.subtotal).sum();
+        let shipping_fee = calculate_shipping_fee(subtotal, country);
+        let tax = calculate_tax(subtotal);
+        Order {
+            id: Uuid::new_v4(),
+            user_id: Some(Uuid::new_v4()),
+            order_number: "ORD-20260101-00000000".to_string(),
+            status: OrderStatus::Paid,
+            items,
+            subtotal,
+            shipping_fee,
+            tax,
+            total: subtotal + shipping_fee + tax,
+            currency: "JPY".to_string(),
+            shipping_address: OrderAddress {
+                name: "山田 太郎".to_string(),
+                country: country.to_string(),
+                postal_code: "100-0001".to_string(),
+                prefecture: "東京都".to_string(),
+                city: "千代田区".to_string(),
+                address_line1: "1-1".to_string(),
+                address_line2: None,
+                phone: None,
+            },
+            billing_address: None,
+            payment_method: PaymentMethod::CreditCard,
+            payment_status: PaymentStatus::Paid,
+            payment_id: Some("pi_test".to_string()),
+            notes: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            shipped_at: None,
+            delivered_at: None,
+            is_guest_order: false,
+            guest_email: None,
+            guest_name: None,
+            guest_phone: None,
+            guest_access_token_hash: None,
+            guest_token_expires_at: None,
+            crypto_tx_hash: None,
+            crypto_chain_id: None,
+            crypto_sender_address: None,
+            crypto_confirmed_at: None,
+            split_shipment: false,
+        }
+    }
+
+    #[test]
+    fn test_recalculate_order_totals() {
+        let product_id = Uuid::new_v4();
+        let from_variant_id = Uuid::new_v4();
+        let item = OrderItem {
+            product_id,
+            product_name: "Tシャツ".to_string(),
+            product_sku: "TS-1".to_string(),
+            price: 3000,
+            quantity: 2,
+            subtotal: 6000,
+            image_url: None,
+            variant_id: Some(from_variant_id),
+            size: Some("M".to_string()),
+            is_preorder: false,
+            expected_ship_date: None,
+            preorder_released_at: None,
+        };
+        let order = order("JP", vec![item]);
+
+        // 金額に関係しない変更は注文時の金額のまま
+        let same = recalculate_order_totals(&order, &[], "JP");
+        assert_eq!(same.total, order.total);
+
+        let change = ItemVariantChange {
+            product_id,
+            from_variant_id,
+            to_variant_id: Uuid::new_v4(),
+            to_size: "XL".to_string(),
+            quantity: 2,
+            price: swapped_item_price(3000, 0, 500),
+        };
+        let swapped = recalculate_order_totals(&order, &[change], "JP");
+        assert_eq!(swapped.subtotal, 7000);
+        assert_eq!(swapped.tax, calculate_tax(7000));
+        assert_eq!(swapped.total, 7000 + swapped.shipping_fee + swapped.tax);
+
+        // 配送先の国が変わると送料を再計算
+        let moved = recalculate_order_totals(&order, &[], "US");
+        assert_eq!(moved.shipping_fee, calculate_shipping_fee(6000, "US"));
+
+        assert_eq!(swapped_item_price(1000, 500, 0), 500);
+        assert_eq!(swapped_item_price(100, 500, 0), 0);
+    }
+}
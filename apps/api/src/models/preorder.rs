@@ -0,0 +1,220 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{OrderItem, OrderSummary, Product, ProductVariant};
+
+/// 予約販売の設定（products テーブル）
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PreorderSettings {
+    /// 在庫が足りない場合に予約として受注する
+    pub preorder_enabled: bool,
+    /// 予約の受付上限（数量）
+    pub preorder_cap: i32,
+    /// 受付済みで在庫未引当の予約数量
+    pub preorder_allocated: i32,
+    /// 予約分の出荷予定日
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_ship_date: Option<NaiveDate>,
+}
+
+impl PreorderSettings {
+    /// 予約の残り受付数（予約販売でなければ0）
+    pub fn remaining(&self) -> i32 {
+        if !self.preorder_enabled {
+            return 0;
+        }
+        (self.preorder_cap - self.preorder_allocated).max(0)
+    }
+}
+
+/// 注文明細の引当区分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineAllocation {
+    InStock,
+    Preorder,
+    Unavailable,
+}
+
+/// 在庫で受けられる数量（バリアントは商品とバリアントの在庫の少ない方）
+fn available_stock(product: &Product, variant: Option<&ProductVariant>) -> i32 {
+    variant.map_or(product.stock, |v| v.stock.min(product.stock))
+}
+
+/// 予約の残り受付数（バリアント別の上限があればそれも超えない）
+fn preorder_remaining(product: &Product, variant: Option<&ProductVariant>) -> i32 {
+    let remaining = product.preorder.remaining();
+    match variant.and_then(|v| v.preorder_cap.map(|cap| cap - v.preorder_allocated)) {
+        Some(variant_remaining) => remaining.min(variant_remaining.max(0)),
+        None => remaining,
+    }
+}
+
+/// 明細を在庫・予約のどちらで受けるか（1明細を在庫と予約に分けることはしない）
+/// バリアント指定の明細はバリアントの在庫・予約上限も確認する
+pub fn allocate_line(product: &Product, variant: Option<&ProductVariant>, quantity: i32) -> LineAllocation {
+    if available_stock(product, variant) >= quantity {
+        LineAllocation::InStock
+    } else if preorder_remaining(product, variant) >= quantity {
+        LineAllocation::Preorder
+    } else {
+        LineAllocation::Unavailable
+    }
+}
+
+/// カートに入れられる数量（在庫または予約の残り受付数の多い方）
+pub fn orderable_quantity(product: &Product, variant: Option<&ProductVariant>) -> i32 {
+    available_stock(product, variant).max(preorder_remaining(product, variant))
+}
+
+/// 予約枠の確保単位（商品ID, バリアントID, 数量）
+pub type PreorderReservation = (Uuid, Option<Uuid>, i32);
+
+/// 明細を在庫で確保する分（商品ID, 数量）と予約枠で確保する分に分ける
+pub fn split_reservations(items: &[OrderItem]) -> (Vec<(Uuid, i32)>, Vec<PreorderReservation>) {
+    let (preorders, in_stock): (Vec<&OrderItem>, Vec<&OrderItem>) = items.iter().partition(|i| i.is_preorder);
+    (
+        in_stock.iter().map(|i| (i.product_id, i.quantity)).collect(),
+        preorders.iter().map(|i| (i.product_id, i.variant_id, i.quantity)).collect(),
+    )
+}
+
+/// 予約明細と在庫明細が混在しているか
+pub fn has_mixed_preorder(items: &[OrderItem]) -> bool {
+    items.iter().any(|i| i.is_preorder) && items.iter().any(|i| !i.is_preorder)
+}
+
+/// 在庫の引当待ちの予約明細か
+pub fn is_awaiting_release(item: &OrderItem) -> bool {
+    item.is_preorder && item.preorder_released_at.is_none()
+}
+
+/// 通常の出荷キューに出すか
+/// 分割発送でない注文は予約明細の引当が全て済んでから、分割発送は出荷できる明細が1つでもあれば出す
+pub fn is_ready_for_fulfillment(items: &[OrderItem], split_shipment: bool) -> bool {
+    if split_shipment {
+        items.iter().any(|i| !is_awaiting_release(i))
+    } else {
+        !items.iter().any(is_awaiting_release)
+    }
+}
+
+/// 出荷キューの注文
+#[derive(Debug, Clone, Serialize)]
+pub struct FulfillmentQueueEntry {
+    #[serde(flatten)]
+    pub order: OrderSummary,
+    pub split_shipment: bool,
+    /// 引当待ちで今回は出荷しない明細数
+    pub awaiting_release_items: i32,
+}
+
+/// 予約引当の結果（管理者用）
+#[derive(Debug, Clone, Serialize)]
+pub struct PreorderReleaseResponse {
+    /// 在庫を引き当てた明細数
+    pub released_items: i32,
+    pub product: Product,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn item(is_preorder: bool) -> OrderItem {
+        OrderItem {
+            product_id: Uuid::new_v4(),
+            product_name: "限定Tシャツ".to_string(),
+            product_sku: "DROP-1".to_string(),
+            price: 5000,
+            quantity: 1,
+            subtotal: 5000,
+            image_url: None,
+            variant_id: None,
+            size: None,
+            is_preorder,
+            expected_ship_date: None,
+            preorder_released_at: None,
+        }
+    }
+
+    #[test]
+    fn test_preorder_allocation_and_fulfillment() {
+        let settings = PreorderSettings {
+            preorder_enabled: true,
+            preorder_cap: 10,
+            preorder_allocated: 8,
+            expected_ship_date: NaiveDate::from_ymd_opt(2026, 12, 1),
+        };
+        let drop = Product { stock: 1, preorder: settings.clone(), ..Product::test_fixture() };
+        assert_eq!(allocate_line(&drop, None, 1), LineAllocation::InStock);
+        assert_eq!(allocate_line(&drop, None, 2), LineAllocation::Preorder);
+        assert_eq!(allocate_line(&drop, None, 3), LineAllocation::Unavailable);
+        assert_eq!(orderable_quantity(&drop, None), 2);
+
+        let disabled = Product {
+            stock: 0,
+            preorder: PreorderSettings { preorder_enabled: false, ..settings },
+            ..Product::test_fixture()
+        };
+        assert_eq!(allocate_line(&disabled, None, 1), LineAllocation::Unavailable);
+
+        let mixed = vec![item(false), item(true)];
+        assert!(has_mixed_preorder(&mixed));
+        assert!(!has_mixed_preorder(&[item(true)]));
+
+        // 引当前は分割発送の場合のみ在庫分を出荷キューに出す
+        assert!(!is_ready_for_fulfillment(&mixed, false));
+        assert!(is_ready_for_fulfillment(&mixed, true));
+        assert!(!is_ready_for_fulfillment(&[item(true)], true));
+
+        let released = OrderItem {
+            preorder_released_at: Some(Utc::now()),
+            ..item(true)
+        };
+        assert!(is_ready_for_fulfillment(&[item(false), released], false));
+    }
+
+    #[test]
+    fn test_allocate_line_checks_variant() {
+        let settings = PreorderSettings {
+            preorder_enabled: true,
+            preorder_cap: 10,
+            preorder_allocated: 0,
+            expected_ship_date: None,
+        };
+        let drop = Product { stock: 5, preorder: settings, ..Product::test_fixture() };
+        let variant = |stock: i32, preorder_cap: Option<i32>| ProductVariant {
+            id: Uuid::new_v4(),
+            product_id: drop.id,
+            size: "M".to_string(),
+            sku: None,
+            stock,
+            price_adjustment: 0,
+            sort_order: 0,
+            is_active: true,
+            body_length: None,
+            body_width: None,
+            shoulder_width: None,
+            sleeve_length: None,
+            preorder_cap,
+            preorder_allocated: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        // 商品の在庫があってもバリアントの在庫が足りなければ予約になる
+        let sold_out = variant(0, None);
+        assert_eq!(allocate_line(&drop, Some(&sold_out), 2), LineAllocation::Preorder);
+        assert_eq!(allocate_line(&drop, Some(&variant(2, None)), 2), LineAllocation::InStock);
+
+        // バリアント別の予約上限
+        let capped = variant(0, Some(3));
+        assert_eq!(allocate_line(&drop, Some(&capped), 2), LineAllocation::Preorder);
+        assert_eq!(allocate_line(&drop, Some(&capped), 3), LineAllocation::Unavailable);
+        assert_eq!(orderable_quantity(&drop, Some(&capped)), 2);
+    }
+}
@@ -1,9 +1,9 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
-use super::{Category, PaginationQuery, SortOrder};
+use super::{Category, PaginationQuery, PreorderSettings, SortOrder};
 
 /// 商品
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,10 +31,43 @@ pub struct Product {
     pub tags: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<std::collections::HashMap<String, String>>,
+    /// 予約販売の設定
+    #[serde(flatten)]
+    pub preorder: PreorderSettings,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+#[cfg(test)]
+impl Product {
+    /// テスト用の商品（必要なフィールドは構造体更新構文で上書きする）
+    pub fn test_fixture() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            slug: "tee".to_string(),
+            name: "Tシャツ".to_string(),
+            description: String::new(),
+            price: 1000,
+            compare_at_price: None,
+            currency: "JPY".to_string(),
+            category_id: None,
+            category: None,
+            images: vec![],
+            stock: 10,
+            sku: "TEE".to_string(),
+            weight: None,
+            is_active: true,
+            is_featured: false,
+            tags: None,
+            metadata: None,
+            preorder: PreorderSettings::default(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
 /// カテゴリサマリ（商品に埋め込み用）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CategorySummary {
@@ -178,6 +211,11 @@ pub struct ProductVariant {
     pub shoulder_width: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sleeve_length: Option<i32>,
+    /// バリアント別の予約受付上限（未設定は商品の上限のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preorder_cap: Option<i32>,
+    #[serde(default)]
+    pub preorder_allocated: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -197,6 +235,8 @@ pub struct CreateVariantInput {
     pub body_width: Option<i32>,
     pub shoulder_width: Option<i32>,
     pub sleeve_length: Option<i32>,
+    #[serde(default)]
+    pub preorder_cap: Option<i32>,
 }
 
 fn default_true() -> bool {
@@ -219,6 +259,7 @@ pub struct UpdateVariantRequest {
     pub body_width: Option<i32>,
     pub shoulder_width: Option<i32>,
     pub sleeve_length: Option<i32>,
+    pub preorder_cap: Option<i32>,
 }
 
 /// 商品作成リクエスト（管理者用）
@@ -244,6 +285,11 @@ pub struct AdminCreateProductRequest {
     pub is_featured: bool,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub preorder_enabled: bool,
+    #[serde(default)]
+    pub preorder_cap: i32,
+    pub expected_ship_date: Option<NaiveDate>,
 }
 
 /// 商品更新リクエスト（管理者用）
@@ -261,4 +307,7 @@ pub struct AdminUpdateProductRequest {
     pub product_type: Option<String>,
     pub material: Option<String>,
     pub material_detail: Option<String>,
+    pub preorder_enabled: Option<bool>,
+    pub preorder_cap: Option<i32>,
+    pub expected_ship_date: Option<NaiveDate>,
 }
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// 通知の種類
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductAlertKind {
+    /// 再入荷
+    BackInStock,
+    /// 値下げ
+    PriceDrop,
+}
+
+impl std::fmt::Display for ProductAlertKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProductAlertKind::BackInStock => write!(f, "back_in_stock"),
+            ProductAlertKind::PriceDrop => write!(f, "price_drop"),
+        }
+    }
+}
+
+/// 購読の状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductAlertStatus {
+    /// メールの確認リンクを開くまで通知しない（ゲストの購読）
+    PendingConfirmation,
+    /// 条件を満たすまで待機
+    Active,
+    /// 条件を満たし、送信待ち（宛先ごとに間隔を空けて送る）
+    Queued,
+    Sending,
+    /// 通知済み（購読終了）
+    Sent,
+    Cancelled,
+    /// 最大試行回数を超えた
+    Failed,
+}
+
+impl std::fmt::Display for ProductAlertStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProductAlertStatus::PendingConfirmation => write!(f, "pending_confirmation"),
+            ProductAlertStatus::Active => write!(f, "active"),
+            ProductAlertStatus::Queued => write!(f, "queued"),
+            ProductAlertStatus::Sending => write!(f, "sending"),
+            ProductAlertStatus::Sent => write!(f, "sent"),
+            ProductAlertStatus::Cancelled => write!(f, "cancelled"),
+            ProductAlertStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// 再入荷・値下げ通知の購読
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductAlert {
+    pub id: Uuid,
+    pub kind: ProductAlertKind,
+    pub product_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<Uuid>,
+    #[serde(skip_serializing)]
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_price: Option<i64>,
+    pub status: ProductAlertStatus,
+    #[serde(skip_serializing)]
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// 購読リクエスト（ゲストはemail必須、ログイン中はアカウントのメールアドレスを使う）
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct SubscribeProductAlertRequest {
+    pub kind: ProductAlertKind,
+    #[serde(default)]
+    pub variant_id: Option<Uuid>,
+    #[serde(default)]
+    #[validate(email(message = "有効なメールアドレスを入力してください"))]
+    pub email: Option<String>,
+}
+
+/// 登録確認・購読解除リクエスト（メールのリンクに含まれるトークン）
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProductAlertTokenRequest {
+    pub token: String,
+}
+
+/// 通知条件を満たしているか（送信直前の再確認に使う）
+pub fn alert_condition_met(
+    kind: ProductAlertKind,
+    target_price: Option<i64>,
+    available_stock: i32,
+    price: i64,
+) -> bool {
+    match kind {
+        ProductAlertKind::BackInStock => available_stock > 0,
+        ProductAlertKind::PriceDrop => target_price.is_some_and(|target| price < target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_condition_met() {
+        assert!(alert_condition_met(ProductAlertKind::BackInStock, None, 1, 1000));
+        assert!(!alert_condition_met(ProductAlertKind::BackInStock, None, 0, 1000));
+        assert!(alert_condition_met(ProductAlertKind::PriceDrop, Some(1000), 0, 900));
+        assert!(!alert_condition_met(ProductAlertKind::PriceDrop, Some(1000), 5, 1000));
+        assert!(!alert_condition_met(ProductAlertKind::PriceDrop, None, 5, 900));
+    }
+}
@@ -96,3 +96,22 @@ pub struct ChangePasswordRequest {
     #[validate(length(min = 8, max = 100))]
     pub new_password: String,
 }
+
+/// 退会リクエスト
+/// 誤操作防止のため、登録メールアドレスの再入力を必須とする
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct DeleteAccountRequest {
+    #[validate(email)]
+    pub confirm_email: String,
+}
+
+/// 個人データエクスポート（退会前のデータ持ち出し用）
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserPublic,
+    pub addresses: Vec<crate::models::Address>,
+    pub orders: Vec<crate::models::Order>,
+    pub reviews: Vec<crate::models::Review>,
+    pub contact_submissions: Vec<serde_json::Value>,
+}
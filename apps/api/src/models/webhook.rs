@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use super::DomainEventType;
+
+/// 外部連携Webhookで購読可能なイベント
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WebhookEventType {
+    #[serde(rename = "order.paid")]
+    OrderPaid,
+    #[serde(rename = "order.shipped")]
+    OrderShipped,
+    #[serde(rename = "order.refunded")]
+    OrderRefunded,
+    #[serde(rename = "product.stock_changed")]
+    ProductStockChanged,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::OrderPaid => "order.paid",
+            WebhookEventType::OrderShipped => "order.shipped",
+            WebhookEventType::OrderRefunded => "order.refunded",
+            WebhookEventType::ProductStockChanged => "product.stock_changed",
+        }
+    }
+
+    /// 対応するドメインイベント（外部公開しないイベントはNone）
+    pub fn from_domain(event_type: DomainEventType) -> Option<Self> {
+        match event_type {
+            DomainEventType::OrderPaid => Some(WebhookEventType::OrderPaid),
+            DomainEventType::OrderShipped => Some(WebhookEventType::OrderShipped),
+            DomainEventType::RefundIssued => Some(WebhookEventType::OrderRefunded),
+            DomainEventType::StockChanged => Some(WebhookEventType::ProductStockChanged),
+            _ => None,
+        }
+    }
+}
+
+/// Webhookエンドポイント（secret はレスポンスに含めない）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Vec<WebhookEventType>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// エンドポイント作成レスポンス（secret は作成時のみ返す）
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhookEndpoint {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
+/// エンドポイント作成リクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateWebhookEndpointRequest {
+    #[validate(length(min = 1, max = 2048))]
+    pub url: String,
+    #[validate(length(max = 200))]
+    pub description: Option<String>,
+    #[validate(length(min = 1, message = "購読するイベントを1つ以上指定してください"))]
+    pub event_types: Vec<WebhookEventType>,
+}
+
+/// エンドポイント更新リクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateWebhookEndpointRequest {
+    #[validate(length(min = 1, max = 2048))]
+    pub url: Option<String>,
+    #[validate(length(max = 200))]
+    pub description: Option<String>,
+    #[validate(length(min = 1, message = "購読するイベントを1つ以上指定してください"))]
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub is_active: Option<bool>,
+}
+
+/// 配信状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Processing,
+    Succeeded,
+    /// 最大試行回数を超えた（再送可能）
+    Dead,
+}
+
+impl std::fmt::Display for WebhookDeliveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookDeliveryStatus::Pending => write!(f, "pending"),
+            WebhookDeliveryStatus::Processing => write!(f, "processing"),
+            WebhookDeliveryStatus::Succeeded => write!(f, "succeeded"),
+            WebhookDeliveryStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+/// 配信ログ（webhook_deliveries テーブル）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub body: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub duration_ms: Option<i32>,
+    pub replay_of: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// 配信ログ一覧クエリ（管理者用）
+#[derive(Debug, Deserialize)]
+pub struct WebhookDeliveryListQuery {
+    pub status: Option<WebhookDeliveryStatus>,
+    pub limit: Option<usize>,
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use validator::Validate;
+
+/// ウィッシュリストの所有者
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WishlistOwner {
+    /// ログインユーザー
+    User(Uuid),
+    /// ゲスト（署名検証済みのセッションID）
+    Session(String),
+}
+
+/// ウィッシュリストアイテム（保存内容）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WishlistItem {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<Uuid>,
+    pub added_at: DateTime<Utc>,
+}
+
+/// ウィッシュリストアイテム（商品情報・在庫付き）
+#[derive(Debug, Clone, Serialize)]
+pub struct WishlistItemView {
+    pub product_id: Uuid,
+    pub product_name: String,
+    pub product_slug: String,
+    pub price: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    /// 販売中かつ在庫あり（バリアント指定時はバリアントの在庫）
+    pub in_stock: bool,
+    pub added_at: DateTime<Utc>,
+}
+
+/// ウィッシュリストレスポンス
+#[derive(Debug, Clone, Serialize)]
+pub struct WishlistResponse {
+    /// ゲストの場合のみ（署名が無効で再発行された場合に気付けるよう返す）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub items: Vec<WishlistItemView>,
+    pub item_count: usize,
+}
+
+/// ウィッシュリストに追加リクエスト
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddToWishlistRequest {
+    pub product_id: Uuid,
+    #[serde(default)]
+    pub variant_id: Option<Uuid>,
+}
+
+/// ウィッシュリストから削除するアイテムの指定（未指定なら商品の全バリアント）
+#[derive(Debug, Clone, Deserialize)]
+pub struct WishlistItemParams {
+    #[serde(default)]
+    pub variant_id: Option<Uuid>,
+}
+
+/// カートへ移動リクエスト
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct MoveToCartRequest {
+    #[serde(default)]
+    pub variant_id: Option<Uuid>,
+    #[serde(default = "default_move_quantity")]
+    #[validate(range(min = 1, max = 99))]
+    pub quantity: i32,
+}
+
+fn default_move_quantity() -> i32 {
+    1
+}
+
+/// ウィッシュリスト統合リクエスト（ログイン時）
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeWishlistRequest {
+    pub guest_session_id: String,
+}
+
+/// 共有リンク発行レスポンス（トークンはこの時だけ返す）
+#[derive(Debug, Clone, Serialize)]
+pub struct WishlistShareResponse {
+    pub token: String,
+    pub path: String,
+}
+
+/// 共有トークンを生成（トークン, ハッシュ）
+pub fn generate_share_token() -> (String, String) {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("wl_{}", hex::encode(bytes));
+    let hash = hash_share_token(&token);
+    (token, hash)
+}
+
+/// 共有トークンをハッシュ化
+pub fn hash_share_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 共有トークンの形式チェック（DB照会前に不正な値を弾く）
+pub fn is_valid_share_token(token: &str) -> bool {
+    token
+        .strip_prefix("wl_")
+        .map(|hex| hex.len() == 48 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_token_roundtrip() {
+        let (token, hash) = generate_share_token();
+        assert!(is_valid_share_token(&token));
+        assert_eq!(hash_share_token(&token), hash);
+        assert_ne!(generate_share_token().0, token);
+    }
+
+    #[test]
+    fn test_share_token_rejects_malformed() {
+        assert!(!is_valid_share_token(""));
+        assert!(!is_valid_share_token("wl_xyz"));
+        assert!(!is_valid_share_token(&format!("wl_{}", "g".repeat(48))));
+        assert!(!is_valid_share_token(&"a".repeat(51)));
+    }
+}
@@ -7,8 +7,11 @@ use axum::{
 use crate::config::AppState;
 use crate::handlers;
 use crate::middleware::{
-    auth_middleware, admin_middleware,
-    rate_limiter::{payment_rate_limiter_middleware, contact_rate_limiter_middleware, guest_order_rate_limiter_middleware},
+    auth_middleware, admin_middleware, idempotency_middleware, optional_auth_middleware,
+    rate_limiter::{
+        payment_rate_limiter_middleware, contact_rate_limiter_middleware, guest_order_rate_limiter_middleware,
+        passkey_login_rate_limiter_middleware,
+    },
     session::{session_signature_middleware, bff_proxy_token_middleware},
 };
 
@@ -22,6 +25,10 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/auth/register", post(handlers::auth::register))
         .route("/api/v1/auth/login", post(handlers::auth::login))
         .route("/api/v1/auth/refresh", post(handlers::auth::refresh_token))
+        .route("/api/v1/auth/magic-link", post(handlers::auth::request_magic_link))
+        .route("/api/v1/auth/magic-link/verify", post(handlers::auth::verify_magic_link))
+        .route("/api/v1/auth/oauth/callback", post(handlers::oauth::callback))
+        .route("/api/v1/auth/oauth/:provider/authorize", get(handlers::oauth::authorize))
         // 商品（公開）
         .route("/api/v1/products", get(handlers::products::list_products))
         .route("/api/v1/products/featured", get(handlers::products::get_featured_products))
@@ -38,18 +45,64 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/cart/items", post(handlers::cart::add_to_cart))
         .route("/api/v1/cart/items/:product_id", put(handlers::cart::update_cart_item))
         .route("/api/v1/cart/items/:product_id", delete(handlers::cart::remove_from_cart))
+        .route("/api/v1/cart/recover", post(handlers::cart::recover_cart))
+        // ウィッシュリスト共有リンク（公開）
+        .route("/api/v1/wishlists/shared/:token", get(handlers::wishlist::get_shared_wishlist))
+        // 再入荷・値下げ通知の登録確認・解除（メールで受け取ったトークン）
+        .route("/api/v1/alerts/confirm", post(handlers::product_alerts::confirm))
+        .route("/api/v1/alerts/unsubscribe", post(handlers::product_alerts::unsubscribe))
         // レビュー（読み取りは公開）
         .route("/api/v1/products/:id/reviews", get(handlers::reviews::list_reviews))
         .route("/api/v1/products/:id/reviews/stats", get(handlers::reviews::get_review_stats))
         // Webhook（公開：署名検証あり）
         .route("/api/v1/webhooks/stripe", post(handlers::payments::handle_webhook));
 
+    // パスキーログイン（認証不要 + 専用レート制限）
+    // チャレンジの大量発行対策: 1IPあたり60秒間に10回まで
+    let passkey_login_routes = Router::new()
+        .route("/api/v1/auth/passkeys/login/options", post(handlers::passkeys::authentication_options))
+        .route("/api/v1/auth/passkeys/login", post(handlers::passkeys::authenticate))
+        .layer(middleware::from_fn(passkey_login_rate_limiter_middleware));
+
+    // ウィッシュリスト（ログイン中はユーザー単位、ゲストは署名付きセッション単位）
+    let wishlist_routes = Router::new()
+        .route("/api/v1/wishlist", get(handlers::wishlist::get_wishlist))
+        .route("/api/v1/wishlist/items", post(handlers::wishlist::add_to_wishlist))
+        .route("/api/v1/wishlist/items/:product_id", delete(handlers::wishlist::remove_from_wishlist))
+        .route("/api/v1/wishlist/items/:product_id/move-to-cart", post(handlers::wishlist::move_to_cart))
+        .layer(middleware::from_fn_with_state(state.clone(), optional_auth_middleware));
+
+    // 再入荷・値下げ通知の購読（ログイン中はアカウントのメールアドレス、ゲストは入力したメールアドレス宛て）
+    let product_alert_routes = Router::new()
+        .route("/api/v1/products/:id/alerts", post(handlers::product_alerts::subscribe))
+        .layer(middleware::from_fn_with_state(state.clone(), optional_auth_middleware));
+
+    // 見積もり（ゲストも可、ログイン中は登録済み住所を指定できる）
+    let checkout_routes = Router::new()
+        .route("/api/v1/checkout/quote", post(handlers::checkout::create_quote))
+        .layer(middleware::from_fn_with_state(state.clone(), optional_auth_middleware));
+
     // ゲスト注文ルート（認証不要 + 専用レート制限）
     // DoS/在庫枯渇攻撃対策: 1IPあたり60秒間に3回まで
+    // セッションID（カートのクリア・Idempotency-Key の名前空間に使う）は署名を検証する
     let guest_order_routes = Router::new()
-        .route("/api/v1/orders/guest", post(handlers::orders::create_guest_order))
+        .route(
+            "/api/v1/orders/guest",
+            post(handlers::orders::create_guest_order)
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware)),
+        )
         .route("/api/v1/orders/guest/:id", get(handlers::orders::get_guest_order))
-        .layer(middleware::from_fn(guest_order_rate_limiter_middleware));
+        // 注文番号 + メールアドレス/電話番号で照会し、1回限りの再アクセスリンクをメールで送る
+        .route("/api/v1/orders/guest/lookup", post(handlers::orders::lookup_guest_order))
+        .route("/api/v1/orders/guest/access", post(handlers::orders::access_guest_order))
+        // 発送前の注文変更（トークンで認可）
+        .route(
+            "/api/v1/orders/guest/:id/modifications",
+            post(handlers::order_modifications::modify_guest_order)
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware)),
+        )
+        .layer(middleware::from_fn(guest_order_rate_limiter_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware));
 
     // お問い合わせルート（認証必須、専用レート制限）
     // スパム対策: 1IPあたり1時間に5回まで
@@ -57,8 +110,8 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/contact", post(handlers::contact::submit_contact))
         .layer(middleware::from_fn(contact_rate_limiter_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     // 決済ルート（認証 + 専用レート制限）
     // カードテスティング攻撃対策: 1IPあたり60秒間に5回まで
@@ -67,37 +120,42 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/payments/confirm", post(handlers::payments::confirm_payment))
         .route("/api/v1/payments/refund", post(handlers::payments::create_refund))
         // ミドルウェア（外側から: BFF検証 → セッション署名検証 → 認証 → 決済レート制限）
+        // Idempotency-Key（ヘッダー指定時のみ。認証より内側でユーザー単位に保存）
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware))
         .layer(middleware::from_fn(payment_rate_limiter_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     // ゲスト決済ルート（認証不要 + 専用レート制限）
     let guest_payment_routes = Router::new()
         .route("/api/v1/payments/guest/intent", post(handlers::payments::create_payment_intent_guest))
         .route("/api/v1/payments/guest/order-intent", post(handlers::payments::create_payment_intent_for_guest_order))
         // ミドルウェア（外側から: BFF検証 → セッション署名検証 → 決済レート制限）
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware))
         .layer(middleware::from_fn(payment_rate_limiter_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     // JPYC決済ルート（認証必須 + 専用レート制限）
     let jpyc_payment_routes = Router::new()
         .route("/api/v1/payments/jpyc/prepare", post(handlers::jpyc::prepare_jpyc_payment))
         .route("/api/v1/payments/jpyc/verify", post(handlers::jpyc::verify_jpyc_payment))
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware))
         .layer(middleware::from_fn(payment_rate_limiter_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     // JPYC決済ルート（ゲスト用 + 専用レート制限）
     let jpyc_guest_payment_routes = Router::new()
         .route("/api/v1/payments/jpyc/guest/prepare", post(handlers::jpyc::prepare_jpyc_payment_guest))
         .route("/api/v1/payments/jpyc/guest/verify", post(handlers::jpyc::verify_jpyc_payment_guest))
         .route("/api/v1/payments/jpyc/info", get(handlers::jpyc::get_jpyc_payment_info))
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware))
         .layer(middleware::from_fn(payment_rate_limiter_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     let auth_routes = Router::new()
         // プロファイル作成（Supabase Auth登録後にusersテーブルに追加）
@@ -108,57 +166,113 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/auth/mfa/verify", post(handlers::mfa::verify))
         .route("/api/v1/auth/mfa/unenroll", post(handlers::mfa::unenroll))
         .route("/api/v1/auth/mfa/factors", get(handlers::mfa::list_factors))
+        // パスキー管理
+        .route("/api/v1/auth/passkeys", get(handlers::passkeys::list_passkeys))
+        .route("/api/v1/auth/passkeys/:id", delete(handlers::passkeys::delete_passkey))
+        .route("/api/v1/auth/passkeys/register/options", post(handlers::passkeys::registration_options))
+        .route("/api/v1/auth/passkeys/register", post(handlers::passkeys::register))
         // ユーザー
         .route("/api/v1/users/me", get(handlers::users::get_me))
         .route("/api/v1/users/me", put(handlers::users::update_me))
+        .route("/api/v1/users/me", delete(handlers::users::delete_me))
+        .route("/api/v1/users/me/export", get(handlers::users::export_me))
+        .route("/api/v1/users/me/identities", get(handlers::oauth::list_identities))
+        .route("/api/v1/users/me/identities/link/:provider", post(handlers::oauth::link_identity))
+        .route("/api/v1/users/me/identities/:identity_id", delete(handlers::oauth::unlink_identity))
+        .route("/api/v1/users/me/sessions", get(handlers::sessions::list_sessions))
+        .route("/api/v1/users/me/sessions/revoke-others", post(handlers::sessions::revoke_other_sessions))
+        .route("/api/v1/users/me/sessions/:id", delete(handlers::sessions::revoke_session))
         .route("/api/v1/users/me/addresses", get(handlers::users::list_addresses))
         .route("/api/v1/users/me/addresses", post(handlers::users::create_address))
         .route("/api/v1/users/me/addresses/:id", put(handlers::users::update_address))
         .route("/api/v1/users/me/addresses/:id", delete(handlers::users::delete_address))
+        .route("/api/v1/users/me/alerts", get(handlers::product_alerts::list_my_alerts))
+        .route("/api/v1/users/me/alerts/:id", delete(handlers::product_alerts::cancel_my_alert))
         // カート（認証：統合機能）
         .route("/api/v1/cart/merge", post(handlers::cart::merge_cart))
+        // ウィッシュリスト（認証：統合・共有リンク）
+        .route("/api/v1/wishlist/merge", post(handlers::wishlist::merge_wishlist))
+        .route("/api/v1/wishlist/share", post(handlers::wishlist::create_share_link))
+        .route("/api/v1/wishlist/share", delete(handlers::wishlist::revoke_share_link))
         // 注文
-        .route("/api/v1/orders", post(handlers::orders::create_order))
+        .route(
+            "/api/v1/orders",
+            post(handlers::orders::create_order)
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware)),
+        )
         .route("/api/v1/orders", get(handlers::orders::list_orders))
         .route("/api/v1/orders/:id", get(handlers::orders::get_order))
         .route("/api/v1/orders/:id/cancel", post(handlers::orders::cancel_order))
+        // 発送前の注文変更（配送先・サイズ交換、差額は追加決済または一部返金）
+        .route(
+            "/api/v1/orders/:id/modifications",
+            post(handlers::order_modifications::modify_order)
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware)),
+        )
+        .route("/api/v1/orders/:id/modifications", get(handlers::order_modifications::list_order_modifications))
         .route("/api/v1/orders/by-payment/:payment_intent_id", get(handlers::orders::get_order_by_payment_intent))
+        // ゲスト注文の紐付け（確認済みメールアドレスと一致するもの）
+        .route("/api/v1/orders/guest/claim", post(handlers::orders::claim_guest_orders))
         // レビュー（投稿は認証必要）
         .route("/api/v1/products/:id/reviews", post(handlers::reviews::create_review))
         // ミドルウェア（外側から: BFF検証 → セッション署名検証 → 認証）
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
 
     // 管理者専用ルート
     let admin_routes = Router::new()
         // ユーザー管理
         .route("/api/v1/admin/users", get(handlers::users::list_users_admin))
         .route("/api/v1/admin/users/:id", patch(handlers::users::update_user_admin))
+        .route("/api/v1/admin/users/:id/unlock", post(handlers::users::unlock_user_login_admin))
         // 注文管理
         .route("/api/v1/admin/orders", get(handlers::orders::list_orders_admin))
+        .route("/api/v1/admin/orders/fulfillment", get(handlers::orders::list_fulfillment_queue_admin))
         .route("/api/v1/admin/orders/:id", get(handlers::orders::get_order_admin))
         .route("/api/v1/admin/orders/:id/status", patch(handlers::orders::update_order_status_admin))
         // 商品管理
         .route("/api/v1/admin/products", post(handlers::products::create_product))
         .route("/api/v1/admin/products/:id", put(handlers::products::update_product))
         .route("/api/v1/admin/products/:id", delete(handlers::products::delete_product))
+        .route("/api/v1/admin/products/:id/preorders/release", post(handlers::products::release_preorders))
         // バリアント管理
         .route("/api/v1/admin/products/:id/variants", post(handlers::products::create_variants))
         .route("/api/v1/admin/products/:id/variants/:variant_id", put(handlers::products::update_variant))
         .route("/api/v1/admin/products/:id/variants/:variant_id", delete(handlers::products::delete_variant))
+        // カゴ落ち回復
+        .route("/api/v1/admin/cart-recovery/stats", get(handlers::cart::get_recovery_stats_admin))
         // お問い合わせ管理
         .route("/api/v1/admin/contacts", get(handlers::contact::list_contacts))
         .route("/api/v1/admin/contacts/:id", get(handlers::contact::get_contact))
         .route("/api/v1/admin/contacts/:id", put(handlers::contact::update_contact_status))
+        // ドメインイベント（アウトボックス）
+        .route("/api/v1/admin/events", get(handlers::events::list_events_admin))
+        .route("/api/v1/admin/events/:id/retry", post(handlers::events::retry_event_admin))
+        // 外部連携Webhook
+        .route("/api/v1/admin/webhooks", get(handlers::webhooks::list_webhooks))
+        .route("/api/v1/admin/webhooks", post(handlers::webhooks::create_webhook))
+        .route("/api/v1/admin/webhooks/:id", patch(handlers::webhooks::update_webhook))
+        .route("/api/v1/admin/webhooks/:id", delete(handlers::webhooks::delete_webhook))
+        .route("/api/v1/admin/webhooks/:id/deliveries", get(handlers::webhooks::list_deliveries))
+        .route("/api/v1/admin/webhooks/deliveries/:id/replay", post(handlers::webhooks::replay_delivery))
         // ミドルウェア（外側から: BFF検証 → セッション署名検証 → 認証 → 管理者権限）
         .layer(middleware::from_fn_with_state(state.clone(), admin_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
-        .layer(middleware::from_fn(session_signature_middleware))
-        .layer(middleware::from_fn(bff_proxy_token_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), session_signature_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), bff_proxy_token_middleware));
+
+    // Prometheusメトリクス（スクレイパーから直接取得するためBFF検証なし、METRICS_TOKENで保護）
+    let metrics_routes = Router::new()
+        .route("/metrics", get(handlers::metrics::export_metrics));
 
     Router::new()
         .merge(public_routes)
+        .merge(passkey_login_routes)
+        .merge(wishlist_routes)
+        .merge(product_alert_routes)
+        .merge(checkout_routes)
+        .merge(metrics_routes)
         .merge(guest_order_routes)
         .merge(contact_routes)
         .merge(payment_routes)
@@ -0,0 +1,173 @@
+//! 退会（アカウント削除）の実行と再開
+//! トークン無効化 → 個人データ匿名化 → Supabase Authユーザー削除 の順に進め、
+//! 途中で失敗した退会は users の進捗カラムをもとに定期ジョブで再開する
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::{AccountAnonymizeResult, TokenBlacklistRepository, UserRepository};
+use crate::error::Result;
+
+/// 再開ジョブの実行間隔
+const RESUME_INTERVAL_SECS: u64 = 600;
+/// 受付からこの時間を過ぎても完了していない退会を再開対象にする（処理中のリクエストと競合しないため）
+const RESUME_GRACE_SECS: i64 = 600;
+/// 1回の実行で再開する件数
+const RESUME_BATCH_SIZE: usize = 50;
+
+/// 発行済みトークンを全て無効化（以降のリクエストを即座に拒否）
+pub async fn revoke_all_tokens(state: &AppState, user_id: Uuid) -> Result<()> {
+    let blacklist_repo = TokenBlacklistRepository::new(state.db.service());
+    if !blacklist_repo.is_user_blacklisted(user_id).await? {
+        let expires_at = Utc::now() + chrono::Duration::seconds(state.config.jwt.refresh_token_expiry);
+        blacklist_repo.blacklist_all_user_tokens(user_id, expires_at).await?;
+    }
+    Ok(())
+}
+
+/// 個人データを匿名化（再実行しても結果は変わらない）
+pub async fn anonymize(state: &AppState, user_id: Uuid) -> Result<AccountAnonymizeResult> {
+    UserRepository::new(state.db.service())
+        .anonymize_account(user_id)
+        .await
+}
+
+/// Supabase Authユーザーを削除し、完了を記録
+pub async fn delete_auth_user(state: &AppState, user_id: Uuid) -> Result<()> {
+    state.db.delete_auth_user(user_id).await?;
+    UserRepository::new(state.db.service())
+        .mark_auth_user_deleted(user_id)
+        .await
+}
+
+/// 退会の各手順（再開処理のテストではメモリ上の実装に差し替える）
+#[async_trait]
+pub trait AccountDeletionSteps: Send + Sync {
+    async fn revoke_all_tokens(&self, user_id: Uuid) -> Result<()>;
+    async fn anonymize(&self, user_id: Uuid) -> Result<AccountAnonymizeResult>;
+    async fn delete_auth_user(&self, user_id: Uuid) -> Result<()>;
+}
+
+#[async_trait]
+impl AccountDeletionSteps for AppState {
+    async fn revoke_all_tokens(&self, user_id: Uuid) -> Result<()> {
+        revoke_all_tokens(self, user_id).await
+    }
+
+    async fn anonymize(&self, user_id: Uuid) -> Result<AccountAnonymizeResult> {
+        anonymize(self, user_id).await
+    }
+
+    async fn delete_auth_user(&self, user_id: Uuid) -> Result<()> {
+        delete_auth_user(self, user_id).await
+    }
+}
+
+/// 完了していない退会を定期的に再開
+pub fn spawn_account_deletion_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(RESUME_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+
+            let requested_before = Utc::now() - chrono::Duration::seconds(RESUME_GRACE_SECS);
+            let pending = match UserRepository::new(state.db.service())
+                .find_pending_deletions(requested_before, RESUME_BATCH_SIZE)
+                .await
+            {
+                Ok(pending) => pending,
+                Err(e) => {
+                    tracing::warn!("account_deletion: failed to find pending deletions: {}", e);
+                    continue;
+                }
+            };
+
+            for deletion in pending {
+                if let Err(e) = resume(&state, deletion.id, deletion.deleted_at.is_some()).await {
+                    tracing::warn!("account_deletion: failed to resume user_id={}: {}", deletion.id, e);
+                    continue;
+                }
+                tracing::info!("Account deletion resumed and completed: user_id={}", deletion.id);
+            }
+        }
+    });
+}
+
+/// 中断した退会を残りの手順から再開
+async fn resume(steps: &dyn AccountDeletionSteps, user_id: Uuid, anonymized: bool) -> Result<()> {
+    if !anonymized {
+        steps.revoke_all_tokens(user_id).await?;
+        steps.anonymize(user_id).await?;
+    }
+    steps.delete_auth_user(user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::error::AppError;
+
+    /// 実行した手順を記録する実装
+    #[derive(Default)]
+    struct RecordingSteps {
+        calls: Mutex<Vec<&'static str>>,
+        fail_anonymize: bool,
+    }
+
+    impl RecordingSteps {
+        fn calls(&self) -> Vec<&'static str> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl AccountDeletionSteps for RecordingSteps {
+        async fn revoke_all_tokens(&self, _user_id: Uuid) -> Result<()> {
+            self.calls.lock().unwrap().push("revoke");
+            Ok(())
+        }
+
+        async fn anonymize(&self, _user_id: Uuid) -> Result<AccountAnonymizeResult> {
+            self.calls.lock().unwrap().push("anonymize");
+            if self.fail_anonymize {
+                return Err(AppError::Internal("boom".to_string()));
+            }
+            Ok(AccountAnonymizeResult::default())
+        }
+
+        async fn delete_auth_user(&self, _user_id: Uuid) -> Result<()> {
+            self.calls.lock().unwrap().push("delete_auth_user");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_before_anonymize() {
+        // 匿名化前に中断: トークン無効化から全手順をやり直す
+        let steps = RecordingSteps::default();
+        resume(&steps, Uuid::new_v4(), false).await.unwrap();
+        assert_eq!(steps.calls(), vec!["revoke", "anonymize", "delete_auth_user"]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_anonymize() {
+        // 匿名化後に中断: Authユーザー削除のみ
+        let steps = RecordingSteps::default();
+        resume(&steps, Uuid::new_v4(), true).await.unwrap();
+        assert_eq!(steps.calls(), vec!["delete_auth_user"]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_stops_when_anonymize_fails() {
+        // 匿名化に失敗した場合はAuthユーザーを削除しない（次回の実行で再開）
+        let steps = RecordingSteps { fail_anonymize: true, ..Default::default() };
+        assert!(resume(&steps, Uuid::new_v4(), false).await.is_err());
+        assert_eq!(steps.calls(), vec!["revoke", "anonymize"]);
+    }
+}
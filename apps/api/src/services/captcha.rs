@@ -0,0 +1,43 @@
+//! Cloudflare Turnstile によるCAPTCHA検証
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::config::CaptchaConfig;
+
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+#[derive(Debug, Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Turnstileのシークレットキー（未設定時はCAPTCHA検証を行わない）
+pub fn get_turnstile_secret(config: &CaptchaConfig) -> Option<String> {
+    config
+        .turnstile_secret_key
+        .as_ref()
+        .filter(|k| !k.is_empty())
+        .map(|k| k.expose().to_string())
+}
+
+/// Turnstileトークンを検証
+pub async fn verify_turnstile(secret: &str, token: &str, remote_ip: &str) -> Result<bool> {
+    let res: SiteVerifyResponse = reqwest::Client::new()
+        .post(TURNSTILE_VERIFY_URL)
+        .form(&[("secret", secret), ("response", token), ("remoteip", remote_ip)])
+        .send()
+        .await
+        .context("turnstile request failed")?
+        .json()
+        .await
+        .context("turnstile response parse failed")?;
+
+    if !res.success {
+        tracing::info!("Turnstile verification failed: {:?}", res.error_codes);
+    }
+
+    Ok(res.success)
+}
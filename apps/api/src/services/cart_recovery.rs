@@ -0,0 +1,170 @@
+//! カゴ落ち（放置カート）の回復
+//! ログインユーザーの放置カートを定期的に検出し、署名付きの回復リンクをメールで送る。
+//! 回復リンクは `{回復ID}.{有効期限(unix秒)}.{HMAC}` 形式（鍵はセッション署名と同じ）
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::{CartRecoveryRepository, CartRepository, NewCartRecovery};
+use crate::models::{AbandonedCart, Order};
+use crate::services::email::send_email;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn token_mac(id: Uuid, expires_at: i64, secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(format!("cart_recovery.{}.{}", id, expires_at).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 回復トークンを生成
+pub fn sign_recovery_token(id: Uuid, expires_at: DateTime<Utc>, secret: &str) -> String {
+    let expires_at = expires_at.timestamp();
+    format!("{}.{}.{}", id, expires_at, token_mac(id, expires_at, secret))
+}
+
+/// 回復トークンを検証し、回復IDを返す（署名不一致・期限切れはNone）
+pub fn verify_recovery_token(token: &str, secret: &str, now: DateTime<Utc>) -> Option<Uuid> {
+    let mut parts = token.splitn(3, '.');
+    let id: Uuid = parts.next()?.parse().ok()?;
+    let expires_at: i64 = parts.next()?.parse().ok()?;
+    let signature = parts.next()?;
+
+    let expected = token_mac(id, expires_at, secret);
+    if !bool::from(expected.as_bytes().ct_eq(signature.as_bytes())) || now.timestamp() > expires_at {
+        return None;
+    }
+    Some(id)
+}
+
+/// 復元したセッションで作成された注文をコンバージョンとして記録（失敗しても注文処理は止めない）
+pub async fn record_conversion(state: &AppState, session_id: &str, order: &Order) {
+    match CartRecoveryRepository::new(state.db.service())
+        .mark_converted(session_id, order.id, order.total)
+        .await
+    {
+        Ok(0) => {}
+        Ok(_) => tracing::info!("cart_recovery: order {} converted from recovered cart", order.id),
+        Err(e) => tracing::warn!("cart_recovery: failed to record conversion for order {}: {}", order.id, e),
+    }
+}
+
+/// 定期ジョブを起動（cart_recovery.enabled のときのみ）
+pub fn spawn_cart_recovery_job(state: AppState) {
+    let config = state.config.cart_recovery.clone();
+    if !config.enabled {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_seconds.max(30)));
+        loop {
+            ticker.tick().await;
+
+            let repo = CartRecoveryRepository::new(state.db.service());
+            let carts = match repo
+                .find_abandoned(config.idle_seconds, config.max_age_seconds, config.batch_size.clamp(1, 500))
+                .await
+            {
+                Ok(carts) => carts,
+                Err(e) => {
+                    tracing::warn!("cart_recovery: failed to find abandoned carts: {}", e);
+                    continue;
+                }
+            };
+
+            for cart in carts {
+                if let Err(e) = send_recovery_link(&state, &repo, cart).await {
+                    tracing::warn!("cart_recovery: failed to process cart: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// 1件の放置カートに回復リンクを送る
+async fn send_recovery_link(
+    state: &AppState,
+    repo: &CartRecoveryRepository,
+    abandoned: AbandonedCart,
+) -> anyhow::Result<()> {
+    let config = &state.config.cart_recovery;
+    let cart = CartRepository::new(state.db.service())
+        .find_by_session(&abandoned.session_id)
+        .await?;
+    if cart.items.is_empty() {
+        return Ok(());
+    }
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(config.link_ttl_seconds);
+    let Some(recovery) = repo
+        .create(&NewCartRecovery {
+            session_id: abandoned.session_id,
+            user_id: abandoned.user_id,
+            email: abandoned.email.clone(),
+            cart_updated_at: abandoned.cart_updated_at,
+            item_count: cart.item_count,
+            subtotal: cart.subtotal,
+            items: cart.items,
+            expires_at,
+        })
+        .await?
+    else {
+        // 別インスタンスが処理済み
+        return Ok(());
+    };
+
+    let token = sign_recovery_token(recovery.id, expires_at, state.config.session_secret());
+    let link = format!("{}?token={}", config.link_base_url, token);
+    let names: Vec<&str> = recovery.items.iter().map(|i| i.product_name.as_str()).collect();
+    let body = format!(
+        "カートに商品が残っています。\n\n・{}\n\n小計: ¥{}\n\n以下のリンクからカートを復元してお買い物を続けられます（{}まで有効）。\n{}\n",
+        names.join("\n・"),
+        recovery.subtotal,
+        expires_at
+            .with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).expect("valid offset"))
+            .format("%Y/%m/%d %H:%M"),
+        link
+    );
+
+    match send_email(&state.config.email, &abandoned.email, "【Spirom】カートに商品が残っています", &body).await {
+        Ok(()) => {
+            repo.mark_sent(recovery.id).await?;
+            tracing::info!("cart_recovery: sent recovery link: id={}", recovery.id);
+        }
+        Err(e) => {
+            tracing::warn!("cart_recovery: email failed: id={}, error={:#}", recovery.id, e);
+            repo.mark_failed(recovery.id, &format!("{:#}", e)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_token_roundtrip() {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = sign_recovery_token(id, now + chrono::Duration::hours(1), "secret");
+
+        assert_eq!(verify_recovery_token(&token, "secret", now), Some(id));
+        assert_eq!(verify_recovery_token(&token, "other", now), None);
+        assert_eq!(verify_recovery_token(&token, "secret", now + chrono::Duration::hours(2)), None);
+
+        // 有効期限の改ざん
+        let (head, mac) = token.rsplit_once('.').unwrap();
+        let (id_part, _) = head.split_once('.').unwrap();
+        let forged = format!("{}.{}.{}", id_part, (now + chrono::Duration::days(30)).timestamp(), mac);
+        assert_eq!(verify_recovery_token(&forged, "secret", now), None);
+        assert_eq!(verify_recovery_token("garbage", "secret", now), None);
+    }
+}
@@ -0,0 +1,34 @@
+//! トランザクションメール送信
+//! Resend互換のHTTP API（POST /emails）を使用。未設定時は送信せずログのみ
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::EmailConfig;
+
+/// テキストメールを送信
+pub async fn send_email(config: &EmailConfig, to: &str, subject: &str, text: &str) -> Result<()> {    let Some(api_key) = config.api_key.as_ref().filter(|k| !k.is_empty()) else {
+        tracing::warn!("EMAIL_API_KEY is not set; email not sent: subject={}", subject);
+        return Ok(());
+    };
+
+    let res = reqwest::Client::new()
+        .post(&config.api_url)
+        .bearer_auth(api_key.expose())
+        .json(&serde_json::json!({
+            "from": config.from,
+            "to": [to],
+            "subject": subject,
+            "text": text
+        }))
+        .send()
+        .await
+        .context("email request failed")?;
+
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        bail!("email send failed: {} {}", status, body);
+    }
+
+    Ok(())
+}
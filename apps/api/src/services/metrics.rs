@@ -0,0 +1,204 @@
+//! Prometheusメトリクス（`GET /metrics` で出力）
+//! ラベルは値の種類が有限なもの（ルートテンプレート・操作名・エラーコード等）のみ使う
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    supabase_duration: HistogramVec,
+    supabase_errors: IntCounterVec,
+    rate_limit_rejections: IntCounterVec,
+    payment_webhook_events: IntCounterVec,
+    outgoing_webhook_deliveries: IntCounterVec,
+    reconciler_actions: IntCounterVec,
+    stock_reservation_failures: IntCounterVec,
+    jpyc_verifications: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric definition");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("metric registered once");
+    counter
+}
+
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let histogram = HistogramVec::new(
+        HistogramOpts::new(name, help)
+            .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        labels,
+    )
+    .expect("valid metric definition");
+    registry
+        .register(Box::new(histogram.clone()))
+        .expect("metric registered once");
+    histogram
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("spirom".to_string()), None).expect("valid registry prefix");
+
+        Self {
+            http_requests: counter(
+                &registry,
+                "http_requests_total",
+                "HTTP requests by route and status",
+                &["method", "route", "status"],
+            ),
+            http_duration: histogram(
+                &registry,
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+                &["method", "route"],
+            ),
+            supabase_duration: histogram(
+                &registry,
+                "supabase_request_duration_seconds",
+                "Supabase REST call latency (including retries)",
+                &["operation", "outcome"],
+            ),
+            supabase_errors: counter(
+                &registry,
+                "supabase_errors_total",
+                "Supabase REST errors by SQLSTATE / PGRST code",
+                &["operation", "code"],
+            ),
+            rate_limit_rejections: counter(
+                &registry,
+                "rate_limit_rejections_total",
+                "Requests rejected by the rate limiter",
+                &["policy"],
+            ),
+            payment_webhook_events: counter(
+                &registry,
+                "payment_webhook_events_total",
+                "Incoming payment provider webhook events",
+                &["event_type", "outcome"],
+            ),
+            outgoing_webhook_deliveries: counter(
+                &registry,
+                "outgoing_webhook_deliveries_total",
+                "Outgoing partner webhook delivery attempts",
+                &["event_type", "outcome"],
+            ),
+            reconciler_actions: counter(
+                &registry,
+                "payment_reconciler_actions_total",
+                "Actions taken by the payment reconciler",
+                &["action"],
+            ),
+            stock_reservation_failures: counter(
+                &registry,
+                "stock_reservation_failures_total",
+                "Checkouts rejected because stock could not be reserved",
+                &["flow"],
+            ),
+            jpyc_verifications: counter(
+                &registry,
+                "jpyc_verifications_total",
+                "JPYC transaction verification results",
+                &["result"],
+            ),
+            registry,
+        }
+    }
+
+    /// Prometheusテキスト形式で出力
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::warn!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// グローバルなメトリクス（初回アクセス時に登録）
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+pub fn record_http_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    let m = metrics();
+    m.http_requests
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    m.http_duration
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_supabase_call(operation: &str, success: bool, elapsed: Duration) {
+    metrics()
+        .supabase_duration
+        .with_label_values(&[operation, if success { "ok" } else { "error" }])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// code はSQLSTATE/PGRSTコード（不明時は "http_<status>"）
+pub fn record_supabase_error(operation: &str, code: &str) {
+    metrics().supabase_errors.with_label_values(&[operation, code]).inc();
+}
+
+pub fn record_rate_limit_rejection(policy: &str) {
+    metrics().rate_limit_rejections.with_label_values(&[policy]).inc();
+}
+
+/// outcome: processed / ignored / invalid_signature / error
+pub fn record_payment_webhook(event_type: &str, outcome: &str) {
+    metrics()
+        .payment_webhook_events
+        .with_label_values(&[event_type, outcome])
+        .inc();
+}
+
+/// outcome: succeeded / failed / dead
+pub fn record_outgoing_webhook(event_type: &str, outcome: &str) {
+    metrics()
+        .outgoing_webhook_deliveries
+        .with_label_values(&[event_type, outcome])
+        .inc();
+}
+
+/// action: marked_paid / cancelled / refunded_mismatch
+pub fn record_reconciler_action(action: &str) {
+    metrics().reconciler_actions.with_label_values(&[action]).inc();
+}
+
+/// flow: order / payment_intent / webhook / jpyc 等
+pub fn record_stock_reservation_failure(flow: &str) {
+    metrics().stock_reservation_failures.with_label_values(&[flow]).inc();
+}
+
+/// result: verified / pending / failed 等
+pub fn record_jpyc_verification(result: &str) {
+    metrics().jpyc_verifications.with_label_values(&[result]).inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_recorded_metrics() {
+        record_http_request("GET", "/api/v1/products/:id", 200, Duration::from_millis(12));
+        record_rate_limit_rejection("auth");
+
+        let output = metrics().render();
+        assert!(output.contains(
+            "spirom_http_requests_total{method=\"GET\",route=\"/api/v1/products/:id\",status=\"200\"} 1"
+        ));
+        assert!(output.contains("spirom_rate_limit_rejections_total{policy=\"auth\"} 1"));
+    }
+}
@@ -1,5 +1,14 @@
+pub mod account_deletion;
+pub mod captcha;
+pub mod cart_recovery;
+pub mod email;
+pub mod metrics;
+pub mod outbox;
 pub mod password;
 pub mod payment;
+pub mod product_alerts;
+pub mod webauthn;
+pub mod webhooks;
 
 pub use password::*;
 pub use payment::*;
@@ -0,0 +1,362 @@
+//! ドメインイベントのディスパッチャー（トランザクショナル・アウトボックス）
+//! DBトリガーが domain_events に書き込んだイベントをポーリングし、プロセス内の購読者へ配信する。
+//! 配信は at-least-once。失敗した購読者のみ指数バックオフで再試行し、上限を超えたらデッドレター（status=dead）にする
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::config::{AppState, EmailConfig};
+use crate::db::repositories::{DomainEventRepository, DomainEventStore, OrderRepository};
+use crate::models::{DomainEvent, DomainEventType};
+use crate::services::email::send_email;
+use crate::services::product_alerts::ProductAlertSubscriber;
+use crate::services::webhooks::WebhookFanoutSubscriber;
+
+/// イベント購読者
+#[async_trait]
+pub trait EventSubscriber: Send + Sync {
+    /// 購読者名（completed_subscribers に記録される）
+    fn name(&self) -> &'static str;
+
+    fn handles(&self, event_type: DomainEventType) -> bool;
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()>;
+}
+
+/// 再試行までの待機時間（attempts回目の失敗後、5秒から倍々で最大1時間）
+pub(crate) fn retry_delay(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.clamp(1, 20) as u32 - 1;
+    let seconds = 5i64.saturating_mul(1i64 << exponent).min(3600);
+    chrono::Duration::seconds(seconds)
+}
+
+/// 既定の購読者
+fn default_subscribers(state: &AppState) -> Vec<Arc<dyn EventSubscriber>> {
+    vec![
+        Arc::new(StockReleaseSubscriber { state: state.clone() }),
+        Arc::new(StockLowAlertSubscriber {
+            email: state.config.email.clone(),
+            to: state.config.outbox.stock_alert_email.clone().filter(|s| !s.trim().is_empty()),
+        }),
+        Arc::new(WebhookFanoutSubscriber { state: state.clone() }),
+        Arc::new(ProductAlertSubscriber { state: state.clone() }),
+    ]
+}
+
+/// ディスパッチャーを起動
+/// 間隔・バッチサイズ・最大試行回数は outbox.*（OUTBOX_*）
+pub fn spawn_outbox_dispatcher(state: AppState) {
+    let interval_ms = state.config.outbox.poll_interval_ms.max(200);
+    let batch_size = state.config.outbox.batch_size.clamp(1, 500);
+    let max_attempts = state.config.outbox.max_attempts.max(1);
+    // 購読者の処理がこの時間を超えた場合は別インスタンスが再取得する
+    let lock_seconds = 300;
+    let subscribers = default_subscribers(&state);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+        loop {
+            ticker.tick().await;
+
+            let repo = DomainEventRepository::new(state.db.service());
+            let events = match repo.claim_batch(batch_size, lock_seconds).await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!("outbox: failed to claim events: {}", e);
+                    continue;
+                }
+            };
+
+            for event in events {
+                dispatch(&repo, &subscribers, event, max_attempts).await;
+            }
+        }
+    });
+}
+
+/// 1イベントを未完了の購読者へ配信し、結果を記録する
+async fn dispatch(
+    repo: &dyn DomainEventStore,
+    subscribers: &[Arc<dyn EventSubscriber>],
+    event: DomainEvent,
+    max_attempts: i32,
+) {
+    let mut completed = event.completed_subscribers.clone();
+    let mut errors = Vec::new();
+
+    for subscriber in subscribers {
+        if !subscriber.handles(event.event_type) || completed.iter().any(|c| c == subscriber.name()) {
+            continue;
+        }
+        match subscriber.handle(&event).await {
+            Ok(()) => completed.push(subscriber.name().to_string()),
+            Err(e) => errors.push(format!("{}: {:#}", subscriber.name(), e)),
+        }
+    }
+
+    let result = if errors.is_empty() {
+        repo.mark_delivered(event.id, &completed).await
+    } else {
+        let error = errors.join("; ");
+        let next_attempt_at = if event.attempts >= max_attempts {
+            tracing::error!(
+                "outbox: event dead-lettered: id={}, type={:?}, attempts={}, error={}",
+                event.id,
+                event.event_type,
+                event.attempts,
+                error
+            );
+            None
+        } else {
+            tracing::warn!(
+                "outbox: delivery failed (will retry): id={}, type={:?}, attempts={}, error={}",
+                event.id,
+                event.event_type,
+                event.attempts,
+                error
+            );
+            Some(Utc::now() + retry_delay(event.attempts))
+        };
+        repo.mark_failed(event.id, &completed, &error, next_attempt_at).await
+    };
+
+    if let Err(e) = result {
+        // ロック期限切れで再取得される（完了済み購読者は記録されないため再実行されうる）
+        tracing::warn!("outbox: failed to record delivery result: id={}, error={}", event.id, e);
+    }
+}
+
+/// 注文キャンセル時の在庫解放（release_order_stock は冪等）
+struct StockReleaseSubscriber {
+    state: AppState,
+}
+
+#[async_trait]
+impl EventSubscriber for StockReleaseSubscriber {
+    fn name(&self) -> &'static str {
+        "stock_release"
+    }
+
+    fn handles(&self, event_type: DomainEventType) -> bool {
+        event_type == DomainEventType::OrderCancelled
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let released = OrderRepository::new(self.state.db.service())
+            .release_stock(event.aggregate_id)
+            .await?;
+        if released {
+            tracing::info!("outbox: released stock for cancelled order {}", event.aggregate_id);
+        }
+        Ok(())
+    }
+}
+
+/// 在庫僅少の通知（STOCK_ALERT_EMAIL 未設定時はログのみ）
+struct StockLowAlertSubscriber {
+    email: EmailConfig,
+    to: Option<String>,
+}
+
+#[async_trait]
+impl EventSubscriber for StockLowAlertSubscriber {
+    fn name(&self) -> &'static str {
+        "stock_low_alert"
+    }
+
+    fn handles(&self, event_type: DomainEventType) -> bool {
+        event_type == DomainEventType::StockLow
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let name = event.payload["name"].as_str().unwrap_or_default();
+        let sku = event.payload["sku"].as_str().unwrap_or_default();
+        let stock = event.payload["stock"].as_i64().unwrap_or_default();
+        tracing::warn!("Stock low: product_id={}, sku={}, stock={}", event.aggregate_id, sku, stock);
+
+        if let Some(to) = &self.to {
+            send_email(
+                &self.email,
+                to,
+                &format!("【Spirom】在庫僅少: {}", name),
+                &format!("商品「{}」（SKU: {}）の在庫が残り{}点になりました。", name, sku, stock),
+            )
+            .await
+            .context("stock alert email")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::DateTime;
+    use uuid::Uuid;
+
+    use crate::models::DomainEventStatus;
+
+    /// 記録された配信結果
+    #[derive(Debug, PartialEq)]
+    enum Recorded {
+        Delivered(Vec<String>),
+        Failed {
+            completed: Vec<String>,
+            error: String,
+            next_attempt_at: Option<DateTime<Utc>>,
+        },
+    }
+
+    /// メモリ上の記録先
+    #[derive(Default)]
+    struct MemoryStore {
+        results: Mutex<Vec<Recorded>>,
+    }
+
+    #[async_trait]
+    impl DomainEventStore for MemoryStore {
+        async fn mark_delivered(
+            &self,
+            _id: Uuid,
+            completed_subscribers: &[String],
+        ) -> crate::error::Result<()> {
+            self.results.lock().unwrap().push(Recorded::Delivered(completed_subscribers.to_vec()));
+            Ok(())
+        }
+
+        async fn mark_failed(
+            &self,
+            _id: Uuid,
+            completed_subscribers: &[String],
+            error: &str,
+            next_attempt_at: Option<DateTime<Utc>>,
+        ) -> crate::error::Result<()> {
+            self.results.lock().unwrap().push(Recorded::Failed {
+                completed: completed_subscribers.to_vec(),
+                error: error.to_string(),
+                next_attempt_at,
+            });
+            Ok(())
+        }
+    }
+
+    /// 呼び出し回数を数える購読者
+    struct TestSubscriber {
+        name: &'static str,
+        event_type: DomainEventType,
+        fail: bool,
+        calls: AtomicUsize,
+    }
+
+    impl TestSubscriber {
+        fn new(name: &'static str, event_type: DomainEventType, fail: bool) -> Arc<Self> {
+            Arc::new(Self { name, event_type, fail, calls: AtomicUsize::new(0) })
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl EventSubscriber for TestSubscriber {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn handles(&self, event_type: DomainEventType) -> bool {
+            event_type == self.event_type
+        }
+
+        async fn handle(&self, _event: &DomainEvent) -> anyhow::Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                anyhow::bail!("boom");
+            }
+            Ok(())
+        }
+    }
+
+    fn event(event_type: DomainEventType, attempts: i32, completed: &[&str]) -> DomainEvent {
+        let now = Utc::now();
+        DomainEvent {
+            id: Uuid::new_v4(),
+            event_type,
+            aggregate_type: "order".to_string(),
+            aggregate_id: Uuid::new_v4(),
+            payload: serde_json::json!({}),
+            status: DomainEventStatus::Processing,
+            attempts,
+            next_attempt_at: now,
+            completed_subscribers: completed.iter().map(|s| s.to_string()).collect(),
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_routes_to_pending_subscribers() {
+        let release = TestSubscriber::new("release", DomainEventType::OrderCancelled, false);
+        let stock = TestSubscriber::new("stock", DomainEventType::StockLow, false);
+        let done = TestSubscriber::new("done", DomainEventType::OrderCancelled, false);
+        let subscribers: Vec<Arc<dyn EventSubscriber>> = vec![release.clone(), stock.clone(), done.clone()];
+        let store = MemoryStore::default();
+
+        dispatch(&store, &subscribers, event(DomainEventType::OrderCancelled, 1, &["done"]), 3).await;
+
+        // 対象外の種別・完了済みの購読者は呼ばない
+        assert_eq!((release.calls(), stock.calls(), done.calls()), (1, 0, 0));
+        assert_eq!(
+            *store.results.lock().unwrap(),
+            vec![Recorded::Delivered(vec!["done".to_string(), "release".to_string()])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_retries_then_dead_letters() {
+        let failing = TestSubscriber::new("failing", DomainEventType::StockLow, true);
+        let ok = TestSubscriber::new("ok", DomainEventType::StockLow, false);
+        let subscribers: Vec<Arc<dyn EventSubscriber>> = vec![failing.clone(), ok.clone()];
+        let store = MemoryStore::default();
+
+        // 上限未満: 成功した購読者を記録し、バックオフ後に再試行
+        let before = Utc::now();
+        dispatch(&store, &subscribers, event(DomainEventType::StockLow, 1, &[]), 3).await;
+        match store.results.lock().unwrap().pop() {
+            Some(Recorded::Failed { completed, error, next_attempt_at: Some(next) }) => {
+                assert_eq!(completed, vec!["ok".to_string()]);
+                assert_eq!(error, "failing: boom");
+                assert!(next >= before + retry_delay(1) && next <= Utc::now() + retry_delay(1));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        // 再試行では成功済みの購読者を呼ばず、上限に達したらデッドレター
+        dispatch(&store, &subscribers, event(DomainEventType::StockLow, 3, &["ok"]), 3).await;
+        assert_eq!((failing.calls(), ok.calls()), (2, 1));
+        assert_eq!(
+            store.results.lock().unwrap().pop(),
+            Some(Recorded::Failed {
+                completed: vec!["ok".to_string()],
+                error: "failing: boom".to_string(),
+                next_attempt_at: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(5));
+        assert_eq!(retry_delay(2), chrono::Duration::seconds(10));
+        assert_eq!(retry_delay(5), chrono::Duration::seconds(80));
+        assert_eq!(retry_delay(20), chrono::Duration::seconds(3600));
+    }
+}
@@ -3,44 +3,35 @@ use std::time::Duration;
 use chrono::Utc;
 
 use crate::config::AppState;
-use crate::db::repositories::{OrderRepository, ProductRepository};
+use crate::db::repositories::{OrderModificationRepository, OrderRepository};
 use crate::models::{OrderStatus, PaymentStatus};
+use crate::services::metrics;
 
 use super::{PaymentProvider, PaymentResultStatus, StripePaymentProvider};
 
-fn env_i64(name: &str, default: i64) -> i64 {
-    std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
-}
-
-fn env_i32(name: &str, default: i32) -> i32 {
-    std::env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
-}
-
 /// Webhook不達/遅延に備えた「決済状態の回収」タスクを起動する
 /// - pending の注文を一定間隔で照合して、Paid/Cancelled を自動反映する
-/// - 在庫戻しは `update_status_if_current` で競合時の二重実行を防ぐ
+/// - 期限切れの注文変更の追加決済（PaymentIntent）を取り消す
+/// - ステータスは条件付き更新で競合を防ぎ、在庫解放はOrderCancelledイベントの購読者が行う
 pub fn spawn_payment_reconciler(state: AppState) {
-    let interval_seconds = env_i64("PAYMENT_RECONCILE_INTERVAL_SECONDS", 60).max(10);
-    let min_age_seconds = env_i64("PAYMENT_RECONCILE_MIN_AGE_SECONDS", 30).max(0);
-    let batch_size = env_i32("PAYMENT_RECONCILE_BATCH_SIZE", 50).clamp(1, 200);
-    let max_age_seconds = env_i64("PAYMENT_INTENT_MAX_AGE_SECONDS", 1800).max(60);
+    let config = &state.config.reconciler;
+    let interval_seconds = config.interval_seconds.max(10);
+    let min_age_seconds = config.min_age_seconds.max(0);
+    let batch_size = config.batch_size.clamp(1, 200);
+    let max_age_seconds = config.payment_intent_max_age_seconds.max(60);
 
     tokio::spawn(async move {
         let mut ticker = tokio::time::interval(Duration::from_secs(interval_seconds as u64));
         loop {
             ticker.tick().await;
 
-            let stripe_key = match std::env::var("STRIPE_SECRET_KEY") {
-                Ok(v) if !v.trim().is_empty() => v,
-                _ => continue, // Stripe未設定環境では何もしない
+            let Some(provider) = StripePaymentProvider::from_config(&state.config) else {
+                continue; // Stripe未設定環境では何もしない
             };
-            let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default();
-            let webhook_secrets = std::env::var("STRIPE_WEBHOOK_SECRETS").ok();
-            let provider = StripePaymentProvider::new(stripe_key, webhook_secret, webhook_secrets);
 
-            let db = state.db.service();
-            let order_repo = OrderRepository::new(db.clone());
-            let product_repo = ProductRepository::new(db);
+            expire_modification_payments(&state, &provider, batch_size as usize).await;
+
+            let order_repo = OrderRepository::new(state.db.service());
 
             let created_before = Utc::now() - chrono::Duration::seconds(min_age_seconds);
             let candidates = match order_repo
@@ -57,26 +48,10 @@ pub fn spawn_payment_reconciler(state: AppState) {
             for row in candidates {
                 let age_seconds = (Utc::now() - row.created_at).num_seconds();
 
-                // 1) PaymentIntent未作成で期限切れ：自動キャンセル + 在庫解放
+                // 1) PaymentIntent未作成で期限切れ：自動キャンセル
                 if row.payment_id.is_none() && age_seconds > max_age_seconds {
-                    if let Ok(Some(order)) = order_repo.find_by_id(row.id).await {
-                        let updated = match order_repo
-                            .update_status_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Cancelled)
-                            .await
-                        {
-                            Ok(v) => v,
-                            Err(e) => {
-                                tracing::warn!("payment reconciler: cancel status update failed: {}", e);
-                                continue;
-                            }
-                        };
-                        if updated {
-                            let _ = order_repo.update_payment_status(order.id, PaymentStatus::Failed).await;
-                            let release_items: Vec<(uuid::Uuid, i32)> =
-                                order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                            let _ = product_repo.release_stock_bulk(&release_items).await;
-                            tracing::info!("payment reconciler: cancelled expired order (no intent): {}", order.id);
-                        }
+                    if cancel(&order_repo, row.id).await {
+                        tracing::info!("payment reconciler: cancelled expired order (no intent): {}", row.id);
                     }
                     continue;
                 }
@@ -115,60 +90,39 @@ pub fn spawn_payment_reconciler(state: AppState) {
                             );
 
                             // 返金は非同期（タスク自体の遅延を防ぐ）
+                            metrics::record_reconciler_action("refunded_mismatch");
                             let refund_provider = provider.clone();
                             let pid = payment_id.clone();
                             tokio::spawn(async move {
-                                let _ = refund_provider.refund(&pid, None).await;
+                                if let Err(e) = refund_provider.refund(&pid, None).await {
+                                    tracing::error!("payment reconciler: refund failed: payment_id={}, err={}", pid, e);
+                                }
                             });
 
-                            let updated = order_repo
-                                .update_status_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Cancelled)
-                                .await
-                                .unwrap_or(false);
-                            if updated {
-                                let _ = order_repo.update_payment_status(order.id, PaymentStatus::Failed).await;
-                                let release_items: Vec<(uuid::Uuid, i32)> =
-                                    order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                                let _ = product_repo.release_stock_bulk(&release_items).await;
-                            }
+                            cancel(&order_repo, order.id).await;
                         } else {
-                            let updated = order_repo
-                                .update_status_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Paid)
+                            match order_repo
+                                .transition_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Paid, PaymentStatus::Paid)
                                 .await
-                                .unwrap_or(false);
-                            if updated {
-                                let _ = order_repo.update_payment_status(order.id, PaymentStatus::Paid).await;
-                                tracing::info!("payment reconciler: marked paid: {}", order.id);
+                            {
+                                Ok(true) => {
+                                    metrics::record_reconciler_action("marked_paid");
+                                    tracing::info!("payment reconciler: marked paid: {}", order.id);
+                                }
+                                Ok(false) => {}
+                                Err(e) => tracing::warn!("payment reconciler: paid update failed: order_id={}, err={}", order.id, e),
                             }
                         }
                     }
                     PaymentResultStatus::Failed => {
-                        let updated = order_repo
-                            .update_status_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Cancelled)
-                            .await
-                            .unwrap_or(false);
-                        if updated {
-                            let _ = order_repo.update_payment_status(order.id, PaymentStatus::Failed).await;
-                            let release_items: Vec<(uuid::Uuid, i32)> =
-                                order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                            let _ = product_repo.release_stock_bulk(&release_items).await;
+                        if cancel(&order_repo, order.id).await {
                             tracing::info!("payment reconciler: cancelled failed intent: {}", order.id);
                         }
                     }
                     PaymentResultStatus::Pending => {
                         // 期限超過ならキャンセル（在庫をいつまでも抱えない）
-                        if age_seconds > max_age_seconds {
-                            let updated = order_repo
-                                .update_status_if_current(order.id, OrderStatus::PendingPayment, OrderStatus::Cancelled)
-                                .await
-                                .unwrap_or(false);
-                            if updated {
-                                let _ = order_repo.update_payment_status(order.id, PaymentStatus::Failed).await;
-                                let release_items: Vec<(uuid::Uuid, i32)> =
-                                    order.items.iter().map(|i| (i.product_id, i.quantity)).collect();
-                                let _ = product_repo.release_stock_bulk(&release_items).await;
-                                tracing::info!("payment reconciler: cancelled timeout order: {}", order.id);
-                            }
+                        if age_seconds > max_age_seconds && cancel(&order_repo, order.id).await {
+                            tracing::info!("payment reconciler: cancelled timeout order: {}", order.id);
                         }
                     }
                 }
@@ -177,4 +131,69 @@ pub fn spawn_payment_reconciler(state: AppState) {
     });
 }
 
+/// 期限切れの注文変更の追加決済を取り消して expired にする
+/// 取り消せなかった場合（支払い済み・処理中・通信エラー）はWebhookでの適用に任せ、次回に持ち越す
+async fn expire_modification_payments(state: &AppState, provider: &StripePaymentProvider, batch_size: usize) {
+    let repo = OrderModificationRepository::new(state.db.service());
+    let expired = match repo.find_expired_awaiting_payment(Utc::now(), batch_size).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("payment reconciler: failed to list order modifications: {}", e);
+            return;
+        }
+    };
+
+    for modification in expired {
+        if let Some(payment_id) = modification.payment_id.as_deref() {
+            if let Err(e) = provider.cancel_intent(payment_id).await {
+                // 取り消し済みなら期限切れにしてよい
+                let cancelled = matches!(
+                    provider.retrieve_intent(payment_id).await,
+                    Ok(pi) if pi.status == PaymentResultStatus::Failed
+                );
+                if !cancelled {
+                    tracing::warn!(
+                        "payment reconciler: cancel modification intent failed: modification={}, payment_id={}, err={}",
+                        modification.id,
+                        payment_id,
+                        e
+                    );
+                    continue;
+                }
+            }
+        }
+
+        match repo.mark_expired(modification.id).await {
+            Ok(true) => {
+                metrics::record_reconciler_action("modification_expired");
+                tracing::info!("payment reconciler: expired order modification: {}", modification.id);
+            }
+            Ok(false) => {}
+            Err(e) => tracing::warn!(
+                "payment reconciler: expire modification failed: modification={}, err={}",
+                modification.id,
+                e
+            ),
+        }
+    }
+}
 
+/// 決済待ちの注文をキャンセル（status/payment_status を1回で更新、在庫はイベント経由で解放）
+/// 戻り値: この呼び出しで遷移したか
+async fn cancel(order_repo: &OrderRepository, order_id: uuid::Uuid) -> bool {
+    match order_repo
+        .transition_if_current(order_id, OrderStatus::PendingPayment, OrderStatus::Cancelled, PaymentStatus::Failed)
+        .await
+    {
+        Ok(updated) => {
+            if updated {
+                metrics::record_reconciler_action("cancelled");
+            }
+            updated
+        }
+        Err(e) => {
+            tracing::warn!("payment reconciler: cancel failed: order_id={}, err={}", order_id, e);
+            false
+        }
+    }
+}
@@ -3,43 +3,61 @@ use hmac::{Hmac, Mac};
 use sha2::{Sha256, Digest};
 
 use super::provider::*;
+use crate::config::Config;
+use crate::middleware::request_id_headers;
 
 /// Stripe決済プロバイダ
 #[derive(Clone)]
 pub struct StripePaymentProvider {
     api_key: String,
-    /// 互換用（単一secret）
-    webhook_secret: String,
-    /// ローテーション用（カンマ区切りで複数指定）
+    /// ローテーション用（複数指定。STRIPE_WEBHOOK_SECRET も含む）
     webhook_secrets: Vec<String>,
+    /// 署名タイムスタンプの許容範囲（秒）
+    webhook_tolerance_seconds: i64,
     client: reqwest::Client,
 }
 
 impl StripePaymentProvider {
-    /// `webhook_secrets_csv` は `STRIPE_WEBHOOK_SECRETS` の値（カンマ区切り）を想定
-    pub fn new(api_key: String, webhook_secret: String, webhook_secrets_csv: Option<String>) -> Self {
-        let mut secrets: Vec<String> = vec![];
-        if let Some(csv) = webhook_secrets_csv {
-            secrets.extend(
-                csv.split(',')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-            );
-        }
-        // 後方互換: STRIPE_WEBHOOK_SECRET も候補に入れる
-        if !webhook_secret.trim().is_empty() {
-            secrets.push(webhook_secret.clone());
-        }
-        secrets.sort();
-        secrets.dedup();
+    pub fn new(api_key: String, mut webhook_secrets: Vec<String>, webhook_tolerance_seconds: i64) -> Self {
+        webhook_secrets.retain(|s| !s.trim().is_empty());
+        webhook_secrets.sort();
+        webhook_secrets.dedup();
         Self {
             api_key,
-            webhook_secret,
-            webhook_secrets: secrets,
+            webhook_secrets,
+            webhook_tolerance_seconds,
             client: reqwest::Client::new(),
         }
     }
 
+    /// 設定から作成（STRIPE_SECRET_KEY 未設定時はNone）
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let stripe = &config.stripe;
+        let api_key = stripe.secret_key.as_ref().filter(|k| !k.is_empty())?;
+
+        // 後方互換: STRIPE_WEBHOOK_SECRET も候補に入れる
+        let secrets = stripe
+            .webhook_secrets
+            .iter()
+            .chain(stripe.webhook_secret.as_ref())
+            .map(|s| s.expose().trim().to_string())
+            .collect();
+
+        // リプレイ対策: 本番環境では120秒以下を強制
+        let is_prod = config.is_production();
+        let default_tolerance: i64 = if is_prod { 120 } else { 300 };
+        let mut tolerance = stripe.webhook_tolerance_seconds.unwrap_or(default_tolerance);
+        if is_prod && tolerance > 120 {
+            tracing::warn!(
+                "STRIPE_WEBHOOK_TOLERANCE_SECONDS={} exceeds 120s limit for production, capping to 120s",
+                tolerance
+            );
+            tolerance = 120;
+        }
+
+        Some(Self::new(api_key.expose().to_string(), secrets, tolerance))
+    }
+
     fn api_base_url(&self) -> &'static str {
         "https://api.stripe.com/v1"
     }
@@ -50,6 +68,7 @@ impl StripePaymentProvider {
             .client
             .get(&format!("{}/payment_intents/{}", self.api_base_url(), intent_id))
             .basic_auth(&self.api_key, None::<&str>)
+            .headers(request_id_headers())
             .send()
             .await
             .map_err(|e| PaymentError::NetworkError(e.to_string()))?;
@@ -91,6 +110,27 @@ impl StripePaymentProvider {
             paid_at: None,
         })
     }
+
+    /// PaymentIntentの取り消し（支払い済み・取り消し済みの場合はエラー）
+    pub async fn cancel_intent(&self, intent_id: &str) -> Result<(), PaymentError> {
+        let response = self
+            .client
+            .post(format!("{}/payment_intents/{}/cancel", self.api_base_url(), intent_id))
+            .basic_auth(&self.api_key, None::<&str>)
+            .headers(request_id_headers())
+            .send()
+            .await
+            .map_err(|e| PaymentError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            tracing::warn!("Stripe API error (cancel_intent): {}", error_text);
+            return Err(PaymentError::ProviderError(
+                "Payment cancellation failed. Please try again.".to_string()
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -138,6 +178,7 @@ impl PaymentProvider for StripePaymentProvider {
             .client
             .post(&format!("{}/payment_intents", self.api_base_url()))
             .basic_auth(&self.api_key, None::<&str>)
+            .headers(request_id_headers())
             // 冪等性キー（同一注文の多重生成防止）
             .header(
                 "Idempotency-Key",
@@ -186,6 +227,7 @@ impl PaymentProvider for StripePaymentProvider {
                 intent_id
             ))
             .basic_auth(&self.api_key, None::<&str>)
+            .headers(request_id_headers())
             .send()
             .await
             .map_err(|e| PaymentError::NetworkError(e.to_string()))?;
@@ -237,6 +279,7 @@ impl PaymentProvider for StripePaymentProvider {
             .client
             .post(&format!("{}/refunds", self.api_base_url()))
             .basic_auth(&self.api_key, None::<&str>)
+            .headers(request_id_headers())
             .form(&form)
             .send()
             .await
@@ -303,24 +346,7 @@ impl PaymentProvider for StripePaymentProvider {
         }
 
         // リプレイ対策: タイムスタンプ許容範囲
-        // 本番環境では120秒以下を強制（リプレイ攻撃対策）
-        let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string());
-        let is_prod = env == "production";
-        let default_tolerance: i64 = if is_prod { 120 } else { 300 };
-
-        let mut tolerance: i64 = std::env::var("STRIPE_WEBHOOK_TOLERANCE_SECONDS")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(default_tolerance);
-
-        // 本番環境では120秒を超える設定を許可しない
-        if is_prod && tolerance > 120 {
-            tracing::warn!(
-                "STRIPE_WEBHOOK_TOLERANCE_SECONDS={} exceeds 120s limit for production, capping to 120s",
-                tolerance
-            );
-            tolerance = 120;
-        }
+        let tolerance = self.webhook_tolerance_seconds;
 
         let now = chrono::Utc::now().timestamp();
         if (now - timestamp).abs() > tolerance {
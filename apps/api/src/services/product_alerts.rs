@@ -0,0 +1,385 @@
+//! 再入荷・値下げ通知
+//! BackInStock / PriceDropped イベントで該当する購読を送信待ちにし、送信タスクが宛先ごとにまとめて通知する。
+//! 同じ宛先へは product_alerts.recipient_interval_seconds 以上の間隔を空け、送信した購読は終了する
+//!
+//! ゲストの購読はダブルオプトイン（確認メールのリンクを開くまで通知しない）。
+//! 確認リンクは `{購読ID}.{有効期限(unix秒)}.{HMAC}`、解除リンクは宛先単位の `{base64url(メールアドレス)}.{HMAC}`
+//! （鍵はセッション署名と同じ）で、解除リンクは確認メール・通知メールのすべてに載せる
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::{ProductAlertRepository, ProductRepository};
+use crate::models::{
+    alert_condition_met, DomainEvent, DomainEventType, Product, ProductAlert, ProductAlertKind, ProductVariant,
+};
+use crate::services::email::send_email;
+use crate::services::outbox::EventSubscriber;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn token_mac(message: &str, secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 登録確認トークンを生成
+pub fn sign_confirmation_token(id: Uuid, expires_at: DateTime<Utc>, secret: &str) -> String {
+    let expires_at = expires_at.timestamp();
+    let mac = token_mac(&format!("product_alert_confirm.{}.{}", id, expires_at), secret);
+    format!("{}.{}.{}", id, expires_at, mac)
+}
+
+/// 登録確認トークンを検証し、購読IDを返す（署名不一致・期限切れはNone）
+pub fn verify_confirmation_token(token: &str, secret: &str, now: DateTime<Utc>) -> Option<Uuid> {
+    let mut parts = token.splitn(3, '.');
+    let id: Uuid = parts.next()?.parse().ok()?;
+    let expires_at: i64 = parts.next()?.parse().ok()?;
+    let signature = parts.next()?;
+
+    let expected = token_mac(&format!("product_alert_confirm.{}.{}", id, expires_at), secret);
+    if !bool::from(expected.as_bytes().ct_eq(signature.as_bytes())) || now.timestamp() > expires_at {
+        return None;
+    }
+    Some(id)
+}
+
+/// 購読解除トークンを生成（宛先単位、期限なし）
+pub fn sign_unsubscribe_token(email: &str, secret: &str) -> String {
+    let mac = token_mac(&format!("product_alert_unsubscribe.{}", email), secret);
+    format!("{}.{}", URL_SAFE_NO_PAD.encode(email), mac)
+}
+
+/// 購読解除トークンを検証し、宛先のメールアドレスを返す
+pub fn verify_unsubscribe_token(token: &str, secret: &str) -> Option<String> {
+    let (encoded, signature) = token.split_once('.')?;
+    let email = String::from_utf8(URL_SAFE_NO_PAD.decode(encoded).ok()?).ok()?;
+
+    let expected = token_mac(&format!("product_alert_unsubscribe.{}", email), secret);
+    bool::from(expected.as_bytes().ct_eq(signature.as_bytes())).then_some(email)
+}
+
+fn unsubscribe_link(state: &AppState, email: &str) -> String {
+    format!(
+        "{}?token={}",
+        state.config.product_alerts.unsubscribe_link_base_url,
+        sign_unsubscribe_token(email, state.config.session_secret())
+    )
+}
+
+/// 登録確認メールを送る（ゲストの購読）
+pub async fn send_confirmation_email(
+    state: &AppState,
+    alert: &ProductAlert,
+    product: &Product,
+    size: Option<&str>,
+) -> anyhow::Result<()> {
+    let config = &state.config.product_alerts;
+    let expires_at = Utc::now() + chrono::Duration::seconds(config.confirmation_ttl_seconds);
+    let token = sign_confirmation_token(alert.id, expires_at, state.config.session_secret());
+
+    let name = match size {
+        Some(size) => format!("{}（{}）", product.name, size),
+        None => product.name.clone(),
+    };
+    let kind = match alert.kind {
+        ProductAlertKind::BackInStock => "再入荷",
+        ProductAlertKind::PriceDrop => "値下げ",
+    };
+    let body = format!(
+        "「{}」の{}通知の登録を受け付けました。\n\n以下のリンクを開くと登録が完了します（{}まで有効）。\n{}?token={}\n\nお心当たりのない場合は、このメールを破棄してください（登録は完了しません）。\n今後このメールアドレスへのお知らせを停止する: {}\n",
+        name,
+        kind,
+        expires_at
+            .with_timezone(&chrono::FixedOffset::east_opt(9 * 3600).expect("valid offset"))
+            .format("%Y/%m/%d %H:%M"),
+        config.confirm_link_base_url,
+        token,
+        unsubscribe_link(state, &alert.email)
+    );
+    send_email(&state.config.email, &alert.email, "【Spirom】通知登録の確認", &body).await
+}
+
+/// 在庫補充・値下げで購読を送信待ちにする
+pub struct ProductAlertSubscriber {
+    pub state: AppState,
+}
+
+#[async_trait]
+impl EventSubscriber for ProductAlertSubscriber {
+    fn name(&self) -> &'static str {
+        "product_alerts"
+    }
+
+    fn handles(&self, event_type: DomainEventType) -> bool {
+        matches!(event_type, DomainEventType::BackInStock | DomainEventType::PriceDropped)
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let repo = ProductAlertRepository::new(self.state.db.service());
+        let queued = match event.event_type {
+            DomainEventType::BackInStock => {
+                let variant_id = event.payload["variant_id"]
+                    .as_str()
+                    .and_then(|s| s.parse::<Uuid>().ok());
+                repo.queue_back_in_stock(event.aggregate_id, variant_id).await?
+            }
+            DomainEventType::PriceDropped => {
+                let Some(price) = event.payload["price"].as_i64() else {
+                    return Ok(());
+                };
+                repo.queue_price_drop(event.aggregate_id, price).await?
+            }
+            _ => 0,
+        };
+        if queued > 0 {
+            tracing::info!(
+                "product_alerts: queued {} alerts: product_id={}, type={:?}",
+                queued,
+                event.aggregate_id,
+                event.event_type
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 通知メールの1行分
+struct AlertLine<'a> {
+    kind: ProductAlertKind,
+    product: &'a Product,
+    size: Option<&'a str>,
+    target_price: Option<i64>,
+}
+
+/// 宛先ごとのまとめ通知メール（件名, 本文）
+fn compose_alert_email(lines: &[AlertLine<'_>], unsubscribe_link: &str) -> (String, String) {
+    let subject = match lines {
+        [line] => match line.kind {
+            ProductAlertKind::BackInStock => format!("【Spirom】再入荷のお知らせ: {}", line.product.name),
+            ProductAlertKind::PriceDrop => format!("【Spirom】値下げのお知らせ: {}", line.product.name),
+        },
+        _ => format!("【Spirom】お気に入り商品のお知らせ（{}件）", lines.len()),
+    };
+
+    let mut body = String::from("ご登録いただいた商品についてお知らせします。\n\n");
+    for line in lines {
+        let name = match line.size {
+            Some(size) => format!("{}（{}）", line.product.name, size),
+            None => line.product.name.clone(),
+        };
+        let detail = match (line.kind, line.target_price) {
+            (ProductAlertKind::PriceDrop, Some(target)) => {
+                format!("¥{}に値下げされました（登録時 ¥{}）", line.product.price, target)
+            }
+            (ProductAlertKind::PriceDrop, None) => format!("¥{}に値下げされました", line.product.price),
+            (ProductAlertKind::BackInStock, _) => "再入荷しました".to_string(),
+        };
+        body.push_str(&format!("・{}: {}\n  /products/{}\n", name, detail, line.product.slug));
+    }
+    body.push_str("\n※ このお知らせの送信をもって、上記商品の通知登録は終了しました。");
+    body.push_str(&format!("\n今後このメールアドレスへのお知らせを停止する: {}\n", unsubscribe_link));
+
+    (subject, body)
+}
+
+/// 送信タスクを起動（間隔・宛先数・宛先ごとの間隔・試行回数は product_alerts.*）
+pub fn spawn_product_alert_sender(state: AppState) {
+    let config = state.config.product_alerts.clone();
+    let interval = Duration::from_secs(config.interval_seconds.max(5));
+    let batch_size = config.batch_size.clamp(1, 500);
+    let recipient_interval = config.recipient_interval_seconds.max(0);
+    let max_attempts = config.max_attempts.max(1);
+    let confirmation_ttl = chrono::Duration::seconds(config.confirmation_ttl_seconds);
+    let lock_seconds = 300;
+    // 解除リンクを載せられない通知メールは送らない
+    if config.unsubscribe_link_base_url.is_empty() {
+        tracing::warn!("PRODUCT_ALERT_UNSUBSCRIBE_LINK_BASE_URL is not set; product alert sender disabled");
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let repo = ProductAlertRepository::new(state.db.service());
+            if let Err(e) = repo.cancel_unconfirmed(Utc::now() - confirmation_ttl).await {
+                tracing::warn!("product_alerts: failed to cancel unconfirmed alerts: {}", e);
+            }
+            let alerts = match repo.claim_batch(batch_size, recipient_interval, lock_seconds).await {
+                Ok(alerts) => alerts,
+                Err(e) => {
+                    tracing::warn!("product_alerts: failed to claim alerts: {}", e);
+                    continue;
+                }
+            };
+            if alerts.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = send_batch(&state, &repo, alerts, max_attempts).await {
+                // ロック期限切れで再取得される
+                tracing::warn!("product_alerts: failed to process batch: {}", e);
+            }
+        }
+    });
+}
+
+/// 取得した購読を宛先ごとにまとめて送る
+async fn send_batch(
+    state: &AppState,
+    repo: &ProductAlertRepository,
+    alerts: Vec<ProductAlert>,
+    max_attempts: i32,
+) -> anyhow::Result<()> {
+    let product_repo = ProductRepository::new(state.db.service());
+    let product_ids: Vec<Uuid> = alerts.iter().map(|a| a.product_id).collect();
+    let variant_ids: Vec<Uuid> = alerts.iter().filter_map(|a| a.variant_id).collect();
+    let products = product_repo.find_by_ids(&product_ids).await?;
+    let variants = product_repo.find_variants_by_ids(&variant_ids).await?;
+
+    let mut by_email: BTreeMap<String, Vec<ProductAlert>> = BTreeMap::new();
+    for alert in alerts {
+        by_email.entry(alert.email.clone()).or_default().push(alert);
+    }
+
+    for (email, alerts) in by_email {
+        send_to_recipient(state, repo, &email, alerts, &products, &variants, max_attempts).await?;
+    }
+    Ok(())
+}
+
+async fn send_to_recipient(
+    state: &AppState,
+    repo: &ProductAlertRepository,
+    email: &str,
+    alerts: Vec<ProductAlert>,
+    products: &HashMap<Uuid, Product>,
+    variants: &HashMap<Uuid, ProductVariant>,
+    max_attempts: i32,
+) -> anyhow::Result<()> {
+    // 送信直前に条件を再確認（再入荷直後に売り切れた・値上げされた等は待機中に戻す）
+    let mut lines = Vec::new();
+    let mut ready = Vec::new();
+    let mut stale = Vec::new();
+    for alert in &alerts {
+        let product = products.get(&alert.product_id).filter(|p| p.is_active);
+        let variant = alert.variant_id.and_then(|id| variants.get(&id));
+        let met = product.is_some_and(|product| {
+            let stock = match alert.variant_id {
+                Some(_) => variant.filter(|v| v.is_active).map(|v| v.stock).unwrap_or(0),
+                None => product.stock,
+            };
+            alert_condition_met(alert.kind, alert.target_price, stock, product.price)
+        });
+        match product {
+            Some(product) if met => {
+                ready.push(alert.id);
+                lines.push(AlertLine {
+                    kind: alert.kind,
+                    product,
+                    size: variant.map(|v| v.size.as_str()),
+                    target_price: alert.target_price,
+                });
+            }
+            _ => stale.push(alert.id),
+        }
+    }
+
+    repo.reactivate(&stale).await?;
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let (subject, body) = compose_alert_email(&lines, &unsubscribe_link(state, email));
+    match send_email(&state.config.email, email, &subject, &body).await {
+        Ok(()) => {
+            repo.mark_sent(&ready).await?;
+            tracing::info!("product_alerts: notified {} alerts", ready.len());
+        }
+        Err(e) => {
+            let attempts = alerts.iter().map(|a| a.attempts).max().unwrap_or(0);
+            let retry = attempts < max_attempts;
+            tracing::warn!("product_alerts: email failed (retry={}): {:#}", retry, e);
+            repo.mark_failed(&ready, &format!("{:#}", e), retry).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_confirmation_token() {
+        let secret = "0123456789abcdef0123456789abcdef";
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = sign_confirmation_token(id, now + chrono::Duration::hours(1), secret);
+
+        assert_eq!(verify_confirmation_token(&token, secret, now), Some(id));
+        assert_eq!(verify_confirmation_token(&token, "other-secret", now), None);
+        assert_eq!(verify_confirmation_token(&token, secret, now + chrono::Duration::hours(2)), None);
+        let forged = token.replacen(&id.to_string(), &Uuid::new_v4().to_string(), 1);
+        assert_eq!(verify_confirmation_token(&forged, secret, now), None);
+    }
+
+    #[test]
+    fn test_unsubscribe_token() {
+        let secret = "0123456789abcdef0123456789abcdef";
+        let token = sign_unsubscribe_token("guest@example.com", secret);
+
+        assert_eq!(verify_unsubscribe_token(&token, secret).as_deref(), Some("guest@example.com"));
+        assert_eq!(verify_unsubscribe_token(&token, "other-secret"), None);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode("victim@example.com"), signature);
+        assert_eq!(verify_unsubscribe_token(&forged, secret), None);
+    }
+
+    #[test]
+    fn test_compose_alert_email() {
+        let tee = Product { name: "Tee".to_string(), price: 3000, ..Product::test_fixture() };
+        let hoodie = Product { name: "Hoodie".to_string(), price: 8000, ..Product::test_fixture() };
+
+        let (subject, body) = compose_alert_email(&[AlertLine {
+            kind: ProductAlertKind::BackInStock,
+            product: &tee,
+            size: Some("M"),
+            target_price: None,
+        }], "https://spirom.com/alerts/unsubscribe?token=t");
+        assert!(subject.contains("再入荷") && subject.contains("Tee"));
+        assert!(body.contains("Tee（M）: 再入荷しました"));
+        assert!(body.contains("/products/tee"));
+        assert!(body.contains("https://spirom.com/alerts/unsubscribe?token=t"));
+
+        let (subject, body) = compose_alert_email(&[
+            AlertLine {
+                kind: ProductAlertKind::BackInStock,
+                product: &tee,
+                size: None,
+                target_price: None,
+            },
+            AlertLine {
+                kind: ProductAlertKind::PriceDrop,
+                product: &hoodie,
+                size: None,
+                target_price: Some(9000),
+            },
+        ], "https://spirom.com/alerts/unsubscribe?token=t");
+        assert!(subject.contains("2件"));
+        assert!(body.contains("¥8000に値下げされました（登録時 ¥9000）"));
+    }
+}
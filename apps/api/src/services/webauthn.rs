@@ -0,0 +1,370 @@
+//! WebAuthn（パスキー）検証
+//! ES256（P-256）のみ対応。アテステーションは要求しない（"none"）
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value as CborValue;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::config::{AppState, Config};
+use crate::db::repositories::PasskeyRepository;
+
+/// COSEアルゴリズムID: ES256
+pub const COSE_ALG_ES256: i64 = -7;
+
+/// authenticatorData フラグ
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// チャレンジの有効期限（秒）
+pub const CHALLENGE_TTL_SECONDS: i64 = 300;
+
+/// WebAuthn設定
+#[derive(Debug, Clone)]
+pub struct WebAuthnConfig {
+    /// Relying Party ID（例: spirom.com）
+    pub rp_id: String,
+    /// Relying Party 表示名
+    pub rp_name: String,
+    /// 許可するオリジン（例: https://spirom.com）
+    pub origins: Vec<String>,
+}
+
+/// 設定からWebAuthn設定を取得
+/// passkey.origins（WEBAUTHN_ORIGINS）未設定時は cors.allowed_origins を使用
+pub fn get_webauthn_config(config: &Config) -> WebAuthnConfig {
+    let configured = if config.passkey.origins.is_empty() {
+        &config.cors.allowed_origins
+    } else {
+        &config.passkey.origins
+    };
+    let mut origins: Vec<String> = configured
+        .iter()
+        .map(|s| s.trim().trim_end_matches('/').to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if origins.is_empty() {
+        origins.push("http://localhost:3000".to_string());
+    }
+
+    // RP IDは最初のオリジンのホスト名をデフォルトとする
+    let default_rp_id = origins
+        .first()
+        .and_then(|o| o.split("://").nth(1))
+        .map(|host| host.split(':').next().unwrap_or(host).to_string())
+        .unwrap_or_else(|| "localhost".to_string());
+
+    WebAuthnConfig {
+        rp_id: config.passkey.rp_id.clone().unwrap_or(default_rp_id),
+        rp_name: config.passkey.rp_name.clone(),
+        origins,
+    }
+}
+
+/// ランダムなチャレンジを生成（base64url）
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// base64url デコード（パディング有無どちらも受け付ける）
+pub fn decode_b64url(input: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(input.trim_end_matches('='))
+        .map_err(|e| anyhow!("Invalid base64url: {}", e))
+}
+
+/// base64url エンコード
+pub fn encode_b64url(input: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(input)
+}
+
+/// 期限切れチャレンジの定期削除
+/// ログインオプションは未認証で発行できるため、消費されないチャレンジを溜めない
+pub fn spawn_challenge_cleanup(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(CHALLENGE_TTL_SECONDS as u64));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = PasskeyRepository::new(state.db.service()).purge_expired_challenges().await {
+                tracing::warn!("Failed to purge expired WebAuthn challenges: {}", e);
+            }
+        }
+    });
+}
+
+/// clientDataJSON
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+/// 登録検証済みのクレデンシャル
+#[derive(Debug, Clone)]
+pub struct RegisteredCredential {
+    pub credential_id: String,
+    /// SEC1 非圧縮形式の公開鍵（base64url）
+    pub public_key: String,
+    pub sign_count: u32,
+}
+
+/// パース済み authenticatorData
+struct AuthData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// アテステーション済みクレデンシャルデータ（登録時のみ）
+    attested: Option<(&'a [u8], &'a [u8])>,
+}
+
+impl WebAuthnConfig {
+    /// clientDataJSON を検証
+    fn verify_client_data(&self, client_data_json: &[u8], expected_type: &str, expected_challenge: &str) -> Result<()> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json)
+            .map_err(|e| anyhow!("Invalid clientDataJSON: {}", e))?;
+
+        if client_data.ceremony_type != expected_type {
+            bail!("Unexpected ceremony type: {}", client_data.ceremony_type);
+        }
+        if client_data.challenge.trim_end_matches('=') != expected_challenge {
+            bail!("Challenge mismatch");
+        }
+        if !self.origins.iter().any(|o| o == &client_data.origin) {
+            bail!("Origin not allowed: {}", client_data.origin);
+        }
+        Ok(())
+    }
+
+    /// authenticatorData の RP ID・フラグを検証
+    fn verify_auth_data(&self, auth_data: &AuthData<'_>) -> Result<()> {
+        let expected = Sha256::digest(self.rp_id.as_bytes());
+        if auth_data.rp_id_hash != expected.as_slice() {
+            bail!("RP ID hash mismatch");
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            bail!("User not present");
+        }
+        // パスワードレスのため本人確認（生体認証/PIN）を必須とする
+        if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+            bail!("User not verified");
+        }
+        Ok(())
+    }
+
+    /// 登録レスポンス（navigator.credentials.create）を検証
+    pub fn verify_registration(
+        &self,
+        expected_challenge: &str,
+        client_data_json_b64: &str,
+        attestation_object_b64: &str,
+    ) -> Result<RegisteredCredential> {
+        let client_data_json = decode_b64url(client_data_json_b64)?;
+        self.verify_client_data(&client_data_json, "webauthn.create", expected_challenge)?;
+
+        let attestation_object = decode_b64url(attestation_object_b64)?;
+        let attestation: CborValue = ciborium::de::from_reader(attestation_object.as_slice())
+            .map_err(|e| anyhow!("Invalid attestationObject: {}", e))?;
+        let auth_data_bytes = cbor_map_get_text(&attestation, "authData")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("Missing authData"))?;
+
+        let auth_data = parse_auth_data(auth_data_bytes)?;
+        self.verify_auth_data(&auth_data)?;
+
+        let (credential_id, cose_key) = auth_data
+            .attested
+            .ok_or_else(|| anyhow!("Missing attested credential data"))?;
+        let public_key = cose_es256_to_sec1(cose_key)?;
+
+        Ok(RegisteredCredential {
+            credential_id: encode_b64url(credential_id),
+            public_key: encode_b64url(&public_key),
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// 認証レスポンス（navigator.credentials.get）を検証し、新しい署名カウンタを返す
+    pub fn verify_assertion(
+        &self,
+        expected_challenge: &str,
+        public_key_b64: &str,
+        stored_sign_count: u32,
+        client_data_json_b64: &str,
+        authenticator_data_b64: &str,
+        signature_b64: &str,
+    ) -> Result<u32> {
+        let client_data_json = decode_b64url(client_data_json_b64)?;
+        self.verify_client_data(&client_data_json, "webauthn.get", expected_challenge)?;
+
+        let auth_data_bytes = decode_b64url(authenticator_data_b64)?;
+        let auth_data = parse_auth_data(&auth_data_bytes)?;
+        self.verify_auth_data(&auth_data)?;
+
+        // 署名対象: authenticatorData || SHA-256(clientDataJSON)
+        let mut signed = auth_data_bytes.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+
+        let verifying_key = VerifyingKey::from_sec1_bytes(&decode_b64url(public_key_b64)?)
+            .map_err(|_| anyhow!("Invalid stored public key"))?;
+        let signature = Signature::from_der(&decode_b64url(signature_b64)?)
+            .map_err(|_| anyhow!("Invalid signature encoding"))?;
+        verifying_key
+            .verify(&signed, &signature)
+            .map_err(|_| anyhow!("Signature verification failed"))?;
+
+        // クローン検知: カウンタ対応の認証器でカウンタが増えていなければ拒否
+        if (auth_data.sign_count != 0 || stored_sign_count != 0) && auth_data.sign_count <= stored_sign_count {
+            bail!("Sign count did not increase (possible cloned authenticator)");
+        }
+
+        Ok(auth_data.sign_count)
+    }
+}
+
+/// authenticatorData をパース
+fn parse_auth_data(data: &[u8]) -> Result<AuthData<'_>> {
+    if data.len() < 37 {
+        bail!("authenticatorData too short");
+    }
+    let rp_id_hash = &data[..32];
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // aaguid(16) + credentialIdLength(2) + credentialId + credentialPublicKey(COSE)
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            bail!("Attested credential data too short");
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        if rest.len() < 18 + id_len {
+            bail!("Credential ID out of range");
+        }
+        Some((&rest[18..18 + id_len], &rest[18 + id_len..]))
+    } else {
+        None
+    };
+
+    Ok(AuthData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+/// COSE_Key（EC2 / P-256 / ES256）を SEC1 非圧縮形式に変換
+fn cose_es256_to_sec1(cose_key: &[u8]) -> Result<Vec<u8>> {
+    let key: CborValue = ciborium::de::from_reader(cose_key)
+        .map_err(|e| anyhow!("Invalid COSE key: {}", e))?;
+
+    let kty = cbor_map_get_int(&key, 1).and_then(cbor_as_i64);
+    let alg = cbor_map_get_int(&key, 3).and_then(cbor_as_i64);
+    let crv = cbor_map_get_int(&key, -1).and_then(cbor_as_i64);
+    if kty != Some(2) || alg != Some(COSE_ALG_ES256) || crv != Some(1) {
+        bail!("Unsupported credential algorithm (ES256 only)");
+    }
+
+    let x = cbor_map_get_int(&key, -2).and_then(|v| v.as_bytes()).ok_or_else(|| anyhow!("Missing x"))?;
+    let y = cbor_map_get_int(&key, -3).and_then(|v| v.as_bytes()).ok_or_else(|| anyhow!("Missing y"))?;
+    if x.len() != 32 || y.len() != 32 {
+        bail!("Invalid EC point length");
+    }
+
+    let mut sec1 = Vec::with_capacity(65);
+    sec1.push(0x04);
+    sec1.extend_from_slice(x);
+    sec1.extend_from_slice(y);
+
+    // 曲線上の点であることを確認
+    VerifyingKey::from_sec1_bytes(&sec1).map_err(|_| anyhow!("Invalid EC point"))?;
+    Ok(sec1)
+}
+
+fn cbor_map_get_text<'a>(map: &'a CborValue, key: &str) -> Option<&'a CborValue> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn cbor_map_get_int(map: &CborValue, key: i64) -> Option<&CborValue> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| cbor_as_i64(k) == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn cbor_as_i64(value: &CborValue) -> Option<i64> {
+    value.as_integer().and_then(|i| i64::try_from(i).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    fn test_config() -> WebAuthnConfig {
+        WebAuthnConfig {
+            rp_id: "localhost".to_string(),
+            rp_name: "Spirom".to_string(),
+            origins: vec!["http://localhost:3000".to_string()],
+        }
+    }
+
+    fn client_data(ceremony: &str, challenge: &str) -> String {
+        encode_b64url(
+            serde_json::json!({
+                "type": ceremony,
+                "challenge": challenge,
+                "origin": "http://localhost:3000"
+            })
+            .to_string()
+            .as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let config = test_config();
+        let signing_key = SigningKey::from_bytes(&[7u8; 32].into()).unwrap();
+        let public_key = encode_b64url(
+            signing_key.verifying_key().to_encoded_point(false).as_bytes(),
+        );
+
+        let challenge = generate_challenge();
+        let client_data_b64 = client_data("webauthn.get", &challenge);
+
+        let mut auth_data = Sha256::digest(b"localhost").to_vec();
+        auth_data.push(FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+        auth_data.extend_from_slice(&5u32.to_be_bytes());
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(decode_b64url(&client_data_b64).unwrap()));
+        let signature: Signature = signing_key.sign(&signed);
+        let signature_b64 = encode_b64url(signature.to_der().as_bytes());
+        let auth_data_b64 = encode_b64url(&auth_data);
+
+        let count = config
+            .verify_assertion(&challenge, &public_key, 4, &client_data_b64, &auth_data_b64, &signature_b64)
+            .unwrap();
+        assert_eq!(count, 5);
+
+        // カウンタが増えていない（クローン疑い）
+        assert!(config
+            .verify_assertion(&challenge, &public_key, 5, &client_data_b64, &auth_data_b64, &signature_b64)
+            .is_err());
+
+        // チャレンジ不一致
+        assert!(config
+            .verify_assertion(&generate_challenge(), &public_key, 4, &client_data_b64, &auth_data_b64, &signature_b64)
+            .is_err());
+    }
+}
@@ -0,0 +1,451 @@
+//! 外部連携Webhook（フルフィルメント・会計ツール等への通知）
+//! ドメインイベントを購読中のエンドポイントごとに配信ログ（webhook_deliveries）へ登録し、
+//! 別タスクがHMAC署名付きでPOSTする。失敗時は指数バックオフで再試行し、上限を超えたら dead にする
+//!
+//! 署名はStripeと同形式: `Spirom-Signature: t=<unix秒>,v1=<hex(HMAC-SHA256(secret, "{t}.{body}"))>`
+//!
+//! SSRF対策として、配信先ホストがプライベート・ループバック・リンクローカル・メタデータのIPに
+//! 解決される場合は登録時に拒否し、配信時も接続の度に名前解決結果を確認する（DNSリバインディング対策）
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::AppState;
+use crate::db::repositories::{DeliveryAttempt, DomainEventRepository, WebhookRepository, WebhookTarget};
+use crate::error::{AppError, Result};
+use crate::models::{DomainEvent, DomainEventType, WebhookDelivery, WebhookEventType};
+use crate::services::outbox::{retry_delay, EventSubscriber};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 署名ヘッダー名
+pub const SIGNATURE_HEADER: &str = "Spirom-Signature";
+
+/// 署名用シークレットを生成
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+/// 署名ヘッダーの値を生成
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// 配信先として許可するIPか（プライベート・ループバック・リンクローカル・メタデータ等は拒否）
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_ipv4(v4),
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ipv4(v4);
+            }
+            let segments = v6.segments();
+            // NAT64（64:ff9b::/96）は埋め込まれたIPv4で判定する
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [a, b] = segments[6].to_be_bytes();
+                let [c, d] = segments[7].to_be_bytes();
+                return is_public_ipv4(Ipv4Addr::new(a, b, c, d));
+            }
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // ユニークローカル fc00::/7（AWSのメタデータ fd00:ec2::254 を含む）
+                || (segments[0] & 0xfe00) == 0xfc00
+                // リンクローカル fe80::/10
+                || (segments[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, _, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        // 169.254.0.0/16（クラウドのメタデータ 169.254.169.254 を含む）
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // 0.0.0.0/8・予約済み 240.0.0.0/4
+        || a == 0
+        || a >= 240
+        // キャリアグレードNAT 100.64.0.0/10（Alibaba Cloudのメタデータ 100.100.100.200 を含む）
+        || (a == 100 && (b & 0xc0) == 64))
+}
+
+/// 配信先URLの形式検証（本番はhttpsのみ、IPアドレス直指定は公開IPのみ）
+fn validate_endpoint_url(url: &str, is_production: bool) -> Result<Url> {
+    let parsed = Url::parse(url).map_err(|_| AppError::BadRequest("URLの形式が正しくありません".to_string()))?;
+
+    match parsed.scheme() {
+        "https" => {}
+        "http" if !is_production => {}
+        _ => return Err(AppError::BadRequest("URLはhttpsで指定してください".to_string())),
+    }
+    let Some(host) = parsed.host_str().filter(|h| !h.is_empty()) else {
+        return Err(AppError::BadRequest("URLにホストがありません".to_string()));
+    };
+    if host_ip(host).is_some_and(|ip| !is_public_ip(ip)) {
+        return Err(AppError::BadRequest(
+            "プライベートネットワークのアドレスは指定できません".to_string(),
+        ));
+    }
+    Ok(parsed)
+}
+
+/// IPアドレス直指定のホスト（IPv6は角括弧付き）
+fn host_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+/// 配信先URLの検証（登録・更新時）
+/// 形式に加えてホストを名前解決し、公開IP以外に解決される場合は拒否する
+pub async fn check_endpoint_url(url: &str, is_production: bool) -> Result<()> {
+    let parsed = validate_endpoint_url(url, is_production)?;
+    let host = parsed.host_str().unwrap_or_default();
+    if host_ip(host).is_some() {
+        return Ok(());
+    }
+    let port = parsed.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| AppError::BadRequest("URLのホストを名前解決できません".to_string()))?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+        return Err(AppError::BadRequest(
+            "プライベートネットワークのアドレスは指定できません".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// 公開IP以外に解決されるホストへの接続を拒否するリゾルバー（配信用HTTPクライアントで使用）
+struct PublicOnlyResolver;
+
+impl Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+                return Err(format!("{} resolves to a non-public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// ドメインイベントから送信ボディを生成
+fn event_body(event: &DomainEvent, event_type: WebhookEventType) -> serde_json::Value {
+    serde_json::json!({
+        "id": event.id,
+        "type": event_type.as_str(),
+        "created_at": event.created_at,
+        "data": event.payload,
+    })
+}
+
+/// アウトボックス購読者: 対象イベントを購読中のエンドポイントへ配信登録する
+pub struct WebhookFanoutSubscriber {
+    pub state: AppState,
+}
+
+#[async_trait]
+impl EventSubscriber for WebhookFanoutSubscriber {
+    fn name(&self) -> &'static str {
+        "webhook_fanout"
+    }
+
+    fn handles(&self, event_type: DomainEventType) -> bool {
+        WebhookEventType::from_domain(event_type).is_some()
+    }
+
+    async fn handle(&self, event: &DomainEvent) -> anyhow::Result<()> {
+        let Some(event_type) = WebhookEventType::from_domain(event.event_type) else {
+            return Ok(());
+        };
+        let count = WebhookRepository::new(self.state.db.service())
+            .enqueue(event.id, event_type, &event_body(event, event_type))
+            .await?;
+        if count > 0 {
+            tracing::debug!("webhooks: enqueued {} deliveries for event {}", count, event.id);
+        }
+        Ok(())
+    }
+}
+
+/// 配信タスクを起動（間隔・バッチサイズ・試行回数・タイムアウトは webhooks.*）
+pub fn spawn_webhook_dispatcher(state: AppState) {
+    let config = &state.config.webhooks;
+    let interval_ms = config.poll_interval_ms.max(200);
+    let batch_size = config.batch_size.clamp(1, 200);
+    let max_attempts = config.max_attempts.max(1);
+    let timeout_seconds = config.timeout_seconds.clamp(1, 60);
+    let is_production = state.config.is_production();
+    // 1バッチの全配信がタイムアウトしても重複取得されない長さにする
+    let lock_seconds = (timeout_seconds as i32 + 5) * batch_size;
+
+    let http = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout_seconds))
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicOnlyResolver))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("webhooks: failed to build HTTP client, dispatcher disabled: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+        loop {
+            ticker.tick().await;
+
+            let repo = WebhookRepository::new(state.db.service());
+            let deliveries = match repo.claim_batch(batch_size, lock_seconds).await {
+                Ok(deliveries) => deliveries,
+                Err(e) => {
+                    tracing::warn!("webhooks: failed to claim deliveries: {}", e);
+                    continue;
+                }
+            };
+
+            let mut targets: HashMap<Uuid, Option<WebhookTarget>> = HashMap::new();
+            for delivery in deliveries {
+                let target = match targets.get(&delivery.endpoint_id) {
+                    Some(target) => target.clone(),
+                    None => match repo.find_target(delivery.endpoint_id).await {
+                        Ok(target) => {
+                            targets.insert(delivery.endpoint_id, target.clone());
+                            target
+                        }
+                        Err(e) => {
+                            // ロック期限切れで再取得される
+                            tracing::warn!("webhooks: failed to load endpoint {}: {}", delivery.endpoint_id, e);
+                            continue;
+                        }
+                    },
+                };
+                deliver(&repo, &http, target, delivery, max_attempts, is_production).await;
+            }
+        }
+    });
+}
+
+/// 1件配信して結果を記録する
+async fn deliver(
+    repo: &WebhookRepository,
+    http: &reqwest::Client,
+    target: Option<WebhookTarget>,
+    delivery: WebhookDelivery,
+    max_attempts: i32,
+    is_production: bool,
+) {
+    let started = Instant::now();
+    // 無効化されたエンドポイント・許可されないURLへの配信は再試行しない（修正後に管理APIから再送する）
+    let rejected = match &target {
+        Some(target) if target.is_active => validate_endpoint_url(&target.url, is_production)
+            .err()
+            .map(|_| "endpoint URL is not allowed"),
+        _ => Some("endpoint is disabled"),
+    };
+    let (succeeded, attempt) = match (target, rejected) {
+        (Some(target), None) => send(http, &target, &delivery, started).await,
+        (_, reason) => (
+            false,
+            DeliveryAttempt {
+                response_status: None,
+                last_error: reason.map(str::to_string),
+                duration_ms: 0,
+            },
+        ),
+    };
+
+    let next_attempt_at = if succeeded || rejected.is_some() || delivery.attempts >= max_attempts {
+        None
+    } else {
+        Some(Utc::now() + retry_delay(delivery.attempts))
+    };
+    let outcome = match (succeeded, next_attempt_at.is_some()) {
+        (true, _) => "succeeded",
+        (false, true) => "failed",
+        (false, false) => "dead",
+    };
+    crate::services::metrics::record_outgoing_webhook(&delivery.event_type, outcome);
+    if !succeeded {
+        tracing::warn!(
+            "webhooks: delivery failed: id={}, endpoint_id={}, attempts={}, status={:?}, error={:?}, retry={}",
+            delivery.id,
+            delivery.endpoint_id,
+            delivery.attempts,
+            attempt.response_status,
+            attempt.last_error,
+            next_attempt_at.is_some()
+        );
+    }
+
+    if let Err(e) = repo
+        .record_attempt(delivery.id, &attempt, succeeded, next_attempt_at)
+        .await
+    {
+        tracing::warn!("webhooks: failed to record delivery result: id={}, error={}", delivery.id, e);
+    }
+}
+
+async fn send(
+    http: &reqwest::Client,
+    target: &WebhookTarget,
+    delivery: &WebhookDelivery,
+    started: Instant,
+) -> (bool, DeliveryAttempt) {
+    let body = delivery.body.to_string();
+    let signature = sign_payload(&target.secret, Utc::now().timestamp(), body.as_bytes());
+
+    let result = http
+        .post(&target.url)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "Spirom-Webhooks/1.0")
+        .header(SIGNATURE_HEADER, signature)
+        .header("Spirom-Event", &delivery.event_type)
+        .header("Spirom-Delivery", delivery.id.to_string())
+        .body(body)
+        .send()
+        .await;
+
+    match result {
+        // レスポンスボディは読まない（配信先の機微情報を保存しない）
+        Ok(response) => {
+            let status = response.status();
+            (
+                status.is_success(),
+                DeliveryAttempt {
+                    response_status: Some(status.as_u16() as i32),
+                    last_error: (!status.is_success()).then(|| format!("HTTP {}", status.as_u16())),
+                    duration_ms: started.elapsed().as_millis() as i32,
+                },
+            )
+        }
+        Err(e) => (
+            false,
+            DeliveryAttempt {
+                response_status: None,
+                last_error: Some(e.to_string()),
+                duration_ms: started.elapsed().as_millis() as i32,
+            },
+        ),
+    }
+}
+
+/// 保持期間を過ぎた完了済みの配信ログ・ドメインイベントの定期削除
+/// 保持日数は webhooks.retention_days / outbox.retention_days
+pub fn spawn_retention_cleanup(state: AppState) {
+    let delivery_days = state.config.webhooks.retention_days.max(1);
+    let event_days = state.config.outbox.retention_days.max(1);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            let now = Utc::now();
+            if let Err(e) = WebhookRepository::new(state.db.service())
+                .purge_finished(now - chrono::Duration::days(delivery_days))
+                .await
+            {
+                tracing::warn!("webhooks: failed to purge old deliveries: {}", e);
+            }
+            if let Err(e) = DomainEventRepository::new(state.db.service())
+                .purge_finished(now - chrono::Duration::days(event_days))
+                .await
+            {
+                tracing::warn!("outbox: failed to purge old domain events: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_payload() {
+        let signature = sign_payload("whsec_test", 1700000000, b"{\"id\":1}");
+        let (t, v1) = signature.split_once(',').unwrap();
+        assert_eq!(t, "t=1700000000");
+
+        let mut mac = HmacSha256::new_from_slice(b"whsec_test").unwrap();
+        mac.update(b"1700000000.{\"id\":1}");
+        assert_eq!(v1, format!("v1={}", hex::encode(mac.finalize().into_bytes())));
+
+        assert_ne!(signature, sign_payload("whsec_other", 1700000000, b"{\"id\":1}"));
+    }
+
+    #[test]
+    fn test_validate_endpoint_url() {
+        assert!(validate_endpoint_url("https://partner.example.com/hooks", true).is_ok());
+        assert!(validate_endpoint_url("http://partner.example.com/hooks", true).is_err());
+        assert!(validate_endpoint_url("http://partner.example.com:4000/hooks", false).is_ok());
+        assert!(validate_endpoint_url("https://93.184.216.34/hooks", true).is_ok());
+        assert!(validate_endpoint_url("http://127.0.0.1:4000/hooks", false).is_err());
+        assert!(validate_endpoint_url("http://169.254.169.254/latest/meta-data", false).is_err());
+        assert!(validate_endpoint_url("http://[::1]/hooks", false).is_err());
+        assert!(validate_endpoint_url("ftp://partner.example.com", false).is_err());
+        assert!(validate_endpoint_url("not a url", false).is_err());
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        let public = ["93.184.216.34", "8.8.8.8", "2606:2800:220:1::1"];
+        for ip in public {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+
+        let blocked = [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ];
+        for ip in blocked {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    /// IPアドレス直指定のURLはDNSを引かずに判定する
+    #[tokio::test]
+    async fn test_check_endpoint_url_rejects_private_ip_literal() {
+        assert!(check_endpoint_url("http://10.1.2.3/hooks", false).await.is_err());
+        assert!(check_endpoint_url("https://93.184.216.34/hooks", true).await.is_ok());
+    }
+}
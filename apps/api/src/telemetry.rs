@@ -0,0 +1,104 @@
+//! OpenTelemetry（OTLP/HTTP）によるトレースのエクスポート
+//! OTEL_EXPORTER_OTLP_ENDPOINT（例: http://localhost:4318）設定時のみ有効。未設定時はログ出力のみ
+
+use anyhow::Context;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+const SERVICE_NAME: &str = "spirom-api";
+
+/// トレース送信先（OTEL_EXPORTER_OTLP_TRACES_ENDPOINT はそのまま、OTEL_EXPORTER_OTLP_ENDPOINT は /v1/traces を付与）
+fn traces_endpoint() -> Option<String> {
+    let non_empty = |name: &str| std::env::var(name).ok().filter(|s| !s.trim().is_empty());
+
+    non_empty("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| {
+        non_empty("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+    })
+}
+
+/// OTLPエクスポーター付きのTracerProviderを作成
+pub fn init_tracer_provider(endpoint: &str) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Failed to build OTLP span exporter")?;
+
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| SERVICE_NAME.to_string());
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build())
+}
+
+/// トレーシングの初期化（ログ出力 + OTLPエクスポート）
+/// 戻り値のTracerProviderは終了時に shutdown して未送信のspanを送る
+pub fn init_tracing() -> Option<SdkTracerProvider> {
+    let provider = traces_endpoint().and_then(|endpoint| match init_tracer_provider(&endpoint) {
+        Ok(provider) => Some((provider, endpoint)),
+        Err(e) => {
+            eprintln!("OpenTelemetry disabled: {:#}", e);
+            None
+        }
+    });
+
+    let otel_layer = provider
+        .as_ref()
+        .map(|(provider, _)| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "spirom_api=debug,tower_http=debug".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .init();
+
+    provider.map(|(provider, endpoint)| {
+        tracing::info!("Exporting OpenTelemetry traces to {}", endpoint);
+        provider
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// ローカルのダミーコレクターへspanが送信されること
+    #[test]
+    fn test_exports_spans_to_local_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buffer = vec![0u8; 64 * 1024];
+            let n = stream.read(&mut buffer).unwrap_or(0);
+            let request_line = String::from_utf8_lossy(&buffer[..n]).lines().next().unwrap_or_default().to_string();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+            tx.send(request_line).unwrap();
+        });
+
+        let provider = init_tracer_provider(&format!("http://{}/v1/traces", addr)).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("repository_call", repository = "OrderRepository").entered();
+        });
+        provider.force_flush().unwrap();
+
+        let request_line = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(request_line.starts_with("POST /v1/traces"), "{}", request_line);
+        let _ = provider.shutdown();
+    }
+}